@@ -8,4 +8,13 @@ init() {
   cp "${SCRIPT_DIR}"/Cargo_template.toml "${SCRIPT_DIR}"/Cargo.toml
 }
 
+build_cpu_engine() {
+  # Build the engine without the rust-tensorflow dependency (and libtensorflow), so it uses the CPU evaluator
+  # with the cpu_model.json / cpu_model.bin weights exported alongside the model by training.py
+  grep -E -v "^tensorflow" "${SCRIPT_DIR}"/Cargo_template.toml > "${SCRIPT_DIR}"/Cargo.toml
+  pushd "${SCRIPT_DIR}"
+  RUSTFLAGS="--cfg compile_training" cargo build --release
+  popd
+}
+
 $@
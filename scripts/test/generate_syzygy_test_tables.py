@@ -0,0 +1,548 @@
+"""Generates the KRvK and KPvK tables in src/test/resources/syzygy that are used by the Syzygy probing tests
+
+These aren't the official Syzygy files: the positions are solved here by retrograde analysis (independently of the
+engine's own move generation and endgame solver), and then written in the Syzygy file format with the same indexing,
+pair compression and canonical Huffman code as the real tables, so that the tests cover the whole decoding path.
+
+Usage (from the repository root): python3 scripts/test/generate_syzygy_test_tables.py src/test/resources/syzygy
+"""
+import heapq
+import os
+import struct
+import sys
+from collections import deque
+
+WDL_MAGIC = bytes([0x71, 0xE8, 0x23, 0x5D])
+DTZ_MAGIC = bytes([0xD7, 0x66, 0x0C, 0xA5])
+
+FLAG_STM = 1
+FLAG_MAPPED = 2
+FLAG_WIN_PLIES = 4
+FLAG_LOSS_PLIES = 8
+FLAG_SINGLE_VALUE = 128
+
+# Piece codes in the table headers (black pieces have 8 added)
+W_PAWN, W_KNIGHT, W_BISHOP, W_ROOK, W_QUEEN, W_KING, B_KING = 1, 2, 3, 4, 5, 6, 14
+
+LOSS, DRAW, WIN = -2, 0, 2
+
+# Small blocks and spans, so that the sparse index and the block lengths are used for almost every lookup
+BLOCK_SIZE_LOG2 = 5
+SPAN_LOG2 = 8
+MAX_SYMBOLS = 512
+# Each symbol's length is stored in a byte, so a symbol can expand to at most 256 values
+MAX_SYMBOL_VALUES = 256
+MAX_CODE_LENGTH = 24
+
+WHITE, BLACK = 0, 1
+
+
+def file_of(sq: int) -> int:
+    return sq & 7
+
+
+def rank_of(sq: int) -> int:
+    return sq >> 3
+
+
+KING_ATTACKS = [{t for t in range(64) if t != s and abs(file_of(t) - file_of(s)) <= 1 and abs(rank_of(t) - rank_of(s)) <= 1}
+                for s in range(64)]
+DIRECTIONS = {
+    'R': [(1, 0), (-1, 0), (0, 1), (0, -1)],
+    'B': [(1, 1), (1, -1), (-1, 1), (-1, -1)],
+}
+DIRECTIONS['Q'] = DIRECTIONS['R'] + DIRECTIONS['B']
+
+
+def calc_slides(piece: str, sq: int, occupied: set) -> [int]:
+    # The squares reached by a sliding piece, up to and including the first occupied square in each direction
+    targets = []
+    for file_step, rank_step in DIRECTIONS[piece]:
+        file, rank = file_of(sq) + file_step, rank_of(sq) + rank_step
+        while 0 <= file < 8 and 0 <= rank < 8:
+            targets.append(rank * 8 + file)
+            if rank * 8 + file in occupied:
+                break
+            file, rank = file + file_step, rank + rank_step
+    return targets
+
+
+def is_attacked_by(piece: str, sq: int, target: int, occupied: set) -> bool:
+    if piece == 'P':
+        return (file_of(sq) > 0 and target == sq + 7) or (file_of(sq) < 7 and target == sq + 9)
+    return target in calc_slides(piece, sq, occupied)
+
+
+class Endgame:
+    """Every position with a white king, one other white piece and a black king, solved for both sides to move
+
+    States are numbered ((white king * 64 + piece) * 64 + black king) * 2 + side to move, and results are from the
+    point of view of the side to move. DTZ is the number of plies to a zeroing move (a pawn move, a capture or mate)
+    with optimal play, negative for losses, and -1 for a checkmated position.
+    """
+
+    def __init__(self, piece: str, promotion_results: dict):
+        self.piece = piece
+        # Black's result after each promotion, given the material code and the (white king, piece, black king) squares
+        self.promotion_results = promotion_results
+        self.wdl = [DRAW] * (64 * 64 * 64 * 2)
+        self.dtz = [0] * (64 * 64 * 64 * 2)
+        self.legal = bytearray(64 * 64 * 64 * 2)
+
+    @staticmethod
+    def to_state(wk: int, sq: int, bk: int, stm: int) -> int:
+        return ((wk * 64 + sq) * 64 + bk) * 2 + stm
+
+    @staticmethod
+    def from_state(state: int) -> (int, int, int, int):
+        return state >> 13, (state >> 7) & 63, (state >> 1) & 63, state & 1
+
+    def is_legal(self, wk: int, sq: int, bk: int, stm: int) -> bool:
+        if len({wk, sq, bk}) < 3 or bk in KING_ATTACKS[wk]:
+            return False
+        if self.piece == 'P' and rank_of(sq) in (0, 7):
+            return False
+        # Black can't be in check with white to move (and black has no pieces to give check)
+        return stm == BLACK or not is_attacked_by(self.piece, sq, bk, {wk, sq, bk})
+
+    def is_check(self, wk: int, sq: int, bk: int) -> bool:
+        return is_attacked_by(self.piece, sq, bk, {wk, sq, bk})
+
+    def calc_moves(self, state: int) -> [(int, bool, int)]:
+        """Returns (next state, zeroing, result) for each legal move, where the next state is -1 if the move leaves
+        the endgame and the result is then black's result after the move"""
+        wk, sq, bk, stm = Endgame.from_state(state)
+        occupied = {wk, sq, bk}
+        moves = []
+        if stm == WHITE:
+            for target in KING_ATTACKS[wk]:
+                if target != sq and target != bk and target not in KING_ATTACKS[bk]:
+                    moves.append((Endgame.to_state(target, sq, bk, BLACK), False, DRAW))
+
+            if self.piece == 'P':
+                pushes = [sq + 8] if sq + 8 not in occupied else []
+                if pushes and rank_of(sq) == 1 and sq + 16 not in occupied:
+                    pushes.append(sq + 16)
+                for target in pushes:
+                    if rank_of(target) == 7:
+                        for promotion in 'QRBN':
+                            result = self.promotion_results.get(promotion, lambda *_: DRAW)(wk, target, bk)
+                            moves.append((-1, True, result))
+                    else:
+                        moves.append((Endgame.to_state(wk, target, bk, BLACK), True, DRAW))
+            else:
+                for target in calc_slides(self.piece, sq, occupied):
+                    if target not in occupied:
+                        moves.append((Endgame.to_state(wk, target, bk, BLACK), False, DRAW))
+        else:
+            for target in KING_ATTACKS[bk]:
+                if target == wk or target in KING_ATTACKS[wk]:
+                    continue
+                if target == sq:
+                    # Capturing the last white piece leaves a drawn KvK
+                    moves.append((-1, True, DRAW))
+                elif not is_attacked_by(self.piece, sq, target, {wk, sq, target}):
+                    moves.append((Endgame.to_state(wk, sq, target, WHITE), False, DRAW))
+        return moves
+
+    def solve(self):
+        state_count = len(self.wdl)
+        moves = [None] * state_count
+        predecessors = [[] for _ in range(state_count)]
+        for state in range(state_count):
+            if self.is_legal(*Endgame.from_state(state)):
+                self.legal[state] = 1
+                moves[state] = self.calc_moves(state)
+                for next_state, zeroing, _ in moves[state]:
+                    if next_state >= 0:
+                        predecessors[next_state].append((state, zeroing))
+
+        # Win / draw / loss, working back from checkmates and from promotions to won positions
+        resolved = bytearray(state_count)
+        remaining_moves = [0] * state_count
+        queue = deque()
+        for state in range(state_count):
+            if not self.legal[state]:
+                continue
+            wk, sq, bk, stm = Endgame.from_state(state)
+            if stm == BLACK:
+                if not moves[state] and self.is_check(wk, sq, bk):
+                    self.wdl[state] = LOSS
+                    resolved[state] = 1
+                    queue.append(state)
+                # A move out of the endgame (capturing the last piece) can only draw
+                remaining_moves[state] = len(moves[state]) if all(m[0] >= 0 for m in moves[state]) else -1
+            elif any(next_state < 0 and result == LOSS for next_state, _, result in moves[state]):
+                self.wdl[state] = WIN
+                resolved[state] = 1
+                queue.append(state)
+
+        while queue:
+            state = queue.popleft()
+            for previous, _ in predecessors[state]:
+                if resolved[previous]:
+                    continue
+                if previous & 1 == WHITE:
+                    self.wdl[previous] = WIN
+                elif remaining_moves[previous] > 0:
+                    remaining_moves[previous] -= 1
+                    if remaining_moves[previous] > 0:
+                        continue
+                    self.wdl[previous] = LOSS
+                else:
+                    continue
+                resolved[previous] = 1
+                queue.append(previous)
+
+        # DTZ, working back from the winning zeroing moves (which include mates) by the moves that don't reset the
+        # fifty move counter, so that the winning side takes the quickest route and the losing side the longest
+        queue = deque()
+        for state in range(state_count):
+            if not self.legal[state] or self.wdl[state] == DRAW:
+                continue
+            if self.wdl[state] == LOSS:
+                if not moves[state]:
+                    self.dtz[state] = -1
+                remaining_moves[state] = len(moves[state])
+                continue
+            for next_state, zeroing, result in moves[state]:
+                if next_state < 0:
+                    wins = result == LOSS
+                else:
+                    is_mate = self.wdl[next_state] == LOSS and not moves[next_state]
+                    wins = self.wdl[next_state] == LOSS and (zeroing or is_mate)
+                if wins:
+                    self.dtz[state] = 1
+                    queue.append(state)
+                    break
+
+        while queue:
+            state = queue.popleft()
+            plies = abs(self.dtz[state])
+            for previous, zeroing in predecessors[state]:
+                if zeroing or self.dtz[previous] != 0 or self.wdl[previous] == DRAW:
+                    continue
+                if previous & 1 == WHITE:
+                    # Checkmated positions are already covered by the mating moves
+                    if self.wdl[state] == LOSS and self.dtz[state] != -1:
+                        self.dtz[previous] = plies + 1
+                        queue.append(previous)
+                else:
+                    remaining_moves[previous] -= 1
+                    if remaining_moves[previous] == 0:
+                        self.dtz[previous] = -(plies + 1)
+                        queue.append(previous)
+
+        assert all(self.dtz[state] != 0 for state in range(state_count) if self.legal[state] and self.wdl[state] != DRAW)
+
+    def get_result(self, wk: int, sq: int, bk: int, stm: int) -> int:
+        return self.wdl[Endgame.to_state(wk, sq, bk, stm)]
+
+
+def off_a1h8(sq: int) -> int:
+    return rank_of(sq) - file_of(sq)
+
+
+# The index encoding of the Syzygy tables for three pieces (see tbprobe.cpp in Stockfish)
+MAP_B1H1H7 = [0] * 64
+MAP_A1D1D4 = [0] * 64
+_below_diagonal = [sq for sq in range(64) if off_a1h8(sq) < 0]
+for _code, _sq in enumerate(_below_diagonal):
+    MAP_B1H1H7[_sq] = _code
+_triangle = [sq for sq in range(28) if file_of(sq) <= 3 and off_a1h8(sq) < 0] + \
+            [sq for sq in range(28) if file_of(sq) <= 3 and off_a1h8(sq) == 0]
+for _code, _sq in enumerate(_triangle):
+    MAP_A1D1D4[_sq] = _code
+
+
+def encode_pawnless(squares: [int]) -> int:
+    # Mirror so that the first piece is in the a1-d1-d4 triangle and the first piece off the diagonal is below it
+    if file_of(squares[0]) > 3:
+        squares = [sq ^ 7 for sq in squares]
+    if rank_of(squares[0]) > 3:
+        squares = [sq ^ 56 for sq in squares]
+    for i in range(3):
+        if off_a1h8(squares[i]) != 0:
+            if off_a1h8(squares[i]) > 0:
+                squares = squares[:i] + [((sq >> 3) | (sq << 3)) & 63 for sq in squares[i:]]
+            break
+
+    adjust1 = int(squares[1] > squares[0])
+    adjust2 = int(squares[2] > squares[0]) + int(squares[2] > squares[1])
+    if off_a1h8(squares[0]) != 0:
+        return (MAP_A1D1D4[squares[0]] * 63 + squares[1] - adjust1) * 62 + squares[2] - adjust2
+    if off_a1h8(squares[1]) != 0:
+        return (6 * 63 + rank_of(squares[0]) * 28 + MAP_B1H1H7[squares[1]]) * 62 + squares[2] - adjust2
+    if off_a1h8(squares[2]) != 0:
+        return 6 * 63 * 62 + 4 * 28 * 62 + rank_of(squares[0]) * 7 * 28 + (rank_of(squares[1]) - adjust1) * 28 \
+            + MAP_B1H1H7[squares[2]]
+    return 6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(squares[0]) * 7 * 6 + (rank_of(squares[1]) - adjust1) * 6 \
+        + rank_of(squares[2]) - adjust2
+
+
+def encode_pawn(squares: [int]) -> (int, int):
+    # The table is split by the pawn's file (mirrored onto files a-d), and within a file the pawn's rank comes first,
+    # then each other piece's square skipping the squares of the earlier pieces
+    if file_of(squares[0]) > 3:
+        squares = [sq ^ 7 for sq in squares]
+    idx = rank_of(squares[0]) - 1
+    multiplier = 6
+    for i in range(1, len(squares)):
+        idx += (squares[i] - sum(1 for earlier in squares[:i] if earlier < squares[i])) * multiplier
+        multiplier *= 64 - i
+    return file_of(squares[0]), idx
+
+
+PAWNLESS_TABLE_SIZE = 6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + 4 * 7 * 6
+PAWN_TABLE_SIZE = 6 * 63 * 62
+
+
+def collect_values(endgame: Endgame, stm: int, get_value) -> [[int]]:
+    """Returns the values for each file of the table with the given side to move, with None for indices that don't
+    correspond to a legal position"""
+    has_pawns = endgame.piece == 'P'
+    values = [[None] * (PAWN_TABLE_SIZE if has_pawns else PAWNLESS_TABLE_SIZE) for _ in range(4 if has_pawns else 1)]
+    for state in range(len(endgame.wdl)):
+        if not endgame.legal[state] or state & 1 != stm:
+            continue
+        wk, sq, bk, _ = Endgame.from_state(state)
+        value = get_value(state)
+        if has_pawns:
+            file, idx = encode_pawn([sq, wk, bk])
+        else:
+            file, idx = 0, encode_pawnless([wk, sq, bk])
+        # Positions that are mirror images of each other share an index
+        assert values[file][idx] is None or value is None or values[file][idx] == value
+        if value is not None:
+            values[file][idx] = value
+    return values
+
+
+def fill_dont_cares(values: [int]) -> [int]:
+    # Repeating the previous value makes long runs that pair up well
+    filled = []
+    previous = next((value for value in values if value is not None), 0)
+    for value in values:
+        previous = previous if value is None else value
+        filled.append(previous)
+    return filled
+
+
+def calc_code_lengths(frequencies: dict) -> dict:
+    # Huffman code lengths, flattening the frequencies until the longest code is short enough
+    while True:
+        heap = [(frequency, i, [symbol]) for i, (symbol, frequency) in enumerate(sorted(frequencies.items()))]
+        heapq.heapify(heap)
+        lengths = {symbol: 0 for symbol in frequencies}
+        tiebreak = len(heap)
+        while len(heap) > 1:
+            frequency1, _, symbols1 = heapq.heappop(heap)
+            frequency2, _, symbols2 = heapq.heappop(heap)
+            for symbol in symbols1 + symbols2:
+                lengths[symbol] += 1
+            heapq.heappush(heap, (frequency1 + frequency2, tiebreak, symbols1 + symbols2))
+            tiebreak += 1
+        if max(lengths.values()) <= MAX_CODE_LENGTH:
+            return lengths
+        frequencies = {symbol: frequency // 2 + 1 for symbol, frequency in frequencies.items()}
+
+
+def compress(values: [int], flags: int) -> (bytes, [bytes], [int], [int]):
+    """Returns the sizes and Huffman code for the sub-table, its data blocks, the block lengths and the sparse index
+    (as (block, offset) pairs)"""
+    if len(set(values)) == 1:
+        return bytes([flags | FLAG_SINGLE_VALUE, values[0]]), [], [], []
+
+    # Pair compression: repeatedly replace the most common pair of adjacent symbols with a new symbol
+    leaves = sorted(set(values))
+    symbols = [(value, None) for value in leaves]
+    symbol_values = [1] * len(leaves)
+    sequence = [leaves.index(value) for value in values]
+    while len(symbols) < MAX_SYMBOLS:
+        pair_counts = {}
+        i = 0
+        while i < len(sequence) - 1:
+            pair = (sequence[i], sequence[i + 1])
+            if symbol_values[pair[0]] + symbol_values[pair[1]] <= MAX_SYMBOL_VALUES:
+                pair_counts[pair] = pair_counts.get(pair, 0) + 1
+            # Don't count overlapping pairs in runs of the same symbol
+            i += 2 if pair[0] == pair[1] and i + 2 < len(sequence) and sequence[i + 2] == pair[0] else 1
+        if not pair_counts:
+            break
+        pair, count = max(pair_counts.items(), key=lambda item: (item[1], -item[0][0], -item[0][1]))
+        if count < 4:
+            break
+        new_symbol = len(symbols)
+        symbols.append(pair)
+        symbol_values.append(symbol_values[pair[0]] + symbol_values[pair[1]])
+        paired = []
+        i = 0
+        while i < len(sequence):
+            if i < len(sequence) - 1 and (sequence[i], sequence[i + 1]) == pair:
+                paired.append(new_symbol)
+                i += 2
+            else:
+                paired.append(sequence[i])
+                i += 1
+        sequence = paired
+
+    frequencies = {}
+    for symbol in sequence:
+        frequencies[symbol] = frequencies.get(symbol, 0) + 1
+    if len(frequencies) == 1:
+        # A code needs at least two symbols
+        frequencies[next(symbol for symbol in range(len(symbols)) if symbol not in frequencies)] = 0
+    lengths = calc_code_lengths(frequencies)
+
+    # Canonical code: the symbols are renumbered so that longer codes come first, and the first code of each length is
+    # half of the code after the last code of the next length
+    coded = sorted(frequencies, key=lambda symbol: (-lengths[symbol], symbol))
+    uncoded = [symbol for symbol in range(len(symbols)) if symbol not in frequencies]
+    renumbered = {symbol: i for i, symbol in enumerate(coded + uncoded)}
+    min_length, max_length = min(lengths.values()), max(lengths.values())
+    counts = [sum(1 for symbol in coded if lengths[symbol] == length) for length in range(min_length, max_length + 1)]
+    lowest = [0] * len(counts)
+    first_code = [0] * len(counts)
+    for i in range(len(counts) - 2, -1, -1):
+        lowest[i] = lowest[i + 1] + counts[i + 1]
+        assert (first_code[i + 1] + counts[i + 1]) % 2 == 0
+        first_code[i] = (first_code[i + 1] + counts[i + 1]) // 2
+    assert first_code[0] + counts[0] == 1 << min_length
+    codes = {symbol: first_code[lengths[symbol] - min_length] + renumbered[symbol] - lowest[lengths[symbol] - min_length]
+             for symbol in coded}
+
+    tree = bytearray()
+    for symbol in coded + uncoded:
+        left, right = symbols[symbol]
+        left, right = (left, 0xFFF) if right is None else (renumbered[left], renumbered[right])
+        tree += bytes([left & 0xFF, (left >> 8) | ((right & 0xF) << 4), right >> 4])
+
+    # Fill the blocks with whole symbols
+    block_bits = 8 << BLOCK_SIZE_LOG2
+    blocks, block_lengths, block_starts = [], [], []
+    bits, bit_count, value_count, start = 0, 0, 0, 0
+    for symbol in sequence + [None]:
+        if (symbol is None and value_count > 0) or (symbol is not None and bit_count + lengths[symbol] > block_bits):
+            blocks.append((bits << (block_bits - bit_count)).to_bytes(block_bits // 8, 'big'))
+            block_lengths.append(value_count - 1)
+            block_starts.append(start)
+            start += value_count
+            bits, bit_count, value_count = 0, 0, 0
+        if symbol is None:
+            break
+        bits = (bits << lengths[symbol]) | codes[symbol]
+        bit_count += lengths[symbol]
+        value_count += symbol_values[symbol]
+
+    # The sparse index gives the block and offset of the value in the middle of each span
+    span = 1 << SPAN_LOG2
+    sparse_index = []
+    block = 0
+    for k in range((len(values) + span - 1) // span):
+        idx = k * span + span // 2
+        while block + 1 < len(block_starts) and block_starts[block + 1] <= idx:
+            block += 1
+        sparse_index.append((block, idx - block_starts[block]))
+
+    sizes = bytes([flags, BLOCK_SIZE_LOG2, SPAN_LOG2, 0]) + struct.pack('<I', len(blocks)) \
+        + bytes([max_length, min_length]) + b''.join(struct.pack('<H', value) for value in lowest) \
+        + struct.pack('<H', len(symbols)) + bytes(tree) + bytes(len(symbols) & 1)
+    return sizes, blocks, block_lengths, sparse_index
+
+
+def write_table(path: str, is_dtz: bool, has_pawns: bool, pieces: [int], sub_tables: [[(int, [int])]], maps: [[[int]]]):
+    """Writes a table given the (flags, values) of each side to move for each file, and for DTZ tables the four value
+    maps (win, loss, cursed win, blessed loss) of each file if the values are mapped"""
+    data = bytearray(DTZ_MAGIC if is_dtz else WDL_MAGIC)
+    data.append(1 | (2 if has_pawns else 0))
+    for _ in sub_tables:
+        data.append(0)
+        data += bytes(piece | (piece << 4) for piece in pieces)
+    data += bytes(len(data) & 1)
+
+    compressed = [[compress(values, flags) for flags, values in sides] for sides in sub_tables]
+    for sides in compressed:
+        for sizes, _, _, _ in sides:
+            data += sizes
+    if is_dtz:
+        for file_maps in maps:
+            for value_map in file_maps:
+                data.append(len(value_map))
+                data += bytes(value_map)
+        data += bytes(len(data) & 1)
+    for sides in compressed:
+        for _, _, _, sparse_index in sides:
+            for block, offset in sparse_index:
+                data += struct.pack('<IH', block, offset)
+    for sides in compressed:
+        for _, _, block_lengths, _ in sides:
+            data += b''.join(struct.pack('<H', length) for length in block_lengths)
+    for sides in compressed:
+        for _, blocks, _, _ in sides:
+            data += bytes(-len(data) & 0x3F)
+            data += b''.join(blocks)
+
+    with open(path, 'wb') as file:
+        file.write(data)
+    print(f'{path}: {len(data)} bytes')
+
+
+def write_wdl_table(path: str, endgame: Endgame, pieces: [int]):
+    sides = [collect_values(endgame, stm, lambda state: endgame.wdl[state] + 2) for stm in (WHITE, BLACK)]
+    sub_tables = [[(0, fill_dont_cares(sides[stm][file])) for stm in (WHITE, BLACK)] for file in range(len(sides[0]))]
+    write_table(path, False, endgame.piece == 'P', pieces, sub_tables, [])
+
+
+def main():
+    output_dir = sys.argv[1] if len(sys.argv) > 1 else 'src/test/resources/syzygy'
+    os.makedirs(output_dir, exist_ok=True)
+
+    endgames = {}
+    for piece in 'QRP':
+        promotion_results = {promotion: (lambda p: lambda wk, sq, bk: endgames[p].get_result(wk, sq, bk, BLACK))(promotion)
+                             for promotion in 'QR'}
+        endgames[piece] = Endgame(piece, promotion_results)
+        endgames[piece].solve()
+        print(f'K{piece}vK solved: longest DTZ {max(endgames[piece].dtz)} plies for white, '
+              f'{-min(endgames[piece].dtz)} for black')
+
+    krk, kpk = endgames['R'], endgames['P']
+    write_wdl_table(os.path.join(output_dir, 'KRvK.rtbw'), krk, [W_KING, W_ROOK, B_KING])
+    write_wdl_table(os.path.join(output_dir, 'KPvK.rtbw'), kpk, [W_PAWN, W_KING, B_KING])
+
+    # Probing KPvK needs the tables for the promotions too (every KBvK and KNvK position is a draw)
+    write_wdl_table(os.path.join(output_dir, 'KQvK.rtbw'), endgames['Q'], [W_KING, W_QUEEN, B_KING])
+    for code, piece in [('KBvK', W_BISHOP), ('KNvK', W_KNIGHT)]:
+        draws = [DRAW + 2] * PAWNLESS_TABLE_SIZE
+        write_table(os.path.join(output_dir, f'{code}.rtbw'), False, False, [W_KING, piece, B_KING],
+                    [[(0, draws), (0, draws)]], [])
+
+    # The KRvK DTZ table stores black to move, in plies since mates are an odd number of plies and other losses even
+    def get_krk_dtz(state: int):
+        return -krk.dtz[state] - 1 if krk.wdl[state] == LOSS else None
+    values = collect_values(krk, BLACK, get_krk_dtz)[0]
+    write_table(os.path.join(output_dir, 'KRvK.rtbz'), True, False, [W_KING, W_ROOK, B_KING],
+                [[(FLAG_STM | FLAG_LOSS_PLIES, fill_dont_cares(values))]], [[]])
+
+    # The KPvK DTZ table stores white to move, in moves since every win is an odd number of plies, and with the
+    # values mapped in order of frequency (positions where pushing the pawn wins don't need a value)
+    def get_kpk_dtz(state: int):
+        if kpk.wdl[state] != WIN or kpk.dtz[state] == 1:
+            return None
+        assert kpk.dtz[state] % 2 == 1
+        return (kpk.dtz[state] - 1) // 2
+    sub_tables, maps = [], []
+    for values in collect_values(kpk, WHITE, get_kpk_dtz):
+        frequencies = {}
+        for value in values:
+            if value is not None:
+                frequencies[value] = frequencies.get(value, 0) + 1
+        win_map = sorted(frequencies, key=lambda value: (-frequencies[value], value))
+        mapped = [None if value is None else win_map.index(value) for value in values]
+        sub_tables.append([(FLAG_MAPPED, fill_dont_cares(mapped))])
+        maps.append([win_map, [], [], []])
+    write_table(os.path.join(output_dir, 'KPvK.rtbz'), True, True, [W_PAWN, W_KING, B_KING], sub_tables, maps)
+
+
+if __name__ == '__main__':
+    main()
@@ -70,13 +70,17 @@ class Training:
         # print(optimizer.learning_rate)
 
     @staticmethod
-    def run_training(pgn_path: str, model_path: str, tensorboard_log_dir: str):
+    def run_training(pgn_path: str, model_path: str, tensorboard_log_dir: str, use_tfrecords: bool = False):
         # print(tf.config.list_physical_devices())
 
         model = Training.load_model(model_path=model_path)
         Training.adjust_modeL_learning_rate(model)
 
-        x, y, x_val, y_val = TrainingData.get_datasets(pgn_path)
+        if use_tfrecords:
+            ds_train, ds_validation = TrainingData.get_tfrecord_datasets(pgn_path)
+        else:
+            x, y, w, x_val, y_val, w_val = TrainingData.get_datasets(pgn_path)
+            ds_train, ds_validation = tf.data.Dataset.zip((x, y, w)), tf.data.Dataset.zip((x_val, y_val, w_val))   # not sure why this additional zip is needed, but it is
 
         tensorboard_dir = os.path.join(tensorboard_log_dir, datetime.datetime.now().strftime("%Y%m%d-%H%M%S"))
         tensorboard_callback = tf.keras.callbacks.TensorBoard(log_dir=tensorboard_dir, histogram_freq=1)
@@ -84,12 +88,12 @@ class Training:
         history = None
         try:
             history = model.fit(
-                x=tf.data.Dataset.zip((x, y)),  # not sure why this additional zip is needed, but it is
+                x=ds_train,
                 # batch_size=512,   # this isn't allowed here since the datasets themselves are already batched
                 # shuffle=False,    # not allowed here either since the datasets are also already shuffled
                 epochs=EPOCHS,
                 steps_per_epoch=BATCHES_PER_EPOCH,
-                validation_data=tf.data.Dataset.zip((x_val, y_val)),
+                validation_data=ds_validation,
                 validation_steps=5,
                 use_multiprocessing=True,
                 workers=3,
@@ -100,6 +104,7 @@ class Training:
 
         print("\nSaving model to {}".format(model_path))
         model.save(filepath=model_path, overwrite=True)
+        TrainingModel.export_cpu_weights(model, model_path)
 
         if history:
             Training.visualize_training_results(history)
@@ -113,9 +118,11 @@ class Training:
                             help='a path to the folder of an existing model file (to resume from) or where to save existing results')
         parser.add_argument('tb_log_path', type=str,
                             help='a path to place tensorboard log files')
+        parser.add_argument('--tfrecords', action='store_true',
+                            help='pgn_path contains .tfrecord files written by the export-tfrecord command instead of PGN files')
         args = parser.parse_args()
 
-        Training.run_training(args.pgn_path, args.model_path, args.tb_log_path)
+        Training.run_training(args.pgn_path, args.model_path, args.tb_log_path, args.tfrecords)
 
 
 if __name__ == "__main__":
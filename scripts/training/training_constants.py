@@ -1,9 +1,25 @@
 # Neural Net training parameters
 # SHUFFLE_BUFFER_SIZE = 4000
 SHUFFLE_BUFFER_SIZE = 2000
-TRAIN_TEST_SPLIT = 0.9  # must contain a single decimal place only
+RUST_SHUFFLE_BUFFER_SIZE = 5000     # shuffling done in Rust before positions reach the python generator (0 = disabled)
+POSITIONS_PER_GAME = 0      # maximum number of random positions used per game (0 = all)
+DEDUPLICATE_POSITIONS = True
+RANDOM_SEED = 12
+# Sample weight parameters passed to Rust (see SampleWeightConfig in pgn.rs) - missing values default to no weighting
+SAMPLE_WEIGHTS = {
+    'below_min_elo_weight': 0.5,
+    'elo_weight_per_100': 0.1,
+    'opening_moves': 8,
+    'opening_weight': 0.5,
+}
+EVAL_TARGET_WEIGHT = 0.5    # weight of the %eval annotation (vs. the game result) in the value target, when available
+EVAL_CP_TANH_SCALE = 0.0029     # ln(10) / 800, so tanh() matches the usual logistic centipawn -> win probability curve
+TFRECORD_SHUFFLE_BUFFER_SIZE = 50000
+TFRECORD_INTERLEAVE_CYCLE_LENGTH = 8
+VALIDATION_SPLIT = 0.05  # fraction of games (not positions) held out for validation
+TEST_SPLIT = 0.05   # fraction of games held out for final testing - never used during training
 # BATCH_SIZE = 20     # preferably in multiples of 10 so train/test split will produce expected results
-BATCH_SIZE = 500     # preferably in multiples of 10 so train/test split will produce expected results
+BATCH_SIZE = 500
 # BATCHES_PER_EPOCH = 500
 BATCHES_PER_EPOCH = 200
 EPOCHS = 10
@@ -25,6 +41,8 @@ NN_TOTAL_PLANES_PER_POS = NN_TOTAL_PIECE_PLANES_PER_POS + NN_AUX_PLANES
 
 NN_TOTAL_INPUT_SIZE_PER_POS = NN_TOTAL_PLANES_PER_POS << 6
 NN_TOTAL_OUTPUT_SIZE_PER_POS = 1858
+# (filter count, resnet block count, pooling type) for each inception layer - also exported for the Rust CPU evaluator
+NN_INCEPTION_LAYERS = [(64, 2, ''), (64, 2, 'max'), (64, 2, 'max')]
 
 # NN model constants
 INPUT_MAIN_LAYER_NAME = 'main_input'
@@ -32,4 +50,14 @@ OUTPUT_MASK_LAYER_NAME = 'output_mask'
 OUTPUT_RAW_LAYER_NAME = 'output_raw'
 OUTPUT_MOVEMENTS_LAYER_NAME = 'movement_output'
 OUTPUT_TOP_K_MOVEMENTS_LAYER_NAME = 'top_k_outputs'
-OUTPUT_WIN_PROBABILITY_LAYER_NAME = 'win_probability'
\ No newline at end of file
+OUTPUT_WIN_PROBABILITY_LAYER_NAME = 'win_probability'
+
+# TFRecord feature names (in addition to the layer names above)
+TFRECORD_POSITION_EVAL_FEATURE = 'position_eval'
+TFRECORD_MOVER_CLOCK_FEATURE = 'mover_clock'
+TFRECORD_OPPONENT_CLOCK_FEATURE = 'opponent_clock'
+TFRECORD_SAMPLE_WEIGHT_FEATURE = 'sample_weight'
+
+# Weights exported for the pure-Rust CPU evaluator (see CPUEvaluator.rs)
+CPU_MODEL_MANIFEST_FILE = 'cpu_model.json'
+CPU_MODEL_WEIGHTS_FILE = 'cpu_model.bin'
@@ -14,55 +14,101 @@ class TrainingData:
                     yield os.path.join(root, file).decode('utf-8')
 
     @staticmethod
-    def get_next_position(path) -> ([float], [float], [float], float, bool, bool):
+    def get_next_position(path, split) -> ([float], [float], [float], float, bool, bool, float, float, float, float):
         for file_path in TrainingData.get_next_pgn_file(path):
             # noinspection PyUnresolvedReferences
-            pgn = my_chess_ql.NeuralTrainer(file_path)
+            pgn = my_chess_ql.NeuralTrainer(file_path, shuffle_buffer_size=RUST_SHUFFLE_BUFFER_SIZE,
+                                            positions_per_game=POSITIONS_PER_GAME, deduplicate=DEDUPLICATE_POSITIONS,
+                                            seed=RANDOM_SEED, split=split.decode('utf-8'),
+                                            validation_ratio=VALIDATION_SPLIT, test_ratio=TEST_SPLIT,
+                                            sample_weights=SAMPLE_WEIGHTS)
             while True:
                 try:
                     nn_data = pgn.__next__()
-                    yield nn_data
+                    # Evaluations / clock times are None if the PGN file doesn't have %eval / %clk annotations
+                    yield nn_data[:6] + tuple(float('nan') if x is None else x for x in nn_data[6:9]) + nn_data[9:]
                 except StopIteration:
                     break
         return None
 
     @staticmethod
-    def get_datasets(path: str) -> (tf.data.Dataset, tf.data.Dataset):
-        ds_positions = tf.data.Dataset.from_generator(
-            TrainingData.get_next_position,
-            args=[path],
-            output_types=(tf.float32, tf.float32, tf.float32, tf.float32, tf.bool, tf.bool),
-            output_shapes=((NN_TOTAL_INPUT_SIZE_PER_POS,), (NN_TOTAL_OUTPUT_SIZE_PER_POS,), (NN_TOTAL_OUTPUT_SIZE_PER_POS,), (), (), ())
-        )
+    def get_value_target(win_result, position_eval):
+        # Blend the game result with the engine evaluation of the position (when available), converting the
+        # centipawn evaluation into the same [-1, 1] range as the game result
+        eval_value = tf.math.tanh(position_eval * EVAL_CP_TANH_SCALE)
+        value = tf.where(tf.math.is_nan(position_eval), win_result, EVAL_TARGET_WEIGHT * eval_value + (1.0 - EVAL_TARGET_WEIGHT) * win_result)
+        # The sigmoid output predicts white's expected score (0 to 1), which the engine converts to centipawns
+        # using the value calibration fitted by the 'fit-value-calibration' subcommand
+        return (value + 1.0) / 2.0
 
-        # Shuffle the raw dataset being generated from Rust
-        # ds_positions = ds_positions.shuffle(buffer_size=SHUFFLE_BUFFER_SIZE, seed=12, reshuffle_each_iteration=False)
+    @staticmethod
+    def parse_tfrecord_example(serialized_example):
+        # Records are written by the Rust 'export-tfrecord' subcommand
+        features = tf.io.parse_single_example(serialized_example, {
+            INPUT_MAIN_LAYER_NAME: tf.io.FixedLenFeature([NN_TOTAL_INPUT_SIZE_PER_POS], tf.float32),
+            OUTPUT_MASK_LAYER_NAME: tf.io.FixedLenFeature([NN_TOTAL_OUTPUT_SIZE_PER_POS], tf.float32),
+            OUTPUT_MOVEMENTS_LAYER_NAME: tf.io.FixedLenFeature([NN_TOTAL_OUTPUT_SIZE_PER_POS], tf.float32),
+            OUTPUT_WIN_PROBABILITY_LAYER_NAME: tf.io.FixedLenFeature([], tf.float32),
+            TFRECORD_POSITION_EVAL_FEATURE: tf.io.FixedLenFeature([], tf.float32),
+            TFRECORD_SAMPLE_WEIGHT_FEATURE: tf.io.FixedLenFeature([], tf.float32),
+        })
+        win_result = TrainingData.get_value_target(features[OUTPUT_WIN_PROBABILITY_LAYER_NAME], features[TFRECORD_POSITION_EVAL_FEATURE])
 
-        # For every WINDOW_SIZE samples, split across train / test sets using the TRAIN_TEST_SPLIT percentage
-        WINDOW_SIZE = 10
-        split = int(TRAIN_TEST_SPLIT * WINDOW_SIZE)
-        ds_train = ds_positions.shuffle(buffer_size=SHUFFLE_BUFFER_SIZE, seed=12, reshuffle_each_iteration=False) \
-            .window(split, WINDOW_SIZE).flat_map(lambda *ds: ds[0] if len(ds) == 1 else tf.data.Dataset.zip(ds))
-        ds_validation = ds_positions.skip(split).window(WINDOW_SIZE - split, WINDOW_SIZE).flat_map(lambda *ds: ds[0] if len(ds) == 1 else tf.data.Dataset.zip(ds))
+        return {INPUT_MAIN_LAYER_NAME: features[INPUT_MAIN_LAYER_NAME], OUTPUT_MASK_LAYER_NAME: features[OUTPUT_MASK_LAYER_NAME]}, \
+               {OUTPUT_MOVEMENTS_LAYER_NAME: features[OUTPUT_MOVEMENTS_LAYER_NAME], OUTPUT_WIN_PROBABILITY_LAYER_NAME: win_result}, \
+               features[TFRECORD_SAMPLE_WEIGHT_FEATURE]
 
-        # Calculate batch sizes for train/test data using TRAIN_TEST_SPLIT
-        # AUTOTUNE = tf.data.AUTOTUNE
-        train_batch_size = int(BATCH_SIZE * TRAIN_TEST_SPLIT)
-        val_batch_size = BATCH_SIZE - train_batch_size
+    @staticmethod
+    def get_tfrecord_datasets(path: str) -> (tf.data.Dataset, tf.data.Dataset):
+        AUTOTUNE = tf.data.AUTOTUNE
+        # Files are expected to be exported separately for each split, e.g.:
+        #   my_chess_ql export-tfrecord --split train --output <path>/train ...
+        #   my_chess_ql export-tfrecord --split validation --output <path>/validation ...
+        train_files = tf.data.Dataset.list_files(os.path.join(path, 'train-*.tfrecord'), shuffle=True, seed=RANDOM_SEED)
+        validation_files = tf.data.Dataset.list_files(os.path.join(path, 'validation-*.tfrecord'), shuffle=False)
+
+        def load_files(ds_files: tf.data.Dataset, shuffle: bool) -> tf.data.Dataset:
+            ds = ds_files.interleave(tf.data.TFRecordDataset, cycle_length=TFRECORD_INTERLEAVE_CYCLE_LENGTH, num_parallel_calls=AUTOTUNE)
+            if shuffle:
+                ds = ds.shuffle(buffer_size=TFRECORD_SHUFFLE_BUFFER_SIZE, seed=RANDOM_SEED)
+            return ds.map(TrainingData.parse_tfrecord_example, num_parallel_calls=AUTOTUNE)
+
+        ds_train = load_files(train_files, shuffle=True).repeat().batch(BATCH_SIZE).prefetch(AUTOTUNE)
+        ds_validation = load_files(validation_files, shuffle=False).repeat().batch(BATCH_SIZE).prefetch(AUTOTUNE)
+        return ds_train, ds_validation
+
+    @staticmethod
+    def get_split_dataset(path: str, split: str) -> tf.data.Dataset:
+        return tf.data.Dataset.from_generator(
+            TrainingData.get_next_position,
+            args=[path, split],
+            output_types=(tf.float32, tf.float32, tf.float32, tf.float32, tf.bool, tf.bool, tf.float32, tf.float32, tf.float32, tf.float32),
+            output_shapes=((NN_TOTAL_INPUT_SIZE_PER_POS,), (NN_TOTAL_OUTPUT_SIZE_PER_POS,), (NN_TOTAL_OUTPUT_SIZE_PER_POS,), (), (), (), (), (), (), ())
+        )
+
+    @staticmethod
+    def get_datasets(path: str) -> (tf.data.Dataset, tf.data.Dataset, tf.data.Dataset, tf.data.Dataset, tf.data.Dataset, tf.data.Dataset):
+        # Games are assigned to the train / validation sets in Rust by hashing each game, so the
+        # validation games never appear in the training data
+        ds_train = TrainingData.get_split_dataset(path, 'train') \
+            .shuffle(buffer_size=SHUFFLE_BUFFER_SIZE, seed=RANDOM_SEED, reshuffle_each_iteration=False)
+        ds_validation = TrainingData.get_split_dataset(path, 'validation')
 
         # Enable caching and prefetch for better performance for subsequent epochs
-        # return ds_train.cache().prefetch(buffer_size=AUTOTUNE).batch(train_batch_size), \
-        #        ds_validation.cache().prefetch(buffer_size=AUTOTUNE).batch(val_batch_size)
-        # ds_train = ds_train.prefetch(buffer_size=AUTOTUNE).batch(train_batch_size)
-        # ds_validation = ds_validation.prefetch(buffer_size=AUTOTUNE).batch(val_batch_size)
-        ds_train = ds_train.batch(train_batch_size)
-        ds_validation = ds_validation.batch(val_batch_size)
+        # AUTOTUNE = tf.data.AUTOTUNE
+        # return ds_train.cache().prefetch(buffer_size=AUTOTUNE).batch(BATCH_SIZE), \
+        #        ds_validation.cache().prefetch(buffer_size=AUTOTUNE).batch(BATCH_SIZE)
+        ds_train = ds_train.batch(BATCH_SIZE)
+        ds_validation = ds_validation.batch(BATCH_SIZE)
 
-        x = ds_train.map(lambda main_input, output_mask, t, t2, t3, t4: {INPUT_MAIN_LAYER_NAME: main_input, OUTPUT_MASK_LAYER_NAME: output_mask})
-        y = ds_train.map(lambda t, t2, output_target, win_result, t3, t4: {OUTPUT_MOVEMENTS_LAYER_NAME: output_target, OUTPUT_WIN_PROBABILITY_LAYER_NAME: win_result})
+        x = ds_train.map(lambda main_input, output_mask, *t: {INPUT_MAIN_LAYER_NAME: main_input, OUTPUT_MASK_LAYER_NAME: output_mask})
+        y = ds_train.map(lambda t, t2, output_target, win_result, t3, t4, position_eval, *t5: {OUTPUT_MOVEMENTS_LAYER_NAME: output_target, OUTPUT_WIN_PROBABILITY_LAYER_NAME: TrainingData.get_value_target(win_result, position_eval)})
 
-        x_val = ds_validation.map(lambda main_input, output_mask, t, t2, t3, t4: {INPUT_MAIN_LAYER_NAME: main_input, OUTPUT_MASK_LAYER_NAME: output_mask})
-        y_val = ds_validation.map(lambda t, t2, output_target, win_result, t3, t4: {OUTPUT_MOVEMENTS_LAYER_NAME: output_target, OUTPUT_WIN_PROBABILITY_LAYER_NAME: win_result})
+        x_val = ds_validation.map(lambda main_input, output_mask, *t: {INPUT_MAIN_LAYER_NAME: main_input, OUTPUT_MASK_LAYER_NAME: output_mask})
+        y_val = ds_validation.map(lambda t, t2, output_target, win_result, t3, t4, position_eval, *t5: {OUTPUT_MOVEMENTS_LAYER_NAME: output_target, OUTPUT_WIN_PROBABILITY_LAYER_NAME: TrainingData.get_value_target(win_result, position_eval)})
 
-        return x.cache(), y.cache(), x_val, y_val
+        # Per-position sample weights calculated in Rust (see SAMPLE_WEIGHTS)
+        w = ds_train.map(lambda *t: t[-1])
+        w_val = ds_validation.map(lambda *t: t[-1])
 
+        return x.cache(), y.cache(), w.cache(), x_val, y_val, w_val
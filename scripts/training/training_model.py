@@ -1,3 +1,6 @@
+import json
+import os.path
+
 import keras.regularizers
 import tensorflow as tf
 from tensorflow.keras import layers, models
@@ -20,7 +23,8 @@ class TopKLayer(layers.Layer):
     # def call(self, inputs, training, *args, **kwargs):
     def call(self, inputs, training=False, mask=None):
         result = tf.math.top_k(input=inputs, k=self.k)
-        result_values_norm = result.values / (tf.norm(result.values, ord=1) + 0.000001)    # to prevent NaN in case all inputs are 0.0
+        # Normalise each position's top k values separately, otherwise batched predictions get normalised across the whole batch
+        result_values_norm = result.values / (tf.norm(result.values, ord=1, axis=-1, keepdims=True) + 0.000001)    # to prevent NaN in case all inputs are 0.0
         return tf.concat([tf.cast(result.indices, tf.float32), result_values_norm], -1)
 
     def get_config(self):
@@ -146,11 +150,11 @@ class TrainingModel:
         reshape = layers.Reshape((NN_TOTAL_PLANES_PER_POS, 8, 8))(main_input)
         move_channels_last = TransposeChannelsLastLayer()(reshape)
 
-        inception1 = TrainingModel.add_inception_layer(1, move_channels_last, 64, block_count=2, pooling_type='')
-        inception2 = TrainingModel.add_inception_layer(2, inception1, 64, block_count=2, pooling_type='max')
-        inception3 = TrainingModel.add_inception_layer(3, inception2, 64, block_count=2, pooling_type='max')
+        last_inception = move_channels_last
+        for i, (filter_count, block_count, pooling_type) in enumerate(NN_INCEPTION_LAYERS):
+            last_inception = TrainingModel.add_inception_layer(i + 1, last_inception, filter_count, block_count=block_count, pooling_type=pooling_type)
 
-        flatten = layers.Flatten()(inception3)
+        flatten = layers.Flatten()(last_inception)
         # # dense1 = layers.Dense(2048)(flatten)
 
 
@@ -185,3 +189,25 @@ class TrainingModel:
                       }
                       )
         return model
+
+    @staticmethod
+    def export_cpu_weights(model: models.Model, model_path: str):
+        """Exports the model weights for the pure-Rust CPU evaluator, so the engine can run without libtensorflow"""
+        tensors = {}
+        offset = 0
+        with open(os.path.join(model_path, CPU_MODEL_WEIGHTS_FILE), 'wb') as weights_file:
+            for layer in model.layers:
+                for weight in layer.weights:
+                    # e.g. 'i1_conv2x2_1_bn1/moving_mean'
+                    name = '{}/{}'.format(layer.name, weight.name.split('/')[-1].split(':')[0])
+                    values = weight.numpy().astype('<f4')
+                    tensors[name] = {'shape': list(values.shape), 'offset': offset}
+                    weights_file.write(values.tobytes())
+                    offset += values.size
+
+        manifest = {
+            'inception_layers': [{'filters': f, 'block_count': b, 'pooling': p} for f, b, p in NN_INCEPTION_LAYERS],
+            'tensors': tensors,
+        }
+        with open(os.path.join(model_path, CPU_MODEL_MANIFEST_FILE), 'w') as manifest_file:
+            json.dump(manifest, manifest_file)
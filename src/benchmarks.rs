@@ -1 +1,2 @@
 pub mod perftbenchmark;
+pub mod moveformatbenchmark;
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::moves::gamemovelist::*;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::position::Position;
+
+pub struct MoveFormatBenchmark {}
+
+impl MoveFormatBenchmark {
+    // Perft using the moves in their packed 16-bit form, the same as PerftBenchmark::run_perft_recursive()
+    fn run_compact_perft(position: &mut Position, depth: u8) -> usize {
+        let mut move_maker = MoveMaker::default();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+
+        if depth <= 1 {
+            return move_list.list_len;
+        }
+
+        let mut nodes = 0;
+        for compact_move in move_list.moves().iter() {
+            move_maker.make_compact_move(position, *compact_move, true);
+            nodes += MoveFormatBenchmark::run_compact_perft(position, depth - 1);
+            move_maker.unmake_move(position);
+        }
+        nodes
+    }
+
+    // Perft that expands every generated move into a GameMove (with its moving and captured pieces), as the move lists
+    // used to store them, and makes the expanded moves
+    fn run_expanded_perft(position: &mut Position, depth: u8) -> usize {
+        let mut move_maker = MoveMaker::default();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+        let mut game_moves = [GameMove::default(); MAX_MOVES_PER_POSITION];
+        for (i, game_move) in game_moves[..move_list.list_len].iter_mut().enumerate() {
+            *game_move = move_list.get_move(i, position);
+        }
+
+        if depth <= 1 {
+            return move_list.list_len;
+        }
+
+        let mut nodes = 0;
+        for game_move in game_moves[..move_list.list_len].iter() {
+            move_maker.make_move(position, game_move, true);
+            nodes += MoveFormatBenchmark::run_expanded_perft(position, depth - 1);
+            move_maker.unmake_move(position);
+        }
+        nodes
+    }
+
+    // Times one perft run, returning the node count and elapsed time
+    fn time_perft(fen_str: Option<&str>, depth: u8, perft: fn(&mut Position, u8) -> usize) -> (usize, Duration) {
+        let mut position = Position::from_fen(fen_str, false).unwrap();
+        let before = Instant::now();
+        let nodes = perft(&mut position, depth);
+        (nodes, before.elapsed())
+    }
+
+    // Compares perft with the packed moves against perft with every move expanded to a GameMove
+    // The runs alternate between the two and the fastest of each is reported, to cut down on timing noise
+    pub fn run_move_format_benchmark(fen_str: Option<&str>, depth: u8, runs: usize) {
+        println!("\nComparing move formats with perft to depth {} ({} runs each)...", depth, runs);
+
+        let mut best_times = [Duration::MAX; 2];
+        let mut node_counts = [0usize; 2];
+        for _ in 0..runs {
+            for (i, perft) in [MoveFormatBenchmark::run_expanded_perft as fn(&mut Position, u8) -> usize, MoveFormatBenchmark::run_compact_perft].iter().enumerate() {
+                let (nodes, elapsed) = MoveFormatBenchmark::time_perft(fen_str, depth, *perft);
+                node_counts[i] = nodes;
+                best_times[i] = best_times[i].min(elapsed);
+            }
+        }
+
+        for (i, label) in ["Expanded GameMove", "Packed CompactMove"].iter().enumerate() {
+            println!("{}:\tNodes: {}\t\tBest elapsed: {:.2?}  ({:.1?} pos/s)", label, node_counts[i], best_times[i],
+                     (node_counts[i] as f64 / best_times[i].as_millis() as f64) * 1000f64);
+        }
+        println!("Speedup: {:.2}x", best_times[0].as_secs_f64() / best_times[1].as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_formats_give_same_perft() {
+        let fen = Some("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        assert_eq!(MoveFormatBenchmark::time_perft(fen, 3, MoveFormatBenchmark::run_compact_perft).0, 97862);
+        assert_eq!(MoveFormatBenchmark::time_perft(fen, 3, MoveFormatBenchmark::run_expanded_perft).0, 97862);
+    }
+}
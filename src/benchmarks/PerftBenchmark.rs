@@ -8,7 +8,7 @@ use json::JsonValue;
 use simple_error::SimpleError;
 use crate::constants::PlayerColour;
 use crate::game::analysis::kingattackrayanalyzer::KingAttackRayAnalyzer;
-use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::analysis::positionanalyzer::{MoveGenType, PositionAnalyzer};
 use crate::game::moves::gamemovelist::GameMoveList;
 use crate::game::moves::movemaker::MoveMaker;
 use crate::game::position::Position;
@@ -60,7 +60,11 @@ impl PerftBenchmark {
 
         if intense_verify {
             let fen = position.to_fen();
-            let mut temp_pos = Position::from_fen(Some(fen.as_str()), false).unwrap();
+            let mut temp_pos = if position.chess960 {
+                Position::from_chess960_fen(Some(fen.as_str()), false).unwrap()
+            } else {
+                Position::from_fen(Some(fen.as_str()), false).unwrap()
+            };
             let mut temp_move_list = GameMoveList::default();
             PositionAnalyzer::calc_legal_moves(&mut temp_pos, &mut temp_move_list);
 
@@ -93,7 +97,7 @@ impl PerftBenchmark {
 
         let mut nodes = 0;
         for i in 0..move_list.list_len {
-            move_maker.make_move(position, &move_list.move_list[i], true);
+            move_maker.make_compact_move(position, move_list.move_list[i], true);
 
             match PerftBenchmark::run_perft_recursive(position, depth - 1, intense_verify, stockfish) {
                 Ok(n) => nodes += n,
@@ -103,7 +107,72 @@ impl PerftBenchmark {
                 }
             }
 
-            move_maker.unmake_move(position, &move_list.move_list[i]);
+            move_maker.unmake_move(position);
+        }
+        Ok(nodes)
+    }
+
+    // Same as run_perft_recursive(), but counts only the moves of the given type at the last ply (so the totals can be
+    // compared with the captures / checks columns of published perft results).  At every node, the staged move
+    // generation is also validated against the full generation: captures and quiets must split the legal moves
+    // exactly, evasions must match them whenever in check and checks must be a subset of them
+    pub fn run_perft_by_gen_type(position: &mut Position, depth: u8, gen_type: MoveGenType) -> Result<usize, SimpleError> {
+        let mut move_maker = MoveMaker::default();
+        let mut move_list = GameMoveList::default();
+
+        // The analysis masks are left on the position after generating moves, so each staged generation gets a fresh
+        // copy of it
+        let mut staged_lists: Vec<(MoveGenType, GameMoveList)> = vec![];
+        for staged_gen_type in [MoveGenType::CAPTURES, MoveGenType::QUIETS, MoveGenType::EVASIONS, MoveGenType::CHECKS].iter() {
+            let mut staged_move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves_by_type(&mut position.clone(), &mut staged_move_list, *staged_gen_type);
+            staged_lists.push((*staged_gen_type, staged_move_list));
+        }
+        PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+
+        let all_moves: Vec<String> = move_list.moves().iter().map(|m| format!("{:?}", m)).collect();
+        let mut split_moves: Vec<String> = staged_lists[0].1.moves().iter()
+            .chain(staged_lists[1].1.moves().iter())
+            .map(|m| format!("{:?}", m))
+            .collect();
+        split_moves.sort();
+        let mut sorted_all_moves = all_moves.clone();
+        sorted_all_moves.sort();
+        let evasions_str = format!("{:?}", staged_lists[2].1);
+        let expected_evasions_str = if position.king_in_check { format!("{:?}", move_list) } else { String::new() };
+        let checks_are_legal = staged_lists[3].1.moves().iter()
+            .all(|m| all_moves.contains(&format!("{:?}", m)));
+
+        if split_moves != sorted_all_moves || evasions_str != expected_evasions_str || !checks_are_legal {
+            println!("STAGED MOVE GENERATION MISMATCH!!!");
+            println!("Failing position:\t{}", position.to_fen());
+            println!("All moves:\t{:?}", move_list);
+            for (staged_gen_type, staged_move_list) in staged_lists.iter() {
+                println!("{:?}:\t{:?}", staged_gen_type, staged_move_list);
+            }
+            return Err(SimpleError::new("Staged move list mismatch"));
+        }
+
+        if depth <= 1 {
+            return Ok(match gen_type {
+                MoveGenType::ALL => move_list.list_len,
+                _ => staged_lists.iter().find(|(t, _)| *t == gen_type).unwrap().1.list_len,
+            });
+        }
+
+        let mut nodes = 0;
+        for i in 0..move_list.list_len {
+            move_maker.make_compact_move(position, move_list.move_list[i], true);
+            let result = PerftBenchmark::run_perft_by_gen_type(position, depth - 1, gen_type);
+            move_maker.unmake_move(position);
+
+            match result {
+                Ok(n) => nodes += n,
+                Err(e) => {
+                    println!("Last move played: {:?}", move_list.move_list[i]);
+                    return Err(e);
+                }
+            }
         }
         Ok(nodes)
     }
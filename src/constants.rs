@@ -35,7 +35,13 @@ pub const NN_TOTAL_OUTPUT_SIZE_PER_POS: usize = 1858;   // The length of the Has
 
 pub const START_POSITION: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
+// FNV-1a is used for hashes that need to stay the same between runs, since std's DefaultHasher is not guaranteed
+// to be stable between Rust releases
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+pub const FNV_PRIME: u64 = 0x100000001b3;
+
 #[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PlayerColour {
     WHITE = 0,
     BLACK = 1,
@@ -53,6 +59,17 @@ pub enum PieceType {
     PAWN = 6,
 }
 
+// Special kinds of move that need more than the piece to be moved from the source to the target square
+#[allow(non_camel_case_types)]
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MoveKind {
+    NORMAL = 0,
+    DOUBLE_PAWN_PUSH = 1,
+    EN_PASSANT = 2,
+    CASTLE = 3,
+}
+
 pub const RANK_1: u64 = 0xff;
 pub const RANK_2: u64 = 0xff00;
 pub const RANK_3: u64 = 0xff0000;
@@ -1 +1,4 @@
-pub mod enginecontroller;
\ No newline at end of file
+pub mod enginecontroller;
+pub mod endgametablebase;
+pub mod positionevaluator;
+pub mod syzygy;
\ No newline at end of file
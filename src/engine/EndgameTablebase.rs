@@ -0,0 +1,697 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use simple_error::{bail, SimpleError};
+use crate::constants::*;
+use crate::engine::syzygy::calc_material_code;
+use crate::game::analysis::kingattackrayanalyzer::KingAttackRayAnalyzer;
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::pieces::bishop::Bishop;
+use crate::game::pieces::king::King;
+use crate::game::pieces::knight::Knight;
+use crate::game::pieces::piece::Piece;
+use crate::game::pieces::queen::Queen;
+use crate::game::pieces::rook::Rook;
+use crate::game::position::Position;
+
+// Generating a table takes one byte per position for each of the working arrays below, so five pieces
+// (around 1GB each) isn't practical
+pub const MAX_ENDGAME_PIECES: usize = 4;
+pub const ENDGAME_TABLE_SUFFIX: &str = "dtm";
+const ENDGAME_TABLE_MAGIC: &[u8; 8] = b"MCQLDTM1";
+
+// Piece letters in the order used by the material codes (the same as the Syzygy table names)
+const ENDGAME_PIECE_LETTERS: [(char, PieceType); 6] = [
+    ('K', PieceType::KING), ('Q', PieceType::QUEEN), ('R', PieceType::ROOK),
+    ('B', PieceType::BISHOP), ('N', PieceType::KNIGHT), ('P', PieceType::PAWN),
+];
+
+// Status of each position while generating a table
+const UNRESOLVED: u8 = 0;
+const RESOLVED_WIN: u8 = 1;
+const RESOLVED_LOSS: u8 = 2;
+const RESOLVED_DRAW: u8 = 3;
+const ILLEGAL: u8 = 4;
+
+/// Distance to mate from the point of view of the side to move, in moves (i.e. WIN(1) is mate in 1 and
+/// LOSS(0) is already checkmated)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EndgameResult {
+    WIN(u8),
+    DRAW,
+    LOSS(u8),
+}
+
+impl EndgameResult {
+    /// Stored as one byte per position: 0 for a draw, 1 to 127 for mate in N and 128 + N for mated in N
+    fn from_byte(value: u8) -> EndgameResult {
+        match value {
+            0 => EndgameResult::DRAW,
+            1..=127 => EndgameResult::WIN(value),
+            _ => EndgameResult::LOSS(value - 128),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            EndgameResult::WIN(moves) => moves,
+            EndgameResult::DRAW => 0,
+            EndgameResult::LOSS(moves) => 128 + moves,
+        }
+    }
+
+    /// Wins are always an odd number of plies from mate and losses an even number
+    fn from_plies(status: u8, plies: u8) -> EndgameResult {
+        match status {
+            RESOLVED_WIN => EndgameResult::WIN(plies.div_ceil(2)),
+            RESOLVED_LOSS => EndgameResult::LOSS(plies / 2),
+            _ => EndgameResult::DRAW,
+        }
+    }
+
+    fn to_plies(self) -> u8 {
+        match self {
+            EndgameResult::WIN(moves) => moves * 2 - 1,
+            EndgameResult::DRAW => 0,
+            EndgameResult::LOSS(moves) => moves * 2,
+        }
+    }
+
+    /// The result for the side that made the move leading to this position
+    pub fn flip(&self) -> EndgameResult {
+        match self {
+            EndgameResult::WIN(moves) => EndgameResult::LOSS(*moves),
+            EndgameResult::DRAW => EndgameResult::DRAW,
+            EndgameResult::LOSS(moves) => EndgameResult::WIN(moves + 1),
+        }
+    }
+
+    /// Higher is better for the side to move: quicker wins, then draws, then slower losses
+    fn calc_rank(&self) -> i32 {
+        match self {
+            EndgameResult::WIN(moves) => 1000 - *moves as i32,
+            EndgameResult::DRAW => 0,
+            EndgameResult::LOSS(moves) => -1000 + *moves as i32,
+        }
+    }
+}
+
+/// The pieces in an endgame table, given by a material code such as KBNvK
+/// Positions are indexed by the squares of the white king, the black king and then the other pieces (white's first
+/// and in KQRBNP order), with the board mirrored so that the white king is always on files a-d (and ranks 1-4 if
+/// there are no pawns)
+#[derive(Clone, Debug)]
+pub struct EndgameMaterial {
+    pub code: String,
+    pieces: Vec<(PieceType, bool)>,
+    has_pawns: bool,
+}
+
+impl EndgameMaterial {
+    pub fn from_code(code: &str) -> Result<EndgameMaterial, SimpleError> {
+        let sides: Vec<&str> = code.split('v').collect();
+        if sides.len() != 2 { bail!("Invalid material code {}", code); }
+
+        let mut pieces = vec![(PieceType::KING, true), (PieceType::KING, false)];
+        for (side, is_white) in [(sides[0], true), (sides[1], false)] {
+            let mut letters: Vec<char> = side.chars().collect();
+            if letters.iter().filter(|letter| **letter == 'K').count() != 1 { bail!("Invalid material code {}", code); }
+            letters.sort_by_key(|letter| ENDGAME_PIECE_LETTERS.iter().position(|(l, _)| l == letter).unwrap_or(usize::MAX));
+            for letter in letters.iter().skip(1) {
+                match ENDGAME_PIECE_LETTERS.iter().find(|(l, _)| l == letter) {
+                    Some((_, piece)) => pieces.push((*piece, is_white)),
+                    None => bail!("Invalid material code {}", code),
+                }
+            }
+        }
+        if pieces.len() > MAX_ENDGAME_PIECES { bail!("Endgame tables are limited to {} pieces", MAX_ENDGAME_PIECES); }
+
+        let piece_letter = |piece: &(PieceType, bool)| ENDGAME_PIECE_LETTERS.iter().find(|(_, p)| *p == piece.0).unwrap().0;
+        let side_code = |is_white: bool| -> String {
+            pieces.iter().filter(|piece| piece.1 == is_white).map(piece_letter).collect()
+        };
+        Ok(EndgameMaterial {
+            code: format!("{}v{}", side_code(true), side_code(false)),
+            has_pawns: pieces.iter().any(|piece| piece.0 == PieceType::PAWN),
+            pieces,
+        })
+    }
+
+    /// Returns the same material with the colours swapped
+    pub fn flip(&self) -> EndgameMaterial {
+        let sides: Vec<&str> = self.code.split('v').collect();
+        EndgameMaterial::from_code(&format!("{}v{}", sides[1], sides[0])).unwrap()
+    }
+
+    fn king_square_count(&self) -> usize {
+        if self.has_pawns { 32 } else { 16 }
+    }
+
+    pub fn table_size(&self) -> usize {
+        self.king_square_count() * 64usize.pow(self.pieces.len() as u32 - 1) * 2
+    }
+
+    /// Returns the index of the position with the pieces on the given squares (in the same order as the pieces),
+    /// mirroring the squares and sorting identical pieces into a standard order first
+    fn calc_index(&self, squares: &mut [usize], white_to_move: bool) -> usize {
+        let mirror = (if squares[0] & 7 > 3 { 7 } else { 0 }) | (if !self.has_pawns && squares[0] >> 3 > 3 { 56 } else { 0 });
+        for sq in squares.iter_mut() { *sq ^= mirror; }
+
+        let mut start = 2;
+        while start < squares.len() {
+            let end = (start..squares.len()).find(|i| self.pieces[*i] != self.pieces[start]).unwrap_or(squares.len());
+            squares[start..end].sort();
+            start = end;
+        }
+
+        let king_index = (squares[0] >> 3) * 4 + (squares[0] & 7);
+        let index = squares[1..].iter().fold(king_index, |index, sq| index * 64 + sq);
+        index * 2 + (!white_to_move) as usize
+    }
+
+    /// Returns the squares (in the same order as the pieces) and side to move for the index, or None if the index
+    /// doesn't correspond to a valid placement of the pieces
+    fn decode_index(&self, mut index: usize) -> Option<(Vec<usize>, bool)> {
+        let white_to_move = index & 1 == 0;
+        index >>= 1;
+        let mut squares = vec![0; self.pieces.len()];
+        for i in (1..self.pieces.len()).rev() {
+            squares[i] = index & 63;
+            index >>= 6;
+        }
+        squares[0] = (index >> 2) * 8 + (index & 3);
+
+        for i in 0..squares.len() {
+            if squares[..i].contains(&squares[i]) { return None; }
+            if self.pieces[i].0 == PieceType::PAWN && (squares[i] < 8 || squares[i] >= 56) { return None; }
+            // Only one ordering of identical pieces is used
+            if i > 2 && self.pieces[i] == self.pieces[i - 1] && squares[i] < squares[i - 1] { return None; }
+        }
+        Some((squares, white_to_move))
+    }
+
+    fn to_position(&self, squares: &[usize], white_to_move: bool) -> Position {
+        let mut position = Position::default();
+        for (piece, sq) in self.pieces.iter().zip(squares.iter()) {
+            *EndgameMaterial::get_bitboard(&mut position, piece) |= SINGLE_BITBOARDS[*sq];
+        }
+        position.white_to_move = white_to_move;
+        position.update_occupancy();
+        position
+    }
+
+    /// Returns the squares of the pieces in the position (in the same order as the pieces)
+    fn calc_squares(&self, position: &Position) -> Vec<usize> {
+        let mut position = position.clone();
+        self.pieces.iter().map(|piece| {
+            let bitboard = EndgameMaterial::get_bitboard(&mut position, piece);
+            let sq = bitboard.trailing_zeros() as usize;
+            *bitboard &= *bitboard - 1;
+            sq
+        }).collect()
+    }
+
+    fn get_bitboard<'a>(position: &'a mut Position, piece: &(PieceType, bool)) -> &'a mut u64 {
+        match piece {
+            (PieceType::KING, true) => &mut position.wk,
+            (PieceType::QUEEN, true) => &mut position.wq,
+            (PieceType::ROOK, true) => &mut position.wr,
+            (PieceType::BISHOP, true) => &mut position.wb,
+            (PieceType::KNIGHT, true) => &mut position.wn,
+            (PieceType::KING, false) => &mut position.bk,
+            (PieceType::QUEEN, false) => &mut position.bq,
+            (PieceType::ROOK, false) => &mut position.br,
+            (PieceType::BISHOP, false) => &mut position.bb,
+            (PieceType::KNIGHT, false) => &mut position.bn,
+            (_, true) => &mut position.wp,
+            (_, false) => &mut position.bp,
+        }
+    }
+
+    /// Returns the material codes of the tables reachable by a capture or a promotion
+    fn calc_conversion_codes(&self) -> Vec<String> {
+        let mut codes = vec![];
+        for i in 2..self.pieces.len() {
+            let mut pieces = self.pieces.clone();
+            pieces.remove(i);
+            codes.push(EndgameMaterial::calc_code(&pieces));
+            if self.pieces[i].0 == PieceType::PAWN {
+                for promotion_piece in [PieceType::QUEEN, PieceType::ROOK, PieceType::BISHOP, PieceType::KNIGHT] {
+                    pieces = self.pieces.clone();
+                    pieces[i].0 = promotion_piece;
+                    codes.push(EndgameMaterial::calc_code(&pieces));
+                }
+            }
+        }
+        codes.sort();
+        codes.dedup();
+        codes
+    }
+
+    fn calc_code(pieces: &[(PieceType, bool)]) -> String {
+        let side_code = |is_white: bool| -> String {
+            pieces.iter().filter(|piece| piece.1 == is_white)
+                .map(|piece| ENDGAME_PIECE_LETTERS.iter().find(|(_, p)| *p == piece.0).unwrap().0)
+                .collect()
+        };
+        EndgameMaterial::from_code(&format!("{}v{}", side_code(true), side_code(false))).unwrap().code
+    }
+}
+
+/// A generated distance-to-mate table, with one byte per position
+pub struct EndgameTable {
+    pub material: EndgameMaterial,
+    values: Vec<u8>,
+}
+
+impl EndgameTable {
+    pub fn get_result(&self, position: &Position) -> EndgameResult {
+        let mut squares = self.material.calc_squares(position);
+        EndgameResult::from_byte(self.values[self.material.calc_index(&mut squares, position.white_to_move)])
+    }
+
+    /// Returns the longest mate in the table (for the side to move)
+    pub fn calc_longest_mate(&self) -> u8 {
+        self.values.iter().filter(|value| **value < 128).max().copied().unwrap_or(0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = ENDGAME_TABLE_MAGIC.to_vec();
+        bytes.push(self.material.code.len() as u8);
+        bytes.extend(self.material.code.as_bytes());
+        bytes.extend(self.values.iter());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<EndgameTable, SimpleError> {
+        let magic_len = ENDGAME_TABLE_MAGIC.len();
+        if bytes.len() <= magic_len || &bytes[..magic_len] != ENDGAME_TABLE_MAGIC { bail!("Invalid endgame table"); }
+
+        let code_end = magic_len + 1 + bytes[magic_len] as usize;
+        let code = match bytes.get(magic_len + 1..code_end).map(std::str::from_utf8) {
+            Some(Ok(code)) => code,
+            _ => bail!("Invalid endgame table"),
+        };
+        let material = EndgameMaterial::from_code(code)?;
+        if bytes.len() - code_end != material.table_size() { bail!("Endgame table {} has the wrong size", code); }
+        Ok(EndgameTable { material, values: bytes[code_end..].to_vec() })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SimpleError> {
+        fs::write(path, self.to_bytes()).map_err(SimpleError::from)
+    }
+
+    pub fn load(path: &Path) -> Result<EndgameTable, SimpleError> {
+        match fs::read(path) {
+            Err(why) => bail!("Couldn't read endgame table {}: {}", path.display(), why),
+            Ok(bytes) => EndgameTable::from_bytes(&bytes),
+        }
+    }
+}
+
+/// Working state while generating a table by retrograde analysis
+struct EndgameTableGenerator<'a> {
+    material: EndgameMaterial,
+    tablebase: &'a EndgameTablebase,
+    status: Vec<u8>,
+    plies: Vec<u8>,
+    // Number of moves that haven't yet been shown to lose, so that the position is lost once it reaches zero
+    remaining_moves: Vec<u8>,
+    // The longest loss from captures / promotions into other tables, which any loss must take at least as long as
+    conversion_loss_plies: Vec<u8>,
+    // Positions to resolve at each ply (as a win if the flag is set, otherwise a loss)
+    pending: Vec<Vec<(usize, bool)>>,
+}
+
+impl<'a> EndgameTableGenerator<'a> {
+    fn new(material: EndgameMaterial, tablebase: &'a EndgameTablebase) -> EndgameTableGenerator<'a> {
+        let size = material.table_size();
+        EndgameTableGenerator {
+            material,
+            tablebase,
+            status: vec![UNRESOLVED; size],
+            plies: vec![0; size],
+            remaining_moves: vec![0; size],
+            conversion_loss_plies: vec![0; size],
+            pending: vec![],
+        }
+    }
+
+    fn add_pending(&mut self, plies: usize, index: usize, is_win: bool) {
+        if self.pending.len() <= plies { self.pending.resize(plies + 1, vec![]); }
+        self.pending[plies].push((index, is_win));
+    }
+
+    /// Counts the legal moves in every position and resolves checkmates, stalemates and moves into other tables
+    fn init_positions(&mut self) -> Result<(), SimpleError> {
+        let mut move_list = GameMoveList::default();
+        let mut king_attack_analyzer = KingAttackRayAnalyzer::default();
+        for index in 0..self.status.len() {
+            let (squares, white_to_move) = match self.material.decode_index(index) {
+                Some(decoded) => decoded,
+                None => { self.status[index] = ILLEGAL; continue; },
+            };
+            let mut position = self.material.to_position(&squares, white_to_move);
+            let (player, enemy_king) = if white_to_move { (PlayerColour::WHITE, position.bk) } else { (PlayerColour::BLACK, position.wk) };
+            if PositionAnalyzer::calc_all_attacked_squares(&position, &player, 0, &mut king_attack_analyzer) & enemy_king > 0 {
+                self.status[index] = ILLEGAL;
+                continue;
+            }
+
+            move_list.clear();
+            PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+            if position.is_checkmate {
+                self.add_pending(0, index, false);
+                continue;
+            } else if position.is_stalemate {
+                self.status[index] = RESOLVED_DRAW;
+                continue;
+            }
+
+            let mut remaining_moves = move_list.list_len;
+            let mut best_conversion_win = None;
+            for compact_move in move_list.moves().iter() {
+                if !compact_move.is_capture() && !compact_move.is_promotion() { continue; }
+
+                let mut next_position = position.clone();
+                MoveMaker::default().make_compact_move(&mut next_position, *compact_move, false);
+                match self.tablebase.probe_dtm(&next_position)? {
+                    EndgameResult::LOSS(moves) => {
+                        let plies = EndgameResult::LOSS(moves).to_plies() as usize + 1;
+                        best_conversion_win = Some(best_conversion_win.map_or(plies, |best: usize| best.min(plies)));
+                    },
+                    EndgameResult::WIN(moves) => {
+                        remaining_moves -= 1;
+                        self.conversion_loss_plies[index] = self.conversion_loss_plies[index].max(EndgameResult::WIN(moves).to_plies() + 1);
+                    },
+                    EndgameResult::DRAW => (),
+                }
+            }
+
+            self.remaining_moves[index] = remaining_moves as u8;
+            if let Some(plies) = best_conversion_win {
+                self.add_pending(plies, index, true);
+            } else if remaining_moves == 0 {
+                self.add_pending(self.conversion_loss_plies[index] as usize, index, false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the indexes of the positions from which the side that just moved could have reached this position,
+    /// without a capture or promotion (which would have come from a different table)
+    fn calc_predecessors(&self, index: usize) -> Vec<usize> {
+        let (squares, white_to_move) = self.material.decode_index(index).unwrap();
+        let position = self.material.to_position(&squares, white_to_move);
+        let player = if white_to_move { PlayerColour::BLACK } else { PlayerColour::WHITE };
+        let mut king_attack_analyzer = KingAttackRayAnalyzer::default();
+
+        let mut predecessors = vec![];
+        for (i, (piece, is_white)) in self.material.pieces.iter().enumerate() {
+            if *is_white == white_to_move { continue; }
+
+            let sq = squares[i];
+            let piece_pos = SINGLE_BITBOARDS[sq];
+            let source_squares = match piece {
+                PieceType::KING => King::calc_attacked_squares(&position, piece_pos, &player, 0, &mut king_attack_analyzer),
+                PieceType::QUEEN => Queen::calc_attacked_squares(&position, piece_pos, &player, 0, &mut king_attack_analyzer),
+                PieceType::ROOK => Rook::calc_attacked_squares(&position, piece_pos, &player, 0, &mut king_attack_analyzer),
+                PieceType::BISHOP => Bishop::calc_attacked_squares(&position, piece_pos, &player, 0, &mut king_attack_analyzer),
+                PieceType::KNIGHT => Knight::calc_attacked_squares(&position, piece_pos, &player, 0, &mut king_attack_analyzer),
+                _ => {
+                    // Pawns move one square forward (or two from the second rank)
+                    let (single, double, double_rank) = if *is_white {
+                        ((piece_pos >> 8) & !RANK_1, (piece_pos >> 16) & RANK_2, RANK_4)
+                    } else {
+                        ((piece_pos << 8) & !RANK_8, (piece_pos << 16) & RANK_7, RANK_5)
+                    };
+                    let single = single & position.non_occupancy;
+                    single | if piece_pos & double_rank > 0 && single > 0 { double & position.non_occupancy } else { 0 }
+                },
+            } & position.non_occupancy;
+
+            let mut remaining = source_squares;
+            while remaining > 0 {
+                let mut predecessor_squares = squares.clone();
+                predecessor_squares[i] = remaining.trailing_zeros() as usize;
+                predecessors.push(self.material.calc_index(&mut predecessor_squares, !white_to_move));
+                remaining &= remaining - 1;
+            }
+        }
+        predecessors
+    }
+
+    /// Resolves positions in order of distance to mate, working back from each one to the positions before it
+    fn generate(mut self) -> Result<EndgameTable, SimpleError> {
+        self.init_positions()?;
+
+        let mut plies = 0;
+        while plies < self.pending.len() {
+            if plies > u8::MAX as usize { bail!("Mate is too far away to store in the endgame table"); }
+
+            let pending = std::mem::take(&mut self.pending[plies]);
+            for (index, is_win) in pending {
+                if self.status[index] != UNRESOLVED { continue; }
+                self.status[index] = if is_win { RESOLVED_WIN } else { RESOLVED_LOSS };
+                self.plies[index] = plies as u8;
+
+                for predecessor in self.calc_predecessors(index) {
+                    if self.status[predecessor] != UNRESOLVED { continue; }
+                    if !is_win {
+                        // A move to a lost position wins
+                        self.add_pending(plies + 1, predecessor, true);
+                    } else {
+                        // Lost once every move is to a won position, as late as the slowest of them
+                        self.remaining_moves[predecessor] -= 1;
+                        if self.remaining_moves[predecessor] == 0 {
+                            let loss_plies = (plies + 1).max(self.conversion_loss_plies[predecessor] as usize);
+                            self.add_pending(loss_plies, predecessor, false);
+                        }
+                    }
+                }
+            }
+            plies += 1;
+        }
+
+        // Anything that isn't a forced win or loss is a draw (illegal positions are stored as draws)
+        let values = self.status.iter().zip(self.plies.iter())
+            .map(|(status, plies)| EndgameResult::from_plies(*status, *plies).to_byte())
+            .collect();
+        Ok(EndgameTable { material: self.material, values })
+    }
+}
+
+/// A collection of generated endgame tables, loaded from a folder when first probed
+#[derive(Default)]
+pub struct EndgameTablebase {
+    dir: Option<PathBuf>,
+    tables: HashMap<String, EndgameTable>,
+}
+
+impl EndgameTablebase {
+    pub fn init(dir: &Path) -> EndgameTablebase {
+        EndgameTablebase { dir: Some(dir.to_path_buf()), tables: HashMap::new() }
+    }
+
+    pub fn get_table(&self, code: &str) -> Option<&EndgameTable> {
+        self.tables.get(code)
+    }
+
+    /// Loads the table from the folder if it hasn't been loaded yet, returning false if there is no such table
+    fn load_table(&mut self, code: &str) -> Result<bool, SimpleError> {
+        if self.tables.contains_key(code) { return Ok(true); }
+
+        let path = match self.dir.as_ref() {
+            Some(dir) => dir.join(format!("{}.{}", code, ENDGAME_TABLE_SUFFIX)),
+            None => return Ok(false),
+        };
+        if !path.exists() { return Ok(false); }
+
+        let table = EndgameTable::load(&path)?;
+        self.tables.insert(code.to_string(), table);
+        Ok(true)
+    }
+
+    /// Generates the table for the material code (and any tables reachable from it by captures / promotions)
+    /// unless it is already available
+    pub fn generate(&mut self, code: &str) -> Result<(), SimpleError> {
+        let material = EndgameMaterial::from_code(code)?;
+        if material.pieces.len() <= 2 || self.load_table(&material.code)? || self.load_table(&material.flip().code)? {
+            return Ok(());
+        }
+
+        for conversion_code in material.calc_conversion_codes() {
+            self.generate(&conversion_code)?;
+        }
+
+        println!("Generating endgame table {} ({} positions)", material.code, material.table_size());
+        let table = EndgameTableGenerator::new(material.clone(), self).generate()?;
+        self.tables.insert(material.code, table);
+        Ok(())
+    }
+
+    /// Saves the loaded / generated tables that aren't already in the folder, returning the number saved
+    pub fn save_tables(&self, dir: &Path) -> Result<usize, SimpleError> {
+        fs::create_dir_all(dir).map_err(SimpleError::from)?;
+        let mut saved_count = 0;
+        for (code, table) in self.tables.iter() {
+            let path = dir.join(format!("{}.{}", code, ENDGAME_TABLE_SUFFIX));
+            if path.exists() { continue; }
+            table.save(&path)?;
+            saved_count += 1;
+        }
+        Ok(saved_count)
+    }
+
+    /// Loads any tables needed to probe the position, returning an error if one isn't available
+    pub fn load_tables_for(&mut self, position: &Position) -> Result<(), SimpleError> {
+        let material = EndgameMaterial::from_code(&calc_material_code(position))?;
+        if material.pieces.len() <= 2 { return Ok(()); }
+        if !self.load_table(&material.code)? && !self.load_table(&material.flip().code)? {
+            bail!("No endgame table for {}", material.code);
+        }
+        for conversion_code in material.calc_conversion_codes() {
+            let conversion_material = EndgameMaterial::from_code(&conversion_code)?;
+            if conversion_material.pieces.len() > 2 && !self.load_table(&conversion_code)? && !self.load_table(&conversion_material.flip().code)? {
+                bail!("No endgame table for {}", conversion_code);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the distance to mate for the side to move, from the loaded tables
+    pub fn probe_dtm(&self, position: &Position) -> Result<EndgameResult, SimpleError> {
+        if position.castling_rights > 0 { bail!("Endgame tables don't include castling rights"); }
+
+        // The tables don't include en passant, so search one move further if there is an en passant square
+        if position.en_passant_sq > 0 {
+            if let Some((_, result)) = self.score_moves(position)?.first() { return Ok(*result); }
+        }
+
+        let material = EndgameMaterial::from_code(&calc_material_code(position))?;
+        if material.pieces.len() <= 2 { return Ok(EndgameResult::DRAW); }
+        if let Some(table) = self.tables.get(&material.code) {
+            return Ok(table.get_result(position));
+        }
+
+        // Tables are only stored for one side having the material, so swap the colours and flip the board
+        let flipped_material = material.flip();
+        match self.tables.get(&flipped_material.code) {
+            Some(table) => Ok(table.get_result(&EndgameTablebase::flip_position(position))),
+            None => bail!("No endgame table for {}", material.code),
+        }
+    }
+
+    /// Returns every legal move with the result after it (for the side to move), best first
+    pub fn score_moves(&self, position: &Position) -> Result<Vec<(GameMove, EndgameResult)>, SimpleError> {
+        let mut position = position.clone();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+
+        let mut scored_moves = vec![];
+        for compact_move in move_list.moves().iter() {
+            let mut next_position = position.clone();
+            MoveMaker::default().make_compact_move(&mut next_position, *compact_move, false);
+            scored_moves.push((compact_move.to_game_move(&position), self.probe_dtm(&next_position)?.flip()));
+        }
+        scored_moves.sort_by_key(|(_, result)| -result.calc_rank());
+        Ok(scored_moves)
+    }
+
+    /// Returns the move that mates quickest / holds out longest, or None if there are no legal moves
+    pub fn get_best_move(&self, position: &Position) -> Result<Option<(GameMove, EndgameResult)>, SimpleError> {
+        Ok(self.score_moves(position)?.first().copied())
+    }
+
+    fn flip_position(position: &Position) -> Position {
+        let mut flipped = position.clone();
+        (flipped.wp, flipped.wn, flipped.wb, flipped.wr, flipped.wq, flipped.wk) =
+            (position.bp.swap_bytes(), position.bn.swap_bytes(), position.bb.swap_bytes(), position.br.swap_bytes(), position.bq.swap_bytes(), position.bk.swap_bytes());
+        (flipped.bp, flipped.bn, flipped.bb, flipped.br, flipped.bq, flipped.bk) =
+            (position.wp.swap_bytes(), position.wn.swap_bytes(), position.wb.swap_bytes(), position.wr.swap_bytes(), position.wq.swap_bytes(), position.wk.swap_bytes());
+        flipped.en_passant_sq = position.en_passant_sq.swap_bytes();
+        flipped.white_to_move = !position.white_to_move;
+        flipped.update_occupancy();
+        flipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe_fen(tablebase: &EndgameTablebase, fen: &str) -> EndgameResult {
+        tablebase.probe_dtm(&Position::from_fen(Some(fen), false).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_material() {
+        let material = EndgameMaterial::from_code("KNBvK").unwrap();
+        assert_eq!(material.code, "KBNvK");
+        assert_eq!(material.flip().code, "KvKBN");
+        assert_eq!(material.table_size(), 16 * 64 * 64 * 64 * 2);
+        assert_eq!(EndgameMaterial::from_code("KPvKP").unwrap().calc_conversion_codes(), vec!["KBvKP", "KNvKP", "KPvK", "KPvKB", "KPvKN", "KPvKQ", "KPvKR", "KQvKP", "KRvKP", "KvKP"]);
+        assert!(EndgameMaterial::from_code("KQRBvK").is_err());
+        assert!(EndgameMaterial::from_code("KQvQ").is_err());
+
+        // Mirrored positions have the same index
+        let index = material.calc_index(&mut [0, 63, 10, 20], true);
+        assert_eq!(material.calc_index(&mut [7, 56, 13, 19], true), index);
+        assert_eq!(material.calc_index(&mut [63, 0, 53, 43], true), index);
+        assert_eq!(material.decode_index(index), Some((vec![0, 63, 10, 20], true)));
+        let material = EndgameMaterial::from_code("KRRvK").unwrap();
+        assert_eq!(material.calc_index(&mut [0, 63, 20, 10], false), material.calc_index(&mut [0, 63, 10, 20], false));
+    }
+
+    #[test]
+    fn test_generate_and_probe() {
+        let mut tablebase = EndgameTablebase::default();
+        tablebase.generate("KQvK").unwrap();
+        tablebase.generate("KRvK").unwrap();
+
+        // The longest mates with king and queen / king and rook
+        assert_eq!(tablebase.get_table("KQvK").unwrap().calc_longest_mate(), 10);
+        assert_eq!(tablebase.get_table("KRvK").unwrap().calc_longest_mate(), 16);
+
+        assert_eq!(probe_fen(&tablebase, "7k/8/6K1/8/8/8/8/1Q6 w - - 0 1"), EndgameResult::WIN(1));
+        assert_eq!(probe_fen(&tablebase, "Q6k/8/6K1/8/8/8/8/8 b - - 0 1"), EndgameResult::LOSS(0));
+        assert_eq!(probe_fen(&tablebase, "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), EndgameResult::DRAW);
+        // Black has the queen, so the table is probed with the colours swapped
+        assert_eq!(probe_fen(&tablebase, "7K/8/6k1/8/8/8/8/1q6 b - - 0 1"), EndgameResult::WIN(1));
+        // The rook can be taken
+        assert_eq!(probe_fen(&tablebase, "8/8/8/8/8/8/1k6/1R2K3 b - - 0 1"), EndgameResult::DRAW);
+
+        let position = Position::from_fen(Some("7k/8/6K1/8/8/8/8/1Q6 w - - 0 1"), false).unwrap();
+        let (best_move, result) = tablebase.get_best_move(&position).unwrap().unwrap();
+        assert_eq!(best_move.get_uci_move_string(), "b1b8");
+        assert_eq!(result, EndgameResult::WIN(1));
+
+        assert!(tablebase.probe_dtm(&Position::from_fen(Some("8/8/8/8/8/8/1k6/1B2K3 b - - 0 1"), false).unwrap()).is_err());
+        assert_eq!(probe_fen(&tablebase, "8/8/8/8/8/8/1k6/4K3 b - - 0 1"), EndgameResult::DRAW);
+    }
+
+    #[test]
+    fn test_generate_with_pawns() {
+        let mut dir = std::env::temp_dir();
+        dir.push("my_chess_ql_test_endgame_tables");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut tablebase = EndgameTablebase::default();
+        tablebase.generate("KPvK").unwrap();
+        assert_eq!(tablebase.save_tables(&dir).unwrap(), 5);
+
+        // With the king in front of the pawn on the sixth rank, it wins whoever is to move
+        let mut tablebase = EndgameTablebase::init(&dir);
+        let position = Position::from_fen(Some("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"), false).unwrap();
+        tablebase.load_tables_for(&position).unwrap();
+        assert!(matches!(tablebase.probe_dtm(&position).unwrap(), EndgameResult::WIN(_)));
+        assert!(matches!(probe_fen(&tablebase, "4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), EndgameResult::LOSS(_)));
+        // Stalemate, and a draw with the pawn on the rook file
+        assert_eq!(probe_fen(&tablebase, "4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), EndgameResult::DRAW);
+        assert_eq!(probe_fen(&tablebase, "k7/8/8/8/8/8/P7/7K w - - 0 1"), EndgameResult::DRAW);
+    }
+}
@@ -1,38 +1,221 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use rand::prelude::*;
+use simple_error::{bail, SimpleError};
 use crate::constants::*;
+use crate::engine::positionevaluator::{EvaluationTrace, PositionEvaluator, MATE_SCORE};
+use crate::engine::syzygy::{SyzygyTablebase, SyzygyWDL, TablebaseRootMove};
 use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::fenvalidator::{FenError, FenValidator};
 use crate::game::moves::gamemove::GameMove;
 use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
 use crate::game::position::*;
-use crate::neural::nnprediction::NNPrediction;
+use crate::interfaces::polyglot::PolyglotBook;
+use crate::neural::nnprediction::{NNPolicyPrediction, NNPrediction};
+use crate::neural::nnue::{NNUEAccumulator, NNUENetwork};
 use crate::neural::positionconverter::NNPositionConverter;
+use crate::neural::valuecalibration::ValueCalibration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvaluatorType {
+    NEURAL,
+    CLASSICAL,
+    NNUE,
+}
+
+impl EvaluatorType {
+    pub fn from_str(evaluator_type: &str) -> Result<EvaluatorType, SimpleError> {
+        match evaluator_type.to_lowercase().as_str() {
+            "neural" => Ok(EvaluatorType::NEURAL),
+            "classical" => Ok(EvaluatorType::CLASSICAL),
+            "nnue" => Ok(EvaluatorType::NNUE),
+            _ => bail!("Unknown evaluator type {}", evaluator_type)
+        }
+    }
+}
+
+/// Score of a move as reported to the UI, from the point of view of the side to move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EngineScore {
+    CENTIPAWNS(i32),
+    MATE(i32),
+}
+
+impl EngineScore {
+    /// Formats the score as used in the UCI 'info score' command
+    pub fn to_uci_string(self) -> String {
+        match self {
+            EngineScore::CENTIPAWNS(cp) => format!("cp {}", cp),
+            EngineScore::MATE(moves) => format!("mate {}", moves),
+        }
+    }
+}
 
 pub struct EngineController {
     pub position: Option<Position>,
-    pub nn_predictor: NNPrediction,
+    pub nn_predictor: Option<NNPrediction>,
+    pub evaluator_type: EvaluatorType,
+    pub nnue_network: Option<Arc<NNUENetwork>>,
+    pub value_calibration: ValueCalibration,
+    pub book: Option<PolyglotBook>,
+    pub use_book: bool,
+    pub tablebase: Option<SyzygyTablebase>,
+    // Set by the UCI_Chess960 option, so that KQkq in the FEN castling field refer to the outermost rooks
+    pub chess960: bool,
 }
 
 impl EngineController {
+    /// Loads the neural network model, falling back to the classical evaluator if it cannot be loaded
     pub fn init(nn_model_dir: PathBuf) -> EngineController {
+        let value_calibration = ValueCalibration::load_or_default(&nn_model_dir);
+        let nn_predictor = match NNPrediction::init_from_saved_model(nn_model_dir) {
+            Ok(nn_predictor) => Some(nn_predictor),
+            Err(e) => {
+                println!("info string {} - using the classical evaluator", e);
+                None
+            }
+        };
+
         EngineController {
             position: None,
-            nn_predictor: NNPrediction::init_from_saved_model(nn_model_dir).unwrap(),
+            evaluator_type: if nn_predictor.is_some() { EvaluatorType::NEURAL } else { EvaluatorType::CLASSICAL },
+            nn_predictor,
+            nnue_network: None,
+            value_calibration,
+            book: None,
+            use_book: false,
+            tablebase: None,
+            chess960: false,
         }
     }
 
+    pub fn set_evaluator_type(&mut self, evaluator_type: EvaluatorType) -> Result<(), SimpleError> {
+        if evaluator_type == EvaluatorType::NEURAL && self.nn_predictor.is_none() {
+            bail!("No neural network model is loaded");
+        }
+        if evaluator_type == EvaluatorType::NNUE && self.nnue_network.is_none() {
+            bail!("No NNUE network is loaded (set EvalFile first)");
+        }
+        self.evaluator_type = evaluator_type;
+        Ok(())
+    }
+
+    /// Loads the NNUE weights used by the NNUE evaluator, or removes the current network if the path is empty (in which
+    /// case the NNUE evaluator falls back to the classical one)
+    pub fn set_eval_file(&mut self, eval_file: &str) -> Result<(), SimpleError> {
+        if eval_file.is_empty() || eval_file == "<empty>" {
+            self.nnue_network = None;
+            return Ok(());
+        }
+        self.nnue_network = Some(Arc::new(NNUENetwork::load(Path::new(eval_file))?));
+        Ok(())
+    }
+
+    /// Loads the Polyglot opening book to use when use_book is set, or removes the current book if the path is empty
+    pub fn set_book_file(&mut self, book_path: &str) -> Result<(), SimpleError> {
+        if book_path.is_empty() || book_path == "<empty>" {
+            self.book = None;
+            return Ok(());
+        }
+        self.book = Some(PolyglotBook::load(Path::new(book_path))?);
+        Ok(())
+    }
+
+    /// Returns a weighted random move from the opening book for the current position, if there is one
+    pub fn get_book_move(&self) -> Option<GameMove> {
+        if !self.use_book { return None; }
+        self.book.as_ref()?.choose_move(self.position.as_ref()?, &mut thread_rng())
+    }
+
+    /// Finds the Syzygy tablebases in the given folders (separated as in the PATH variable), or removes the current
+    /// tablebases if the path is empty
+    pub fn set_syzygy_path(&mut self, syzygy_path: &str) -> Result<(), SimpleError> {
+        if syzygy_path.is_empty() || syzygy_path == "<empty>" {
+            self.tablebase = None;
+            return Ok(());
+        }
+        self.tablebase = Some(SyzygyTablebase::init(syzygy_path)?);
+        Ok(())
+    }
+
+    /// Returns the best moves for the current position according to the tablebases, if it has few enough pieces
+    pub fn get_tablebase_moves(&mut self) -> Option<Vec<TablebaseRootMove>> {
+        let position = self.position.as_ref()?;
+        let tablebase = self.tablebase.as_mut()?;
+        if !tablebase.can_probe(position) { return None; }
+
+        match tablebase.probe_root(position) {
+            Ok(root_moves) if !root_moves.is_empty() => Some(root_moves),
+            Ok(_) => None,
+            Err(e) => {
+                println!("info string {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn get_tb_hits(&self) -> u64 {
+        self.tablebase.as_ref().map_or(0, |tablebase| tablebase.tb_hits)
+    }
+
+    /// Returns the score (and win / draw / loss permille) for a tablebase move, where cursed wins and blessed losses
+    /// count as draws since the fifty move rule applies
+    pub fn calc_tablebase_move_score(&self, root_move: &TablebaseRootMove) -> (EngineScore, (u32, u32, u32)) {
+        let position = self.position.as_ref().unwrap();
+        if root_move.wdl == SyzygyWDL::WIN && EngineController::is_checkmating_move(position, &root_move.game_move) {
+            return (EngineScore::MATE(1), (1000, 0, 0));
+        }
+
+        let wdl = match root_move.wdl {
+            SyzygyWDL::WIN => (1000, 0, 0),
+            SyzygyWDL::LOSS => (0, 0, 1000),
+            _ => (0, 1000, 0),
+        };
+        (EngineScore::CENTIPAWNS(root_move.calc_score()), wdl)
+    }
+
     pub fn init_new_game(&mut self) {
-        self.nn_predictor.init_new_game();
+        if let Some(nn_predictor) = self.nn_predictor.as_mut() {
+            nn_predictor.init_new_game();
+        }
     }
 
-    pub fn init_position(&mut self, fen_str: Option<&str>) {
-        self.position = Some(Position::from_fen(fen_str, false).expect("Invalid FEN string"));
+    // Positions from the GUI must be reachable in a legal game, but castling rights and en passant squares that don't fit
+    // the position are dropped rather than rejected.  If the FEN is invalid, no position is left set (rather than the
+    // previous one) so that nothing is searched until a valid position is given
+    pub fn init_position(&mut self, fen_str: Option<&str>) -> Result<(), FenError> {
+        self.position = None;
+        let fen_str = fen_str.unwrap_or(START_POSITION);
+        let mut position = if self.chess960 { FenValidator::parse_chess960(fen_str, true)? } else { FenValidator::parse(fen_str, true)? };
+        position.chess960 |= self.chess960;
+        self.position = Some(position);
+        Ok(())
     }
 
-    /// Returns the top K best moves for teh given input position
-    pub fn get_best_moves(&mut self) -> ([(Option<GameMove>, f32); TOP_K_OUTPUTS], f32) {
-        let mut pos = self.position.as_mut().unwrap();
-        self.nn_predictor.make_prediction(&mut pos)
+    /// Returns the UCI string for a move in the current position (castling is given as king takes rook in Chess960)
+    pub fn get_uci_move_string(&self, game_move: &GameMove) -> String {
+        match self.position.as_ref() {
+            Some(position) => position.get_uci_move_string(game_move),
+            None => game_move.get_uci_move_string(),
+        }
+    }
+
+    /// Returns every legal move in the current position with its probability (best first), along with the probability
+    /// of white winning (i.e. white's expected score)
+    pub fn get_best_moves(&mut self) -> NNPolicyPrediction {
+        let pos = self.position.as_mut().unwrap();
+        match (self.evaluator_type, self.nn_predictor.as_mut(), self.nnue_network.as_ref()) {
+            (EvaluatorType::NEURAL, Some(nn_predictor), _) => match nn_predictor.make_policy_prediction(pos) {
+                Ok(prediction) => prediction,
+                Err(e) => {
+                    println!("info string {} - using the classical evaluator", e);
+                    EngineController::get_classical_best_moves(pos, &self.value_calibration)
+                }
+            },
+            (EvaluatorType::NNUE, _, Some(nnue_network)) => EngineController::get_nnue_best_moves(pos, nnue_network, &self.value_calibration),
+            _ => EngineController::get_classical_best_moves(pos, &self.value_calibration),
+        }
 
         // // TODO: save random move logic as an engine option
         // let mut move_list = GameMoveList::default();
@@ -41,7 +224,289 @@ impl EngineController {
         // format!("{:?}", move_list.move_list[random_move_index])
     }
 
+    /// Returns the classical evaluation breakdown of the current position
+    pub fn get_evaluation_trace(&self) -> Option<EvaluationTrace> {
+        self.position.as_ref().map(PositionEvaluator::evaluate_with_trace)
+    }
+
+    /// Scores every legal move with a 1-ply search using the classical evaluator, converting the scores into
+    /// the same (move probability, win probability) form as the neural network outputs
+    fn get_classical_best_moves(position: &mut Position, value_calibration: &ValueCalibration) -> NNPolicyPrediction {
+        let scored_moves = PositionEvaluator::score_legal_moves(position);
+        EngineController::convert_scored_moves(position, &scored_moves, value_calibration)
+    }
+
+    /// Same as get_classical_best_moves(), but evaluated by the NNUE network, whose accumulator is updated incrementally
+    /// as each move is made and unmade
+    fn get_nnue_best_moves(position: &mut Position, nnue_network: &Arc<NNUENetwork>, value_calibration: &ValueCalibration) -> NNPolicyPrediction {
+        let mut move_maker = MoveMaker::default();
+        move_maker.nnue_accumulator = Some(NNUEAccumulator::new(nnue_network.clone(), position));
+        let scored_moves = PositionEvaluator::score_legal_moves_with(position, &mut move_maker,
+            |move_maker, next_position| move_maker.nnue_accumulator.as_ref().unwrap().evaluate(next_position.white_to_move));
+        EngineController::convert_scored_moves(position, &scored_moves, value_calibration)
+    }
+
+    // Converts moves scored in centipawns (best first) into the same (move probability, win probability) form as the
+    // neural network outputs
+    fn convert_scored_moves(position: &Position, scored_moves: &[(GameMove, i32)], value_calibration: &ValueCalibration) -> NNPolicyPrediction {
+        let to_white_score = |score: f32| if position.white_to_move { score } else { 1.0 - score };
+        if scored_moves.is_empty() {
+            return (vec![], to_white_score(if position.is_checkmate { 0.0 } else { 0.5 }));
+        }
+
+        // Softmax over the scores in pawns, so a move a pawn worse is e times less likely
+        let best_score = scored_moves[0].1;
+        let weights: Vec<f32> = scored_moves.iter().map(|(_, score)| ((score - best_score) as f32 / 100.0).exp()).collect();
+        let total_weight: f32 = weights.iter().sum();
+        let best_moves = scored_moves.iter().zip(weights).map(|((game_move, _), weight)| (*game_move, weight / total_weight)).collect();
+
+        let expected_score = if best_score >= MATE_SCORE { 1.0 } else { value_calibration.calc_expected_score(best_score as f32) };
+        (best_moves, to_white_score(expected_score))
+    }
+
+    /// Scores each of the given moves in the current position by evaluating the position after it, so that every candidate
+    /// gets its own score (and win / draw / loss permille) for the side to move
+    /// The neural network evaluates all of the moves in one batch, and should have just made a prediction for the current
+    /// position so that its move history is up to date
+    pub fn calc_move_scores(&mut self, game_moves: &[GameMove]) -> Vec<(EngineScore, (u32, u32, u32))> {
+        let position = self.position.as_ref().unwrap();
+        if let (EvaluatorType::NEURAL, Some(nn_predictor)) = (self.evaluator_type, self.nn_predictor.as_mut()) {
+            match nn_predictor.make_move_value_predictions(position, game_moves) {
+                Ok(win_probabilities) => return game_moves.iter().zip(win_probabilities)
+                    .map(|(game_move, win_probability)| self.calc_move_score(game_move, win_probability))
+                    .collect(),
+                Err(e) => println!("info string {} - using the classical evaluator", e),
+            }
+        }
+
+        let mut move_maker = MoveMaker::default();
+        game_moves.iter().map(|game_move| {
+            let mut next_position = position.clone();
+            move_maker.make_move(&mut next_position, game_move, false);
+            PositionAnalyzer::calc_legal_moves(&mut next_position, &mut GameMoveList::default());
+            if next_position.is_checkmate {
+                return (EngineScore::MATE(1), (1000, 0, 0));
+            }
+
+            let cp = if next_position.is_stalemate || next_position.fifty_move_count >= 100 {
+                0
+            } else {
+                match (self.evaluator_type, self.nnue_network.as_ref()) {
+                    (EvaluatorType::NNUE, Some(nnue_network)) => -NNUEAccumulator::new(nnue_network.clone(), &next_position).evaluate(next_position.white_to_move),
+                    _ => -PositionEvaluator::evaluate(&next_position),
+                }
+            };
+            (EngineScore::CENTIPAWNS(cp), self.value_calibration.calc_wdl_permille(cp as f32))
+        }).collect()
+    }
+
+    /// Converts white's win probability into a calibrated score (and win / draw / loss permille) for the side
+    /// to move after the given move, with moves that deliver checkmate scored as a mate in 1
+    pub fn calc_move_score(&self, game_move: &GameMove, win_probability: f32) -> (EngineScore, (u32, u32, u32)) {
+        let position = self.position.as_ref().unwrap();
+        if EngineController::is_checkmating_move(position, game_move) {
+            return (EngineScore::MATE(1), (1000, 0, 0));
+        }
+
+        let expected_score = if position.white_to_move { win_probability } else { 1.0 - win_probability };
+        let cp = self.value_calibration.calc_centipawns(expected_score);
+        (EngineScore::CENTIPAWNS(cp), self.value_calibration.calc_wdl_permille(cp as f32))
+    }
+
+    fn is_checkmating_move(position: &Position, game_move: &GameMove) -> bool {
+        let mut next_position = position.clone();
+        MoveMaker::default().make_move(&mut next_position, game_move, false);
+        PositionAnalyzer::calc_legal_moves(&mut next_position, &mut GameMoveList::default());
+        next_position.is_checkmate
+    }
+
+    /// Resets the statistics reported during a search (e.g. tbhits), which are counted per 'go' command
+    pub fn start_search(&mut self) {
+        if let Some(tablebase) = self.tablebase.as_mut() {
+            tablebase.tb_hits = 0;
+        }
+    }
+
     pub fn stop_search(&self) {
 
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::polyglot::PolyglotBookBuilder;
+    use crate::neural::nnue::*;
+    use super::*;
+
+    #[test]
+    fn test_init_without_model() {
+        let mut engine = EngineController::init(PathBuf::from("src/test/resources/missing_model"));
+        assert!(engine.nn_predictor.is_none());
+        assert_eq!(engine.evaluator_type, EvaluatorType::CLASSICAL);
+        assert!(engine.set_evaluator_type(EvaluatorType::NEURAL).is_err());
+
+        engine.init_position(Some("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1")).unwrap();
+        let (best_moves, win_probability) = engine.get_best_moves();
+        assert_eq!(best_moves[0].0.get_uci_move_string(), "d2d5");
+        assert!(best_moves[0].1 > 0.99);
+        assert!(win_probability > 0.5);
+
+        // Every legal move is returned, not only the top K
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(engine.position.as_mut().unwrap(), &mut move_list);
+        assert!(move_list.list_len > TOP_K_OUTPUTS);
+        assert_eq!(best_moves.len(), move_list.list_len);
+
+        // The win probability is always from white's point of view
+        engine.init_position(Some("4k3/8/8/3Q4/8/8/3r4/4K3 b - - 0 1")).unwrap();
+        let (best_moves, win_probability) = engine.get_best_moves();
+        assert_eq!(best_moves[0].0.get_uci_move_string(), "d2d5");
+        assert!(win_probability < 0.5);
+        match engine.calc_move_score(&best_moves[0].0, win_probability) {
+            (EngineScore::CENTIPAWNS(cp), (win, draw, loss)) => {
+                assert!(cp > 0);
+                assert!(win > loss);
+                assert_eq!(win + draw + loss, 1000);
+            },
+            _ => panic!("Expected a centipawn score"),
+        }
+
+        // An invalid FEN clears the previous position, while an empty one is the start position
+        assert!(engine.init_position(Some("4k3/8/8/8 w - - 0 1")).is_err());
+        assert!(engine.position.is_none());
+        engine.init_position(Some(" ")).unwrap();
+        assert_eq!(engine.position.as_ref().unwrap().to_fen(), START_POSITION);
+    }
+
+    #[test]
+    fn test_calc_move_scores() {
+        let mut engine = EngineController::init(PathBuf::from("src/test/resources/missing_model"));
+        engine.init_position(Some("6k1/5ppp/8/7q/8/8/7R/R5K1 w - - 0 1")).unwrap();
+        let (best_moves, _) = engine.get_best_moves();
+        let game_moves: Vec<GameMove> = best_moves.iter().take(TOP_K_OUTPUTS).map(|(game_move, _)| *game_move).collect();
+        let move_scores = engine.calc_move_scores(&game_moves);
+        assert_eq!(move_scores.len(), TOP_K_OUTPUTS);
+
+        // The mate, winning the queen and the moves that follow all get their own scores
+        assert_eq!(game_moves[0].get_uci_move_string(), "a1a8");
+        assert_eq!(move_scores[0], (EngineScore::MATE(1), (1000, 0, 0)));
+        assert_eq!(game_moves[1].get_uci_move_string(), "h2h5");
+        let cp = |score: EngineScore| match score { EngineScore::CENTIPAWNS(cp) => cp, _ => panic!("Expected a centipawn score") };
+        assert!(cp(move_scores[1].0) > 500);
+        assert!(cp(move_scores[2].0) < cp(move_scores[1].0));
+        assert!(move_scores[1].1.0 > move_scores[2].1.0);
+    }
+
+    #[test]
+    fn test_nnue_evaluator() {
+        let mut engine = EngineController::init(PathBuf::from("src/test/resources/missing_model"));
+        assert!(engine.set_evaluator_type(EvaluatorType::NNUE).is_err());
+        assert!(engine.set_eval_file("src/test/resources/missing.nnue").is_err());
+
+        // With every weight zero, all positions evaluate the same so only the mate stands out
+        engine.nnue_network = Some(Arc::new(NNUENetwork {
+            ft_biases: vec![0; NNUE_L1],
+            ft_weights: vec![0; NNUE_FEATURES * NNUE_L1],
+            l1_biases: vec![0; NNUE_L2],
+            l1_weights: vec![0; NNUE_L2 * 2 * NNUE_L1],
+            l2_biases: vec![0; NNUE_L3],
+            l2_weights: vec![0; NNUE_L3 * NNUE_L2],
+            output_bias: 0,
+            output_weights: vec![0; NNUE_L3],
+        }));
+        engine.set_evaluator_type(EvaluatorType::NNUE).unwrap();
+        engine.init_position(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1")).unwrap();
+        let (best_moves, win_probability) = engine.get_best_moves();
+        assert_eq!(best_moves[0].0.get_uci_move_string(), "a1a8");
+        assert_eq!(win_probability, 1.0);
+
+        // Without a network, the classical evaluator is used instead
+        engine.set_eval_file("<empty>").unwrap();
+        engine.init_position(Some("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1")).unwrap();
+        assert_eq!(engine.get_best_moves().0[0].0.get_uci_move_string(), "d2d5");
+    }
+
+    #[test]
+    fn test_book_move() {
+        let mut book_path = std::env::temp_dir();
+        book_path.push("my_chess_ql_test_book.bin");
+        let mut builder = PolyglotBookBuilder::new(20, 1, 2, 1, 0);
+        builder.add_pgn_file(Path::new("src/test/resources/TestMoveHistoryPGN.pgn"));
+        builder.build().save(&book_path).unwrap();
+
+        let mut engine = EngineController::init(PathBuf::from("src/test/resources/missing_model"));
+        engine.set_book_file(book_path.to_str().unwrap()).unwrap();
+        engine.init_position(None).unwrap();
+        assert!(engine.get_book_move().is_none());
+
+        engine.use_book = true;
+        assert_eq!(engine.get_book_move().unwrap().get_uci_move_string(), "d2d4");
+        engine.init_position(Some("4k3/8/8/8/8/8/8/4K3 w - - 0 1")).unwrap();
+        assert!(engine.get_book_move().is_none());
+
+        assert!(engine.set_book_file("src/test/resources/missing_book.bin").is_err());
+        engine.set_book_file("<empty>").unwrap();
+        assert!(engine.book.is_none());
+    }
+
+    #[test]
+    fn test_mate_score() {
+        let mut engine = EngineController::init(PathBuf::from("src/test/resources/missing_model"));
+        engine.init_position(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1")).unwrap();
+        let (best_moves, win_probability) = engine.get_best_moves();
+        let (score, wdl) = engine.calc_move_score(&best_moves[0].0, win_probability);
+        assert_eq!(score, EngineScore::MATE(1));
+        assert_eq!(score.to_uci_string(), "mate 1");
+        assert_eq!(wdl, (1000, 0, 0));
+        assert_eq!(EngineScore::CENTIPAWNS(-35).to_uci_string(), "cp -35");
+    }
+
+    #[test]
+    fn test_tablebase_moves() {
+        let mut engine = EngineController::init(PathBuf::from("src/test/resources/missing_model"));
+        engine.init_position(Some("8/8/8/8/8/3k4/1p6/K7 w - - 0 1")).unwrap();
+        assert!(engine.get_tablebase_moves().is_none());
+
+        // A KNvK table in which every position is a draw (the header, piece order and a single value for each side)
+        let syzygy_dir = std::env::temp_dir().join("my_chess_ql_test_engine_syzygy");
+        std::fs::create_dir_all(&syzygy_dir).unwrap();
+        std::fs::write(syzygy_dir.join("KNvK.rtbw"), [0x71, 0xE8, 0x23, 0x5D, 0x01, 0x00, 0x66, 0x22, 0xEE, 0x00, 0x80, 0x02, 0x80, 0x02]).unwrap();
+        engine.set_syzygy_path(syzygy_dir.to_str().unwrap()).unwrap();
+        assert!(engine.get_tablebase_moves().is_none());
+
+        engine.init_position(Some("8/8/8/8/8/3k4/8/KN6 w - - 0 1")).unwrap();
+        let root_moves = engine.get_tablebase_moves().unwrap();
+        assert_eq!(root_moves.len(), 5);
+        assert_eq!(engine.calc_tablebase_move_score(&root_moves[0]), (EngineScore::CENTIPAWNS(0), (0, 1000, 0)));
+        assert!(engine.get_tb_hits() > 0);
+        engine.start_search();
+        assert_eq!(engine.get_tb_hits(), 0);
+
+        assert!(engine.set_syzygy_path("src/test/resources/missing_tablebases").is_err());
+        engine.set_syzygy_path("<empty>").unwrap();
+        assert!(engine.tablebase.is_none());
+    }
+
+    #[test]
+    fn test_chess960_position() {
+        // Without UCI_Chess960, KQkq need the kings on the e-file so they are dropped here
+        let fen = "1r4kr/8/8/8/8/8/8/1R4KR w KQkq - 0 1";
+        let mut engine = EngineController::init(PathBuf::from("src/test/resources/missing_model"));
+        engine.init_position(Some(fen)).unwrap();
+        assert_eq!(engine.position.as_ref().unwrap().castling_rights, 0);
+
+        engine.chess960 = true;
+        engine.init_position(Some(fen)).unwrap();
+        let position = engine.position.as_mut().unwrap();
+        assert_eq!(position.to_fen(), fen);
+
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+        let position = position.clone();
+        let castling_moves: Vec<String> = move_list.moves().iter()
+            .filter(|m| m.is_castle())
+            .map(|m| engine.get_uci_move_string(&m.to_game_move(&position)))
+            .collect();
+        assert_eq!(castling_moves, vec!["g1b1", "g1h1"]);
+    }
+}
@@ -0,0 +1,445 @@
+use std::cmp::Reverse;
+use std::fmt::{Display, Formatter, Result};
+use crate::constants::*;
+use crate::game::analysis::kingattackrayanalyzer::KingAttackRayAnalyzer;
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::pieces::bishop::Bishop;
+use crate::game::pieces::knight::Knight;
+use crate::game::pieces::pawn::Pawn;
+use crate::game::pieces::piece::*;
+use crate::game::pieces::queen::Queen;
+use crate::game::pieces::rook::Rook;
+use crate::game::position::Position;
+
+/// Score given to a checkmate, well above anything the evaluation terms can add up to
+pub const MATE_SCORE: i32 = 30000;
+
+// Piece values in centipawns (pawn, knight, bishop, rook, queen)
+const PIECE_VALUES: [i32; 5] = [100, 320, 330, 500, 900];
+
+// Once both sides are down to this much non-pawn material (e.g. rook + minor piece), the king
+// should head for the centre rather than hide behind its pawns
+const ENDGAME_MATERIAL: i32 = 1300;
+
+// (weight per square, number of squares considered 'average' mobility) for knights, bishops, rooks, queens
+const MOBILITY_WEIGHTS: [(i32, i32); 4] = [(4, 4), (5, 7), (2, 7), (1, 14)];
+
+const DOUBLED_PAWN_PENALTY: i32 = -15;
+const ISOLATED_PAWN_PENALTY: i32 = -15;
+// Indexed by the rank of the pawn relative to its own side (i.e. rank 7 for white = rank 2 for black)
+const PASSED_PAWN_BONUS: [i32; 8] = [0, 5, 10, 20, 35, 60, 100, 0];
+
+const PAWN_SHIELD_BONUS: [i32; 2] = [10, 5];
+const KING_ZONE_ATTACK_PENALTY: i32 = -10;
+
+// Piece-square tables from the 'Simplified Evaluation Function' on the chess programming wiki
+// They are laid out as seen from white's side of the board (i.e. the first row is rank 8), so white
+// pieces are looked up with the rank flipped (sq ^ 56) and black pieces use the square index directly
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+const KING_MIDDLEGAME_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+const KING_ENDGAME_TABLE: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+/// The evaluation terms for one side, in centipawns
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EvaluationTerms {
+    pub material: i32,
+    pub piece_squares: i32,
+    pub mobility: i32,
+    pub pawn_structure: i32,
+    pub king_safety: i32,
+}
+
+impl EvaluationTerms {
+    pub fn total(&self) -> i32 {
+        self.material + self.piece_squares + self.mobility + self.pawn_structure + self.king_safety
+    }
+}
+
+/// Breakdown of a position's evaluation into its individual terms for each side
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EvaluationTrace {
+    pub white: EvaluationTerms,
+    pub black: EvaluationTerms,
+    pub is_endgame: bool,
+}
+
+impl EvaluationTrace {
+    /// The final evaluation in centipawns from white's point of view
+    pub fn total(&self) -> i32 {
+        self.white.total() - self.black.total()
+    }
+}
+
+impl Display for EvaluationTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let rows = [
+            ("Material", self.white.material, self.black.material),
+            ("Piece squares", self.white.piece_squares, self.black.piece_squares),
+            ("Mobility", self.white.mobility, self.black.mobility),
+            ("Pawn structure", self.white.pawn_structure, self.black.pawn_structure),
+            ("King safety", self.white.king_safety, self.black.king_safety),
+        ];
+
+        // Values shown in pawns, as most UIs / engines do
+        writeln!(f, "{:>15} | {:>7} | {:>7} | {:>7}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:-<16}+{:-<9}+{:-<9}+{:-<8}", "", "", "", "")?;
+        for (name, white, black) in rows {
+            writeln!(f, "{:>15} | {:>7.2} | {:>7.2} | {:>7.2}", name, white as f32 / 100.0, black as f32 / 100.0, (white - black) as f32 / 100.0)?;
+        }
+        writeln!(f, "{:-<16}+{:-<9}+{:-<9}+{:-<8}", "", "", "", "")?;
+        writeln!(f, "{:>15} | {:>7.2} | {:>7.2} | {:>7.2}", "Total", self.white.total() as f32 / 100.0, self.black.total() as f32 / 100.0, self.total() as f32 / 100.0)?;
+        write!(f, "Game phase: {}", if self.is_endgame { "endgame" } else { "middlegame" })
+    }
+}
+
+/// Classical (handcrafted) evaluation function, used when no neural network model is available
+pub struct PositionEvaluator {
+
+}
+
+impl PositionEvaluator {
+    /// Evaluates the position in centipawns from the point of view of the side to move
+    pub fn evaluate(position: &Position) -> i32 {
+        let score = PositionEvaluator::evaluate_with_trace(position).total();
+        if position.white_to_move { score } else { -score }
+    }
+
+    /// Evaluates the position, keeping the individual evaluation terms for each side
+    pub fn evaluate_with_trace(position: &Position) -> EvaluationTrace {
+        // The attack generators limit attacks to the check ray when the side to move is in check, so
+        // work from a copy without any check restriction to see every square each piece controls
+        let mut attack_position = position.clone();
+        attack_position.check_ray_mask = u64::MAX;
+        attack_position.en_passant_sq = 0;
+        attack_position.white_occupancy = position.wp | position.wn | position.wb | position.wr | position.wq | position.wk;
+        attack_position.black_occupancy = position.bp | position.bn | position.bb | position.br | position.bq | position.bk;
+        attack_position.all_occupancy = attack_position.white_occupancy | attack_position.black_occupancy;
+        attack_position.non_occupancy = !attack_position.all_occupancy;
+
+        let white_pieces = [position.wp, position.wn, position.wb, position.wr, position.wq, position.wk];
+        let black_pieces = [position.bp, position.bn, position.bb, position.br, position.bq, position.bk];
+
+        let is_endgame = PositionEvaluator::calc_non_pawn_material(&white_pieces) <= ENDGAME_MATERIAL
+            && PositionEvaluator::calc_non_pawn_material(&black_pieces) <= ENDGAME_MATERIAL;
+
+        let white_pawn_attacks = Pawn::calc_attacked_squares(&attack_position, position.wp, &PlayerColour::WHITE, 0, &mut KingAttackRayAnalyzer::default());
+        let black_pawn_attacks = Pawn::calc_attacked_squares(&attack_position, position.bp, &PlayerColour::BLACK, 0, &mut KingAttackRayAnalyzer::default());
+
+        let mut white = EvaluationTerms::default();
+        let mut black = EvaluationTerms::default();
+
+        white.material = PositionEvaluator::calc_material(&white_pieces);
+        black.material = PositionEvaluator::calc_material(&black_pieces);
+        white.piece_squares = PositionEvaluator::calc_piece_squares(&white_pieces, &PlayerColour::WHITE, is_endgame);
+        black.piece_squares = PositionEvaluator::calc_piece_squares(&black_pieces, &PlayerColour::BLACK, is_endgame);
+
+        let (white_mobility, white_attacks) = PositionEvaluator::calc_mobility(&attack_position, &white_pieces, &PlayerColour::WHITE, attack_position.white_occupancy, black_pawn_attacks);
+        let (black_mobility, black_attacks) = PositionEvaluator::calc_mobility(&attack_position, &black_pieces, &PlayerColour::BLACK, attack_position.black_occupancy, white_pawn_attacks);
+        white.mobility = white_mobility;
+        black.mobility = black_mobility;
+
+        white.pawn_structure = PositionEvaluator::calc_pawn_structure(position.wp, position.bp, &PlayerColour::WHITE);
+        black.pawn_structure = PositionEvaluator::calc_pawn_structure(position.bp, position.wp, &PlayerColour::BLACK);
+
+        // King safety only matters while there is enough material left on the board to mount an attack
+        if !is_endgame {
+            white.king_safety = PositionEvaluator::calc_king_safety(position.wk, position.wp, black_attacks | black_pawn_attacks, &PlayerColour::WHITE);
+            black.king_safety = PositionEvaluator::calc_king_safety(position.bk, position.bp, white_attacks | white_pawn_attacks, &PlayerColour::BLACK);
+        }
+
+        EvaluationTrace { white, black, is_endgame }
+    }
+
+    /// Scores every legal move with a 1-ply search, returning the moves sorted from best to worst
+    /// Scores are in centipawns from the point of view of the side to move (MATE_SCORE for a mating move)
+    pub fn score_legal_moves(position: &mut Position) -> Vec<(GameMove, i32)> {
+        PositionEvaluator::score_legal_moves_with(position, &mut MoveMaker::default(), |_, next_position| PositionEvaluator::evaluate(next_position))
+    }
+
+    /// Same as score_legal_moves(), but evaluates the position after each move with the given function instead (from the
+    /// point of view of its side to move), which also gets the move maker so it can use e.g. its NNUE accumulator
+    pub fn score_legal_moves_with<F: Fn(&MoveMaker, &Position) -> i32>(position: &mut Position, move_maker: &mut MoveMaker, evaluate: F) -> Vec<(GameMove, i32)> {
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+
+        let mut scored_moves: Vec<(GameMove, i32)> = Vec::with_capacity(move_list.list_len);
+        for compact_move in move_list.moves().iter() {
+            let mut next_position = position.clone();
+            move_maker.make_compact_move(&mut next_position, *compact_move, true);
+
+            // The legal moves are needed to detect mate / stalemate for the opponent
+            let mut next_move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves(&mut next_position, &mut next_move_list);
+
+            let score = if next_position.is_checkmate {
+                MATE_SCORE
+            } else if next_position.is_stalemate || next_position.fifty_move_count >= 100 {
+                0
+            } else {
+                -evaluate(move_maker, &next_position)
+            };
+            move_maker.unmake_move(&mut next_position);
+            scored_moves.push((compact_move.to_game_move(position), score));
+        }
+
+        // Stable sort so that equal moves stay in move generation order
+        scored_moves.sort_by_key(|(_, score)| Reverse(*score));
+        scored_moves
+    }
+
+    fn calc_non_pawn_material(pieces: &[u64; 6]) -> i32 {
+        (1..5).map(|i| pieces[i].count_ones() as i32 * PIECE_VALUES[i]).sum()
+    }
+
+    fn calc_material(pieces: &[u64; 6]) -> i32 {
+        (0..5).map(|i| pieces[i].count_ones() as i32 * PIECE_VALUES[i]).sum()
+    }
+
+    fn calc_piece_squares(pieces: &[u64; 6], player: &PlayerColour, is_endgame: bool) -> i32 {
+        let king_table = if is_endgame { &KING_ENDGAME_TABLE } else { &KING_MIDDLEGAME_TABLE };
+        let tables = [&PAWN_TABLE, &KNIGHT_TABLE, &BISHOP_TABLE, &ROOK_TABLE, &QUEEN_TABLE, king_table];
+        let flip = match player { PlayerColour::WHITE => 56, PlayerColour::BLACK => 0 };
+
+        let mut score = 0;
+        for (mut piece_pos, table) in pieces.iter().copied().zip(tables) {
+            while piece_pos > 0 {
+                let sq_ind = piece_pos.trailing_zeros() as usize;
+                score += table[sq_ind ^ flip];
+                piece_pos &= piece_pos - 1;
+            }
+        }
+        score
+    }
+
+    /// Returns the mobility score, based on the number of safe squares each piece can move to,
+    /// and all squares attacked by the knights, bishops, rooks & queens
+    fn calc_mobility(position: &Position, pieces: &[u64; 6], player: &PlayerColour, own_occupancy: u64, enemy_pawn_attacks: u64) -> (i32, u64) {
+        let safe_squares = !own_occupancy & !enemy_pawn_attacks;
+        let mut score = 0;
+        let mut all_attacks = 0u64;
+
+        for i in 0..4 {
+            let mut piece_pos = pieces[i + 1];
+            while piece_pos > 0 {
+                let single_piece = SINGLE_BITBOARDS[piece_pos.trailing_zeros() as usize];
+                let attacks = PositionEvaluator::calc_piece_attacks(position, i + 1, single_piece, player);
+                let (weight, baseline) = MOBILITY_WEIGHTS[i];
+                score += weight * ((attacks & safe_squares).count_ones() as i32 - baseline);
+                all_attacks |= attacks;
+                piece_pos &= piece_pos - 1;
+            }
+        }
+        (score, all_attacks)
+    }
+
+    fn calc_piece_attacks(position: &Position, piece_index: usize, piece_pos: u64, player: &PlayerColour) -> u64 {
+        let mut king_attack_analyzer = KingAttackRayAnalyzer::default();
+        match piece_index {
+            1 => Knight::calc_attacked_squares(position, piece_pos, player, 0, &mut king_attack_analyzer),
+            2 => Bishop::calc_attacked_squares(position, piece_pos, player, 0, &mut king_attack_analyzer),
+            3 => Rook::calc_attacked_squares(position, piece_pos, player, 0, &mut king_attack_analyzer),
+            _ => Queen::calc_attacked_squares(position, piece_pos, player, 0, &mut king_attack_analyzer),
+        }
+    }
+
+    /// Penalises doubled & isolated pawns and rewards passed pawns based on how far advanced they are
+    fn calc_pawn_structure(own_pawns: u64, enemy_pawns: u64, player: &PlayerColour) -> i32 {
+        let mut score = 0;
+
+        for file in FILES[..8].iter() {
+            let pawns_on_file = (own_pawns & file).count_ones() as i32;
+            if pawns_on_file > 1 {
+                score += DOUBLED_PAWN_PENALTY * (pawns_on_file - 1);
+            }
+        }
+
+        let mut pawns = own_pawns;
+        while pawns > 0 {
+            let sq_ind = pawns.trailing_zeros() as usize;
+            let file_mask = FILES[sq_ind];
+            let adjacent_files = ((file_mask & !H_FILE) << 1) | ((file_mask & !A_FILE) >> 1);
+
+            if own_pawns & adjacent_files == 0 {
+                score += ISOLATED_PAWN_PENALTY;
+            }
+
+            // All squares in front of the pawn (from its own side's point of view) on its own & adjacent files
+            let rank = sq_ind / 8;
+            let (squares_ahead, relative_rank) = match player {
+                PlayerColour::WHITE => (u64::MAX.checked_shl(((rank + 1) * 8) as u32).unwrap_or(0), rank),
+                PlayerColour::BLACK => ((1u64 << (rank * 8)) - 1, 7 - rank),
+            };
+            if enemy_pawns & (file_mask | adjacent_files) & squares_ahead == 0 {
+                score += PASSED_PAWN_BONUS[relative_rank];
+            }
+
+            pawns &= pawns - 1;
+        }
+        score
+    }
+
+    /// Rewards pawns sheltering the king and penalises enemy attacks on the squares around it
+    fn calc_king_safety(king_pos: u64, own_pawns: u64, enemy_attacks: u64, player: &PlayerColour) -> i32 {
+        if king_pos == 0 { return 0; }
+        let king_sq = king_pos.trailing_zeros() as usize;
+        let file_mask = FILES[king_sq];
+        let shield_files = file_mask | ((file_mask & !H_FILE) << 1) | ((file_mask & !A_FILE) >> 1);
+
+        let mut score = 0;
+        for (distance, bonus) in PAWN_SHIELD_BONUS.iter().enumerate() {
+            let shield_rank = match player {
+                PlayerColour::WHITE => (king_sq / 8) + distance + 1,
+                PlayerColour::BLACK => (king_sq / 8).wrapping_sub(distance + 1),
+            };
+            if shield_rank < 8 {
+                score += bonus * (own_pawns & shield_files & RANKS[shield_rank * 8]).count_ones() as i32;
+            }
+        }
+
+        score + KING_ZONE_ATTACK_PENALTY * (KING_ATTACKS[king_sq] & enemy_attacks).count_ones() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_start_position() {
+        let position = Position::from_fen(None, false).unwrap();
+        let trace = PositionEvaluator::evaluate_with_trace(&position);
+        assert_eq!(trace.white, trace.black);
+        assert_eq!(trace.white.material, 8 * 100 + 2 * 320 + 2 * 330 + 2 * 500 + 900);
+        assert_eq!(PositionEvaluator::evaluate(&position), 0);
+        assert!(!trace.is_endgame);
+    }
+
+    #[test]
+    fn test_evaluate_symmetry() {
+        // The same position with the colours reversed should evaluate identically for the side to move
+        let position = Position::from_fen(Some("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"), false).unwrap();
+        let mirrored = Position::from_fen(Some("rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR b KQkq - 2 3"), false).unwrap();
+
+        let trace = PositionEvaluator::evaluate_with_trace(&position);
+        let mirrored_trace = PositionEvaluator::evaluate_with_trace(&mirrored);
+        assert_eq!(trace.white, mirrored_trace.black);
+        assert_eq!(trace.black, mirrored_trace.white);
+        assert_eq!(PositionEvaluator::evaluate(&position), PositionEvaluator::evaluate(&mirrored));
+    }
+
+    #[test]
+    fn test_evaluate_material_and_side_to_move() {
+        // White is a queen up
+        let position = Position::from_fen(Some("4k3/pppppppp/8/8/8/8/PPPPPPPP/3QK3 w - - 0 1"), false).unwrap();
+        assert!(PositionEvaluator::evaluate(&position) > 800);
+
+        let position = Position::from_fen(Some("4k3/pppppppp/8/8/8/8/PPPPPPPP/3QK3 b - - 0 1"), false).unwrap();
+        assert!(PositionEvaluator::evaluate(&position) < -800);
+    }
+
+    #[test]
+    fn test_evaluate_pawn_structure() {
+        // Isolated passed pawn on d6
+        let position = Position::from_fen(Some("4k3/8/3P4/8/8/8/8/4K3 w - - 0 1"), false).unwrap();
+        let trace = PositionEvaluator::evaluate_with_trace(&position);
+        assert_eq!(trace.white.pawn_structure, PASSED_PAWN_BONUS[5] + ISOLATED_PAWN_PENALTY);
+        assert!(trace.is_endgame);
+        assert_eq!(trace.white.king_safety, 0);
+
+        // Doubled, isolated black pawns on the c-file, blocked by the white pawn on c3 so not passed
+        let position = Position::from_fen(Some("4k3/2p5/2p5/8/8/2P5/8/4K3 w - - 0 1"), false).unwrap();
+        let trace = PositionEvaluator::evaluate_with_trace(&position);
+        assert_eq!(trace.black.pawn_structure, DOUBLED_PAWN_PENALTY + 2 * ISOLATED_PAWN_PENALTY);
+        assert_eq!(trace.white.pawn_structure, ISOLATED_PAWN_PENALTY);
+    }
+
+    #[test]
+    fn test_score_legal_moves() {
+        // Hanging queen
+        let mut position = Position::from_fen(Some("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1"), false).unwrap();
+        let scored_moves = PositionEvaluator::score_legal_moves(&mut position);
+        assert_eq!(scored_moves[0].0.get_uci_move_string(), "d2d5");
+
+        // Back rank mate
+        let mut position = Position::from_fen(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), false).unwrap();
+        let scored_moves = PositionEvaluator::score_legal_moves(&mut position);
+        assert_eq!(scored_moves[0].0.get_uci_move_string(), "a1a8");
+        assert_eq!(scored_moves[0].1, MATE_SCORE);
+    }
+}
@@ -0,0 +1,1267 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use simple_error::{bail, SimpleError};
+use crate::constants::*;
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::position::Position;
+
+// Probing follows the reference implementation used by Stockfish / Fathom (tbprobe.cpp), which is the only real
+// documentation of the Syzygy file format
+
+pub const TB_MAX_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const WDL_SUFFIX: &str = "rtbw";
+const DTZ_SUFFIX: &str = "rtbz";
+
+// Flags stored per table in the file header
+const TB_FLAG_STM: u8 = 1;
+const TB_FLAG_MAPPED: u8 = 2;
+const TB_FLAG_WIN_PLIES: u8 = 4;
+const TB_FLAG_LOSS_PLIES: u8 = 8;
+const TB_FLAG_WIDE: u8 = 16;
+const TB_FLAG_SINGLE_VALUE: u8 = 128;
+
+// Syzygy's piece codes (the same as Stockfish's): white pawn = 1 ... white king = 6, black pieces have bit 3 set
+const TB_W_PAWN: u8 = 1;
+const TB_B_PAWN: u8 = 9;
+const TB_PIECE_LETTERS: [char; 6] = ['K', 'Q', 'R', 'B', 'N', 'P'];
+
+// Winning root moves are reported with this score, less the distance to zeroing the fifty move counter
+// (below MATE_SCORE so that GUIs don't mistake it for a mate announcement)
+pub const TB_WIN_SCORE: i32 = 20000;
+// Root move rank for a win within the fifty move rule (larger than any DTZ, so that ranks of wins and losses that
+// depend on the fifty move rule never overlap)
+pub const TB_MAX_DTZ: i32 = 1 << 18;
+
+/// Win / draw / loss from the point of view of the side to move, where cursed wins and blessed losses are
+/// only won / lost if the fifty move rule is ignored
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SyzygyWDL {
+    LOSS = -2,
+    BLESSED_LOSS = -1,
+    DRAW = 0,
+    CURSED_WIN = 1,
+    WIN = 2,
+}
+
+impl SyzygyWDL {
+    fn from_value(value: i32) -> SyzygyWDL {
+        match value {
+            i32::MIN..=-2 => SyzygyWDL::LOSS,
+            -1 => SyzygyWDL::BLESSED_LOSS,
+            0 => SyzygyWDL::DRAW,
+            1 => SyzygyWDL::CURSED_WIN,
+            _ => SyzygyWDL::WIN,
+        }
+    }
+
+    /// The same result from the opponent's point of view
+    pub fn flip(&self) -> SyzygyWDL {
+        SyzygyWDL::from_value(-(*self as i32))
+    }
+
+    /// The DTZ of a winning / losing position in which the best move resets the fifty move counter
+    fn calc_dtz_before_zeroing(&self) -> i32 {
+        match self {
+            SyzygyWDL::WIN => 1,
+            SyzygyWDL::CURSED_WIN => 101,
+            SyzygyWDL::BLESSED_LOSS => -101,
+            SyzygyWDL::LOSS => -1,
+            SyzygyWDL::DRAW => 0,
+        }
+    }
+}
+
+/// A legal move at the root along with its tablebase result, from the point of view of the side to move
+#[derive(Clone, Copy, Debug)]
+pub struct TablebaseRootMove {
+    pub game_move: GameMove,
+    pub wdl: SyzygyWDL,
+    // Plies until the fifty move counter is reset by a capture / pawn move (or mate), counted from the root
+    pub dtz: i32,
+    // Higher is better: TB_MAX_DTZ for a win within the fifty move rule, -TB_MAX_DTZ for a loss, 0 for a draw and in between
+    // for a result that depends on how many moves are left before the fifty move rule applies
+    pub rank: i32,
+}
+
+impl TablebaseRootMove {
+    /// Returns the score to report for the move in centipawns (following Stockfish, so that cursed wins / blessed
+    /// losses are scored as small advantages)
+    pub fn calc_score(&self) -> i32 {
+        match self.rank {
+            r if r >= TB_MAX_DTZ - 100 => TB_WIN_SCORE - self.dtz,
+            r if r > 0 => (r - (TB_MAX_DTZ - 200)).max(3) / 2,
+            0 => 0,
+            r if r > -TB_MAX_DTZ + 100 => (r + (TB_MAX_DTZ - 200)).min(-3) / 2,
+            _ => -TB_WIN_SCORE - self.dtz,
+        }
+    }
+}
+
+/// Indexing tables shared by all tablebases
+struct SyzygyIndexTables {
+    // Encodes a square below the a1-h8 diagonal as 0..27
+    map_b1h1h7: [u64; 64],
+    // Encodes a square in the a1-d1-d4 triangle as 0..9 (with the diagonal squares last)
+    map_a1d1d4: [usize; 64],
+    // Encodes the 462 legal placements of two kings where the first is in the a1-d1-d4 triangle
+    map_kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; TB_MAX_PIECES],
+    // Encodes squares a2-h7 as 0..47, where the leading pawn has the highest value
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; TB_MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; TB_MAX_PIECES],
+}
+
+lazy_static! {
+    static ref INDEX_TABLES: SyzygyIndexTables = SyzygyIndexTables::init();
+}
+
+fn off_a1h8(sq: usize) -> i32 {
+    (sq >> 3) as i32 - (sq & 7) as i32
+}
+
+impl SyzygyIndexTables {
+    fn init() -> SyzygyIndexTables {
+        let mut tables = SyzygyIndexTables {
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; TB_MAX_PIECES],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; TB_MAX_PIECES],
+            lead_pawns_size: [[0; 4]; TB_MAX_PIECES],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 { tables.map_b1h1h7[sq] = code; code += 1; }
+        }
+
+        let mut code = 0;
+        let mut diagonal = vec![];
+        for sq in 0..=27 {
+            if sq & 7 > 3 { continue; }
+            if off_a1h8(sq) < 0 { tables.map_a1d1d4[sq] = code; code += 1; }
+            else if off_a1h8(sq) == 0 { diagonal.push(sq); }
+        }
+        for sq in diagonal {
+            tables.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // If the first king is on the a1-d4 diagonal, the other one can't be above the a1-h8 diagonal, and
+        // placements with both kings on the diagonal come last
+        let mut code = 0;
+        let mut both_on_diagonal = vec![];
+        for idx in 0..10 {
+            for sq1 in 0..=27 {
+                if sq1 & 7 > 3 || tables.map_a1d1d4[sq1] != idx || (idx == 0 && sq1 != 1) { continue; }
+                for sq2 in 0..64 {
+                    let (rank_diff, file_diff) = ((sq1 >> 3) as i32 - (sq2 >> 3) as i32, (sq1 & 7) as i32 - (sq2 & 7) as i32);
+                    if rank_diff.abs() <= 1 && file_diff.abs() <= 1 { continue; }
+                    if off_a1h8(sq1) == 0 && off_a1h8(sq2) > 0 { continue; }
+                    if off_a1h8(sq1) == 0 && off_a1h8(sq2) == 0 {
+                        both_on_diagonal.push((idx, sq2));
+                    } else {
+                        tables.map_kk[idx][sq2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, sq2) in both_on_diagonal {
+            tables.map_kk[idx][sq2] = code;
+            code += 1;
+        }
+
+        tables.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..TB_MAX_PIECES.min(n + 1) {
+                tables.binomial[k][n] = if k > 0 { tables.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { tables.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available_squares: i32 = 47;
+        for lead_pawns_count in 1..=5 {
+            for file in 0..4 {
+                // The index restarts for each file since the tables are split by the leading pawn's file
+                let mut idx = 0;
+                for rank in 1..=6 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns_count == 1 {
+                        tables.map_pawns[sq] = available_squares as usize;
+                        tables.map_pawns[sq ^ 7] = (available_squares - 1) as usize;
+                        available_squares -= 2;
+                    }
+                    tables.lead_pawn_idx[lead_pawns_count][sq] = idx;
+                    idx += tables.binomial[lead_pawns_count - 1][tables.map_pawns[sq]];
+                }
+                tables.lead_pawns_size[lead_pawns_count][file] = idx;
+            }
+        }
+
+        tables
+    }
+}
+
+fn read_u16_le(bytes: &[u8], offset: usize) -> u64 {
+    bytes.get(offset..offset + 2).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]) as u64)
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> u64 {
+    bytes.get(offset..offset + 4).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as u64)
+}
+
+fn read_u32_be(bytes: &[u8], offset: usize) -> u64 {
+    bytes.get(offset..offset + 4).map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64)
+}
+
+fn read_u64_be(bytes: &[u8], offset: usize) -> u64 {
+    (read_u32_be(bytes, offset) << 32) | read_u32_be(bytes, offset + 4)
+}
+
+/// Decoding information for one sub-table (one side to move and, for tables with pawns, one leading pawn file)
+/// All offsets are into the table's file data
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    max_sym_len: u8,
+    min_sym_len: u8,
+    num_blocks: u64,
+    sizeof_block: u64,
+    span: u64,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: u64,
+    sparse_index: usize,
+    sparse_index_size: u64,
+    data: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [u8; TB_MAX_PIECES],
+    group_idx: [u64; TB_MAX_PIECES + 1],
+    group_len: [usize; TB_MAX_PIECES + 1],
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    // Each btree entry is 3 bytes: the left-hand symbol in the first 12 bits and the right-hand symbol in the second
+    fn get_left_symbol(&self, bytes: &[u8], sym: usize) -> usize {
+        let offset = self.btree + 3 * sym;
+        (((bytes[offset + 1] & 0xF) as usize) << 8) | bytes[offset] as usize
+    }
+
+    fn get_right_symbol(&self, bytes: &[u8], sym: usize) -> usize {
+        let offset = self.btree + 3 * sym;
+        ((bytes[offset + 2] as usize) << 4) | (bytes[offset + 1] >> 4) as usize
+    }
+
+    fn calc_symlen(&mut self, bytes: &[u8], sym: usize, visited: &mut Vec<bool>) -> u8 {
+        visited[sym] = true;
+        let right = self.get_right_symbol(bytes, sym);
+        if right == 0xFFF { return 0; }
+
+        let left = self.get_left_symbol(bytes, sym);
+        for child in [left, right] {
+            if child < visited.len() && !visited[child] {
+                self.symlen[child] = self.calc_symlen(bytes, child, visited);
+            }
+        }
+        self.symlen.get(left).unwrap_or(&0).wrapping_add(*self.symlen.get(right).unwrap_or(&0)).wrapping_add(1)
+    }
+
+    /// Reads the sizes and the Huffman code for the sub-table, returning the offset after them
+    fn set_sizes(&mut self, bytes: &[u8], mut data: usize) -> Result<usize, SimpleError> {
+        if data + 2 > bytes.len() { bail!("Tablebase file is truncated"); }
+        self.flags = bytes[data];
+        data += 1;
+        if self.flags & TB_FLAG_SINGLE_VALUE > 0 {
+            // Every position has the same value, which is stored in place of the minimum symbol length
+            self.min_sym_len = bytes[data];
+            return Ok(data + 1);
+        }
+        if data + 9 > bytes.len() { bail!("Tablebase file is truncated"); }
+
+        // The group lengths are zero-terminated and the last group index is the size of the table
+        let table_size = self.group_idx[self.group_len.iter().position(|len| *len == 0).unwrap_or(TB_MAX_PIECES)];
+        self.sizeof_block = 1u64 << bytes[data];
+        self.span = 1u64 << bytes[data + 1];
+        self.sparse_index_size = table_size.div_ceil(self.span);
+        let padding = bytes[data + 2] as u64;
+        self.num_blocks = read_u32_le(bytes, data + 3);
+        self.block_length_size = self.num_blocks + padding;
+        self.max_sym_len = bytes[data + 7];
+        self.min_sym_len = bytes[data + 8];
+        data += 9;
+        if self.max_sym_len < self.min_sym_len || self.min_sym_len == 0 { bail!("Invalid tablebase symbol lengths"); }
+
+        // Canonical Huffman code: longer symbols have lower values, so base64[i] >= base64[i + 1], and any symbol of
+        // length i right-padded to 64 bits lies between base64[i] and base64[i - 1]
+        self.lowest_sym = data;
+        let sym_len_count = (self.max_sym_len - self.min_sym_len) as usize + 1;
+        self.base64 = vec![0; sym_len_count];
+        for i in (0..sym_len_count - 1).rev() {
+            self.base64[i] = self.base64[i + 1].wrapping_add(read_u16_le(bytes, self.lowest_sym + 2 * i))
+                .wrapping_sub(read_u16_le(bytes, self.lowest_sym + 2 * (i + 1))) / 2;
+        }
+        for i in 0..sym_len_count {
+            self.base64[i] = self.base64[i].checked_shl((64 - i - self.min_sym_len as usize) as u32).unwrap_or(0);
+        }
+        data += sym_len_count * 2;
+
+        // Symbols are compressed by recursive pairing, so each one expands to a pair of symbols down to the leaves
+        let symbol_count = read_u16_le(bytes, data) as usize;
+        data += 2;
+        self.btree = data;
+        if self.btree + 3 * symbol_count > bytes.len() { bail!("Tablebase file is truncated"); }
+        self.symlen = vec![0; symbol_count];
+        let mut visited = vec![false; symbol_count];
+        for sym in 0..symbol_count {
+            if !visited[sym] { self.symlen[sym] = self.calc_symlen(bytes, sym, &mut visited); }
+        }
+        Ok(data + symbol_count * 3 + (symbol_count & 1))
+    }
+
+    /// Sets the group lengths and the index multiplier for each group of pieces
+    fn set_groups(&mut self, table: &TableInfo, order: [u8; 2], file: usize) {
+        // The leading group is the kings plus a unique piece if there is one, or the leading pawns
+        let mut first_len: i32 = if table.has_pawns { 0 } else if table.has_unique_pieces { 3 } else { 2 };
+        let mut n = 0;
+        self.group_len[0] = 1;
+        for i in 1..table.piece_count {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // The groups are encoded as g1 * N(g2) * N(g3) + g2 * N(g3) + g3 in the order given in the file, with the
+        // leading group at order[0] and the remaining pawns (when both sides have pawns) at order[1]
+        let tables = &*INDEX_TABLES;
+        let both_sides_have_pawns = table.has_pawns && table.pawn_count[1] > 0;
+        let mut next = if both_sides_have_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - self.group_len[0] - if both_sides_have_pawns { self.group_len[1] } else { 0 };
+        let mut idx = 1u64;
+        let mut k = 0u8;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                self.group_idx[0] = idx;
+                idx *= if table.has_pawns { tables.lead_pawns_size[self.group_len[0]][file] }
+                    else if table.has_unique_pieces { 31332 } else { 462 };
+            } else if k == order[1] {
+                self.group_idx[1] = idx;
+                idx *= tables.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= tables.binomial[self.group_len[next]][free_squares];
+                free_squares -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_idx[n] = idx;
+    }
+
+    /// Returns the value stored at the given index, decompressing the block that contains it
+    fn decompress(&self, bytes: &[u8], idx: u64) -> Result<i32, SimpleError> {
+        if self.flags & TB_FLAG_SINGLE_VALUE > 0 { return Ok(self.min_sym_len as i32); }
+
+        // The sparse index gives the block (and offset within it) of every span-th value, so start from the nearest
+        // one and step through the block lengths (each block holds block_length + 1 values)
+        let k = (idx / self.span) as usize;
+        let mut block = read_u32_le(bytes, self.sparse_index + 6 * k) as i64;
+        let mut offset = read_u16_le(bytes, self.sparse_index + 6 * k + 4) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        let block_length = |block: i64| read_u16_le(bytes, self.block_length + 2 * block as usize) as i64;
+        while offset < 0 {
+            block -= 1;
+            if block < 0 { bail!("Corrupt tablebase sparse index"); }
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+            if block as u64 >= self.block_length_size { bail!("Corrupt tablebase sparse index"); }
+        }
+
+        // Read the Huffman symbols from the start of the block until reaching the one that contains the offset
+        let mut ptr = self.data + block as usize * self.sizeof_block as usize;
+        let mut buf64 = read_u64_be(bytes, ptr);
+        ptr += 8;
+        let mut buf64_size = 64;
+        let min_sym_len = self.min_sym_len as usize;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf64 < self.base64[len] { len += 1; }
+            sym = ((buf64 - self.base64[len]) >> (64 - len - min_sym_len)) as usize;
+            sym += read_u16_le(bytes, self.lowest_sym + 2 * len) as usize;
+            if sym >= self.symlen.len() { bail!("Corrupt tablebase block"); }
+            if offset < self.symlen[sym] as i64 + 1 { break; }
+
+            offset -= self.symlen[sym] as i64 + 1;
+            len += min_sym_len;
+            buf64 = buf64.checked_shl(len as u32).unwrap_or(0);
+            buf64_size -= len as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= read_u32_be(bytes, ptr) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // Expand the symbol's pairs until reaching the single value at the offset
+        while self.symlen[sym] > 0 {
+            let left = self.get_left_symbol(bytes, sym);
+            if left >= self.symlen.len() { bail!("Corrupt tablebase block"); }
+            if offset < self.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= self.symlen[left] as i64 + 1;
+                sym = self.get_right_symbol(bytes, sym);
+                if sym >= self.symlen.len() { bail!("Corrupt tablebase block"); }
+            }
+        }
+        Ok(self.get_left_symbol(bytes, sym) as i32)
+    }
+}
+
+/// Material and file information for a single piece combination (e.g. KRvK), which covers both colours
+struct TableInfo {
+    // Material code with white's pieces first as in the file name (key), and with the colours swapped (key2)
+    key: String,
+    key2: String,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // [leading colour, other colour], where the leading colour is the side with fewer pawns (but at least one)
+    pawn_count: [usize; 2],
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: Option<LoadedTable>,
+    dtz: Option<LoadedTable>,
+}
+
+impl TableInfo {
+    fn from_name(name: &str, wdl_path: PathBuf, dtz_path: Option<PathBuf>) -> Option<TableInfo> {
+        let sides: Vec<&str> = name.split('v').collect();
+        if sides.len() != 2 || name.len() - 1 > TB_MAX_PIECES { return None; }
+        if sides.iter().any(|side| !side.starts_with('K') || side.chars().filter(|c| *c == 'K').count() != 1
+            || side.chars().any(|c| !TB_PIECE_LETTERS.contains(&c))) { return None; }
+
+        let count = |side: &str, letter: char| side.chars().filter(|c| *c == letter).count();
+        let has_unique_pieces = sides.iter().any(|side| TB_PIECE_LETTERS[1..].iter().any(|letter| count(side, *letter) == 1));
+        let (white_pawns, black_pawns) = (count(sides[0], 'P'), count(sides[1], 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+
+        Some(TableInfo {
+            key: name.to_string(),
+            key2: format!("{}v{}", sides[1], sides[0]),
+            piece_count: name.len() - 1,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] },
+            wdl_path,
+            dtz_path,
+            wdl: None,
+            dtz: None,
+        })
+    }
+}
+
+/// The decoding information for a loaded WDL or DTZ file, indexed by [side to move][leading pawn file]
+/// (DTZ tables only store one side to move, and tables without pawns only have one file)
+struct LoadedTable {
+    bytes: Vec<u8>,
+    is_dtz: bool,
+    map: usize,
+    items: Vec<Vec<PairsData>>,
+}
+
+impl LoadedTable {
+    fn load(path: &Path, info: &TableInfo, is_dtz: bool) -> Result<LoadedTable, SimpleError> {
+        let bytes = match fs::read(path) {
+            Err(why) => bail!("Couldn't read tablebase {}: {}", path.display(), why),
+            Ok(bytes) => bytes,
+        };
+        LoadedTable::from_bytes(bytes, info, is_dtz).map_err(|e| SimpleError::new(format!("{}: {}", path.display(), e)))
+    }
+
+    fn from_bytes(bytes: Vec<u8>, info: &TableInfo, is_dtz: bool) -> Result<LoadedTable, SimpleError> {
+        if bytes.len() < 6 || bytes[0..4] != if is_dtz { DTZ_MAGIC } else { WDL_MAGIC } {
+            bail!("Invalid tablebase file (wrong magic number)");
+        }
+
+        // First byte: bit 0 is set if the table is split by side to move, bit 1 if it has pawns
+        let mut data = 4;
+        if ((bytes[data] & 2) > 0) != info.has_pawns || ((bytes[data] & 1) > 0) != (info.key != info.key2) {
+            bail!("Tablebase file doesn't match its name");
+        }
+        data += 1;
+
+        let sides = if !is_dtz && info.key != info.key2 { 2 } else { 1 };
+        let file_count = if info.has_pawns { 4 } else { 1 };
+        let both_sides_have_pawns = info.has_pawns && info.pawn_count[1] > 0;
+        let mut items = vec![vec![PairsData::default(); file_count]; if is_dtz { 1 } else { 2 }];
+
+        for file in 0..file_count {
+            if data + 1 + both_sides_have_pawns as usize + info.piece_count > bytes.len() { bail!("Tablebase file is truncated"); }
+            let order = [
+                [bytes[data] & 0xF, if both_sides_have_pawns { bytes[data + 1] & 0xF } else { 0xF }],
+                [bytes[data] >> 4, if both_sides_have_pawns { bytes[data + 1] >> 4 } else { 0xF }],
+            ];
+            data += 1 + both_sides_have_pawns as usize;
+
+            for k in 0..info.piece_count {
+                for (side, side_items) in items[..sides].iter_mut().enumerate() {
+                    side_items[file].pieces[k] = if side > 0 { bytes[data] >> 4 } else { bytes[data] & 0xF };
+                }
+                data += 1;
+            }
+            for (side_items, side_order) in items[..sides].iter_mut().zip(order) {
+                side_items[file].set_groups(info, side_order, file);
+            }
+        }
+        data += data & 1;
+
+        for file in 0..file_count {
+            for side_items in items[..sides].iter_mut() {
+                data = side_items[file].set_sizes(&bytes, data)?;
+            }
+        }
+
+        // DTZ values are stored by frequency of occurrence, so there is a map back to the real values for each WDL
+        let map = data;
+        if is_dtz {
+            for d in items[0].iter_mut() {
+                if d.flags & TB_FLAG_MAPPED == 0 { continue; }
+                if d.flags & TB_FLAG_WIDE > 0 {
+                    data += data & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = (data - map) / 2 + 1;
+                        data += 2 * read_u16_le(&bytes, data) as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = data - map + 1;
+                        data += *bytes.get(data).unwrap_or(&0) as usize + 1;
+                    }
+                }
+            }
+            data += data & 1;
+        }
+
+        for file in 0..file_count {
+            for side_items in items[..sides].iter_mut() {
+                side_items[file].sparse_index = data;
+                data += side_items[file].sparse_index_size as usize * 6;
+            }
+        }
+        for file in 0..file_count {
+            for side_items in items[..sides].iter_mut() {
+                side_items[file].block_length = data;
+                data += side_items[file].block_length_size as usize * 2;
+            }
+        }
+        for file in 0..file_count {
+            for side_items in items[..sides].iter_mut() {
+                let d = &mut side_items[file];
+                data = (data + 0x3F) & !0x3F;
+                d.data = data;
+                data += (d.num_blocks * d.sizeof_block) as usize;
+                if d.num_blocks > 0 && data > bytes.len() { bail!("Tablebase file is truncated"); }
+            }
+        }
+
+        Ok(LoadedTable { bytes, is_dtz, map, items })
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        &self.items[stm % self.items.len()][file.min(self.items[0].len() - 1)]
+    }
+
+    /// Converts a DTZ table value to plies, using the map for the given WDL result (WDL tables store WDL + 2)
+    fn map_score(&self, file: usize, value: i32, wdl: SyzygyWDL) -> i32 {
+        if !self.is_dtz { return value - 2; }
+
+        let d = self.get(0, file);
+        let mut value = value as usize;
+        if d.flags & TB_FLAG_MAPPED > 0 {
+            let idx = d.map_idx[[1, 3, 0, 2, 0][(wdl as i32 + 2) as usize]] + value;
+            value = if d.flags & TB_FLAG_WIDE > 0 { read_u16_le(&self.bytes, self.map + 2 * idx) as usize }
+                else { *self.bytes.get(self.map + idx).unwrap_or(&0) as usize };
+        }
+
+        // Values may be stored in moves rather than plies
+        let in_moves = match wdl {
+            SyzygyWDL::WIN => d.flags & TB_FLAG_WIN_PLIES == 0,
+            SyzygyWDL::LOSS => d.flags & TB_FLAG_LOSS_PLIES == 0,
+            SyzygyWDL::CURSED_WIN | SyzygyWDL::BLESSED_LOSS => true,
+            SyzygyWDL::DRAW => false,
+        };
+        (if in_moves { value * 2 } else { value }) as i32 + 1
+    }
+}
+
+/// Returns the Syzygy piece code for the piece on the square, or 0 if it is empty
+fn get_piece_code(position: &Position, sq: usize) -> u8 {
+    let bitboards = [
+        position.wp, position.wn, position.wb, position.wr, position.wq, position.wk,
+        position.bp, position.bn, position.bb, position.br, position.bq, position.bk,
+    ];
+    let bb = SINGLE_BITBOARDS[sq];
+    match bitboards.iter().position(|pieces| pieces & bb > 0) {
+        Some(i) if i < 6 => i as u8 + TB_W_PAWN,
+        Some(i) => (i - 6) as u8 + TB_B_PAWN,
+        None => 0,
+    }
+}
+
+/// Returns the material code for the position in the same form as the table names (e.g. KRPvKR), white first
+pub fn calc_material_code(position: &Position) -> String {
+    let side_code = |pieces: [u64; 6]| -> String {
+        TB_PIECE_LETTERS.iter().zip(pieces.iter())
+            .map(|(letter, bb)| letter.to_string().repeat(bb.count_ones() as usize))
+            .collect()
+    };
+    format!("{}v{}",
+        side_code([position.wk, position.wq, position.wr, position.wb, position.wn, position.wp]),
+        side_code([position.bk, position.bq, position.br, position.bb, position.bn, position.bp]))
+}
+
+fn calc_legal_moves(position: &Position) -> (Position, GameMoveList) {
+    let mut position = position.clone();
+    let mut move_list = GameMoveList::default();
+    PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+    (position, move_list)
+}
+
+fn make_move(position: &Position, game_move: &GameMove) -> Position {
+    let mut next_position = position.clone();
+    MoveMaker::default().make_move(&mut next_position, game_move, false);
+    next_position
+}
+
+/// Syzygy endgame tablebases found in one or more folders, with the files loaded into memory the first time they
+/// are probed
+pub struct SyzygyTablebase {
+    tables: Vec<TableInfo>,
+    // Both material codes of each table (e.g. KRvK and KvKR) map to its index
+    table_index: HashMap<String, usize>,
+    pub max_pieces: usize,
+    pub tb_hits: u64,
+}
+
+impl SyzygyTablebase {
+    /// Finds the WDL tables (and matching DTZ tables) in the given folders, separated as in the PATH variable
+    pub fn init(paths: &str) -> Result<SyzygyTablebase, SimpleError> {
+        let mut tablebase = SyzygyTablebase { tables: vec![], table_index: HashMap::new(), max_pieces: 0, tb_hits: 0 };
+        let dirs: Vec<PathBuf> = std::env::split_paths(paths).filter(|dir| !dir.as_os_str().is_empty()).collect();
+
+        let mut dtz_paths = HashMap::new();
+        let mut wdl_paths = vec![];
+        for dir in dirs.iter() {
+            let dir_entries = match fs::read_dir(dir) {
+                Err(why) => bail!("Couldn't read tablebase folder {}: {}", dir.display(), why),
+                Ok(dir_entries) => dir_entries,
+            };
+            for path in dir_entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+                let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+                match path.extension().and_then(|ext| ext.to_str()) {
+                    Some(WDL_SUFFIX) => wdl_paths.push((name, path)),
+                    Some(DTZ_SUFFIX) => { dtz_paths.insert(name, path); },
+                    _ => (),
+                }
+            }
+        }
+
+        wdl_paths.sort();
+        for (name, wdl_path) in wdl_paths {
+            if tablebase.table_index.contains_key(&name) { continue; }
+            let dtz_path = dtz_paths.get(&name).cloned();
+            if let Some(info) = TableInfo::from_name(&name, wdl_path, dtz_path) {
+                tablebase.max_pieces = tablebase.max_pieces.max(info.piece_count);
+                tablebase.table_index.insert(info.key.clone(), tablebase.tables.len());
+                tablebase.table_index.insert(info.key2.clone(), tablebase.tables.len());
+                tablebase.tables.push(info);
+            }
+        }
+        Ok(tablebase)
+    }
+
+    pub fn table_count(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Returns true if the position has few enough pieces to be probed (tables don't include castling rights)
+    pub fn can_probe(&self, position: &Position) -> bool {
+        position.castling_rights == 0 && position.all_occupancy.count_ones() as usize <= self.max_pieces
+    }
+
+    /// Returns the index of the table for the position, loading the WDL or DTZ file if it hasn't been loaded yet
+    fn load_table(&mut self, position: &Position, is_dtz: bool) -> Result<usize, SimpleError> {
+        let material_code = calc_material_code(position);
+        let index = match self.table_index.get(&material_code) {
+            Some(index) => *index,
+            None => bail!("No tablebase for {}", material_code),
+        };
+
+        let info = &self.tables[index];
+        let loaded = if is_dtz { info.dtz.is_some() } else { info.wdl.is_some() };
+        if !loaded {
+            let path = if is_dtz { info.dtz_path.clone() } else { Some(info.wdl_path.clone()) };
+            let path = match path {
+                Some(path) => path,
+                None => bail!("No DTZ tablebase for {}", info.key),
+            };
+            let table = LoadedTable::load(&path, info, is_dtz)?;
+            if is_dtz { self.tables[index].dtz = Some(table); } else { self.tables[index].wdl = Some(table); }
+        }
+        Ok(index)
+    }
+
+    /// Looks up the position in a WDL or DTZ table (passing the WDL result for DTZ lookups)
+    /// Returns None for a DTZ lookup if the table only stores the other side to move
+    fn probe_table(&mut self, position: &Position, is_dtz: bool, wdl: SyzygyWDL) -> Result<Option<i32>, SimpleError> {
+        // KvK isn't stored in a table
+        if position.all_occupancy.count_ones() == 2 { return Ok(Some(SyzygyWDL::DRAW as i32)); }
+
+        let index = self.load_table(position, is_dtz)?;
+        let info = &self.tables[index];
+        let table = if is_dtz { info.dtz.as_ref().unwrap() } else { info.wdl.as_ref().unwrap() };
+        let result = SyzygyTablebase::probe_loaded_table(info, table, position, wdl)?;
+        self.tb_hits += 1;
+        Ok(result)
+    }
+
+    fn probe_loaded_table(info: &TableInfo, table: &LoadedTable, position: &Position, wdl: SyzygyWDL) -> Result<Option<i32>, SimpleError> {
+        let tables = &*INDEX_TABLES;
+        let mut squares = [0usize; TB_MAX_PIECES];
+        let mut pieces = [0u8; TB_MAX_PIECES];
+
+        // Tables are stored with the stronger side as white (the key) and symmetric tables only store white to move,
+        // so flip the colours and the board vertically if needed
+        let symmetric_black_to_move = info.key == info.key2 && !position.white_to_move;
+        let black_stronger = calc_material_code(position) != info.key;
+        let flip = symmetric_black_to_move || black_stronger;
+        let flip_colour = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip as usize) ^ (!position.white_to_move as usize);
+
+        // Tables with pawns are split into four according to the file of the leading pawn (after mirroring), which
+        // is the one furthest from the centre and then closest to the first rank
+        let mut size = 0;
+        let mut lead_pawns = 0u64;
+        let mut lead_pawns_count = 0;
+        let mut tb_file = 0;
+        if info.has_pawns {
+            let lead_pawn_piece = table.get(0, 0).pieces[0] ^ flip_colour;
+            lead_pawns = if lead_pawn_piece == TB_W_PAWN { position.wp } else { position.bp };
+            let mut remaining = lead_pawns;
+            while remaining > 0 {
+                squares[size] = remaining.trailing_zeros() as usize ^ flip_squares;
+                size += 1;
+                remaining &= remaining - 1;
+            }
+            lead_pawns_count = size;
+
+            let mut lead_index = 0;
+            for i in 1..lead_pawns_count {
+                if tables.map_pawns[squares[i]] > tables.map_pawns[squares[lead_index]] { lead_index = i; }
+            }
+            squares.swap(0, lead_index);
+            tb_file = (squares[0] & 7).min(7 - (squares[0] & 7));
+        }
+
+        // DTZ tables only store one side to move (unless the position is symmetric)
+        if table.is_dtz {
+            let stored_stm = (table.get(stm, tb_file).flags & TB_FLAG_STM) as usize;
+            if stored_stm != stm && (info.key != info.key2 || info.has_pawns) { return Ok(None); }
+        }
+
+        let mut remaining = position.all_occupancy ^ lead_pawns;
+        while remaining > 0 {
+            let sq = remaining.trailing_zeros() as usize;
+            if size >= TB_MAX_PIECES { bail!("Too many pieces for the tablebase"); }
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = get_piece_code(position, sq) ^ flip_colour;
+            size += 1;
+            remaining &= remaining - 1;
+        }
+        let d = table.get(stm, tb_file);
+
+        // Put the pieces in the same order as the table
+        for i in lead_pawns_count..size.saturating_sub(1) {
+            for j in (i + 1)..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // Mirror horizontally so that the leading piece is on files a-d
+        if squares[0] & 7 > 3 {
+            for sq in squares[..size].iter_mut() { *sq ^= 7; }
+        }
+
+        let mut idx;
+        if info.has_pawns {
+            idx = tables.lead_pawn_idx[lead_pawns_count][squares[0]];
+            squares[1..lead_pawns_count].sort_by_key(|sq| tables.map_pawns[*sq]);
+            for (i, sq) in squares[..lead_pawns_count].iter().enumerate().skip(1) {
+                idx += tables.binomial[i][tables.map_pawns[*sq]];
+            }
+        } else {
+            // Without pawns, also mirror vertically so that the leading piece is on ranks 1-4, and then along the
+            // a1-h8 diagonal so the first piece of the leading group that isn't on the diagonal is below it
+            if squares[0] >> 3 > 3 {
+                for sq in squares[..size].iter_mut() { *sq ^= 56; }
+            }
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 { continue; }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares[i..size].iter_mut() { *sq = ((*sq >> 3) | (*sq << 3)) & 63; }
+                }
+                break;
+            }
+
+            if info.has_unique_pieces {
+                // The kings and a unique piece are encoded together (462 * 62 placements, allowing for the diagonal)
+                let adjust1 = (squares[1] > squares[0]) as u64;
+                let adjust2 = (squares[2] > squares[0]) as u64 + (squares[2] > squares[1]) as u64;
+                let (sq0, sq1, sq2) = (squares[0] as u64, squares[1] as u64, squares[2] as u64);
+                idx = if off_a1h8(squares[0]) != 0 {
+                    (tables.map_a1d1d4[squares[0]] as u64 * 63 + (sq1 - adjust1)) * 62 + sq2 - adjust2
+                } else if off_a1h8(squares[1]) != 0 {
+                    (6 * 63 + (sq0 >> 3) * 28 + tables.map_b1h1h7[squares[1]]) * 62 + sq2 - adjust2
+                } else if off_a1h8(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + (sq0 >> 3) * 7 * 28 + ((sq1 >> 3) - adjust1) * 28 + tables.map_b1h1h7[squares[2]]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + (sq0 >> 3) * 7 * 6 + ((sq1 >> 3) - adjust1) * 6 + ((sq2 >> 3) - adjust2)
+                };
+            } else {
+                idx = tables.map_kk[tables.map_a1d1d4[squares[0]]][squares[1]];
+            }
+        }
+
+        // Encode the remaining groups, with each square shifted down past the squares of the earlier groups
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = info.has_pawns && info.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] > 0 {
+            let group_end = group_start + d.group_len[next];
+            if group_end > size { bail!("Tablebase piece groups don't match the position"); }
+            squares[group_start..group_end].sort();
+            let mut n = 0u64;
+            for i in 0..d.group_len[next] {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|earlier| sq > **earlier).count();
+                n += tables.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start = group_end;
+            next += 1;
+        }
+
+        let value = d.decompress(&table.bytes, idx)?;
+        Ok(Some(table.map_score(tb_file, value, wdl)))
+    }
+
+    /// Probes the WDL table, first searching captures (and pawn moves, when zeroing moves are needed) since the tables
+    /// don't include en passant and may store "don't care" values where the best move is a capture
+    /// Returns the result, and whether the best move resets the fifty move counter
+    fn search(&mut self, position: &Position, check_zeroing_moves: bool) -> Result<(SyzygyWDL, bool), SimpleError> {
+        let (position, move_list) = calc_legal_moves(position);
+        let mut best_value = SyzygyWDL::LOSS;
+        let mut move_count = 0;
+        for game_move in move_list.moves().iter().map(|m| m.to_game_move(&position)) {
+            if !game_move.is_capture && (!check_zeroing_moves || game_move.piece != PieceType::PAWN) { continue; }
+            move_count += 1;
+
+            let value = self.search(&make_move(&position, &game_move), false)?.0.flip();
+            if value > best_value {
+                best_value = value;
+                if value >= SyzygyWDL::WIN { return Ok((value, true)); }
+            }
+        }
+
+        // No need to probe if every legal move has already been searched
+        let no_more_moves = move_count > 0 && move_count == move_list.list_len;
+        let value = match no_more_moves {
+            true => best_value,
+            false => SyzygyWDL::from_value(self.probe_table(&position, false, SyzygyWDL::DRAW)?.unwrap_or(0)),
+        };
+
+        if best_value >= value {
+            return Ok((best_value, best_value > SyzygyWDL::DRAW || no_more_moves));
+        }
+        Ok((value, false))
+    }
+
+    /// Returns the win / draw / loss result for the side to move
+    pub fn probe_wdl(&mut self, position: &Position) -> Result<SyzygyWDL, SimpleError> {
+        Ok(self.search(position, false)?.0)
+    }
+
+    /// Returns the number of plies until the fifty move counter is reset by a winning (positive) or losing (negative)
+    /// capture, pawn move or mate, with 100 added for cursed wins / blessed losses, or 0 for a draw
+    pub fn probe_dtz(&mut self, position: &Position) -> Result<i32, SimpleError> {
+        let (wdl, zeroing_best_move) = self.search(position, true)?;
+        if wdl == SyzygyWDL::DRAW { return Ok(0); }
+        if zeroing_best_move { return Ok(wdl.calc_dtz_before_zeroing()); }
+
+        let sign = (wdl as i32).signum();
+        if let Some(dtz) = self.probe_table(position, true, wdl)? {
+            let cursed = wdl == SyzygyWDL::CURSED_WIN || wdl == SyzygyWDL::BLESSED_LOSS;
+            return Ok((dtz + if cursed { 100 } else { 0 }) * sign);
+        }
+
+        // The table stores the other side to move, so find the best DTZ after each move
+        let (position, move_list) = calc_legal_moves(position);
+        let mut min_dtz = 0xFFFF;
+        for game_move in move_list.moves().iter().map(|m| m.to_game_move(&position)) {
+            let zeroing = game_move.is_capture || game_move.piece == PieceType::PAWN;
+            let next_position = make_move(&position, &game_move);
+
+            // For zeroing moves, use the DTZ before the move (the sign still comes from the position after it)
+            let mut dtz = match zeroing {
+                true => -self.search(&next_position, false)?.0.calc_dtz_before_zeroing(),
+                false => -self.probe_dtz(&next_position)?,
+            };
+            if dtz == 1 && calc_legal_moves(&next_position).0.is_checkmate { min_dtz = 1; }
+            if !zeroing { dtz += dtz.signum(); }
+            if dtz < min_dtz && dtz.signum() == sign { min_dtz = dtz; }
+        }
+        Ok(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    /// Ranks a root move by its DTZ counted from the root: certain wins rank equally, as do losses unless the fifty
+    /// move rule might save the game
+    pub fn calc_root_rank(dtz: i32, fifty_move_count: i32) -> i32 {
+        if dtz > 0 {
+            if dtz + fifty_move_count <= 99 { TB_MAX_DTZ } else { TB_MAX_DTZ - (dtz + fifty_move_count) }
+        } else if dtz < 0 {
+            if -dtz * 2 + fifty_move_count < 100 { -TB_MAX_DTZ } else { -TB_MAX_DTZ + (-dtz + fifty_move_count) }
+        } else {
+            0
+        }
+    }
+
+    /// Probes every legal move and returns the ones with the best rank, ordered so that winning moves reset the
+    /// fifty move counter as quickly as possible and losing moves hold out as long as possible
+    /// Falls back to ranking the moves by WDL alone if the DTZ tables are missing
+    pub fn probe_root(&mut self, position: &Position) -> Result<Vec<TablebaseRootMove>, SimpleError> {
+        let mut root_moves = match self.probe_root_dtz(position) {
+            Ok(root_moves) => root_moves,
+            Err(_) => self.probe_root_wdl(position)?,
+        };
+
+        root_moves.sort_by_key(|root_move| (-root_move.rank, root_move.dtz));
+        let best_rank = root_moves.first().map_or(0, |root_move| root_move.rank);
+        root_moves.retain(|root_move| root_move.rank == best_rank);
+        Ok(root_moves)
+    }
+
+    fn probe_root_dtz(&mut self, position: &Position) -> Result<Vec<TablebaseRootMove>, SimpleError> {
+        let (position, move_list) = calc_legal_moves(position);
+        let mut root_moves = vec![];
+        for game_move in move_list.moves().iter().map(|m| m.to_game_move(&position)) {
+            let next_position = make_move(&position, &game_move);
+            let wdl;
+            let mut dtz;
+            if next_position.fifty_move_count == 0 {
+                wdl = self.probe_wdl(&next_position)?.flip();
+                dtz = wdl.calc_dtz_before_zeroing();
+            } else {
+                dtz = -self.probe_dtz(&next_position)?;
+                dtz += dtz.signum();
+                wdl = match dtz {
+                    d if d > 100 => SyzygyWDL::CURSED_WIN,
+                    d if d > 0 => SyzygyWDL::WIN,
+                    d if d < -100 => SyzygyWDL::BLESSED_LOSS,
+                    d if d < 0 => SyzygyWDL::LOSS,
+                    _ => SyzygyWDL::DRAW,
+                };
+            }
+
+            // Mating moves always have a DTZ of 1
+            if dtz == 2 && calc_legal_moves(&next_position).0.is_checkmate { dtz = 1; }
+
+            let rank = SyzygyTablebase::calc_root_rank(dtz, position.fifty_move_count as i32);
+            root_moves.push(TablebaseRootMove { game_move, wdl, dtz, rank });
+        }
+        Ok(root_moves)
+    }
+
+    /// Without DTZ, a win can't be guaranteed within the fifty move rule, but any winning move still keeps the win
+    fn probe_root_wdl(&mut self, position: &Position) -> Result<Vec<TablebaseRootMove>, SimpleError> {
+        let (position, move_list) = calc_legal_moves(position);
+        let mut root_moves = vec![];
+        for game_move in move_list.moves().iter().map(|m| m.to_game_move(&position)) {
+            let wdl = self.probe_wdl(&make_move(&position, &game_move))?.flip();
+            let rank = [-TB_MAX_DTZ, -TB_MAX_DTZ + 101, 0, TB_MAX_DTZ - 101, TB_MAX_DTZ][(wdl as i32 + 2) as usize];
+            root_moves.push(TablebaseRootMove { game_move, wdl, dtz: wdl.calc_dtz_before_zeroing(), rank });
+        }
+        Ok(root_moves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::seq::index;
+    use crate::engine::endgametablebase::{EndgameResult, EndgameTablebase};
+    use crate::game::fenvalidator::FenValidator;
+    use super::*;
+
+    /// Writes a table in which every position has the same value, which exercises the header parsing and indexing
+    /// (KNvK is a real example, since every position is a draw)
+    fn write_single_value_table(dir: &Path, name: &str, has_pawns: bool, pieces: &[u8], values: [SyzygyWDL; 2]) {
+        let mut bytes = WDL_MAGIC.to_vec();
+        bytes.push(1 | if has_pawns { 2 } else { 0 });
+        for _ in 0..(if has_pawns { 4 } else { 1 }) {
+            bytes.push(0);
+            bytes.extend(pieces.iter().map(|piece| piece | (piece << 4)));
+        }
+        if bytes.len() & 1 > 0 { bytes.push(0); }
+        // Each file has a value for white to move followed by one for black to move (stored as WDL + 2)
+        for _ in 0..(if has_pawns { 4 } else { 1 }) {
+            for value in values {
+                bytes.extend([TB_FLAG_SINGLE_VALUE, (value as i32 + 2) as u8]);
+            }
+        }
+        fs::write(dir.join(format!("{}.{}", name, WDL_SUFFIX)), bytes).unwrap();
+    }
+
+    #[test]
+    fn test_index_tables() {
+        let tables = &*INDEX_TABLES;
+        // 462 king placements, numbered 0 to 461
+        assert_eq!(tables.map_kk.iter().flat_map(|row| row.iter()).max(), Some(&461));
+        assert_eq!(tables.map_a1d1d4[1], 0);   // b1
+        assert_eq!(tables.map_a1d1d4[0], 6);   // a1 is on the diagonal, so comes after the 6 squares below it
+        assert_eq!(tables.map_b1h1h7[63 - 8], 27);   // h7
+        assert_eq!(tables.binomial[2][5], 10);
+        assert_eq!(tables.binomial[0][63], 1);
+
+        // The leading pawn is the one furthest from the centre and then nearest the first rank
+        assert_eq!(tables.map_pawns[8], 47);   // a2
+        assert_eq!(tables.map_pawns[15], 46);  // h2
+        assert_eq!(tables.lead_pawns_size[1], [6, 6, 6, 6]);
+    }
+
+    #[test]
+    fn test_material_code() {
+        let position = Position::from_fen(Some("8/8/4k3/8/2P5/8/1K1R4/8 w - - 0 1"), false).unwrap();
+        assert_eq!(calc_material_code(&position), "KRPvK");
+
+        let info = TableInfo::from_name("KRPvKR", PathBuf::new(), None).unwrap();
+        assert_eq!(info.key2, "KRvKRP");
+        assert_eq!(info.piece_count, 5);
+        assert!(info.has_pawns && info.has_unique_pieces);
+        assert_eq!(info.pawn_count, [1, 0]);
+        assert!(TableInfo::from_name("KRvKRR", PathBuf::new(), None).unwrap().has_unique_pieces);
+        assert!(!TableInfo::from_name("KRRvKBB", PathBuf::new(), None).unwrap().has_unique_pieces);
+        assert!(TableInfo::from_name("KXvK", PathBuf::new(), None).is_none());
+        assert!(TableInfo::from_name("KQQQvKRRR", PathBuf::new(), None).is_none());
+    }
+
+    #[test]
+    fn test_root_rank() {
+        assert_eq!(SyzygyTablebase::calc_root_rank(5, 10), TB_MAX_DTZ);
+        assert_eq!(SyzygyTablebase::calc_root_rank(95, 10), TB_MAX_DTZ - 105);   // the fifty move rule will draw first
+        assert_eq!(SyzygyTablebase::calc_root_rank(0, 10), 0);
+        assert_eq!(SyzygyTablebase::calc_root_rank(-5, 10), -TB_MAX_DTZ);
+        assert_eq!(SyzygyTablebase::calc_root_rank(-60, 10), -TB_MAX_DTZ + 70);
+
+        let root_move = TablebaseRootMove { game_move: GameMove::default(), wdl: SyzygyWDL::WIN, dtz: 3, rank: TB_MAX_DTZ };
+        assert_eq!(root_move.calc_score(), TB_WIN_SCORE - 3);
+        // A cursed win is only just better than a draw
+        let root_move = TablebaseRootMove { game_move: GameMove::default(), wdl: SyzygyWDL::CURSED_WIN, dtz: 105, rank: TB_MAX_DTZ - 115 };
+        assert_eq!(root_move.calc_score(), 42);
+    }
+
+    #[test]
+    fn test_probe() {
+        let mut dir = std::env::temp_dir();
+        dir.push("my_chess_ql_test_syzygy");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        write_single_value_table(&dir, "KNvK", false, &[6, 2, 14], [SyzygyWDL::DRAW, SyzygyWDL::DRAW]);
+        // Not a real table (KPvK isn't always won), but it exercises the indexing with pawns
+        write_single_value_table(&dir, "KPvK", true, &[1, 6, 14], [SyzygyWDL::WIN, SyzygyWDL::LOSS]);
+        fs::write(dir.join("KQvK.rtbw"), [0u8; 16]).unwrap();
+
+        let mut tablebase = SyzygyTablebase::init(dir.to_str().unwrap()).unwrap();
+        assert_eq!(tablebase.table_count(), 3);
+        assert_eq!(tablebase.max_pieces, 3);
+
+        let position = Position::from_fen(Some("8/8/4k3/8/8/8/1K1N4/8 w - - 0 1"), false).unwrap();
+        assert!(tablebase.can_probe(&position));
+        assert_eq!(tablebase.probe_wdl(&position).unwrap(), SyzygyWDL::DRAW);
+        assert_eq!(tablebase.probe_dtz(&position).unwrap(), 0);
+
+        // Black has the knight, so the colours are swapped before probing
+        let position = Position::from_fen(Some("8/8/4k1n1/8/8/8/1K6/8 b - - 0 1"), false).unwrap();
+        assert_eq!(tablebase.probe_wdl(&position).unwrap(), SyzygyWDL::DRAW);
+        assert!(tablebase.tb_hits > 0);
+
+        let position = Position::from_fen(Some("8/8/4k3/8/8/1p6/8/1K6 b - - 0 1"), false).unwrap();
+        assert_eq!(tablebase.probe_wdl(&position).unwrap(), SyzygyWDL::WIN);
+        let position = Position::from_fen(Some("8/8/4k3/8/8/1p6/8/1K6 w - - 0 1"), false).unwrap();
+        assert_eq!(tablebase.probe_wdl(&position).unwrap(), SyzygyWDL::LOSS);
+
+        // Capturing the last piece leaves KvK, which doesn't need a table (there is no KPvK DTZ table, so the moves
+        // are only ranked by WDL)
+        let position = Position::from_fen(Some("8/8/8/8/8/3k4/1p6/K7 w - - 0 1"), false).unwrap();
+        let root_moves = tablebase.probe_root(&position).unwrap();
+        assert_eq!(root_moves.len(), 1);
+        assert_eq!(root_moves[0].game_move.get_uci_move_string(), "a1b2");
+        assert_eq!(root_moves[0].wdl, SyzygyWDL::DRAW);
+
+        assert!(tablebase.probe_wdl(&Position::from_fen(Some("8/8/4k3/8/8/8/1K1Q4/8 w - - 0 1"), false).unwrap()).is_err());
+        assert!(tablebase.probe_wdl(&Position::from_fen(Some("8/8/4k3/8/8/8/1K1R4/8 w - - 0 1"), false).unwrap()).is_err());
+        assert!(!tablebase.can_probe(&Position::from_fen(None, false).unwrap()));
+        assert!(SyzygyTablebase::init("src/test/resources/missing_tablebases").is_err());
+    }
+
+    /// Returns a random legal position with the two kings and the given piece, which either side can have (so that
+    /// the colour swapped probes are covered too)
+    fn generate_random_position(rng: &mut StdRng, piece: char) -> Position {
+        loop {
+            let mut board = [' '; 64];
+            let squares = index::sample(rng, 64, 3);
+            board[squares.index(0)] = 'K';
+            board[squares.index(1)] = 'k';
+            board[squares.index(2)] = if rng.gen_bool(0.5) { piece } else { piece.to_ascii_lowercase() };
+
+            let ranks: Vec<String> = (0..8).rev().map(|rank| {
+                let mut fen_rank = String::new();
+                let mut empty_count = 0;
+                for c in board[(rank * 8)..(rank * 8 + 8)].iter() {
+                    if *c == ' ' { empty_count += 1; continue; }
+                    if empty_count > 0 { fen_rank.push_str(&empty_count.to_string()); }
+                    empty_count = 0;
+                    fen_rank.push(*c);
+                }
+                if empty_count > 0 { fen_rank.push_str(&empty_count.to_string()); }
+                fen_rank
+            }).collect();
+            let fen = format!("{} {} - - 0 1", ranks.join("/"), if rng.gen_bool(0.5) { "w" } else { "b" });
+
+            // Skips e.g. adjacent kings and pawns on the back ranks
+            if let Ok(position) = FenValidator::parse(&fen, false) { return position; }
+        }
+    }
+
+    fn probe_fen(tablebase: &mut SyzygyTablebase, fen: &str) -> (SyzygyWDL, i32) {
+        let position = Position::from_fen(Some(fen), false).unwrap();
+        (tablebase.probe_wdl(&position).unwrap(), tablebase.probe_dtz(&position).unwrap())
+    }
+
+    // The tables in src/test/resources/syzygy aren't the official ones: they are written by
+    // scripts/test/generate_syzygy_test_tables.py, which solves the positions itself and compresses them in the same
+    // format (the KRvK DTZ table stores black to move in plies, and the KPvK one white to move in moves, mapped)
+    // KQvK, KBvK and KNvK only have WDL tables, for the positions after a promotion
+    #[test]
+    fn test_probe_compressed_tables() {
+        let mut tablebase = SyzygyTablebase::init("src/test/resources/syzygy").unwrap();
+        assert_eq!(tablebase.table_count(), 5);
+
+        // Mate in 1, black's only move allowing mate in 1, checkmate, stalemate and a hanging rook
+        assert_eq!(probe_fen(&mut tablebase, "k7/8/1K6/8/8/8/8/7R w - - 0 1"), (SyzygyWDL::WIN, 1));
+        assert_eq!(probe_fen(&mut tablebase, "k7/8/1K6/8/8/8/8/7R b - - 0 1"), (SyzygyWDL::LOSS, -2));
+        assert_eq!(probe_fen(&mut tablebase, "R6k/8/6K1/8/8/8/8/8 b - - 0 1"), (SyzygyWDL::LOSS, -1));
+        assert_eq!(probe_fen(&mut tablebase, "k7/8/K7/8/8/8/8/1R6 b - - 0 1"), (SyzygyWDL::DRAW, 0));
+        assert_eq!(probe_fen(&mut tablebase, "8/8/8/8/8/2k5/2R5/K7 b - - 0 1"), (SyzygyWDL::DRAW, 0));
+        assert_eq!(probe_fen(&mut tablebase, "K7/8/1k6/8/8/8/8/7r b - - 0 1"), (SyzygyWDL::WIN, 1));
+        assert_eq!(probe_fen(&mut tablebase, "r6K/8/6k1/8/8/8/8/8 w - - 0 1"), (SyzygyWDL::LOSS, -1));
+
+        // With the king on the sixth rank in front of the pawn, Kd8 Kf7 (or Kf8 Kd7) lets the pawn advance next move,
+        // while a stalemate, a pawn that is about to be captured and the rook pawn with the king in the corner draw
+        assert_eq!(probe_fen(&mut tablebase, "4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), (SyzygyWDL::LOSS, -4));
+        assert_eq!(probe_fen(&mut tablebase, "3k4/8/4K3/4P3/8/8/8/8 w - - 0 1"), (SyzygyWDL::WIN, 3));
+        assert_eq!(probe_fen(&mut tablebase, "8/8/8/8/4p3/4k3/8/4K3 w - - 0 1"), (SyzygyWDL::LOSS, -4));
+        assert_eq!(probe_fen(&mut tablebase, "8/8/8/8/4p3/4k3/8/3K4 b - - 0 1"), (SyzygyWDL::WIN, 3));
+        assert_eq!(probe_fen(&mut tablebase, "4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), (SyzygyWDL::DRAW, 0));
+        assert_eq!(probe_fen(&mut tablebase, "8/8/8/8/8/4k3/4p3/4K3 w - - 0 1"), (SyzygyWDL::DRAW, 0));
+        assert_eq!(probe_fen(&mut tablebase, "8/8/8/8/8/8/3kP3/7K b - - 0 1"), (SyzygyWDL::DRAW, 0));
+        assert_eq!(probe_fen(&mut tablebase, "k7/8/8/8/8/8/P7/K7 w - - 0 1"), (SyzygyWDL::DRAW, 0));
+
+        // Compare random positions with the distance to mate tables generated by EndgameTablebase: in KRvK the only
+        // zeroing move is mate, so DTZ is the number of plies to mate
+        let mut dtm_tablebase = EndgameTablebase::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        for (code, piece) in [("KRvK", 'R'), ("KPvK", 'P')] {
+            dtm_tablebase.generate(code).unwrap();
+            for _ in 0..1000 {
+                let position = generate_random_position(&mut rng, piece);
+                let fen = position.to_fen();
+                let dtz = tablebase.probe_dtz(&position).unwrap();
+                match dtm_tablebase.probe_dtm(&position).unwrap() {
+                    EndgameResult::WIN(moves) => {
+                        assert_eq!(tablebase.probe_wdl(&position).unwrap(), SyzygyWDL::WIN, "{}", fen);
+                        assert!(dtz > 0, "{}", fen);
+                        if piece == 'R' { assert_eq!(dtz, moves as i32 * 2 - 1, "{}", fen); }
+                    },
+                    EndgameResult::DRAW => {
+                        assert_eq!(tablebase.probe_wdl(&position).unwrap(), SyzygyWDL::DRAW, "{}", fen);
+                        assert_eq!(dtz, 0, "{}", fen);
+                    },
+                    EndgameResult::LOSS(moves) => {
+                        assert_eq!(tablebase.probe_wdl(&position).unwrap(), SyzygyWDL::LOSS, "{}", fen);
+                        assert!(dtz < 0, "{}", fen);
+                        if piece == 'R' { assert_eq!(dtz, if moves == 0 { -1 } else { -(moves as i32) * 2 }, "{}", fen); }
+                    },
+                }
+            }
+        }
+    }
+
+    // Needs the real KRvK and KPvK tables (e.g. from https://tablebase.lichess.ovh/tables/standard/3-4-5/) in the folder
+    // given by SYZYGY_PATH, so it only runs with: cargo test -- --ignored
+    // Random positions are checked against the distance to mate tables generated by EndgameTablebase
+    #[test]
+    #[ignore]
+    fn test_probe_real_tables() {
+        let syzygy_path = std::env::var("SYZYGY_PATH").expect("SYZYGY_PATH must point to the Syzygy tables");
+        let mut tablebase = SyzygyTablebase::init(&syzygy_path).unwrap();
+        let mut dtm_tablebase = EndgameTablebase::default();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for (code, piece) in [("KRvK", 'R'), ("KPvK", 'P')] {
+            dtm_tablebase.generate(code).unwrap();
+            for _ in 0..10000 {
+                let position = generate_random_position(&mut rng, piece);
+                let expected_wdl = match dtm_tablebase.probe_dtm(&position).unwrap() {
+                    EndgameResult::WIN(_) => SyzygyWDL::WIN,
+                    EndgameResult::DRAW => SyzygyWDL::DRAW,
+                    EndgameResult::LOSS(_) => SyzygyWDL::LOSS,
+                };
+                assert_eq!(tablebase.probe_wdl(&position).unwrap(), expected_wdl, "{}", position.to_fen());
+            }
+        }
+    }
+}
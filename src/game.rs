@@ -6,5 +6,7 @@ pub mod pieces;
 pub mod analysis;
 pub mod moves;
 
+pub mod castling;
+pub mod fenvalidator;
 pub mod position;
 pub mod positionhelper;
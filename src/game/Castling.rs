@@ -0,0 +1,268 @@
+use crate::constants::*;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::position::Position;
+use crate::game::positionhelper::PositionHelper;
+
+// Castling is indexed as white kingside, white queenside, black kingside, black queenside throughout.  Whichever files
+// the king and rook start on (in Chess960), they finish on the same squares as in standard chess, so the castling rights
+// are still stored as the king target squares
+pub const CASTLING_KING_TARGETS: [usize; 4] = [6, 2, 62, 58];
+pub const CASTLING_ROOK_TARGETS: [usize; 4] = [5, 3, 61, 59];
+pub const STANDARD_CASTLING_ROOK_SQUARES: [u8; 4] = [7, 0, 63, 56];
+const CASTLING_CHARS: [char; 4] = ['K', 'Q', 'k', 'q'];
+
+impl Position {
+    #[inline(always)]
+    pub fn get_castling_index(king_target: usize) -> usize {
+        match king_target {
+            6 => 0,
+            2 => 1,
+            62 => 2,
+            _ => 3,
+        }
+    }
+
+    // Returns the (source, target) squares of the rook that moves along with the king when castling to the given square
+    #[inline(always)]
+    pub fn get_castling_rook_move(&self, king_target: usize) -> (usize, usize) {
+        let index = Position::get_castling_index(king_target);
+        (self.castling_rook_squares[index] as usize, CASTLING_ROOK_TARGETS[index])
+    }
+
+    // The king must be on its back rank (and on the e-file outside of Chess960) with the castling rook on the correct
+    // side of it
+    pub fn has_castling_pieces(&self, index: usize) -> bool {
+        let white = index < 2;
+        let king_pos = self.get_piece_bitboard(PieceType::KING, white);
+        let king_square = king_pos.trailing_zeros() as usize;
+        let rook_square = self.castling_rook_squares[index] as usize;
+        let home_rank = if white { RANK_1 } else { RANK_8 };
+
+        king_pos.count_ones() == 1
+            && king_pos & home_rank > 0
+            && (self.chess960 || king_pos & E_FILE > 0)
+            && self.get_piece_bitboard(PieceType::ROOK, white) & home_rank & SINGLE_BITBOARDS[rook_square] > 0
+            && (rook_square > king_square) == (index & 1 == 0)
+    }
+
+    // Returns true if the side can castle with the given index, where is_attacked tells whether the enemy attacks a square.
+    // The king can't be in check, all squares either piece passes over or lands on must be empty (apart from the king and
+    // rook themselves) and the king can't pass through or land on an attacked square
+    pub fn can_castle<F: Fn(usize) -> bool>(&self, index: usize, is_attacked: F) -> bool {
+        let king_target = CASTLING_KING_TARGETS[index];
+        if self.castling_rights & SINGLE_BITBOARDS[king_target] == 0 || self.king_in_check || !self.has_castling_pieces(index) {
+            return false;
+        }
+
+        let king_source = self.get_piece_bitboard(PieceType::KING, index < 2).trailing_zeros() as usize;
+        let rook_source = self.castling_rook_squares[index] as usize;
+        let rook_target = CASTLING_ROOK_TARGETS[index];
+        let castling_pieces = SINGLE_BITBOARDS[king_source] | SINGLE_BITBOARDS[rook_source];
+        let king_path = Position::calc_rank_span(king_source, king_target);
+
+        if (king_path | Position::calc_rank_span(rook_source, rook_target)) & self.all_occupancy & !castling_pieces > 0 {
+            return false;
+        }
+
+        let mut path_squares = king_path;
+        while path_squares > 0 {
+            if is_attacked(path_squares.trailing_zeros() as usize) { return false; }
+            path_squares &= path_squares - 1;
+        }
+
+        // In Chess960, the castling rook may have been shielding the king's target square from an enemy rook or queen
+        // further along the back rank
+        let occupancy = (self.all_occupancy & !castling_pieces) | SINGLE_BITBOARDS[king_target] | SINGLE_BITBOARDS[rook_target];
+        let enemy_rank_sliders = self.get_piece_bitboard(PieceType::ROOK, index >= 2) | self.get_piece_bitboard(PieceType::QUEEN, index >= 2);
+        for step in [-1i8, 1].iter() {
+            let mut file = (king_target & 7) as i8 + step;
+            while (0..8).contains(&file) {
+                let square_pos = SINGLE_BITBOARDS[(king_target & !7) + file as usize];
+                if square_pos & occupancy > 0 {
+                    if square_pos & enemy_rank_sliders > 0 { return false; }
+                    break;
+                }
+                file += step;
+            }
+        }
+
+        true
+    }
+
+    // Squares on a rank from the first to the second square, inclusive
+    #[inline(always)]
+    fn calc_rank_span(square_a: usize, square_b: usize) -> u64 {
+        let (low, high) = if square_a < square_b { (square_a, square_b) } else { (square_b, square_a) };
+        (u64::MAX >> (63 - high)) & (u64::MAX << low)
+    }
+
+    // Sets the castling rights from the FEN castling field.  Besides KQkq, the files of the castling rooks can be given
+    // (as in Shredder-FEN, or X-FEN when there is more than one rook on that side of the king), which also switches the
+    // position to Chess960.  In Chess960, KQkq refer to the outermost rook on that side of the king (as in X-FEN)
+    pub fn set_castling_rights_from_fen(&mut self, castling_field: &str) {
+        for castle_char in castling_field.chars() {
+            let white = castle_char.is_ascii_uppercase();
+            let home_rank_start = if white { 0 } else { 56 };
+            let king_pos = self.get_piece_bitboard(PieceType::KING, white) & if white { RANK_1 } else { RANK_8 };
+            let king_file = if king_pos > 0 { king_pos.trailing_zeros() as usize & 7 } else { 4 };
+
+            let (index, rook_square) = match castle_char.to_ascii_lowercase() {
+                side @ ('k' | 'q') => {
+                    let index = if side == 'k' { 0 } else { 1 } + if white { 0 } else { 2 };
+                    (index, if self.chess960 { self.find_outermost_rook(index, king_file) } else { STANDARD_CASTLING_ROOK_SQUARES[index] })
+                },
+                file_char @ 'a'..='h' => {
+                    self.chess960 = true;
+                    let rook_file = file_char as usize - 'a' as usize;
+                    let index = if rook_file > king_file { 0 } else { 1 } + if white { 0 } else { 2 };
+                    (index, (home_rank_start + rook_file) as u8)
+                },
+                _ => continue,
+            };
+
+            self.castling_rights |= SINGLE_BITBOARDS[CASTLING_KING_TARGETS[index]];
+            self.castling_rook_squares[index] = rook_square;
+        }
+    }
+
+    // The rook furthest from the king on the given castling side of the back rank, or the standard rook square if
+    // there isn't one
+    fn find_outermost_rook(&self, index: usize, king_file: usize) -> u8 {
+        let white = index < 2;
+        let home_rank_start = if white { 0 } else { 56 };
+        let rooks = self.get_piece_bitboard(PieceType::ROOK, white);
+        let mut rook_files: Vec<usize> = (0..8).filter(|file| rooks & SINGLE_BITBOARDS[home_rank_start + file] > 0).collect();
+        if index & 1 == 0 {
+            rook_files.retain(|file| *file > king_file);
+            rook_files.last()
+        } else {
+            rook_files.retain(|file| *file < king_file);
+            rook_files.first()
+        }.map_or(STANDARD_CASTLING_ROOK_SQUARES[index], |file| (home_rank_start + file) as u8)
+    }
+
+    // Returns the castling field for a FEN string, either as in Shredder-FEN (always the rook files) or X-FEN (KQkq unless
+    // another rook sits further out on the same side in Chess960, in which case the rook's file is used)
+    pub fn get_castling_fen_string(&self, shredder: bool) -> String {
+        let mut result = String::new();
+        for index in 0..4 {
+            if self.castling_rights & SINGLE_BITBOARDS[CASTLING_KING_TARGETS[index]] == 0 { continue; }
+            let white = index < 2;
+            let rook_square = self.castling_rook_squares[index];
+            let king_file = (self.get_piece_bitboard(PieceType::KING, white).trailing_zeros() & 7) as usize;
+
+            let use_file = shredder || (self.chess960 && self.find_outermost_rook(index, king_file) != rook_square);
+            let castle_char = if use_file { (b'A' + (rook_square & 7)) as char } else { CASTLING_CHARS[index].to_ascii_uppercase() };
+            result.push(if white { castle_char } else { castle_char.to_ascii_lowercase() });
+        }
+
+        if result.is_empty() { result.push('-'); }
+        result
+    }
+
+    // Returns the UCI string for a move in this position.  In Chess960, castling is given as the king capturing its own
+    // rook (e.g. e1h1), since the king may only move one square or not at all
+    pub fn get_uci_move_string(&self, game_move: &GameMove) -> String {
+        if !self.chess960 || game_move.move_kind != MoveKind::CASTLE {
+            return game_move.get_uci_move_string();
+        }
+
+        let (rook_square, _) = self.get_castling_rook_move(game_move.target_square as usize);
+        format!("{}{}", PositionHelper::algebraic_from_index(game_move.source_square), PositionHelper::algebraic_from_index(rook_square as u8))
+    }
+
+    // Finds the legal move (from the move list for this position) matching a UCI string from get_uci_move_string().
+    // Unlike GameMoveList::get_move_by_uci, Chess960 castling can't be confused with a king move to the same square
+    pub fn get_move_by_uci(&self, move_list: &GameMoveList, move_uci: &str) -> Option<GameMove> {
+        move_list.moves().iter().map(|m| m.to_game_move(self)).find(|m| self.get_uci_move_string(m) == move_uci)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+    use crate::game::moves::movemaker::MoveMaker;
+
+    fn calc_legal_moves(position: &mut Position) -> GameMoveList {
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+        move_list
+    }
+
+    #[test]
+    fn test_castling_fen_fields() {
+        // Shredder-FEN switches to Chess960 and is written back out as X-FEN unless asked for
+        let fen = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+        let position = Position::from_fen(Some(fen), false).unwrap();
+        assert!(position.chess960);
+        assert_eq!(position.castling_rook_squares, [7, 5, 63, 61]);
+        assert_eq!(position.castling_rights, PositionHelper::bitboard_from_algebraic(vec!["c1", "g1", "c8", "g8"]));
+        assert_eq!(position.to_shredder_fen(), fen);
+        assert_eq!(position.to_fen(), "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9");
+
+        // In X-FEN, KQkq mean the outermost rook and a file is only needed for an inner rook
+        let fen = "rr2k2r/8/8/8/8/8/8/1RK1R2R w Qk - 0 1";
+        let position = Position::from_chess960_fen(Some(fen), false).unwrap();
+        assert_eq!(position.castling_rook_squares, [7, 1, 63, 56]);
+        assert_eq!(position.to_fen(), fen);
+        assert_eq!(position.to_shredder_fen(), "rr2k2r/8/8/8/8/8/8/1RK1R2R w Bh - 0 1");
+
+        let position = Position::from_chess960_fen(Some("rr2k2r/8/8/8/8/8/8/1RK1R2R w Eq - 0 1"), false).unwrap();
+        assert_eq!(position.castling_rook_squares, [4, 0, 63, 56]);
+        assert_eq!(position.to_fen(), "rr2k2r/8/8/8/8/8/8/1RK1R2R w Eq - 0 1");
+        assert_eq!(position.to_shredder_fen(), "rr2k2r/8/8/8/8/8/8/1RK1R2R w Ea - 0 1");
+
+        // Standard positions are unchanged
+        let position = Position::from_fen(None, false).unwrap();
+        assert!(!position.chess960);
+        assert_eq!(position.castling_rook_squares, STANDARD_CASTLING_ROOK_SQUARES);
+        assert_eq!(position.to_shredder_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1");
+    }
+
+    #[test]
+    fn test_chess960_castling_moves() {
+        // The king is already on its target square when castling kingside, and moves onto the rook's square queenside
+        let mut position = Position::from_fen(Some("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1"), false).unwrap();
+        let move_list = calc_legal_moves(&mut position);
+        let short_castle = position.get_move_by_uci(&move_list, "g1h1").unwrap();
+        let long_castle = position.get_move_by_uci(&move_list, "g1b1").unwrap();
+        assert_eq!((short_castle.move_kind, short_castle.target_square), (MoveKind::CASTLE, 6));
+        assert_eq!((long_castle.move_kind, long_castle.target_square), (MoveKind::CASTLE, 2));
+        assert_eq!(short_castle.get_uci_move_string(), "g1g1");
+
+        let mut move_maker = MoveMaker::default();
+        let mut next_position = position.clone();
+        move_maker.make_move(&mut next_position, &long_castle, true);
+        assert_eq!(next_position.to_fen(), "1r4kr/8/8/8/8/8/8/2KR3R b kq - 1 1");
+        let reply_list = calc_legal_moves(&mut next_position);
+        let short_castle_reply = next_position.get_move_by_uci(&reply_list, "g8h8").unwrap();
+        move_maker.make_move(&mut next_position, &short_castle_reply, true);
+        assert_eq!(next_position.to_fen(), "1r3rk1/8/8/8/8/8/8/2KR3R w - - 2 2");
+
+        // Moving the castling rook only removes the right on its side
+        let mut next_position = position.clone();
+        move_maker.make_move(&mut next_position, &position.get_move_by_uci(&move_list, "b1b2").unwrap(), true);
+        assert_eq!(next_position.to_shredder_fen(), "1r4kr/8/8/8/8/8/1R6/6KR b Hhb - 1 1");
+
+        // The castling rook can't be shielding the king's target square from an enemy rook
+        let mut position = Position::from_fen(Some("4k3/8/8/8/8/8/8/rRK5 w B - 0 1"), false).unwrap();
+        let move_list = calc_legal_moves(&mut position);
+        assert!(!move_list.moves().iter().any(|m| m.is_castle()));
+
+        // Outside of Chess960, castling is still given as the king moving two squares
+        let mut position = Position::from_fen(Some("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"), false).unwrap();
+        let move_list = calc_legal_moves(&mut position);
+        assert_eq!(position.get_uci_move_string(&move_list.get_move_by_uci("e1g1").unwrap().to_game_move(&position)), "e1g1");
+
+        // Castling onto the square next to the king has the same king movement as a normal king move, so it can only
+        // be told apart as king takes rook
+        let mut position = Position::from_fen(Some("4k3/8/8/8/8/8/8/5K1R w H - 0 1"), false).unwrap();
+        let move_list = calc_legal_moves(&mut position);
+        assert!(move_list.get_move_by_uci("f1g1").is_none());
+        assert_eq!(position.get_move_by_uci(&move_list, "f1g1").unwrap().move_kind, MoveKind::NORMAL);
+        assert_eq!(position.get_move_by_uci(&move_list, "f1h1").unwrap().move_kind, MoveKind::CASTLE);
+        assert_eq!(position.get_move_by_uci(&move_list, "f1h1").unwrap().target_square, 6);
+    }
+}
@@ -0,0 +1,305 @@
+use std::error::Error;
+use std::fmt;
+use crate::constants::*;
+use crate::game::castling::CASTLING_KING_TARGETS;
+use crate::game::position::Position;
+use crate::game::positionhelper::PositionHelper;
+
+// FEN character reported for each castling right when its pieces are missing (in castling index order)
+const CASTLING_CHARS: [char; 4] = ['K', 'Q', 'k', 'q'];
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum FenError {
+    // Problems with the format of the individual FEN fields
+    WRONG_FIELD_COUNT(usize),
+    WRONG_RANK_COUNT(usize),
+    WRONG_RANK_LENGTH(String),
+    INVALID_PIECE(char),
+    INVALID_SIDE_TO_MOVE(String),
+    INVALID_CASTLING_RIGHTS(String),
+    INVALID_EN_PASSANT_SQUARE(String),
+    INVALID_HALF_MOVE_CLOCK(String),
+    INVALID_FULL_MOVE_NUMBER(String),
+
+    // Well-formed positions that can't come up in a legal game
+    WRONG_KING_COUNT(PlayerColour, u32),
+    TOO_MANY_PAWNS(PlayerColour, u32),
+    TOO_MANY_PROMOTED_PIECES(PlayerColour),
+    PAWN_ON_BACK_RANK(String),
+    CASTLING_RIGHTS_WITHOUT_PIECES(char),
+    IMPOSSIBLE_EN_PASSANT_SQUARE(String),
+    OPPONENT_IN_CHECK,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::WRONG_FIELD_COUNT(count) => write!(f, "FEN must have 6 fields but has {}", count),
+            FenError::WRONG_RANK_COUNT(count) => write!(f, "board must have 8 ranks but has {}", count),
+            FenError::WRONG_RANK_LENGTH(rank) => write!(f, "rank '{}' does not have 8 squares", rank),
+            FenError::INVALID_PIECE(piece) => write!(f, "invalid piece '{}'", piece),
+            FenError::INVALID_SIDE_TO_MOVE(side) => write!(f, "invalid side to move '{}'", side),
+            FenError::INVALID_CASTLING_RIGHTS(rights) => write!(f, "invalid castling rights '{}'", rights),
+            FenError::INVALID_EN_PASSANT_SQUARE(square) => write!(f, "invalid en passant square '{}'", square),
+            FenError::INVALID_HALF_MOVE_CLOCK(clock) => write!(f, "invalid half move clock '{}'", clock),
+            FenError::INVALID_FULL_MOVE_NUMBER(number) => write!(f, "invalid full move number '{}'", number),
+            FenError::WRONG_KING_COUNT(colour, count) => write!(f, "{} must have exactly one king but has {}", colour_name(colour), count),
+            FenError::TOO_MANY_PAWNS(colour, count) => write!(f, "{} has {} pawns", colour_name(colour), count),
+            FenError::TOO_MANY_PROMOTED_PIECES(colour) => write!(f, "{} has more promoted pieces than missing pawns", colour_name(colour)),
+            FenError::PAWN_ON_BACK_RANK(square) => write!(f, "pawn on the first or last rank at {}", square),
+            FenError::CASTLING_RIGHTS_WITHOUT_PIECES(right) => write!(f, "castling right '{}' without the king and rook on their home squares", right),
+            FenError::IMPOSSIBLE_EN_PASSANT_SQUARE(square) => write!(f, "en passant square {} does not follow a double pawn push", square),
+            FenError::OPPONENT_IN_CHECK => write!(f, "the side not to move is in check"),
+        }
+    }
+}
+
+impl Error for FenError {}
+
+fn colour_name(colour: &PlayerColour) -> &'static str {
+    match colour { PlayerColour::WHITE => "white", PlayerColour::BLACK => "black" }
+}
+
+pub struct FenValidator {
+
+}
+
+impl FenValidator {
+    // Parses a FEN string into a position, rejecting anything that can't come up in a legal game.  In lenient mode, castling
+    // rights without the king and rook on their home squares are dropped and an impossible en passant square is cleared
+    // instead of being reported (the other rules still apply).  As in Position::from_fen, an empty string is the start position
+    pub fn parse(fen_str: &str, lenient: bool) -> Result<Position, FenError> {
+        FenValidator::parse_with_mode(fen_str, lenient, false)
+    }
+
+    // Same as parse(), but for Chess960 positions (see Position::from_chess960_fen)
+    pub fn parse_chess960(fen_str: &str, lenient: bool) -> Result<Position, FenError> {
+        FenValidator::parse_with_mode(fen_str, lenient, true)
+    }
+
+    fn parse_with_mode(fen_str: &str, lenient: bool, chess960: bool) -> Result<Position, FenError> {
+        let fen_str = if fen_str.trim().is_empty() { START_POSITION } else { fen_str };
+        FenValidator::check_syntax(fen_str)?;
+        let mut position = Position::from_checked_fen(fen_str, chess960);
+        FenValidator::check_position(&mut position, lenient)?;
+        Ok(position)
+    }
+
+    // Checks the format of each field only, which is all that Position::from_fen needs to build a position
+    pub fn check_syntax(fen_str: &str) -> Result<(), FenError> {
+        let tokens: Vec<&str> = fen_str.split_whitespace().collect();
+        if tokens.len() != 6 { return Err(FenError::WRONG_FIELD_COUNT(tokens.len())); }
+
+        let ranks: Vec<&str> = tokens[0].split('/').collect();
+        if ranks.len() != 8 { return Err(FenError::WRONG_RANK_COUNT(ranks.len())); }
+        for rank in ranks.iter() {
+            let mut square_count = 0;
+            for c in rank.chars() {
+                match c {
+                    'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k' => square_count += 1,
+                    '1'..='8' => square_count += c.to_digit(10).unwrap(),
+                    _ => return Err(FenError::INVALID_PIECE(c)),
+                }
+            }
+            if square_count != 8 { return Err(FenError::WRONG_RANK_LENGTH(rank.to_string())); }
+        }
+
+        if tokens[1] != "w" && tokens[1] != "b" { return Err(FenError::INVALID_SIDE_TO_MOVE(tokens[1].to_string())); }
+
+        // Each of KQkq (or the file of a castling rook, as in Shredder-FEN / X-FEN) may appear at most once
+        let castling_rights = tokens[2];
+        if castling_rights != "-" {
+            let mut seen = String::with_capacity(4);
+            for c in castling_rights.chars() {
+                if !"KQkqABCDEFGHabcdefgh".contains(c) || seen.contains(c) { return Err(FenError::INVALID_CASTLING_RIGHTS(castling_rights.to_string())); }
+                seen.push(c);
+            }
+            if seen.is_empty() { return Err(FenError::INVALID_CASTLING_RIGHTS(castling_rights.to_string())); }
+        }
+
+        let en_passant = tokens[3];
+        if en_passant != "-" {
+            let chars: Vec<char> = en_passant.chars().collect();
+            if chars.len() != 2 || !('a'..='h').contains(&chars[0]) || (chars[1] != '3' && chars[1] != '6') {
+                return Err(FenError::INVALID_EN_PASSANT_SQUARE(en_passant.to_string()));
+            }
+        }
+
+        if tokens[4].parse::<u8>().is_err() { return Err(FenError::INVALID_HALF_MOVE_CLOCK(tokens[4].to_string())); }
+        // A full move number of 0 is out of spec but common enough to accept; it's read as 1
+        if tokens[5].parse::<u16>().is_err() { return Err(FenError::INVALID_FULL_MOVE_NUMBER(tokens[5].to_string())); }
+
+        Ok(())
+    }
+
+    // Checks the piece counts, pawn placement, castling rights, en passant square and that the side that just moved
+    // didn't leave its king in check
+    pub fn check_position(position: &mut Position, lenient: bool) -> Result<(), FenError> {
+        for (colour, white) in [(PlayerColour::WHITE, true), (PlayerColour::BLACK, false)].iter() {
+            let count = |piece_type: PieceType| position.get_piece_bitboard(piece_type, *white).count_ones();
+
+            if count(PieceType::KING) != 1 { return Err(FenError::WRONG_KING_COUNT(*colour, count(PieceType::KING))); }
+            let pawn_count = count(PieceType::PAWN);
+            if pawn_count > 8 { return Err(FenError::TOO_MANY_PAWNS(*colour, pawn_count)); }
+
+            // Every piece beyond the starting set must have come from a promoted pawn
+            let promoted_count = count(PieceType::KNIGHT).saturating_sub(2)
+                + count(PieceType::BISHOP).saturating_sub(2)
+                + count(PieceType::ROOK).saturating_sub(2)
+                + count(PieceType::QUEEN).saturating_sub(1);
+            if promoted_count > 8 - pawn_count { return Err(FenError::TOO_MANY_PROMOTED_PIECES(*colour)); }
+        }
+
+        let back_rank_pawns = (position.wp | position.bp) & (RANK_1 | RANK_8);
+        if back_rank_pawns > 0 {
+            return Err(FenError::PAWN_ON_BACK_RANK(PositionHelper::algebraic_from_index(back_rank_pawns.trailing_zeros() as u8)));
+        }
+
+        for index in 0..4 {
+            let right_pos = SINGLE_BITBOARDS[CASTLING_KING_TARGETS[index]];
+            if position.castling_rights & right_pos > 0 && !position.has_castling_pieces(index) {
+                if !lenient { return Err(FenError::CASTLING_RIGHTS_WITHOUT_PIECES(CASTLING_CHARS[index])); }
+                position.castling_rights &= !right_pos;
+            }
+        }
+
+        if position.en_passant_sq > 0 && !FenValidator::is_possible_en_passant_square(position) {
+            if !lenient {
+                return Err(FenError::IMPOSSIBLE_EN_PASSANT_SQUARE(PositionHelper::algebraic_from_index(position.en_passant_sq.trailing_zeros() as u8)));
+            }
+            position.en_passant_sq = 0;
+        }
+
+        // The king of the side that just moved can't be attacked by the side to move
+        let enemy_king_pos = if position.white_to_move { position.bk } else { position.wk };
+        if position.calc_attackers_to(position.all_occupancy, enemy_king_pos.trailing_zeros() as usize) & position.friendly_occupancy > 0 {
+            return Err(FenError::OPPONENT_IN_CHECK);
+        }
+
+        Ok(())
+    }
+
+    // The en passant square must be just behind a pawn of the side that just moved (which has come from the square in
+    // front of it) on the third or sixth rank, with both squares it passed over now empty
+    fn is_possible_en_passant_square(position: &Position) -> bool {
+        let en_passant_sq = position.en_passant_sq;
+        if position.white_to_move {
+            en_passant_sq & RANK_6 > 0 && (en_passant_sq >> 8) & position.bp > 0 && (en_passant_sq | en_passant_sq << 8) & position.all_occupancy == 0
+        } else {
+            en_passant_sq & RANK_3 > 0 && (en_passant_sq << 8) & position.wp > 0 && (en_passant_sq | en_passant_sq >> 8) & position.all_occupancy == 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_fens() {
+        let fens = [
+            START_POSITION,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            // Promoted pieces in place of missing pawns
+            "3k4/8/8/8/8/8/8/QQQQKQQQ b - - 0 60",
+        ];
+        for fen in fens.iter() {
+            let position = FenValidator::parse(fen, false).unwrap();
+            assert_eq!(position.to_fen(), *fen);
+        }
+
+        // Empty strings give the start position, the same as Position::from_fen
+        for fen in ["", "  \t"].iter() {
+            assert_eq!(FenValidator::parse(fen, false).unwrap().to_fen(), START_POSITION);
+            assert_eq!(FenValidator::parse_chess960(fen, false).unwrap().to_fen(), START_POSITION);
+        }
+
+        // KQkq only fit this position in Chess960, where they refer to the outermost rooks
+        let fen = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9";
+        assert_eq!(FenValidator::parse_chess960(fen, false).unwrap().to_fen(), fen);
+        assert_eq!(FenValidator::parse(fen, false).err(), Some(FenError::CASTLING_RIGHTS_WITHOUT_PIECES('K')));
+        assert_eq!(FenValidator::parse("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", false).unwrap().to_fen(), fen);
+
+        // A full move number of 0 is read as 1, by both the validator and Position::from_fen
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0";
+        assert_eq!(FenValidator::parse(fen, false).unwrap().to_fen(), START_POSITION);
+        assert_eq!(Position::from_fen(Some(fen), false).unwrap().move_number, 1);
+    }
+
+    #[test]
+    fn test_fen_syntax_errors() {
+        let test_cases = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0", FenError::WRONG_FIELD_COUNT(5)),
+            ("rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::WRONG_RANK_COUNT(7)),
+            ("rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::WRONG_RANK_LENGTH("ppppppp".to_string())),
+            ("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::INVALID_PIECE('9')),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1", FenError::INVALID_PIECE('X')),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1", FenError::INVALID_SIDE_TO_MOVE("x".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1", FenError::INVALID_CASTLING_RIGHTS("KQkx".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAhh - 0 1", FenError::INVALID_CASTLING_RIGHTS("HAhh".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KKq - 0 1", FenError::INVALID_CASTLING_RIGHTS("KKq".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e4 0 1", FenError::INVALID_EN_PASSANT_SQUARE("e4".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1", FenError::INVALID_HALF_MOVE_CLOCK("x".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 300 1", FenError::INVALID_HALF_MOVE_CLOCK("300".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 one", FenError::INVALID_FULL_MOVE_NUMBER("one".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 -1", FenError::INVALID_FULL_MOVE_NUMBER("-1".to_string())),
+        ];
+
+        for (fen, expected) in test_cases.iter() {
+            assert_eq!(FenValidator::parse(fen, false).err().as_ref(), Some(expected), "{}", fen);
+            assert_eq!(FenValidator::parse(fen, true).err().as_ref(), Some(expected), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_fen_position_errors() {
+        let test_cases = [
+            ("8/8/8/8/8/8/8/8 w - - 0 1", FenError::WRONG_KING_COUNT(PlayerColour::WHITE, 0)),
+            ("4k3/8/8/8/8/8/8/3KK3 w - - 0 1", FenError::WRONG_KING_COUNT(PlayerColour::WHITE, 2)),
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::WRONG_KING_COUNT(PlayerColour::BLACK, 0)),
+            ("4k3/pppppppp/p7/8/8/8/8/4K3 w - - 0 1", FenError::TOO_MANY_PAWNS(PlayerColour::BLACK, 9)),
+            ("3qk3/8/8/8/8/8/PPPPPPPP/QQQQKQQQ w - - 0 1", FenError::TOO_MANY_PROMOTED_PIECES(PlayerColour::WHITE)),
+            ("4k3/8/8/8/8/8/PPPPPPP1/NNNBKQQ1 w - - 0 1", FenError::TOO_MANY_PROMOTED_PIECES(PlayerColour::WHITE)),
+            ("4k3/8/8/8/8/8/8/P3K3 w - - 0 1", FenError::PAWN_ON_BACK_RANK("a1".to_string())),
+            ("4k2p/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PAWN_ON_BACK_RANK("h8".to_string())),
+            ("r3k2r/8/8/8/8/8/8/R3K3 w KQkq - 0 1", FenError::CASTLING_RIGHTS_WITHOUT_PIECES('K')),
+            ("r3k2r/8/8/8/8/8/8/4K2R w Q - 0 1", FenError::CASTLING_RIGHTS_WITHOUT_PIECES('Q')),
+            // Outside of Chess960 the king must be on the e-file, and there must be a rook on the file given
+            ("r3k2r/8/8/8/8/8/8/R2K3R w Q - 0 1", FenError::CASTLING_RIGHTS_WITHOUT_PIECES('Q')),
+            ("r3k2r/8/8/8/8/8/8/1R1K3R w C - 0 1", FenError::CASTLING_RIGHTS_WITHOUT_PIECES('Q')),
+            ("4k2r/8/8/8/8/8/8/R3K2R w KQq - 0 1", FenError::CASTLING_RIGHTS_WITHOUT_PIECES('q')),
+            // Wrong rank for the side to move, no pawn that could have just moved, and a blocked square
+            ("4k3/8/8/8/4P3/8/8/4K3 w - e3 0 1", FenError::IMPOSSIBLE_EN_PASSANT_SQUARE("e3".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 b - e3 0 1", FenError::IMPOSSIBLE_EN_PASSANT_SQUARE("e3".to_string())),
+            ("4k3/8/4n3/4p3/8/8/8/4K3 w - e6 0 1", FenError::IMPOSSIBLE_EN_PASSANT_SQUARE("e6".to_string())),
+            ("4k3/8/8/8/8/8/4Q3/4K3 w - - 0 1", FenError::OPPONENT_IN_CHECK),
+            ("8/8/8/8/8/8/3k4/4K3 b - - 0 1", FenError::OPPONENT_IN_CHECK),
+        ];
+
+        for (fen, expected) in test_cases.iter() {
+            assert_eq!(FenValidator::parse(fen, false).err().as_ref(), Some(expected), "{}", fen);
+        }
+
+        // The side to move can be in check
+        assert!(FenValidator::parse("4k3/8/8/8/8/8/4q3/4K3 w - - 0 1", false).is_ok());
+    }
+
+    #[test]
+    fn test_lenient_fen_repairs() {
+        // Castling rights without the pieces and impossible en passant squares are dropped rather than rejected
+        let position = FenValidator::parse("r3k2r/8/8/8/4P3/8/8/R3K3 w KQkq e3 0 1", true).unwrap();
+        assert_eq!(position.to_fen(), "r3k2r/8/8/8/4P3/8/8/R3K3 w Qkq - 0 1");
+
+        let position = FenValidator::parse("4k3/8/8/8/8/8/8/4K2R b KQk - 0 1", true).unwrap();
+        assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/8/4K2R b K - 0 1");
+
+        // Other rules still apply
+        assert_eq!(FenValidator::parse("4k3/8/8/8/8/8/4Q3/4K3 w K - 0 1", true).err(), Some(FenError::OPPONENT_IN_CHECK));
+        assert_eq!(FenValidator::parse("4k3/8/8/8/8/8/8/8 w - - 0 1", true).err(), Some(FenError::WRONG_KING_COUNT(PlayerColour::WHITE, 0)));
+
+        assert_eq!(FenError::OPPONENT_IN_CHECK.to_string(), "the side not to move is in check");
+    }
+}
@@ -1,6 +1,8 @@
 // use array2d::Array2D;
 use simple_error::{bail, SimpleError};
 use crate::constants::*;
+use crate::game::castling::STANDARD_CASTLING_ROOK_SQUARES;
+use crate::game::fenvalidator::FenValidator;
 use crate::game::positionhelper::PositionHelper;
 
 #[derive(Clone)]
@@ -9,6 +11,8 @@ pub struct Position {
     pub wp: u64, pub wn: u64, pub  wb: u64, pub  wr: u64, pub  wq: u64, pub  wk: u64,
     pub bp: u64, pub  bn: u64, pub  bb: u64, pub  br: u64, pub  bq: u64, pub  bk: u64,
     pub en_passant_sq: u64, pub castling_rights: u64,
+    // Squares of the rooks each castling right applies to (see castling.rs), which only differ from the corners in Chess960
+    pub castling_rook_squares: [u8; 4], pub chess960: bool,
     // Pin rays limit piece movement (but not the squares the piece is attacking)
     // Check ray limits both since a piece cannot attack a square outside
     // the checking ray if its king is in check
@@ -34,6 +38,7 @@ impl Default for Position {
             friendly_occupancy: 0, enemy_occupancy: 0,
 
             en_passant_sq: 0, castling_rights: 0,
+            castling_rook_squares: STANDARD_CASTLING_ROOK_SQUARES, chess960: false,
             white_to_move: true,
             king_in_check: false, king_in_double_check: false,
             is_stalemate: false, is_checkmate: false,
@@ -45,13 +50,33 @@ impl Default for Position {
 
 impl Position {
     pub fn from_fen(fen_str: Option<&str>, print_pos: bool) -> Result<Position, SimpleError> {
+        Position::from_fen_with_mode(fen_str, print_pos, false)
+    }
+
+    // Same as from_fen(), but for Chess960 positions, where KQkq in the castling field refer to the outermost rooks
+    // (as in X-FEN) instead of the rooks in the corners
+    pub fn from_chess960_fen(fen_str: Option<&str>, print_pos: bool) -> Result<Position, SimpleError> {
+        Position::from_fen_with_mode(fen_str, print_pos, true)
+    }
+
+    fn from_fen_with_mode(fen_str: Option<&str>, print_pos: bool, chess960: bool) -> Result<Position, SimpleError> {
         // Default to starting board position
         let mut pos_str: &str = fen_str.unwrap_or(START_POSITION);
         if pos_str.trim().len() <= 0 { pos_str = START_POSITION; }
-        let pos_str_tokens: Vec<&str> = pos_str.split(' ').collect();
-        if pos_str_tokens.len() != 6 { bail!("Invalid FEN string {}", pos_str) }
 
-        let mut position = Position::default();
+        // Only the format of the fields is checked here; FenValidator::parse() also rejects unreachable positions
+        if let Err(e) = FenValidator::check_syntax(pos_str) { bail!("Invalid FEN string {}: {}", pos_str, e) }
+        let position = Position::from_checked_fen(pos_str, chess960);
+
+        if print_pos { PositionHelper::print_position(&position); }
+
+        Ok(position)
+    }
+
+    // Builds the position from a FEN string that has already passed FenValidator::check_syntax()
+    pub fn from_checked_fen(pos_str: &str, chess960: bool) -> Position {
+        let pos_str_tokens: Vec<&str> = pos_str.split_whitespace().collect();
+        let mut position = Position { chess960, ..Position::default() };
 
         // Setup bitboards
         let board_setup_chars = pos_str_tokens[0].chars();
@@ -72,39 +97,26 @@ impl Position {
                 'k' => position.bk |= cur_bit,
                 '/' => cur_bit = cur_bit.rotate_right(16),
                 '1'..='8' => cur_bit = cur_bit.rotate_left(board_setup_char.to_digit(10).unwrap()),
-
-                _ => bail!("Invalid FEN string {}", pos_str)
+                _ => ()
             }
             if board_setup_char as u32 > '8' as u32 { cur_bit = cur_bit.rotate_left(1); }
         }
 
         // Set position properties
-        position.white_to_move = pos_str_tokens[1].to_lowercase() != "b";
+        position.white_to_move = pos_str_tokens[1] != "b";
         if pos_str_tokens[3].len() == 2 {
             position.en_passant_sq = PositionHelper::bitboard_from_algebraic(vec![pos_str_tokens[3]]);
         }
         position.fifty_move_count = pos_str_tokens[4].parse().unwrap();
-        position.move_number = pos_str_tokens[5].parse().unwrap();
-
-        // Castling rights
-        if pos_str_tokens[2] != "-" {
-            for castle_char in pos_str_tokens[2].chars() {
-                match castle_char {
-                    'K' => position.castling_rights |= SINGLE_BITBOARDS[6],
-                    'Q' => position.castling_rights |= SINGLE_BITBOARDS[2],
-                    'k' => position.castling_rights |= SINGLE_BITBOARDS[62],
-                    'q' => position.castling_rights |= SINGLE_BITBOARDS[58],
-                    _ => bail!("Invalid castling side {}", pos_str_tokens[2])
-                };
-            }
-        }
+        position.move_number = pos_str_tokens[5].parse::<u16>().unwrap().max(1);
+
+        // Castling rights (needs the pieces to be set up first to find the castling rooks)
+        position.set_castling_rights_from_fen(pos_str_tokens[2]);
 
         // Set occupancies
         position.update_occupancy();
 
-        if print_pos { PositionHelper::print_position(&position); }
-
-        Ok(position)
+        position
     }
 
     // pub fn calc_occupancy(&self) -> (u64, u64, u64) {
@@ -132,8 +144,30 @@ impl Position {
         self.all_occupancy = self.white_occupancy | self.black_occupancy;
         self.non_occupancy = !self.all_occupancy;
     }
+
+    /// Returns a hash of the piece placement, side to move, castling rights and en passant square
+    /// (i.e. the move counters are ignored) so that transpositions / repeated positions hash the same
+    /// The hash (FNV-1a) is the same between runs, so it can be used to deduplicate training data
+    pub fn calc_position_hash(&self) -> u64 {
+        let bitboards = [self.wp, self.wn, self.wb, self.wr, self.wq, self.wk,
+            self.bp, self.bn, self.bb, self.br, self.bq, self.bk,
+            self.en_passant_sq, self.castling_rights];
+        bitboards.iter().flat_map(|bitboard| bitboard.to_le_bytes())
+            .chain(self.castling_rook_squares)
+            .chain([self.white_to_move as u8])
+            .fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+    }
     
     pub fn to_fen(&self) -> String {
+        self.to_fen_with_castling(self.get_castling_fen_string(false))
+    }
+
+    // Same as to_fen(), but with the castling rights given by the rook files as in Shredder-FEN (e.g. HAha)
+    pub fn to_shredder_fen(&self) -> String {
+        self.to_fen_with_castling(self.get_castling_fen_string(true))
+    }
+
+    fn to_fen_with_castling(&self, castling_field: String) -> String {
         let mut result: Vec<char> = vec![];
         
         for rank in (0..8).rev() {
@@ -164,14 +198,7 @@ impl Position {
             false => result.push('b')
         }
 
-        result.push(' ');
-        if self.castling_rights <= 0 { result.push('-'); }
-        if self.castling_rights & SINGLE_BITBOARDS[6] > 0 { result.push('K');}
-        if self.castling_rights & SINGLE_BITBOARDS[2] > 0 { result.push('Q');}
-        if self.castling_rights & SINGLE_BITBOARDS[62] > 0 { result.push('k');}
-        if self.castling_rights & SINGLE_BITBOARDS[58] > 0 { result.push('q');}
-
-        let mut result: Vec<String> = vec![String::from_iter(result.iter())];
+        let mut result: Vec<String> = vec![String::from_iter(result.iter()), castling_field];
 
         // result.push(String::from(" "));
         if self.en_passant_sq <= 0 { result.push(String::from("-")); }
@@ -265,5 +292,26 @@ mod tests {
         // println!("Elapsed time: {:.2?}", before.elapsed());
     }
 
+    #[test]
+    fn test_calc_position_hash() {
+        // Move counters are ignored, but the side to move, castling rights and en passant square are not
+        let position = Position::from_fen(Some("r2q1rk1/pp2ppbp/2p2np1/6B1/3PP1b1/Q1P2N2/P4PPP/3RKB1R b Kq g3 4 13"), false).unwrap();
+        let hash = position.calc_position_hash();
+        assert_eq!(hash, Position::from_fen(Some("r2q1rk1/pp2ppbp/2p2np1/6B1/3PP1b1/Q1P2N2/P4PPP/3RKB1R b Kq g3 0 20"), false).unwrap().calc_position_hash());
+        assert_ne!(hash, Position::from_fen(Some("r2q1rk1/pp2ppbp/2p2np1/6B1/3PP1b1/Q1P2N2/P4PPP/3RKB1R w Kq g3 4 13"), false).unwrap().calc_position_hash());
+        assert_ne!(hash, Position::from_fen(Some("r2q1rk1/pp2ppbp/2p2np1/6B1/3PP1b1/Q1P2N2/P4PPP/3RKB1R b K g3 4 13"), false).unwrap().calc_position_hash());
+        assert_ne!(hash, Position::from_fen(Some("r2q1rk1/pp2ppbp/2p2np1/6B1/3PP1b1/Q1P2N2/P4PPP/3RKB1R b Kq - 4 13"), false).unwrap().calc_position_hash());
+
+        // The hash must not change between runs or Rust releases
+        assert_eq!(Position::from_fen(None, false).unwrap().calc_position_hash(), 9787068876521803068);
+    }
 
+    #[test]
+    fn test_from_fen_invalid_fields() {
+        // Malformed fields are reported as errors rather than panicking
+        assert!(Position::from_fen(Some("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1"), false).is_err());
+        assert!(Position::from_fen(Some("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 y"), false).is_err());
+        assert!(Position::from_fen(Some("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPP/RNBQKBNR w KQkq - 0 1"), false).is_err());
+        assert!(Position::from_fen(Some("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq"), false).is_err());
+    }
 }
\ No newline at end of file
@@ -1,2 +1,4 @@
 pub mod positionanalyzer;
-pub mod kingattackrayanalyzer;
\ No newline at end of file
+pub mod kingattackrayanalyzer;
+pub mod staticexchange;
+pub mod movelegality;
\ No newline at end of file
@@ -0,0 +1,301 @@
+use crate::constants::*;
+use crate::game::castling::CASTLING_KING_TARGETS;
+use crate::game::moves::compactmove::CompactMove;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::position::Position;
+
+impl Position {
+    // Returns true if the move is legal in this position, without generating the full move list.  Like the move
+    // generation itself, this relies on the pin ray masks, check ray mask and check flags stored on the position, so the
+    // position must have been analyzed first (e.g. by PositionAnalyzer::calc_legal_moves)
+    pub fn is_legal(&self, game_move: &GameMove) -> bool {
+        if game_move.source_square > 63 || game_move.target_square > 63 { return false; }
+
+        let source_square = game_move.source_square as usize;
+        let target_square = game_move.target_square as usize;
+        let target_pos = SINGLE_BITBOARDS[target_square];
+
+        // The moving piece must belong to the side to move and can't land on a friendly piece (except when castling in
+        // Chess960, where the king can finish on its own square or the castling rook's)
+        if self.get_piece_bitboard(game_move.piece, self.white_to_move) & SINGLE_BITBOARDS[source_square] == 0 { return false; }
+        let is_castle = game_move.piece == PieceType::KING && game_move.move_kind == MoveKind::CASTLE;
+        if target_pos & self.friendly_occupancy > 0 && !is_castle { return false; }
+
+        // The capture flag, captured piece and move kind must agree with what is on the board
+        let is_en_passant = game_move.piece == PieceType::PAWN && target_pos & self.en_passant_sq > 0;
+        let captured_piece = if is_en_passant {
+            PieceType::PAWN
+        } else if target_pos & self.enemy_occupancy > 0 && !is_castle {
+            self.get_piece_type_on(target_pos).unwrap_or(PieceType::NONE)
+        } else {
+            PieceType::NONE
+        };
+        if game_move.is_capture != (captured_piece != PieceType::NONE) || game_move.captured_piece != captured_piece { return false; }
+        if game_move.move_kind != self.calc_move_kind(game_move, is_en_passant) { return false; }
+
+        // Pawns must promote (to a knight, bishop, rook or queen) on the last rank, and nothing else can promote
+        let needs_promotion = game_move.piece == PieceType::PAWN && target_pos & (RANK_1 | RANK_8) > 0;
+        match game_move.promotion_piece {
+            PieceType::NONE => if needs_promotion { return false; },
+            PieceType::KNIGHT | PieceType::BISHOP | PieceType::ROOK | PieceType::QUEEN => if !needs_promotion { return false; },
+            _ => return false,
+        }
+
+        if game_move.piece == PieceType::KING {
+            return self.is_legal_king_move(source_square, target_square, is_castle);
+        }
+
+        // Only the king can move out of a double check
+        if self.king_in_double_check { return false; }
+
+        let movement_squares = match game_move.piece {
+            PieceType::KNIGHT => KNIGHT_ATTACKS[source_square],
+            PieceType::BISHOP => Position::calc_slider_rays(self.all_occupancy, source_square).1,
+            PieceType::ROOK => Position::calc_slider_rays(self.all_occupancy, source_square).0,
+            PieceType::QUEEN => {
+                let (rook_rays, bishop_rays) = Position::calc_slider_rays(self.all_occupancy, source_square);
+                rook_rays | bishop_rays
+            },
+            PieceType::PAWN => self.calc_pawn_movement_squares(source_square),
+            _ => 0,
+        };
+
+        // A pinned piece can only move along its pin ray and, when in check, the move must block the check or capture the
+        // checking piece (which an en passant capture can also do if the checking piece is the pawn that just moved)
+        let mut check_ray_mask = self.check_ray_mask;
+        if is_en_passant {
+            let captured_pawn_pos = if self.white_to_move { target_pos >> 8 } else { target_pos << 8 };
+            if captured_pawn_pos == self.check_ray_mask { check_ray_mask |= target_pos; }
+        }
+
+        movement_squares & self.pin_ray_masks[source_square] & check_ray_mask & target_pos > 0
+    }
+
+    // Returns true if the move (which must be legal in this position) gives check, including discovered checks and checks
+    // given by the rook after castling, without making the move
+    pub fn gives_check(&self, compact_move: CompactMove) -> bool {
+        let source_pos = SINGLE_BITBOARDS[compact_move.get_source_square() as usize];
+        let target_pos = SINGLE_BITBOARDS[compact_move.get_target_square() as usize];
+        let enemy_king_pos = if self.white_to_move { self.bk } else { self.wk };
+        let enemy_king_square = enemy_king_pos.trailing_zeros() as usize;
+        let piece = self.get_piece_type_on(source_pos).unwrap_or(PieceType::NONE);
+        let moved_piece = if compact_move.is_promotion() { compact_move.get_promotion_piece() } else { piece };
+
+        // Occupancy after the move, including the pawn taken en passant and the rook moved when castling
+        let mut occupancy = (self.all_occupancy & !source_pos) | target_pos;
+        if compact_move.is_en_passant() {
+            occupancy &= !(if self.white_to_move { target_pos >> 8 } else { target_pos << 8 });
+        }
+        let (mut rook_source_pos, mut rook_target_pos) = (0u64, 0u64);
+        if compact_move.is_castle() {
+            let (rook_source, rook_target) = self.get_castling_rook_move(compact_move.get_target_square() as usize);
+            rook_source_pos = SINGLE_BITBOARDS[rook_source];
+            rook_target_pos = SINGLE_BITBOARDS[rook_target];
+            // In Chess960, the king or rook can finish on the square the other started on
+            occupancy = (self.all_occupancy & !(source_pos | rook_source_pos)) | target_pos | rook_target_pos;
+        }
+
+        // The side to move's pieces after the move
+        let pieces_after_move = |piece_type: PieceType| {
+            let mut pieces = self.get_piece_bitboard(piece_type, self.white_to_move);
+            if piece_type == piece { pieces &= !source_pos; }
+            if piece_type == moved_piece { pieces |= target_pos; }
+            if piece_type == PieceType::ROOK && rook_source_pos > 0 { pieces = (pieces & !rook_source_pos) | rook_target_pos; }
+            pieces
+        };
+
+        // Pawns that attack a square sit diagonally behind it (from their own point of view)
+        let pawn_check_squares = if self.white_to_move {
+            ((enemy_king_pos & !A_FILE) >> 9) | ((enemy_king_pos & !H_FILE) >> 7)
+        } else {
+            ((enemy_king_pos & !A_FILE) << 7) | ((enemy_king_pos & !H_FILE) << 9)
+        };
+
+        let (rook_rays, bishop_rays) = Position::calc_slider_rays(occupancy, enemy_king_square);
+
+        (pawn_check_squares & pieces_after_move(PieceType::PAWN))
+            | (KNIGHT_ATTACKS[enemy_king_square] & pieces_after_move(PieceType::KNIGHT))
+            | (rook_rays & (pieces_after_move(PieceType::ROOK) | pieces_after_move(PieceType::QUEEN)))
+            | (bishop_rays & (pieces_after_move(PieceType::BISHOP) | pieces_after_move(PieceType::QUEEN)))
+            > 0
+    }
+
+    // The kind a move from the source to the target square must have been generated with in this position
+    fn calc_move_kind(&self, game_move: &GameMove, is_en_passant: bool) -> MoveKind {
+        let square_diff = (game_move.target_square as i8 - game_move.source_square as i8).abs();
+        match game_move.piece {
+            PieceType::PAWN if is_en_passant => MoveKind::EN_PASSANT,
+            PieceType::PAWN if square_diff == 16 => MoveKind::DOUBLE_PAWN_PUSH,
+            // Castling can't be told apart from a normal king move by the squares alone in Chess960
+            PieceType::KING if game_move.move_kind == MoveKind::CASTLE => MoveKind::CASTLE,
+            _ => MoveKind::NORMAL,
+        }
+    }
+
+    // Squares the pawn on the source square can move to, by pushing or capturing (pins and checks are not considered)
+    fn calc_pawn_movement_squares(&self, source_square: usize) -> u64 {
+        let source_pos = SINGLE_BITBOARDS[source_square];
+        let capture_squares = self.enemy_occupancy | self.en_passant_sq;
+
+        if self.white_to_move {
+            let single_push = (source_pos << 8) & self.non_occupancy;
+            let double_push = ((single_push & RANK_3) << 8) & self.non_occupancy;
+            let captures = (((source_pos & !A_FILE) << 7) | ((source_pos & !H_FILE) << 9)) & capture_squares;
+            single_push | double_push | captures
+        } else {
+            let single_push = (source_pos >> 8) & self.non_occupancy;
+            let double_push = ((single_push & RANK_6) >> 8) & self.non_occupancy;
+            let captures = (((source_pos & !A_FILE) >> 9) | ((source_pos & !H_FILE) >> 7)) & capture_squares;
+            single_push | double_push | captures
+        }
+    }
+
+    fn is_legal_king_move(&self, source_square: usize, target_square: usize, is_castle: bool) -> bool {
+        let source_pos = SINGLE_BITBOARDS[source_square];
+
+        // The king is taken off the board so that it can't hide from a slider behind itself
+        let occupancy = self.all_occupancy & !source_pos;
+        let is_attacked = |square: usize| self.calc_attackers_to(occupancy, square) & self.enemy_occupancy > 0;
+
+        if !is_castle {
+            return KING_ATTACKS[source_square] & SINGLE_BITBOARDS[target_square] > 0 && !is_attacked(target_square);
+        }
+
+        // Otherwise, the target must be one of the side to move's castling squares, with can_castle() checking the rest
+        let index = Position::get_castling_index(target_square);
+        target_square == CASTLING_KING_TARGETS[index] && (index < 2) == self.white_to_move && self.can_castle(index, is_attacked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::analysis::kingattackrayanalyzer::KingAttackRayAnalyzer;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+    use crate::game::moves::gamemovelist::GameMoveList;
+    use crate::game::moves::movemaker::MoveMaker;
+
+    // Reference implementation for gives_check(): plays the move and looks for attacks on the enemy king
+    fn is_checking_move(position: &Position, compact_move: CompactMove) -> bool {
+        let mut next_position = position.clone();
+        let (player, enemy_king_pos) = if position.white_to_move { (PlayerColour::WHITE, position.bk) } else { (PlayerColour::BLACK, position.wk) };
+        MoveMaker::default().make_compact_move(&mut next_position, compact_move, false);
+        PositionAnalyzer::calc_all_attacked_squares(&next_position, &player, 0, &mut KingAttackRayAnalyzer::default()) & enemy_king_pos > 0
+    }
+
+    fn init_position(fen: &str) -> (Position, GameMoveList) {
+        let mut position = Position::from_fen(Some(fen), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+        (position, move_list)
+    }
+
+    fn make_game_move(piece: PieceType, source_square: u8, target_square: u8, captured_piece: PieceType, promotion_piece: PieceType, move_kind: MoveKind) -> GameMove {
+        GameMove { piece, source_square, target_square, promotion_piece, is_capture: captured_piece != PieceType::NONE, captured_piece, move_kind }
+    }
+
+    // Tries every piece on every source square to every target square (with each captured piece, promotion piece and move
+    // kind) and checks is_legal() only accepts the moves in the generated legal move list
+    fn check_all_candidate_moves(position: &Position, move_list: &GameMoveList) {
+        let legal_moves = format!("{:?}", move_list);
+        let mut accepted_moves = GameMoveList::default();
+
+        for source_square in 0..64u8 {
+            let piece = match position.get_piece_type_on(SINGLE_BITBOARDS[source_square as usize]) {
+                Some(piece) => piece,
+                None => continue,
+            };
+            for target_square in 0..64u8 {
+                for captured_piece in [PieceType::NONE, PieceType::PAWN, PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK, PieceType::QUEEN].iter() {
+                    for promotion_piece in [PieceType::NONE, PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK, PieceType::QUEEN, PieceType::KING].iter() {
+                        for move_kind in [MoveKind::NORMAL, MoveKind::DOUBLE_PAWN_PUSH, MoveKind::EN_PASSANT, MoveKind::CASTLE].iter() {
+                            let game_move = make_game_move(piece, source_square, target_square, *captured_piece, *promotion_piece, *move_kind);
+                            if position.is_legal(&game_move) {
+                                accepted_moves.add_move(source_square, target_square, CompactMove::from_game_move(&game_move).get_flags());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        assert_eq!(format!("{:?}", accepted_moves), legal_moves, "{}", position.to_fen());
+    }
+
+    #[test]
+    fn test_is_legal() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            // Double check, en passant out of check and en passant exposing the king along the rank
+            "4k3/6N1/5b2/4R3/8/8/8/4K3 b - - 1 2",
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+            "8/8/8/8/k2Pp2Q/8/8/3K4 b - d3 1 2",
+            // Castling through or out of check
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+            "r3k2r/8/8/8/8/5b2/8/R3K2R w KQkq - 0 1",
+            "r3k2r/8/8/8/4r3/8/8/R3K2R w KQkq - 0 1",
+        ];
+
+        for fen in fens.iter() {
+            let (position, move_list) = init_position(fen);
+            check_all_candidate_moves(&position, &move_list);
+        }
+
+        // Moves from the wrong side, off the board or for a piece that isn't on the source square
+        let (position, _) = init_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(position.is_legal(&make_game_move(PieceType::PAWN, 12, 28, PieceType::NONE, PieceType::NONE, MoveKind::DOUBLE_PAWN_PUSH)));
+        assert!(!position.is_legal(&make_game_move(PieceType::PAWN, 52, 36, PieceType::NONE, PieceType::NONE, MoveKind::DOUBLE_PAWN_PUSH)));
+        assert!(!position.is_legal(&make_game_move(PieceType::PAWN, 12, 64, PieceType::NONE, PieceType::NONE, MoveKind::NORMAL)));
+        assert!(!position.is_legal(&make_game_move(PieceType::KNIGHT, 12, 28, PieceType::NONE, PieceType::NONE, MoveKind::DOUBLE_PAWN_PUSH)));
+
+        // Moves recorded with the wrong captured piece or move kind
+        assert!(!position.is_legal(&make_game_move(PieceType::PAWN, 12, 28, PieceType::NONE, PieceType::NONE, MoveKind::NORMAL)));
+        let (position, _) = init_position("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1");
+        assert!(position.is_legal(&make_game_move(PieceType::PAWN, 36, 43, PieceType::PAWN, PieceType::NONE, MoveKind::EN_PASSANT)));
+        assert!(!position.is_legal(&make_game_move(PieceType::PAWN, 36, 43, PieceType::PAWN, PieceType::NONE, MoveKind::NORMAL)));
+        assert!(position.is_legal(&make_game_move(PieceType::ROOK, 0, 56, PieceType::ROOK, PieceType::NONE, MoveKind::NORMAL)));
+        assert!(!position.is_legal(&make_game_move(PieceType::ROOK, 0, 56, PieceType::QUEEN, PieceType::NONE, MoveKind::NORMAL)));
+        assert!(!position.is_legal(&make_game_move(PieceType::KING, 4, 6, PieceType::NONE, PieceType::NONE, MoveKind::NORMAL)));
+    }
+
+    #[test]
+    fn test_gives_check() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            // Castling with check, discovered checks and a discovered check through en passant
+            "5k2/8/8/8/8/8/8/4K2R w K - 0 1",
+            "5k2/8/8/8/8/8/5N2/5RK1 w - - 0 1",
+            "8/8/8/R2pP2k/8/8/8/K7 w - d6 0 1",
+        ];
+
+        // Compare with actually making each move (and its replies) and looking for attacks on the enemy king
+        for fen in fens.iter() {
+            let (position, move_list) = init_position(fen);
+            let mut move_maker = MoveMaker::default();
+
+            for compact_move in move_list.moves().iter() {
+                assert_eq!(position.gives_check(*compact_move), is_checking_move(&position, *compact_move), "{} {:?}", fen, compact_move);
+
+                let mut next_position = position.clone();
+
+                move_maker.make_compact_move(&mut next_position, *compact_move, false);
+                let mut reply_list = GameMoveList::default();
+                PositionAnalyzer::calc_legal_moves(&mut next_position, &mut reply_list);
+                for reply in reply_list.moves().iter() {
+                    assert_eq!(next_position.gives_check(*reply), is_checking_move(&next_position, *reply), "{} {:?} {:?}", fen, compact_move, reply);
+                }
+            }
+        }
+
+        let (position, move_list) = init_position("8/8/8/R2pP2k/8/8/8/K7 w - d6 0 1");
+        assert!(position.gives_check(move_list.get_move_by_uci("e5d6").unwrap()));
+    }
+}
@@ -11,6 +11,17 @@ use crate::game::pieces::rook::*;
 use crate::game::position::Position;
 use crate::game::positionhelper::PositionHelper;
 
+// Subsets of the legal moves that can be generated on their own (e.g. captures only for a quiescence-style lookahead)
+// CAPTURES and QUIETS never overlap and together make up ALL
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MoveGenType {
+    ALL,
+    CAPTURES,   // Captures (including en passant) and all promotions
+    QUIETS,     // All other moves, including castling
+    EVASIONS,   // All legal moves when the side to move is in check, otherwise none
+    CHECKS,     // Moves that give check to the enemy king
+}
+
 pub struct PositionAnalyzer {
 
 }
@@ -49,38 +60,88 @@ impl PositionAnalyzer {
 
     pub fn calc_legal_moves(position: &mut Position, move_list: &mut GameMoveList) {
         let mut king_attack_analyzer = KingAttackRayAnalyzer::default();
+        let enemy_attacked_squares = PositionAnalyzer::calc_king_attack_analysis(position, &mut king_attack_analyzer);
+        PositionAnalyzer::add_legal_moves(position, move_list, enemy_attacked_squares, &mut king_attack_analyzer, u64::MAX, u64::MAX);
 
-        if position.white_to_move {
-            let enemy_attacked_squares= PositionAnalyzer::calc_all_attacked_squares(position, &PlayerColour::BLACK, position.wk, &mut king_attack_analyzer);
+        position.is_stalemate = move_list.list_len == 0 && !position.king_in_check;
+        position.is_checkmate = move_list.list_len == 0 && position.king_in_check;
+    }
 
-            PositionAnalyzer::update_position_from_king_ray_attack_analysis(position, &king_attack_analyzer);
+    // Generates only the legal moves of the given type, by restricting the target squares of each piece generator
+    // (e.g. to the enemy occupancy for captures).  The check flags on the position are always set, but the mate /
+    // stalemate flags are only set by the types that generate every legal move (ALL and CHECKS, or EVASIONS in check)
+    pub fn calc_legal_moves_by_type(position: &mut Position, move_list: &mut GameMoveList, gen_type: MoveGenType) {
+        if gen_type == MoveGenType::ALL {
+            PositionAnalyzer::calc_legal_moves(position, move_list);
+            return;
+        }
 
-            let (_king_attacks, _king_movements) = King::calc_movements(position, position.wk, move_list, enemy_attacked_squares, &mut king_attack_analyzer);
-            if position.king_in_double_check { return; }
+        let start_index = move_list.list_len;
+        let mut king_attack_analyzer = KingAttackRayAnalyzer::default();
+        let enemy_attacked_squares = PositionAnalyzer::calc_king_attack_analysis(position, &mut king_attack_analyzer);
+
+        // Pawns have their own target squares since all promotions are generated with the captures
+        let promotion_squares = RANK_1 | RANK_8;
+        let (target_squares, pawn_target_squares) = match gen_type {
+            MoveGenType::CAPTURES => (position.enemy_occupancy, position.enemy_occupancy | position.en_passant_sq | promotion_squares),
+            MoveGenType::QUIETS => (position.non_occupancy, position.non_occupancy & !position.en_passant_sq & !promotion_squares),
+            MoveGenType::EVASIONS if !position.king_in_check => return,
+            _ => (u64::MAX, u64::MAX),
+        };
+        PositionAnalyzer::add_legal_moves(position, move_list, enemy_attacked_squares, &mut king_attack_analyzer, target_squares, pawn_target_squares);
+
+        if gen_type == MoveGenType::EVASIONS || gen_type == MoveGenType::CHECKS {
+            position.is_stalemate = move_list.list_len <= start_index && !position.king_in_check;
+            position.is_checkmate = move_list.list_len <= start_index && position.king_in_check;
+        }
 
-            let (_pawn_attacks, _pawn_movements) = Pawn::calc_movements(position, position.wp, move_list, 0, &mut king_attack_analyzer);
-            let (_knight_attacks, _knight_movements) = Knight::calc_movements(position, position.wn, move_list, 0, &mut king_attack_analyzer);
-            let (_rook_attacks, _rook_movements) = Rook::calc_movements(position, position.wr, move_list, 0, &mut king_attack_analyzer);
-            let (_bishop_attacks, _bishop_movements) = Bishop::calc_movements(position, position.wb, move_list, 0, &mut king_attack_analyzer);
-            let (_queen_attacks, _queen_movements) = Queen::calc_movements(position, position.wq, move_list, 0, &mut king_attack_analyzer);
+        // Checks can come from any kind of move (including discovered checks), so these are filtered from all the moves
+        if gen_type == MoveGenType::CHECKS {
+            move_list.retain(start_index, |m| position.gives_check(m));
+        }
+    }
 
+    // Sets the check and pin masks on the position and returns the squares attacked by the enemy
+    #[inline(always)]
+    fn calc_king_attack_analysis(position: &mut Position, king_attack_analyzer: &mut KingAttackRayAnalyzer) -> u64 {
+        let enemy_attacked_squares = if position.white_to_move {
+            PositionAnalyzer::calc_all_attacked_squares(position, &PlayerColour::BLACK, position.wk, king_attack_analyzer)
         } else {
-            let enemy_attacked_squares = PositionAnalyzer::calc_all_attacked_squares(position, &PlayerColour::WHITE, position.bk, &mut king_attack_analyzer);
+            PositionAnalyzer::calc_all_attacked_squares(position, &PlayerColour::WHITE, position.bk, king_attack_analyzer)
+        };
 
-            PositionAnalyzer::update_position_from_king_ray_attack_analysis(position, &king_attack_analyzer);
+        PositionAnalyzer::update_position_from_king_ray_attack_analysis(position, king_attack_analyzer);
+        enemy_attacked_squares
+    }
 
-            let (_king_attacks, _king_movements) = King::calc_movements(position, position.bk, move_list, enemy_attacked_squares, &mut king_attack_analyzer);
-            if position.king_in_double_check { return; }
+    // Adds the legal moves onto the target squares (or pawn_target_squares for pawns), assuming the king attack
+    // analysis has already been done
+    #[inline(always)]
+    fn add_legal_moves(position: &Position, move_list: &mut GameMoveList, enemy_attacked_squares: u64, king_attack_analyzer: &mut KingAttackRayAnalyzer, target_squares: u64, pawn_target_squares: u64) {
+        if position.white_to_move {
+            let (_king_attacks, _king_movements) = King::calc_movements_to(position, position.wk, move_list, enemy_attacked_squares, king_attack_analyzer, target_squares);
+
+            // Only the king can move when in double check
+            if !position.king_in_double_check {
+                let (_pawn_attacks, _pawn_movements) = Pawn::calc_movements_to(position, position.wp, move_list, 0, king_attack_analyzer, pawn_target_squares);
+                let (_knight_attacks, _knight_movements) = Knight::calc_movements_to(position, position.wn, move_list, 0, king_attack_analyzer, target_squares);
+                let (_rook_attacks, _rook_movements) = Rook::calc_movements_to(position, position.wr, move_list, 0, king_attack_analyzer, target_squares);
+                let (_bishop_attacks, _bishop_movements) = Bishop::calc_movements_to(position, position.wb, move_list, 0, king_attack_analyzer, target_squares);
+                let (_queen_attacks, _queen_movements) = Queen::calc_movements_to(position, position.wq, move_list, 0, king_attack_analyzer, target_squares);
+            }
 
-            let (_pawn_attacks, _pawn_movements) = Pawn::calc_movements(position, position.bp, move_list, 0, &mut king_attack_analyzer);
-            let (_knight_attacks, _knight_movements) = Knight::calc_movements(position, position.bn, move_list, 0, &mut king_attack_analyzer);
-            let (_rook_attacks, _rook_movements) = Rook::calc_movements(position, position.br, move_list, 0, &mut king_attack_analyzer);
-            let (_bishop_attacks, _bishop_movements) = Bishop::calc_movements(position, position.bb, move_list, 0, &mut king_attack_analyzer);
-            let (_queen_attacks, _queen_movements) = Queen::calc_movements(position, position.bq, move_list, 0, &mut king_attack_analyzer);
+        } else {
+            let (_king_attacks, _king_movements) = King::calc_movements_to(position, position.bk, move_list, enemy_attacked_squares, king_attack_analyzer, target_squares);
+
+            // Only the king can move when in double check
+            if !position.king_in_double_check {
+                let (_pawn_attacks, _pawn_movements) = Pawn::calc_movements_to(position, position.bp, move_list, 0, king_attack_analyzer, pawn_target_squares);
+                let (_knight_attacks, _knight_movements) = Knight::calc_movements_to(position, position.bn, move_list, 0, king_attack_analyzer, target_squares);
+                let (_rook_attacks, _rook_movements) = Rook::calc_movements_to(position, position.br, move_list, 0, king_attack_analyzer, target_squares);
+                let (_bishop_attacks, _bishop_movements) = Bishop::calc_movements_to(position, position.bb, move_list, 0, king_attack_analyzer, target_squares);
+                let (_queen_attacks, _queen_movements) = Queen::calc_movements_to(position, position.bq, move_list, 0, king_attack_analyzer, target_squares);
+            }
         }
-
-        position.is_stalemate = move_list.list_len <= 0 && !position.king_in_check;
-        position.is_checkmate = move_list.list_len <= 0 && position.king_in_check;
     }
 }
 
@@ -88,6 +149,7 @@ impl PositionAnalyzer {
 mod tests {
     use std::time::Instant;
     use crate::test::legalmoveshelper::LegalMovesTestHelper;
+    use crate::benchmarks::perftbenchmark::PerftBenchmark;
 
     use super::*;
 
@@ -122,9 +184,18 @@ mod tests {
         assert_eq!(position.king_in_double_check, true);
 
         for i in 0..move_list.list_len {
-            assert_eq!(move_list.move_list[i].piece as u8, PieceType::KING as u8);
+            assert_eq!(move_list.get_move(i, &position).piece as u8, PieceType::KING as u8);
             // assert_eq!(move_list.piece[i] as u8, PieceType::KING as u8);
         }
+
+        // 2. Double check with no king moves available is still checkmate
+        let mut position = Position::from_fen(Some("3Rkr2/4pp2/5N2/B7/8/8/8/4K3 b - - 1 2"), false).unwrap();
+        move_list.clear();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+
+        assert_eq!(position.king_in_double_check, true);
+        assert_eq!(move_list.list_len, 0);
+        assert_eq!(position.is_checkmate, true);
     }
 
     #[test]
@@ -280,6 +351,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_calc_legal_moves_by_type() {
+        // 1. Non-capturing promotions are generated with the captures
+        let mut position = Position::from_fen(Some("1n2k3/P1P5/8/8/8/8/8/4K3 w - - 0 1"), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves_by_type(&mut position, &mut move_list, MoveGenType::CAPTURES);
+        assert_eq!(format!("{:?}", move_list), "a7a8b a7a8n a7a8q a7a8r a7b8b a7b8n a7b8q a7b8r c7b8b c7b8n c7b8q c7b8r c7c8b c7c8n c7c8q c7c8r");
+
+        // 2. Only checking moves, including castling with check and discovered checks
+        let mut position = Position::from_fen(Some("5k2/8/8/8/8/8/8/4K2R w K - 0 1"), false).unwrap();
+        move_list.clear();
+        PositionAnalyzer::calc_legal_moves_by_type(&mut position, &mut move_list, MoveGenType::CHECKS);
+        assert_eq!(format!("{:?}", move_list), "e1g1 h1f1 h1h8");
+
+        let mut position = Position::from_fen(Some("5k2/8/8/8/8/8/5N2/5RK1 w - - 0 1"), false).unwrap();
+        move_list.clear();
+        PositionAnalyzer::calc_legal_moves_by_type(&mut position, &mut move_list, MoveGenType::CHECKS);
+        assert_eq!(format!("{:?}", move_list), "f2d1 f2d3 f2e4 f2g4 f2h1 f2h3");
+
+        // 3. Evasions are only generated when in check
+        let mut position = Position::from_fen(None, false).unwrap();
+        move_list.clear();
+        PositionAnalyzer::calc_legal_moves_by_type(&mut position, &mut move_list, MoveGenType::EVASIONS);
+        assert_eq!(move_list.list_len, 0);
+
+        // 4. Castling is a quiet move, even when the king stays on its square in Chess960
+        let mut position = Position::from_fen(Some("4k3/8/8/8/8/8/8/6KR w H - 0 1"), false).unwrap();
+        for (gen_type, castle_count) in [(MoveGenType::QUIETS, 1), (MoveGenType::CAPTURES, 0)] {
+            move_list.clear();
+            PositionAnalyzer::calc_legal_moves_by_type(&mut position, &mut move_list, gen_type);
+            assert_eq!(move_list.moves().iter().filter(|m| m.is_castle()).count(), castle_count);
+        }
+    }
+
+    #[test]
+    fn test_perft_by_gen_type() {
+        // Node, capture and check counts from https://www.chessprogramming.org/Perft_Results
+        let test_cases = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3, 8902, 34, 12),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 2, 2039, 351, 3),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 3, 2812, 209, 267),
+        ];
+
+        for (fen, depth, nodes, captures, checks) in test_cases.iter() {
+            for (gen_type, expected) in [(MoveGenType::ALL, nodes), (MoveGenType::CAPTURES, captures), (MoveGenType::CHECKS, checks)].iter() {
+                let mut position = Position::from_fen(Some(fen), false).unwrap();
+                let result = PerftBenchmark::run_perft_by_gen_type(&mut position, *depth, *gen_type);
+                assert_eq!(result.ok(), Some(**expected), "{} {:?}", fen, gen_type);
+            }
+        }
+
+        // Every legal move is an evasion in this position (white is in check)
+        let mut position = Position::from_fen(Some("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1"), false).unwrap();
+        assert_eq!(PerftBenchmark::run_perft_by_gen_type(&mut position, 1, MoveGenType::EVASIONS).ok(), Some(6));
+        assert_eq!(PerftBenchmark::run_perft_by_gen_type(&mut position, 2, MoveGenType::CHECKS).ok(), Some(10));
+    }
+
+    #[test]
+    fn test_chess960_perft() {
+        // Node counts to depths 2 and 3 from the Chess960 perft results at https://www.chessprogramming.org/Chess960_Perft_Results
+        let test_cases = [
+            ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 528, 12189),
+            ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", 807, 18002),
+            ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", 479, 10471),
+            ("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", 593, 13440),
+            ("1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9", 1120, 31058),
+            ("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9", 899, 26578),
+            ("q1bnrkr1/ppppp2p/2n2p2/4b1p1/2NP4/8/PPP1PPPP/QNB1RRKB w ge - 1 9", 860, 24566),
+            ("1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9", 502, 14569),
+        ];
+
+        for (fen, depth_2_nodes, depth_3_nodes) in test_cases.iter() {
+            // The intense verification also checks the X-FEN for each position reached reads back the same
+            let mut position = Position::from_fen(Some(fen), false).unwrap();
+            assert_eq!(PerftBenchmark::run_perft_recursive(&mut position, 2, true, &mut None).ok(), Some(*depth_2_nodes), "{}", fen);
+            assert_eq!(PerftBenchmark::run_perft_by_gen_type(&mut position, 3, MoveGenType::ALL).ok(), Some(*depth_3_nodes), "{}", fen);
+        }
+    }
+
     // #[test]
     // // Result - trailing_zeros() is faster by about 33%
     // fn test_bit_scan_speed() {
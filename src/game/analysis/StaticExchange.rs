@@ -0,0 +1,224 @@
+use crate::constants::*;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::pieces::bishop::Bishop;
+use crate::game::pieces::piece::*;
+use crate::game::pieces::rook::Rook;
+use crate::game::position::Position;
+
+// Piece values used for exchange evaluation, indexed by PieceType.  The king is given a value larger than
+// everything else combined so that an exchange sequence never willingly gives it up
+pub const SEE_PIECE_VALUES: [i32; 7] = [0, 320, 330, 500, 900, 20000, 100];
+
+// Order in which attackers are tried, from least to most valuable
+const SEE_ATTACKER_ORDER: [PieceType; 6] = [PieceType::PAWN, PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK, PieceType::QUEEN, PieceType::KING];
+
+// A capture sequence on one square can never be longer than the number of pieces on the board
+const MAX_EXCHANGE_DEPTH: usize = 32;
+
+impl Position {
+    // Returns the material balance (from the point of view of the side making the move) of the given move followed by
+    // the best sequence of captures and recaptures on its target square.  Each side always recaptures with its least
+    // valuable attacker and may stop the sequence whenever continuing would lose material.  Sliding pieces lined up
+    // behind an attacker (x-rays) join the exchange once the pieces in front of them have been used up
+    pub fn see(&self, game_move: &GameMove) -> i32 {
+        let target_square = game_move.target_square as usize;
+        let target_pos = SINGLE_BITBOARDS[target_square];
+        let source_pos = SINGLE_BITBOARDS[game_move.source_square as usize];
+        let mut occupancy = self.all_occupancy ^ source_pos;
+
+        // The piece captured by the move itself (an en passant capture takes the pawn beside the target square)
+        let mut captured_value = match self.get_piece_type_on(target_pos) {
+            Some(piece_type) => SEE_PIECE_VALUES[piece_type as usize],
+            None if game_move.move_kind == MoveKind::EN_PASSANT => {
+                occupancy ^= if self.white_to_move { target_pos >> 8 } else { target_pos << 8 };
+                SEE_PIECE_VALUES[PieceType::PAWN as usize]
+            },
+            None => 0,
+        };
+        let mut attacker_value = SEE_PIECE_VALUES[game_move.piece as usize];
+        if game_move.promotion_piece != PieceType::NONE {
+            attacker_value = SEE_PIECE_VALUES[game_move.promotion_piece as usize];
+            captured_value += attacker_value - SEE_PIECE_VALUES[PieceType::PAWN as usize];
+        }
+
+        let mut gain = [0i32; MAX_EXCHANGE_DEPTH];
+        gain[0] = captured_value;
+        let mut depth = 0usize;
+        let mut white_to_capture = !self.white_to_move;
+
+        let mut attackers = self.calc_attackers_to(occupancy, target_square) & occupancy;
+
+        while depth + 1 < MAX_EXCHANGE_DEPTH {
+            let side_occupancy = if white_to_capture { self.white_occupancy } else { self.black_occupancy };
+            let side_attackers = attackers & side_occupancy;
+            if side_attackers == 0 { break; }
+
+            let (attacker_type, attacker_pos) = self.get_least_valuable_attacker(side_attackers, white_to_capture);
+
+            // The king can only take if the other side has nothing left that could recapture
+            if attacker_type == PieceType::KING && attackers & !side_occupancy > 0 { break; }
+
+            depth += 1;
+            gain[depth] = attacker_value - gain[depth - 1];
+            attacker_value = SEE_PIECE_VALUES[attacker_type as usize];
+
+            // A pawn recapturing on the back rank promotes (always to a queen here)
+            if attacker_type == PieceType::PAWN && target_pos & (RANK_1 | RANK_8) > 0 {
+                let promotion_gain = SEE_PIECE_VALUES[PieceType::QUEEN as usize] - SEE_PIECE_VALUES[PieceType::PAWN as usize];
+                gain[depth] += promotion_gain;
+                attacker_value += promotion_gain;
+            }
+
+            // Removing the attacker can uncover a slider behind it
+            occupancy ^= attacker_pos;
+            attackers = self.calc_attackers_to(occupancy, target_square) & occupancy;
+            white_to_capture = !white_to_capture;
+        }
+
+        // Each side picks the better of stopping or continuing the exchange, working back from the end of the sequence
+        while depth > 0 {
+            gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+            depth -= 1;
+        }
+
+        gain[0]
+    }
+
+    // Returns true if the static exchange evaluation of the move is at least the given threshold
+    // eg. see_ge(move, 0) is true for any capture that doesn't lose material
+    pub fn see_ge(&self, game_move: &GameMove, threshold: i32) -> bool {
+        self.see(game_move) >= threshold
+    }
+
+    // Returns the pieces of both colours that attack the target square, with sliding piece attacks blocked according to
+    // the occupancy given (pieces already removed from it are not filtered out here)
+    pub fn calc_attackers_to(&self, occupancy: u64, target_square: usize) -> u64 {
+        let target_pos = SINGLE_BITBOARDS[target_square];
+
+        // Pawns that attack a square sit diagonally behind it (from their own point of view)
+        let white_pawn_attackers = (((target_pos & !A_FILE) >> 9) | ((target_pos & !H_FILE) >> 7)) & self.wp;
+        let black_pawn_attackers = (((target_pos & !A_FILE) << 7) | ((target_pos & !H_FILE) << 9)) & self.bp;
+
+        let (rook_rays, bishop_rays) = Position::calc_slider_rays(occupancy, target_square);
+
+        white_pawn_attackers
+            | black_pawn_attackers
+            | (KNIGHT_ATTACKS[target_square] & (self.wn | self.bn))
+            | (KING_ATTACKS[target_square] & (self.wk | self.bk))
+            | (rook_rays & (self.wr | self.br | self.wq | self.bq))
+            | (bishop_rays & (self.wb | self.bb | self.wq | self.bq))
+    }
+
+    // Returns the (rank + file, diagonal + anti-diagonal) rays from the square, each ending on the first piece found in
+    // the occupancy given
+    pub fn calc_slider_rays(occupancy: u64, square: usize) -> (u64, u64) {
+        let rook_rays = Rook::calc_rank_attacks(occupancy, square, RANKS[square], 0)
+            | Rook::calc_file_or_diagonal_attacks(occupancy, square, FILES[square], 0);
+        let bishop_rays = Bishop::calc_file_or_diagonal_attacks(occupancy, square, DIAGONALS[square], 0)
+            | Bishop::calc_file_or_diagonal_attacks(occupancy, square, ANTI_DIAGONALS[square], 0);
+        (rook_rays, bishop_rays)
+    }
+
+    fn get_least_valuable_attacker(&self, side_attackers: u64, white: bool) -> (PieceType, u64) {
+        for piece_type in SEE_ATTACKER_ORDER.iter() {
+            let pieces = side_attackers & self.get_piece_bitboard(*piece_type, white);
+            if pieces > 0 {
+                // Isolate a single piece of this type
+                return (*piece_type, pieces & pieces.wrapping_neg());
+            }
+        }
+        (PieceType::NONE, 0)
+    }
+
+    pub fn get_piece_bitboard(&self, piece_type: PieceType, white: bool) -> u64 {
+        match (piece_type, white) {
+            (PieceType::PAWN, true) => self.wp,
+            (PieceType::KNIGHT, true) => self.wn,
+            (PieceType::BISHOP, true) => self.wb,
+            (PieceType::ROOK, true) => self.wr,
+            (PieceType::QUEEN, true) => self.wq,
+            (PieceType::KING, true) => self.wk,
+            (PieceType::PAWN, false) => self.bp,
+            (PieceType::KNIGHT, false) => self.bn,
+            (PieceType::BISHOP, false) => self.bb,
+            (PieceType::ROOK, false) => self.br,
+            (PieceType::QUEEN, false) => self.bq,
+            (PieceType::KING, false) => self.bk,
+            _ => 0,
+        }
+    }
+
+    pub fn get_piece_type_on(&self, square_pos: u64) -> Option<PieceType> {
+        SEE_ATTACKER_ORDER.iter()
+            .find(|piece_type| (self.get_piece_bitboard(**piece_type, true) | self.get_piece_bitboard(**piece_type, false)) & square_pos > 0)
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+    use crate::game::moves::gamemovelist::GameMoveList;
+
+    fn get_position_and_move(fen: &str, uci_move: &str) -> (Position, GameMove) {
+        let mut position = Position::from_fen(Some(fen), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+        let game_move = move_list.moves().iter()
+            .find(|m| m.get_uci_move_string() == uci_move)
+            .map(|m| m.to_game_move(&position))
+            .unwrap_or_else(|| panic!("{} is not legal in {}", uci_move, fen));
+        (position, game_move)
+    }
+
+    #[test]
+    fn test_see() {
+        let test_cases = [
+            // Undefended pawn
+            ("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5", 100),
+            // Knight takes a pawn and the whole exchange on e5 plays out, including the x-rays of the white queen
+            // behind the rook and the black queen behind the bishop
+            ("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5", -220),
+            // Pawn for pawn
+            ("4k3/8/3p4/4p3/3P4/8/8/4K3 w - - 0 1", "d4e5", 0),
+            // Rook takes a pawn defended by a rook, with a second rook behind it
+            ("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5", 100),
+            // Same, but without the second rook
+            ("3rk3/8/8/3p4/8/8/3R4/4K3 w - - 0 1", "d2d5", -400),
+            // Queen takes a pawn defended by a pawn
+            ("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1d5", -800),
+            // Quiet move onto a square attacked by a pawn
+            ("4k3/8/8/8/8/2p5/8/3RK3 w - - 0 1", "d1d2", -400),
+            // The king can't recapture while the square is still defended
+            ("3rk3/3r4/8/8/8/8/3P4/4K3 b - - 0 1", "d7d2", 100),
+            ("4k3/3r4/8/8/8/8/3P4/4K3 b - - 0 1", "d7d2", -400),
+            // En passant
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", 100),
+            // Promotions, with and without the new queen being captured
+            ("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q", 800),
+            ("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q", -100),
+            ("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7a8q", 1300),
+            // Bishop takes a knight defended by a pawn
+            ("4k3/8/2p5/3n4/8/8/B7/4K3 w - - 0 1", "a2d5", -10),
+        ];
+
+        for (fen, uci_move, expected) in test_cases.iter() {
+            let (position, game_move) = get_position_and_move(fen, uci_move);
+            assert_eq!(position.see(&game_move), *expected, "{} {}", fen, uci_move);
+        }
+    }
+
+    #[test]
+    fn test_see_ge() {
+        let (position, game_move) = get_position_and_move("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5");
+        assert!(position.see_ge(&game_move, -220));
+        assert!(!position.see_ge(&game_move, -219));
+        assert!(!position.see_ge(&game_move, 0));
+
+        let (position, game_move) = get_position_and_move("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5");
+        assert!(position.see_ge(&game_move, 0));
+        assert!(position.see_ge(&game_move, 100));
+        assert!(!position.see_ge(&game_move, 101));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod compactmove;
 pub mod gamemove;
 pub mod gamemovelist;
-pub mod movemaker;
\ No newline at end of file
+pub mod movemaker;
+pub mod movepicker;
\ No newline at end of file
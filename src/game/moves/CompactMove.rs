@@ -0,0 +1,186 @@
+use std::fmt::*;
+use crate::constants::*;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::position::Position;
+use crate::game::positionhelper::PositionHelper;
+
+// Move flags, stored in the top 4 bits (see https://www.chessprogramming.org/Encoding_Moves)
+// Bit 2 (value 4) marks a capture and bit 3 (value 8) a promotion, with the promotion piece in the bottom 2 bits
+pub const QUIET_MOVE_FLAG: u16 = 0;
+pub const DOUBLE_PAWN_PUSH_FLAG: u16 = 1;
+pub const KING_CASTLE_FLAG: u16 = 2;
+pub const QUEEN_CASTLE_FLAG: u16 = 3;
+pub const CAPTURE_FLAG: u16 = 4;
+pub const EN_PASSANT_FLAG: u16 = 5;
+pub const PROMOTION_FLAG: u16 = 8;
+
+const PROMOTION_PIECES: [PieceType; 4] = [PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK, PieceType::QUEEN];
+
+/// A move packed into 16 bits: the source square (bits 0-5), target square (bits 6-11) and flags (bits 12-15)
+/// This is the form moves are generated, stored in a GameMoveList, made by MoveMaker and encoded for the neural net in.
+/// The moving piece isn't stored, so the position the move is played from is needed to expand it into a GameMove
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CompactMove(pub u16);
+
+impl CompactMove {
+    #[inline(always)]
+    pub fn new(source_square: u8, target_square: u8, flags: u16) -> CompactMove {
+        CompactMove((source_square as u16) | ((target_square as u16) << 6) | (flags << 12))
+    }
+
+    /// Packs a move, using the move kind recorded on it when it was generated
+    pub fn from_game_move(game_move: &GameMove) -> CompactMove {
+        let flags = if game_move.promotion_piece != PieceType::NONE {
+            let promotion_index = PROMOTION_PIECES.iter().position(|p| *p == game_move.promotion_piece).unwrap_or(3) as u16;
+            PROMOTION_FLAG | (CAPTURE_FLAG * game_move.is_capture as u16) | promotion_index
+        } else {
+            match game_move.move_kind {
+                // The king always castles to the g-file on the king side, even in Chess960
+                MoveKind::CASTLE if game_move.target_square & 7 == 6 => KING_CASTLE_FLAG,
+                MoveKind::CASTLE => QUEEN_CASTLE_FLAG,
+                MoveKind::EN_PASSANT => EN_PASSANT_FLAG,
+                MoveKind::DOUBLE_PAWN_PUSH => DOUBLE_PAWN_PUSH_FLAG,
+                MoveKind::NORMAL if game_move.is_capture => CAPTURE_FLAG,
+                MoveKind::NORMAL => QUIET_MOVE_FLAG,
+            }
+        };
+
+        CompactMove::new(game_move.source_square, game_move.target_square, flags)
+    }
+
+    /// Expands the move back into a GameMove, taking the moving and captured pieces from the given position
+    pub fn to_game_move(self, position: &Position) -> GameMove {
+        let captured_piece = if self.is_en_passant() {
+            PieceType::PAWN
+        } else if self.is_capture() {
+            position.get_piece_type_on(SINGLE_BITBOARDS[self.get_target_square() as usize]).unwrap_or(PieceType::NONE)
+        } else {
+            PieceType::NONE
+        };
+
+        GameMove {
+            piece: position.get_piece_type_on(SINGLE_BITBOARDS[self.get_source_square() as usize]).unwrap_or(PieceType::NONE),
+            source_square: self.get_source_square(),
+            target_square: self.get_target_square(),
+            promotion_piece: self.get_promotion_piece(),
+            is_capture: self.is_capture(),
+            captured_piece,
+            move_kind: self.get_move_kind(),
+        }
+    }
+
+    pub fn get_move_kind(&self) -> MoveKind {
+        match self.get_flags() {
+            DOUBLE_PAWN_PUSH_FLAG => MoveKind::DOUBLE_PAWN_PUSH,
+            KING_CASTLE_FLAG | QUEEN_CASTLE_FLAG => MoveKind::CASTLE,
+            EN_PASSANT_FLAG => MoveKind::EN_PASSANT,
+            _ => MoveKind::NORMAL,
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_source_square(&self) -> u8 { (self.0 & 63) as u8 }
+
+    #[inline(always)]
+    pub fn get_target_square(&self) -> u8 { ((self.0 >> 6) & 63) as u8 }
+
+    #[inline(always)]
+    pub fn get_flags(&self) -> u16 { self.0 >> 12 }
+
+    #[inline(always)]
+    pub fn is_capture(&self) -> bool { self.get_flags() & CAPTURE_FLAG > 0 }
+
+    #[inline(always)]
+    pub fn is_promotion(&self) -> bool { self.get_flags() & PROMOTION_FLAG > 0 }
+
+    #[inline(always)]
+    pub fn is_en_passant(&self) -> bool { self.get_flags() == EN_PASSANT_FLAG }
+
+    #[inline(always)]
+    pub fn is_castle(&self) -> bool { self.get_flags() == KING_CASTLE_FLAG || self.get_flags() == QUEEN_CASTLE_FLAG }
+
+    #[inline(always)]
+    pub fn get_promotion_piece(&self) -> PieceType {
+        if self.is_promotion() { PROMOTION_PIECES[(self.get_flags() & 3) as usize] } else { PieceType::NONE }
+    }
+
+    /// Returns a UCI-formatted string for this movement, in the form <source_sq><target_sq><promotion_piece>
+    /// ex: "b1c3" or "a7a8q"
+    pub fn get_uci_move_string(&self) -> String {
+        let mut result = String::with_capacity(5);
+        result.push(PositionHelper::algebraic_file_from_index(self.get_source_square()));
+        result.push(PositionHelper::algebraic_rank_from_index(self.get_source_square()));
+        result.push(PositionHelper::algebraic_file_from_index(self.get_target_square()));
+        result.push(PositionHelper::algebraic_rank_from_index(self.get_target_square()));
+
+        if self.is_promotion() {
+            result.push(['n', 'b', 'r', 'q'][(self.get_flags() & 3) as usize]);
+        }
+        result
+    }
+}
+
+impl Debug for CompactMove {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(self.get_uci_move_string().as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+    use crate::game::moves::gamemovelist::GameMoveList;
+
+    #[test]
+    fn test_compact_move_round_trip() {
+        assert_eq!(std::mem::size_of::<CompactMove>(), 2);
+
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+
+        for fen in fens.iter() {
+            let mut position = Position::from_fen(Some(fen), false).unwrap();
+            let mut move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+
+            for compact_move in move_list.moves().iter() {
+                let game_move = compact_move.to_game_move(&position);
+                assert_eq!(CompactMove::from_game_move(&game_move), *compact_move);
+                assert_eq!(game_move.get_uci_move_string(), compact_move.get_uci_move_string());
+                assert_eq!(Some(game_move.piece), position.get_piece_type_on(SINGLE_BITBOARDS[game_move.source_square as usize]));
+                assert_eq!(game_move.is_capture, game_move.captured_piece != PieceType::NONE);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_move_flags() {
+        let mut position = Position::from_fen(Some("r3k2r/1P6/8/3pP3/8/8/6P1/R3K2R w KQkq d6 0 1"), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+        let compact_move = |uci: &str| move_list.get_move_by_uci(uci).unwrap();
+
+        assert_eq!(compact_move("g2g3").get_flags(), QUIET_MOVE_FLAG);
+        assert_eq!(compact_move("g2g4").get_flags(), DOUBLE_PAWN_PUSH_FLAG);
+        assert_eq!(compact_move("e1g1").get_flags(), KING_CASTLE_FLAG);
+        assert_eq!(compact_move("e1c1").get_flags(), QUEEN_CASTLE_FLAG);
+        assert_eq!(compact_move("a1a8").get_flags(), CAPTURE_FLAG);
+        assert!(compact_move("e5d6").is_en_passant());
+        assert!(compact_move("e5d6").is_capture());
+        assert!(compact_move("e1g1").is_castle());
+
+        let promotion = compact_move("b7b8r");
+        assert!(promotion.is_promotion() && !promotion.is_capture());
+        assert_eq!(promotion.get_promotion_piece(), PieceType::ROOK);
+
+        let promotion_capture = compact_move("b7a8q");
+        assert!(promotion_capture.is_promotion() && promotion_capture.is_capture());
+        assert_eq!(promotion_capture.get_promotion_piece(), PieceType::QUEEN);
+        assert_eq!(format!("{:?}", promotion_capture), "b7a8q");
+        assert_eq!(CompactMove::new(12, 28, DOUBLE_PAWN_PUSH_FLAG).0, 12 | (28 << 6) | (1 << 12));
+    }
+}
@@ -1,6 +1,10 @@
 use std::collections::VecDeque;
 use crate::constants::*;
 use crate::game::positionhelper::*;
+use crate::game::position::Position;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
 use std::fmt::*;
 use std::ops::Deref;
 use arrayvec::ArrayString;
@@ -13,7 +17,9 @@ pub struct GameMove {
     pub target_square: u8,
     pub promotion_piece: PieceType,
     pub is_capture: bool,
-    pub extended_move_san: ArrayString<16>
+    // The piece taken by this move (a pawn for en passant captures), or NONE if it isn't a capture
+    pub captured_piece: PieceType,
+    pub move_kind: MoveKind,
 }
 
 impl Default for GameMove {
@@ -24,7 +30,8 @@ impl Default for GameMove {
             target_square: 0,
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::<16>::new(),
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL,
         }
     }
 }
@@ -61,36 +68,88 @@ impl GameMove {
         result
     }
 
-    pub fn set_extended_san_move_string(&mut self) {
-        self.extended_move_san.clear();
+    /// Returns the move in extended SAN, which always includes the piece letter and the full source square
+    /// ex: "Ng1f3", "Pe7xd8=Q" or "O-O"
+    pub fn get_extended_san_move_string(&self) -> ArrayString<16> {
+        let mut extended_move_san = ArrayString::<16>::new();
 
-        if self.piece == PieceType::KING {
-            let square_diff = self.source_square as i16 - self.target_square as i16;
-            if square_diff == 2 { self.extended_move_san.push_str("O-O-O"); return; }
-            if square_diff == -2 { self.extended_move_san.push_str("O-O"); return; }
+        if self.move_kind == MoveKind::CASTLE {
+            extended_move_san.push_str(if self.target_square & 7 == 2 { "O-O-O" } else { "O-O" });
+            return extended_move_san;
         }
 
-        self.extended_move_san.push(GameMove::get_piece_type_letter(self.piece));
+        extended_move_san.push(GameMove::get_piece_type_letter(self.piece));
         // Doing this each character at a time is significantly faster since these functions
         // don't need to allocate their own strings internally and that turns out to be a bottleneck
-        self.extended_move_san.push(PositionHelper::algebraic_file_from_index(self.source_square));
-        self.extended_move_san.push(PositionHelper::algebraic_rank_from_index(self.source_square));
-        if self.is_capture { self.extended_move_san.push('x'); };
-        self.extended_move_san.push(PositionHelper::algebraic_file_from_index(self.target_square));
-        self.extended_move_san.push(PositionHelper::algebraic_rank_from_index(self.target_square));
+        extended_move_san.push(PositionHelper::algebraic_file_from_index(self.source_square));
+        extended_move_san.push(PositionHelper::algebraic_rank_from_index(self.source_square));
+        if self.is_capture { extended_move_san.push('x'); };
+        extended_move_san.push(PositionHelper::algebraic_file_from_index(self.target_square));
+        extended_move_san.push(PositionHelper::algebraic_rank_from_index(self.target_square));
         if self.promotion_piece != PieceType::NONE {
-            self.extended_move_san.push('=');
-            self.extended_move_san.push(GameMove::get_piece_type_letter(self.promotion_piece));
+            extended_move_san.push('=');
+            extended_move_san.push(GameMove::get_piece_type_letter(self.promotion_piece));
         }
+        extended_move_san
+    }
+
+    /// Returns the move in Standard Algebraic Notation, e.g. "Nbd7", "exd5", "e8=Q+" or "O-O#"
+    /// position must be the position before this move is made, and legal_moves must contain all of its legal moves
+    /// (used to disambiguate between pieces of the same type that can move to the same square)
+    pub fn get_san_move_string(&self, position: &Position, legal_moves: &GameMoveList) -> String {
+        let mut result = String::with_capacity(8);
+        if self.move_kind == MoveKind::CASTLE {
+            result.push_str(if self.target_square & 7 == 2 { "O-O-O" } else { "O-O" });
+
+        } else {
+            if self.piece == PieceType::PAWN {
+                // Pawn captures always include the source file
+                if self.is_capture { result.push(PositionHelper::algebraic_file_from_index(self.source_square)); }
+            } else {
+                result.push(GameMove::get_piece_type_letter(self.piece));
+
+                // Check whether another piece of the same type can move to the same target square
+                let (mut is_ambiguous, mut shares_file, mut shares_rank) = (false, false, false);
+                let same_pieces = position.get_piece_bitboard(self.piece, position.white_to_move);
+                for other_move in legal_moves.moves().iter() {
+                    let other_source_square = other_move.get_source_square();
+                    if SINGLE_BITBOARDS[other_source_square as usize] & same_pieces == 0 || other_move.get_target_square() != self.target_square || other_source_square == self.source_square { continue; }
+                    is_ambiguous = true;
+                    shares_file |= (other_source_square & 7) == (self.source_square & 7);
+                    shares_rank |= (other_source_square >> 3) == (self.source_square >> 3);
+                }
+
+                // Prefer the file, then the rank, and only use both if neither is unique on its own
+                if is_ambiguous && (!shares_file || shares_rank) { result.push(PositionHelper::algebraic_file_from_index(self.source_square)); }
+                if is_ambiguous && shares_file { result.push(PositionHelper::algebraic_rank_from_index(self.source_square)); }
+            }
+
+            if self.is_capture { result.push('x'); }
+            result.push(PositionHelper::algebraic_file_from_index(self.target_square));
+            result.push(PositionHelper::algebraic_rank_from_index(self.target_square));
+
+            if self.promotion_piece != PieceType::NONE {
+                result.push('=');
+                result.push(GameMove::get_piece_type_letter(self.promotion_piece));
+            }
+        }
+
+        // Make the move to find out whether it gives check or checkmate
+        let mut new_position = position.clone();
+        let mut new_legal_moves = GameMoveList::default();
+        MoveMaker::default().make_move(&mut new_position, self, false);
+        PositionAnalyzer::calc_legal_moves(&mut new_position, &mut new_legal_moves);
+        if new_position.is_checkmate { result.push('#'); }
+        else if new_position.king_in_check { result.push('+'); }
+
+        result
     }
 
     // Checks if this move is a match to the partial movement input in Standard Algebraic Notation
     // (e.g. "Nxf3" would match "Ng1xf3" or "Ng1xf3+")
-    pub fn is_partial_san_match(&mut self, partial_san: &str) -> bool {
-        // Set the extended SAN move string just-in-time
-        self.set_extended_san_move_string();
-
-        let mut extended_san_iter = self.extended_move_san.chars();
+    pub fn is_partial_san_match(&self, partial_san: &str) -> bool {
+        let extended_move_san = self.get_extended_san_move_string();
+        let mut extended_san_iter = extended_move_san.chars();
 
         let mut partial_san_iter = partial_san.chars().peekable();
         // The loop below checks the back half of the string up to and incl. the dest. square
@@ -101,7 +160,7 @@ impl GameMove {
             let partial_san_char = partial_san_char.unwrap();
             match partial_san_char {
                 // For castling moves, just match the whole string directly
-                'O' => return (self.extended_move_san.len() >= (partial_san.len() - 1)) && partial_san.starts_with(self.extended_move_san.as_str()),
+                'O' => return (extended_move_san.len() >= (partial_san.len() - 1)) && partial_san.starts_with(extended_move_san.as_str()),
 
                 // Throw out any check / checkmate or annotation markers
                 '+' | '#' | '?' | '!' => { continue; },
@@ -182,6 +241,31 @@ impl Debug for GameMove {
 mod tests {
     use super::*;
 
+    fn get_san_move_strings(fen: &str) -> String {
+        let mut position = Position::from_fen(Some(fen), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+
+        let mut result: Vec<String> = move_list.moves().iter()
+            .map(|compact_move| compact_move.to_game_move(&position).get_san_move_string(&position, &move_list))
+            .collect();
+        result.sort();
+        result.join(" ")
+    }
+
+    #[test]
+    fn test_game_move_san_notation() {
+        // Castling, checks, pawn captures + promotions
+        assert_eq!(get_san_move_strings("r3k3/1P6/8/8/8/8/8/R3K2R w KQq - 0 1"),
+                   "Kd1 Kd2 Ke2 Kf1 Kf2 O-O O-O-O Ra2 Ra3 Ra4 Ra5 Ra6 Ra7 Rb1 Rc1 Rd1 Rf1 Rg1 Rh2 Rh3 Rh4 Rh5 Rh6 Rh7 Rh8+ Rxa8+ \
+                   b8=B b8=N b8=Q+ b8=R+ bxa8=B bxa8=N bxa8=Q+ bxa8=R+");
+
+        // Disambiguation by rank, by file and by both, and checkmate
+        let san_moves = get_san_move_strings("k7/8/K7/8/8/1Q6/8/1Q1Q4 w - - 0 1");
+        assert_eq!(san_moves.split(' ').filter(|m| m.ends_with("d3") || m.ends_with('#')).collect::<Vec<&str>>().join(" "),
+                   "Q3d3 Qb1d3 Qb7# Qb8# Qbd5# Qbf3# Qd8# Qdd3 Qdd5# Qdf3# Qe4# Qg8# Qh1#");
+    }
+
     #[test]
     fn test_game_move_extended_san_notation() {
         let mut g = GameMove {
@@ -190,10 +274,10 @@ mod tests {
             target_square: 21,
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         };
-        g.set_extended_san_move_string();
-        assert_eq!(g.extended_move_san.as_str(), "Ng1f3");
+        assert_eq!(g.get_extended_san_move_string().as_str(), "Ng1f3");
 
         g = GameMove {
             piece: PieceType::BISHOP,
@@ -201,10 +285,10 @@ mod tests {
             target_square: 63,
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::ROOK,
+            move_kind: MoveKind::NORMAL
         };
-        g.set_extended_san_move_string();
-        assert_eq!(g.extended_move_san.as_str(), "Ba1xh8");
+        assert_eq!(g.get_extended_san_move_string().as_str(), "Ba1xh8");
 
         g = GameMove {
             piece: PieceType::PAWN,
@@ -212,10 +296,10 @@ mod tests {
             target_square: 62,
             promotion_piece: PieceType::KNIGHT,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::KNIGHT,
+            move_kind: MoveKind::NORMAL
         };
-        g.set_extended_san_move_string();
-        assert_eq!(g.extended_move_san.as_str(), "Pf7xg8=N");
+        assert_eq!(g.get_extended_san_move_string().as_str(), "Pf7xg8=N");
     }
 
     #[test]
@@ -226,7 +310,8 @@ mod tests {
             target_square: 21,
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         };
         // Ng1f3
         assert_eq!(g.is_partial_san_match("Ng1f3"), true);
@@ -247,7 +332,8 @@ mod tests {
             target_square: 63,
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::ROOK,
+            move_kind: MoveKind::NORMAL
         };
         // "Ba1xh8"
         assert_eq!(g.is_partial_san_match("Ba1xh8"), true);
@@ -264,7 +350,8 @@ mod tests {
             target_square: 62,
             promotion_piece: PieceType::KNIGHT,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::KNIGHT,
+            move_kind: MoveKind::NORMAL
         };
         // Pf7xg8=N
         assert_eq!(g.is_partial_san_match("Pf7xg8=N"), true);
@@ -283,7 +370,8 @@ mod tests {
             target_square: 2,
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::CASTLE
         };
         // O-O-O
         assert_eq!(g.is_partial_san_match("O-O-O"), true);
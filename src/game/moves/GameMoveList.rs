@@ -1,37 +1,26 @@
 use std::fmt::*;
-use arrayvec::ArrayString;
 use regex::Regex;
-use crate::constants::*;
+use crate::game::moves::compactmove::CompactMove;
 use crate::game::moves::gamemove::*;
+use crate::game::position::Position;
 
-const MAX_MOVES_PER_POSITION: usize = 256;
+pub const MAX_MOVES_PER_POSITION: usize = 256;
 
 pub struct GameMoveList {
     // This is much faster than using a Vec<GameMove>
-    // Also, there is little benefit in using a tuple() instead of a GameMove struct, so will
-    // leave it as a struct for now
-    pub move_list: [GameMove; MAX_MOVES_PER_POSITION],
+    // The moves are stored in their packed 16-bit form, so a full list takes 512 bytes rather than the 1792 needed for
+    // GameMove structs, and the moving / captured pieces don't need to be looked up while generating the moves.  Use
+    // get_move() to expand a move with the position it was generated from when the pieces are needed
+    pub move_list: [CompactMove; MAX_MOVES_PER_POSITION],
     // pub move_list: [(u8, u8, bool); 128],   // This was only slightly faster - not worth it
 
-    // pub piece: [PieceType; MAX_MOVES_PER_POSITION],
-    // pub source_square: [u8; MAX_MOVES_PER_POSITION],
-    // pub target_square: [u8; MAX_MOVES_PER_POSITION],
-    // pub promotion_piece: [PieceType; MAX_MOVES_PER_POSITION],
-    // pub is_capture: [bool; MAX_MOVES_PER_POSITION],
-
     pub list_len: usize,    // num of elements stored in the list
 }
 
 impl Default for GameMoveList {
     fn default() -> GameMoveList{
         GameMoveList {
-            move_list: [GameMove::default(); 256],
-            // piece: [PieceType::NONE; MAX_MOVES_PER_POSITION],
-            // source_square: [0; MAX_MOVES_PER_POSITION],
-            // target_square: [0; MAX_MOVES_PER_POSITION],
-            // promotion_piece: [PieceType::NONE; MAX_MOVES_PER_POSITION],
-            // is_capture: [false; MAX_MOVES_PER_POSITION],
-
+            move_list: [CompactMove::default(); MAX_MOVES_PER_POSITION],
             list_len: 0
         }
     }
@@ -44,32 +33,56 @@ impl GameMoveList {
     }
 
     #[inline(always)]
-    pub fn add_move(&mut self, piece: PieceType, source_square: u8, target_square: u8, is_capture: bool, promotion_piece: PieceType) {
-        self.move_list[self.list_len] = GameMove {
-            piece,
-            source_square,
-            target_square,
-            promotion_piece,
-            is_capture,
-            extended_move_san: ArrayString::<16>::new(),
-        };
+    pub fn add_move(&mut self, source_square: u8, target_square: u8, flags: u16) {
+        self.move_list[self.list_len] = CompactMove::new(source_square, target_square, flags);
+        self.list_len += 1;
+    }
 
-        // self.piece[self.list_len] = piece;
-        // self.source_square[self.list_len] = source_square;
-        // self.target_square[self.list_len] = target_square;
-        // self.promotion_piece[self.list_len] = promotion_piece;
-        // self.is_capture[self.list_len] = is_capture;
+    // The moves stored in the list
+    #[inline(always)]
+    pub fn moves(&self) -> &[CompactMove] {
+        &self.move_list[..self.list_len]
+    }
 
-        self.list_len += 1;
+    // Expands the move at the given index, taking the moving and captured pieces from the position the list was
+    // generated for
+    #[inline(always)]
+    pub fn get_move(&self, index: usize, position: &Position) -> GameMove {
+        self.move_list[index].to_game_move(position)
+    }
+
+    // Removes the moves from start_index onwards that don't satisfy the predicate, keeping the rest in their
+    // original order
+    pub fn retain<F: FnMut(CompactMove) -> bool>(&mut self, start_index: usize, mut keep: F) {
+        let mut new_len = start_index;
+        for i in start_index..self.list_len {
+            if keep(self.move_list[i]) {
+                self.move_list[new_len] = self.move_list[i];
+                new_len += 1;
+            }
+        }
+        self.list_len = new_len;
+    }
+
+    // Finds the move matching the input move in UCI format (e.g. "e2e4" or "a7a8q")
+    // In Chess960, castling can have the same UCI string here as a king move, so None is returned when more than one
+    // move matches (Position::get_move_by_uci gives castling as king takes rook instead)
+    pub fn get_move_by_uci(&self, move_uci: &str) -> Option<CompactMove> {
+        let mut matching_moves = self.moves().iter().filter(|m| m.get_uci_move_string() == move_uci);
+        match (matching_moves.next(), matching_moves.next()) {
+            (Some(compact_move), None) => Some(*compact_move),
+            _ => None,
+        }
     }
 
     // Finds the correct game move in the current position based on the input move in SAN format
-    pub fn get_move_by_partial_san(&mut self, move_partial_san: &str) -> Option<GameMove> {
-        if self.list_len <= 0 { return None };
+    pub fn get_move_by_partial_san(&self, position: &Position, move_partial_san: &str) -> Option<GameMove> {
+        if self.list_len == 0 { return None };
 
         for i in 0..self.list_len {
-            if self.move_list[i].is_partial_san_match(move_partial_san) {
-                return Some(self.move_list[i]);
+            let game_move = self.get_move(i, position);
+            if game_move.is_partial_san_match(move_partial_san) {
+                return Some(game_move);
             }
         }
         None
@@ -78,25 +91,8 @@ impl GameMoveList {
 
 impl Debug for GameMoveList {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        // let result = vec![PositionHelper::algebraic_from_index(self.source_square), PositionHelper::algebraic_from_index(self.target_square)].join("");
-        // f.write_str(result.as_str())
-        let mut moves: Vec<String> = vec![];
-        for i in 0..self.list_len {
-            moves.push(format!("{:?}", self.move_list[i]));
-            // f.write_str(format!("{:?}\n", self.move_list[i]).as_str());
-        }
+        let mut moves: Vec<String> = self.moves().iter().map(|m| format!("{:?}", m)).collect();
         moves.sort();
         f.write_str(moves.join(" ").as_str())
-
-        // Ok(())
-
-
-        // f.debug_struct("GameMove")
-        //     .field("piece", &self.piece)
-        //     .field("source_square", &PositionHelper::algebraic_from_index(self.source_square))
-        //     .field("target_square", &PositionHelper::algebraic_from_index(self.target_square))
-        //     .field("promotion_piece", &self.promotion_piece)
-        //     .field("is_capture", &self.is_capture)
-        //     .finish()
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,10 @@
 use crate::constants::*;
+use crate::game::castling::CASTLING_KING_TARGETS;
 use crate::game::position::*;
+use crate::game::moves::compactmove::*;
 use crate::game::moves::gamemove::*;
 use crate::game::positionhelper::PositionHelper;
+use crate::neural::nnue::{get_piece_bitboards, NNUEAccumulator};
 
 pub struct MoveMaker {
     old_wp: u64, old_wn: u64, old_wb: u64, old_wr: u64, old_wq: u64, old_wk: u64,
@@ -12,6 +15,10 @@ pub struct MoveMaker {
     old_king_in_check: bool, old_king_in_double_check: bool,
     old_is_stalemate: bool, old_is_checkmate: bool,
     old_pin_ray_masks: [u64; 64], old_check_ray_mask: u64,
+    // When set, the NNUE accumulator is kept up to date with every move made / unmade
+    pub nnue_accumulator: Option<NNUEAccumulator>,
+    // Whether the last make_move() pushed the accumulator values, so that unmake_move() knows whether it can pop them
+    nnue_values_pushed: bool,
 }
 
 impl Default for MoveMaker {
@@ -24,7 +31,9 @@ impl Default for MoveMaker {
             old_fifty_move_count: 0,
             old_king_in_check: false, old_king_in_double_check: false,
             old_is_stalemate: false, old_is_checkmate: false,
-            old_pin_ray_masks: [0u64; 64], old_check_ray_mask: 0
+            old_pin_ray_masks: [0u64; 64], old_check_ray_mask: 0,
+            nnue_accumulator: None,
+            nnue_values_pushed: false,
         }
     }
 }
@@ -56,10 +65,10 @@ impl MoveMaker {
     }
 
     #[inline(always)]
-    fn calc_make_move_common_objects(white_to_move: bool, is_pawn_move: bool, en_passant_sq: u64, game_move: &GameMove) -> (u64, u64) {
-        let movement_board = SINGLE_BITBOARDS[game_move.source_square as usize] | SINGLE_BITBOARDS[game_move.target_square as usize];
-        let mut captured_piece_square = SINGLE_BITBOARDS[game_move.target_square as usize];
-        let is_en_passant_capture = en_passant_sq == captured_piece_square && is_pawn_move;
+    fn calc_make_move_common_objects(white_to_move: bool, compact_move: CompactMove) -> (u64, u64) {
+        let movement_board = SINGLE_BITBOARDS[compact_move.get_source_square() as usize] | SINGLE_BITBOARDS[compact_move.get_target_square() as usize];
+        let mut captured_piece_square = SINGLE_BITBOARDS[compact_move.get_target_square() as usize];
+        let is_en_passant_capture = compact_move.is_en_passant();
 
         // This is basically an if statement that shifts the target capture square up one row if white is capturing en passant
         // or down one row if black is capturing en passant, or just using the capture square if it is not an en passant
@@ -71,19 +80,41 @@ impl MoveMaker {
     }
 
     pub fn make_move(&mut self, position: &mut Position, game_move: &GameMove, save_existing_state: bool) {
-        let is_pawn_move = game_move.piece == PieceType::PAWN;
-        let (target_square_board, movement_board) = MoveMaker::calc_make_move_common_objects(position.white_to_move, is_pawn_move, position.en_passant_sq, &game_move);
-        let movement_sq_count = (game_move.target_square as i8 - game_move.source_square as i8).abs();
-        let center_movement_sq_board = SINGLE_BITBOARDS[((game_move.source_square + game_move.target_square) >> 1) as usize];
+        self.make_compact_move(position, CompactMove::from_game_move(game_move), save_existing_state);
+    }
+
+    // Makes a move in its packed form (as stored in the move lists), taking the moving and captured pieces from the board
+    pub fn make_compact_move(&mut self, position: &mut Position, compact_move: CompactMove, save_existing_state: bool) {
+        let source_square = compact_move.get_source_square();
+        let target_square = compact_move.get_target_square();
+        let promotion_piece = compact_move.get_promotion_piece();
+        let piece = position.get_piece_type_on(SINGLE_BITBOARDS[source_square as usize]).unwrap_or(PieceType::NONE);
+        let captured_piece = if compact_move.is_en_passant() {
+            PieceType::PAWN
+        } else if compact_move.is_capture() {
+            position.get_piece_type_on(SINGLE_BITBOARDS[target_square as usize]).unwrap_or(PieceType::NONE)
+        } else {
+            PieceType::NONE
+        };
+
+        let is_pawn_move = piece == PieceType::PAWN;
+        let (target_square_board, movement_board) = MoveMaker::calc_make_move_common_objects(position.white_to_move, compact_move);
+        let is_castle = compact_move.is_castle();
+        let center_movement_sq_board = SINGLE_BITBOARDS[((source_square + target_square) >> 1) as usize];
 
         if save_existing_state { self.save_position_state(position); };
+        let old_pieces = self.nnue_accumulator.as_ref().map(|_| get_piece_bitboards(position));
 
-        // Any movement either from a corner square or to a corner square removes castling rights at that location
-        let remove_castling_rights_board = CORNERS & movement_board;
-        position.castling_rights &= !(remove_castling_rights_board >> 1 | remove_castling_rights_board << 2);
+        // Any movement either from or to a castling rook's square removes the castling right for that rook
+        if position.castling_rights > 0 {
+            for index in 0..4 {
+                position.castling_rights &= !(PositionHelper::bool_to_bitboard(movement_board & SINGLE_BITBOARDS[position.castling_rook_squares[index] as usize] > 0)
+                    & SINGLE_BITBOARDS[CASTLING_KING_TARGETS[index]]);
+            }
+        }
 
         if position.white_to_move {
-            match game_move.piece {
+            match piece {
                 PieceType::PAWN => position.wp ^= movement_board,
                 PieceType::KNIGHT => position.wn ^= movement_board,
                 PieceType::BISHOP => position.wb ^= movement_board,
@@ -93,16 +124,21 @@ impl MoveMaker {
                     position.wk ^= movement_board;
                     // Any king move removes castling rights on both sides
                     position.castling_rights &= !RANK_1;
-                    // Move rook to be beside the king, if the king has just moved 2 squares (i.e. castled)
-                    position.wr ^= PositionHelper::bool_to_bitboard(movement_sq_count == 2)
-                        & (center_movement_sq_board | ((target_square_board << 1 | target_square_board >> 2) & CORNERS));
+                    // When castling, the rook moves to the other side of the king.  In Chess960, the king can start
+                    // on its target square or the rook can start on the king's target square, so the squares are set
+                    // directly rather than toggled
+                    if is_castle {
+                        let (rook_source, rook_target) = position.get_castling_rook_move(target_square as usize);
+                        position.wk = SINGLE_BITBOARDS[target_square as usize];
+                        position.wr = (position.wr & !SINGLE_BITBOARDS[rook_source]) | SINGLE_BITBOARDS[rook_target];
+                    }
                 },
                 PieceType::NONE => ()
             }
 
-            if game_move.promotion_piece != PieceType::NONE {
+            if promotion_piece != PieceType::NONE {
                 position.wp ^= target_square_board;
-                match game_move.promotion_piece {
+                match promotion_piece {
                     PieceType::KNIGHT => position.wn ^= target_square_board,
                     PieceType::ROOK => position.wr ^= target_square_board,
                     PieceType::BISHOP => position.wb ^= target_square_board,
@@ -111,14 +147,17 @@ impl MoveMaker {
                 }
             }
 
-            position.bp ^= PositionHelper::bool_to_bitboard((position.bp & target_square_board) > 0) & target_square_board;
-            position.bn ^= PositionHelper::bool_to_bitboard((position.bn & target_square_board) > 0) & target_square_board;
-            position.bb ^= PositionHelper::bool_to_bitboard((position.bb & target_square_board) > 0) & target_square_board;
-            position.br ^= PositionHelper::bool_to_bitboard((position.br & target_square_board) > 0) & target_square_board;
-            position.bq ^= PositionHelper::bool_to_bitboard((position.bq & target_square_board) > 0) & target_square_board;
+            match captured_piece {
+                PieceType::PAWN => position.bp ^= target_square_board,
+                PieceType::KNIGHT => position.bn ^= target_square_board,
+                PieceType::BISHOP => position.bb ^= target_square_board,
+                PieceType::ROOK => position.br ^= target_square_board,
+                PieceType::QUEEN => position.bq ^= target_square_board,
+                _ => ()
+            }
 
         } else {
-            match game_move.piece {
+            match piece {
                 PieceType::PAWN => position.bp ^= movement_board,
                 PieceType::KNIGHT => position.bn ^= movement_board,
                 PieceType::BISHOP => position.bb ^= movement_board,
@@ -128,16 +167,21 @@ impl MoveMaker {
                     position.bk ^= movement_board;
                     // Any king move removes castling rights on both sides
                     position.castling_rights &= !RANK_8;
-                    // Move rook to be beside the king, if the king has just moved 2 squares (i.e. castled)
-                    position.br ^= PositionHelper::bool_to_bitboard(movement_sq_count == 2)
-                        & (center_movement_sq_board | ((target_square_board << 1 | target_square_board >> 2) & CORNERS));
+                    // When castling, the rook moves to the other side of the king.  In Chess960, the king can start
+                    // on its target square or the rook can start on the king's target square, so the squares are set
+                    // directly rather than toggled
+                    if is_castle {
+                        let (rook_source, rook_target) = position.get_castling_rook_move(target_square as usize);
+                        position.bk = SINGLE_BITBOARDS[target_square as usize];
+                        position.br = (position.br & !SINGLE_BITBOARDS[rook_source]) | SINGLE_BITBOARDS[rook_target];
+                    }
                 },
                 PieceType::NONE => ()
             }
 
-            if game_move.promotion_piece != PieceType::NONE {
+            if promotion_piece != PieceType::NONE {
                 position.bp ^= target_square_board;
-                match game_move.promotion_piece {
+                match promotion_piece {
                     PieceType::KNIGHT => position.bn ^= target_square_board,
                     PieceType::ROOK => position.br ^= target_square_board,
                     PieceType::BISHOP => position.bb ^= target_square_board,
@@ -146,19 +190,22 @@ impl MoveMaker {
                 }
             }
 
-            position.wp ^= PositionHelper::bool_to_bitboard((position.wp & target_square_board) > 0) & target_square_board;
-            position.wn ^= PositionHelper::bool_to_bitboard((position.wn & target_square_board) > 0) & target_square_board;
-            position.wb ^= PositionHelper::bool_to_bitboard((position.wb & target_square_board) > 0) & target_square_board;
-            position.wr ^= PositionHelper::bool_to_bitboard((position.wr & target_square_board) > 0) & target_square_board;
-            position.wq ^= PositionHelper::bool_to_bitboard((position.wq & target_square_board) > 0) & target_square_board;
+            match captured_piece {
+                PieceType::PAWN => position.wp ^= target_square_board,
+                PieceType::KNIGHT => position.wn ^= target_square_board,
+                PieceType::BISHOP => position.wb ^= target_square_board,
+                PieceType::ROOK => position.wr ^= target_square_board,
+                PieceType::QUEEN => position.wq ^= target_square_board,
+                _ => ()
+            }
 
             position.move_number += 1;
         }
 
-        position.en_passant_sq = PositionHelper::bool_to_bitboard(is_pawn_move && movement_sq_count == 16)
+        position.en_passant_sq = PositionHelper::bool_to_bitboard(compact_move.get_flags() == DOUBLE_PAWN_PUSH_FLAG)
             & center_movement_sq_board;
 
-        let not_fifty_move = (is_pawn_move || game_move.is_capture) as u8;
+        let not_fifty_move = (is_pawn_move || compact_move.is_capture()) as u8;
         position.fifty_move_count = (1 - not_fifty_move) * (position.fifty_move_count + 1);
         position.white_to_move = !position.white_to_move;
         position.update_occupancy();
@@ -170,9 +217,15 @@ impl MoveMaker {
 
         position.pin_ray_masks = [u64::MAX; 64];
         position.check_ray_mask =  u64::MAX;
+
+        self.nnue_values_pushed = save_existing_state && old_pieces.is_some();
+        if let (Some(accumulator), Some(old_pieces)) = (self.nnue_accumulator.as_mut(), old_pieces) {
+            if save_existing_state { accumulator.push(); }
+            accumulator.update(&old_pieces, position);
+        }
     }
 
-    pub fn unmake_move(&self, position: &mut Position, _game_move: &GameMove) {
+    pub fn unmake_move(&mut self, position: &mut Position) {
         // Do this first so that the white vs. black logic below aligns with that above
         position.white_to_move = !position.white_to_move;
 
@@ -204,12 +257,20 @@ impl MoveMaker {
 
         position.pin_ray_masks = self.old_pin_ray_masks.clone();
         position.check_ray_mask =  self.old_check_ray_mask;
+
+        // The values are only on the accumulator's stack if make_move() saved the state, otherwise they have to be
+        // recalculated for the restored position
+        if let Some(accumulator) = self.nnue_accumulator.as_mut() {
+            if self.nnue_values_pushed { accumulator.pop(); } else { accumulator.refresh(position); }
+        }
+        self.nnue_values_pushed = false;
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use arrayvec::ArrayString;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+    use crate::game::moves::gamemovelist::GameMoveList;
     use crate::game::pieces::king::King;
     use crate::game::pieces::piece::Piece;
     use crate::test::legalmoveshelper::LegalMovesTestHelper;
@@ -227,7 +288,8 @@ mod tests {
             target_square: 45, // f6
             promotion_piece: PieceType::NONE,
             is_capture: true,
-           extended_move_san: ArrayString::new()
+            captured_piece: PieceType::KNIGHT,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.bn, vec!["g3"]),
@@ -241,7 +303,8 @@ mod tests {
             target_square: 58, // c8
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.br, vec!["c8", "f8"]),
@@ -254,7 +317,8 @@ mod tests {
             target_square: 34, // c5
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::PAWN,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.wq, vec!["c5"]),
@@ -278,7 +342,8 @@ mod tests {
             target_square: 22, // g3
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.bp, vec!["c7", "d6", "f4"]),
@@ -293,7 +358,8 @@ mod tests {
             target_square: 22, // g3
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::PAWN,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.bp, vec!["c7", "d6", "f4"]),
@@ -319,7 +385,8 @@ mod tests {
             target_square: 42, // c6
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::PAWN,
+            move_kind: MoveKind::EN_PASSANT
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.bp, vec!["a7", "b7", "e7", "f7", "g6", "h7", "h3"]),
@@ -334,7 +401,8 @@ mod tests {
             target_square: 32, // a5
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::DOUBLE_PAWN_PUSH
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.bp, vec!["a5", "b7", "e7", "f7", "g6", "h7", "h3"]),
@@ -348,7 +416,8 @@ mod tests {
             target_square: 26, // c4
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::DOUBLE_PAWN_PUSH
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.wp, vec!["a2", "c4", "f2", "g2", "c6", "e5"]),
@@ -366,7 +435,8 @@ mod tests {
             target_square: 58, // c8
             promotion_piece: PieceType::KNIGHT,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.wp, vec!["a2", "c2", "f2", "g2", "d5", "e5"]),
@@ -383,7 +453,8 @@ mod tests {
             target_square: 59, // d8
             promotion_piece: PieceType::QUEEN,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::QUEEN,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.wp, vec!["a2", "c2", "f2", "g2", "d5", "e5"]),
@@ -402,7 +473,8 @@ mod tests {
             target_square: 2, // c1
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::CASTLE
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.wk, vec!["c1"]),
@@ -420,7 +492,8 @@ mod tests {
             target_square: 7, // h1
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::ROOK,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.wk, vec!["e1"]),
@@ -440,7 +513,8 @@ mod tests {
             target_square: 1, // b1
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         }, false);
         MoveMakerTestHelper::check_make_move_result(vec![
             (position.wk, vec!["e1"]),
@@ -460,7 +534,8 @@ mod tests {
             target_square: 2, // c1
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::CASTLE
         }, false);
         assert_eq!(position.fifty_move_count, 2);
 
@@ -471,7 +546,8 @@ mod tests {
             target_square: 7, // h1
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::ROOK,
+            move_kind: MoveKind::NORMAL
         }, false);
         assert_eq!(position.fifty_move_count, 0);
 
@@ -482,7 +558,8 @@ mod tests {
             target_square: 43, // d6
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         }, false);
         assert_eq!(position.fifty_move_count, 0);
 
@@ -493,7 +570,8 @@ mod tests {
             target_square: 43, // d6
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::PAWN,
+            move_kind: MoveKind::NORMAL
         }, false);
         assert_eq!(position.fifty_move_count, 0);
 
@@ -504,7 +582,8 @@ mod tests {
             target_square: 17, // d6
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         }, false);
         assert_eq!(position.fifty_move_count, 1);
     }
@@ -519,7 +598,8 @@ mod tests {
             target_square: 45, // f6
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::KNIGHT,
+            move_kind: MoveKind::NORMAL
         });
 
         // Qxc5
@@ -529,7 +609,8 @@ mod tests {
             target_square: 34, // c5
             promotion_piece: PieceType::NONE,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::PAWN,
+            move_kind: MoveKind::NORMAL
         });
 
         // d6
@@ -539,7 +620,8 @@ mod tests {
             target_square: 12, // e2
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::NORMAL
         });
 
         // o-o-o
@@ -549,7 +631,8 @@ mod tests {
             target_square: 2, // c1
             promotion_piece: PieceType::NONE,
             is_capture: false,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::NONE,
+            move_kind: MoveKind::CASTLE
         });
 
         let (_, mut position, _, _, mut move_maker) = LegalMovesTestHelper::init_test_position_from_fen_str(Some("r2q1rk1/ppP1ppbp/5np1/3PP1B1/8/Q5np/P1P2PP1/R3KB1R w Q - 1 2"));
@@ -560,7 +643,8 @@ mod tests {
             target_square: 59, // d8
             promotion_piece: PieceType::QUEEN,
             is_capture: true,
-            extended_move_san: ArrayString::new()
+            captured_piece: PieceType::QUEEN,
+            move_kind: MoveKind::NORMAL
         });
     }
-}
\ No newline at end of file
+}
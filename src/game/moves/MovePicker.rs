@@ -0,0 +1,175 @@
+use crate::constants::*;
+use crate::game::moves::compactmove::CompactMove;
+use crate::game::moves::gamemovelist::*;
+use crate::game::position::Position;
+
+// Move ordering scores: captures (ordered by MVV-LVA) come first, then promotions, then checks, with any external
+// prior (e.g. the NN policy probability, from 0 to 1) only breaking ties between otherwise equal moves.  The scores
+// above are multiplied by SCORE_STEP so that a prior, scaled by PRIOR_SCALE, always stays below one MVV-LVA step
+const CAPTURE_SCORE: i32 = 10000;
+const PROMOTION_SCORE: i32 = 8000;
+const CHECK_SCORE: i32 = 5000;
+const SCORE_STEP: i32 = 1000;
+const PRIOR_SCALE: f32 = (SCORE_STEP - 1) as f32;
+
+// Relative piece values for MVV-LVA, indexed by PieceType
+const MVV_LVA_VALUES: [i32; 7] = [0, 2, 3, 4, 5, 6, 1];
+
+// Ordering score and check flag for each move of a GameMoveList.  These are kept out of the move list itself so that
+// only the callers that order moves pay for them (the move lists in perft and move generation don't need them)
+pub struct MovePicker {
+    pub move_scores: [i32; MAX_MOVES_PER_POSITION],
+    pub gives_check: [bool; MAX_MOVES_PER_POSITION],
+}
+
+impl Default for MovePicker {
+    fn default() -> MovePicker {
+        MovePicker {
+            move_scores: [0; MAX_MOVES_PER_POSITION],
+            gives_check: [false; MAX_MOVES_PER_POSITION],
+        }
+    }
+}
+
+impl MovePicker {
+    // Scores every move in the list for move ordering (see the constants at the top) and flags the ones that give check
+    // The position must be the one the moves were generated from.  If given, priors holds an external score from 0 to 1
+    // for each move, in the same order as the move list
+    pub fn score_moves(&mut self, move_list: &GameMoveList, position: &Position, priors: Option<&[f32]>) {
+        for i in 0..move_list.list_len {
+            let game_move = move_list.get_move(i, position);
+            let mut score = 0;
+
+            if game_move.is_capture {
+                score += CAPTURE_SCORE + MVV_LVA_VALUES[game_move.captured_piece as usize] * 10 - MVV_LVA_VALUES[game_move.piece as usize];
+            }
+            if game_move.promotion_piece != PieceType::NONE {
+                score += PROMOTION_SCORE + MVV_LVA_VALUES[game_move.promotion_piece as usize] * 10;
+            }
+
+            self.gives_check[i] = position.gives_check(move_list.move_list[i]);
+            if self.gives_check[i] { score += CHECK_SCORE; }
+
+            score *= SCORE_STEP;
+            if let Some(prior) = priors.and_then(|priors| priors.get(i)) {
+                score += (prior.clamp(0.0, 1.0) * PRIOR_SCALE) as i32;
+            }
+
+            self.move_scores[i] = score;
+        }
+    }
+
+    // Sorts the moves (and their scores / check flags) from highest to lowest score, keeping the generation order for
+    // moves with equal scores.  This is an insertion sort done in place since the lists are short
+    pub fn sort_by_score(&mut self, move_list: &mut GameMoveList) {
+        for i in 1..move_list.list_len {
+            let mut j = i;
+            while j > 0 && self.move_scores[j - 1] < self.move_scores[j] {
+                self.swap_moves(move_list, j - 1, j);
+                j -= 1;
+            }
+        }
+    }
+
+    // Moves the highest scoring move from start_index onwards into start_index and returns it.  Calling this with
+    // start_index = 0, 1, 2, ... visits the moves in score order, which is cheaper than sorting the whole list when only
+    // the first few moves are likely to be looked at
+    pub fn pick_next(&mut self, move_list: &mut GameMoveList, start_index: usize) -> Option<CompactMove> {
+        if start_index >= move_list.list_len { return None; }
+
+        let mut best_index = start_index;
+        for i in start_index + 1..move_list.list_len {
+            if self.move_scores[i] > self.move_scores[best_index] { best_index = i; }
+        }
+        self.swap_moves(move_list, start_index, best_index);
+
+        Some(move_list.move_list[start_index])
+    }
+
+    #[inline(always)]
+    fn swap_moves(&mut self, move_list: &mut GameMoveList, i: usize, j: usize) {
+        move_list.move_list.swap(i, j);
+        self.move_scores.swap(i, j);
+        self.gives_check.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+
+    fn index_of(move_list: &GameMoveList, uci: &str) -> usize {
+        move_list.moves().iter().position(|m| m.get_uci_move_string() == uci).unwrap()
+    }
+
+    #[test]
+    fn test_score_and_order_moves() {
+        // White can take the queen with a pawn or the rook, promote with or without capturing the rook, or give check
+        let mut position = Position::from_fen(Some("r3k3/1P6/8/3q4/2PR4/8/8/4KQ2 w - - 0 1"), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+        let mut move_picker = MovePicker::default();
+        move_picker.score_moves(&move_list, &position, None);
+
+        let score_of = |uci: &str| {
+            let i = index_of(&move_list, uci);
+            (move_picker.move_scores[i], move_picker.gives_check[i])
+        };
+        assert!(score_of("c4d5").0 > score_of("d4d5").0);
+        assert!(score_of("b7a8q").0 > score_of("b7b8q").0);
+        assert!(score_of("b7a8q").0 > score_of("b7a8n").0);
+        assert!(score_of("b7b8q").0 > score_of("c4d5").0);
+        assert_eq!(score_of("d4e4"), (CHECK_SCORE * SCORE_STEP, true));
+        assert_eq!(score_of("e1d1"), (0, false));
+
+        // Sorting and picking give the same order
+        let mut picked_moves = vec![];
+        let mut picked_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position.clone(), &mut picked_list);
+        let mut picked_picker = MovePicker::default();
+        picked_picker.score_moves(&picked_list, &position, None);
+        let mut i = 0;
+        while let Some(game_move) = picked_picker.pick_next(&mut picked_list, i) {
+            picked_moves.push(game_move.get_uci_move_string());
+            i += 1;
+        }
+        assert_eq!(picked_moves.len(), move_list.list_len);
+
+        move_picker.sort_by_score(&mut move_list);
+        let sorted_moves: Vec<String> = move_list.moves().iter().map(|m| m.get_uci_move_string()).collect();
+        assert_eq!(sorted_moves[0], "b7a8q");
+        assert_eq!(picked_moves[0], "b7a8q");
+        for j in 1..move_list.list_len {
+            assert!(move_picker.move_scores[j - 1] >= move_picker.move_scores[j]);
+            assert!(picked_picker.move_scores[j - 1] >= picked_picker.move_scores[j]);
+        }
+    }
+
+    #[test]
+    fn test_score_moves_with_priors() {
+        let mut position = Position::from_fen(None, false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+        let mut move_picker = MovePicker::default();
+
+        let mut priors = [0.0f32; MAX_MOVES_PER_POSITION];
+        priors[index_of(&move_list, "e2e4")] = 0.6;
+        move_picker.score_moves(&move_list, &position, Some(&priors[..move_list.list_len]));
+
+        assert_eq!(move_picker.pick_next(&mut move_list, 0).unwrap().get_uci_move_string(), "e2e4");
+        assert_eq!(move_picker.move_scores[0], (0.6 * PRIOR_SCALE) as i32);
+
+        // A prior never lifts a move above one with a better MVV-LVA score, even with fewer priors than moves
+        let mut position = Position::from_fen(Some("r3k3/1P6/8/3q4/2PR4/8/8/4KQ2 w - - 0 1"), false).unwrap();
+        move_list.clear();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+        let mut priors = vec![0.0f32; move_list.list_len - 1];
+        priors[index_of(&move_list, "d4d5")] = 1.0;
+        move_picker.score_moves(&move_list, &position, Some(&priors));
+
+        let score_of = |uci: &str| move_picker.move_scores[index_of(&move_list, uci)];
+        assert!(score_of("c4d5") > score_of("d4d5"));
+        assert!(score_of("d4d5") > score_of("f1d3"));
+    }
+}
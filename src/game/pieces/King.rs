@@ -1,5 +1,6 @@
 use crate::constants::*;
 use crate::game::analysis::kingattackrayanalyzer::KingAttackRayAnalyzer;
+use crate::game::castling::CASTLING_KING_TARGETS;
 use crate::game::moves::gamemovelist::*;
 use crate::game::pieces::piece::*;
 use crate::game::position::Position;
@@ -20,7 +21,7 @@ impl Piece for King {
     // Returns (attackedSquares, movementSquares) where attackedSquares = squares controlled by the king
     //  movementSquares = squares where the king can either move or capture a piece
     #[inline(always)]
-    fn calc_movements(position: &Position, piece_pos: u64, move_list: &mut GameMoveList, enemy_attacked_squares: u64, _king_attack_analyzer: &mut KingAttackRayAnalyzer) -> (u64, u64) {
+    fn calc_movements_to(position: &Position, piece_pos: u64, move_list: &mut GameMoveList, enemy_attacked_squares: u64, _king_attack_analyzer: &mut KingAttackRayAnalyzer, target_squares: u64) -> (u64, u64) {
         // Cannot use the base implementation since the king also cannot move into check
 
         // Squares not controlled by the enemy side (needed because the king cannot move into check)
@@ -31,31 +32,30 @@ impl Piece for King {
         // The XOR flips the check ray mask to disallow any squares along the check ray, but then the
         // OR with the friendly occupancy brings back the single square along that ray containing the checking piece
         let check_ray_mask_for_king = (PositionHelper::bool_to_bitboard(position.king_in_check) ^ position.check_ray_mask) | position.enemy_occupancy;
-        let king_valid_squares = KING_ATTACKS[sq_ind] & !enemy_attacked_squares & check_ray_mask_for_king;
+        let king_valid_squares = KING_ATTACKS[sq_ind] & !enemy_attacked_squares & check_ray_mask_for_king & target_squares;
         let king_captures = king_valid_squares & position.enemy_occupancy;
         let king_non_captures = king_valid_squares & position.non_occupancy;
 
-        // For castling, both squares between the king and castling square must not be occupied
-        // Also, the king must still be on e1 or e8 and not in check, and the target castling
-        // squares must not be occupied or attacked by any enemy piece
+        // For castling, the king must not be in check, the squares between the king, rook and their target squares
+        // must be empty and the king can't pass through or land on a square attacked by an enemy piece (see can_castle)
         // Note: position.castling_rights must be updated externally when a move is made and here is assumed
         // to be a bitboard containing valid, remaining castling squares for both sides
-        let castle_base = king_non_captures | (
-            position.castling_rights
-                & PositionHelper::bool_to_bitboard(sq_ind == 4 || sq_ind == 60)
-                & PositionHelper::bool_to_bitboard(!position.king_in_check)
-                & !enemy_attacked_squares
-                & position.non_occupancy
-        );
-
-        // let castle_base = (king_non_captures | position.castling_rights) & PositionHelper::bool_to_bitboard(!position.king_in_check);
-        let short_castle = castle_base & (castle_base << 1);
-        let long_castle = castle_base & (castle_base >> 1) & (((SINGLE_BITBOARDS[1] | SINGLE_BITBOARDS[57]) & position.non_occupancy) << 1);
-        let castling_squares = (short_castle | long_castle) & position.castling_rights;
-
-
-        King::add_piece_movement(move_list, sq_ind as u8, king_captures, true);
-        King::add_piece_movement(move_list, sq_ind as u8, king_non_captures | castling_squares, false);
+        // Castling is a quiet move (and its target square may be occupied by the king or rook in Chess960), so it is
+        // added whenever moves onto empty squares are wanted, rather than checking its target square
+        let mut castling_squares = 0u64;
+        if position.castling_rights > 0 && target_squares & position.non_occupancy > 0 {
+            let first_index = if position.white_to_move { 0 } else { 2 };
+            for index in first_index..first_index + 2 {
+                if position.can_castle(index, |sq| SINGLE_BITBOARDS[sq] & enemy_attacked_squares > 0) {
+                    castling_squares |= SINGLE_BITBOARDS[CASTLING_KING_TARGETS[index]];
+                }
+            }
+        }
+
+        King::add_piece_movement(move_list, sq_ind as u8, king_captures, true, MoveKind::NORMAL);
+        King::add_piece_movement(move_list, sq_ind as u8, king_non_captures, false, MoveKind::NORMAL);
+        // In Chess960, a castling move can have the same target square as a normal king move (or the king's own square)
+        King::add_piece_movement(move_list, sq_ind as u8, castling_squares, false, MoveKind::CASTLE);
 
         (KING_ATTACKS[sq_ind], king_captures | king_non_captures | castling_squares)
     }
@@ -193,6 +193,8 @@ mod tests {
             vec!["f8", "f7", "e7", "d7", "d8"],
             "e8c8 e8d7 e8d8 e8e7 e8f7 e8f8"
         );
+        assert_eq!(move_list.get_move_by_uci("e8c8").unwrap().get_move_kind(), MoveKind::CASTLE);
+        assert_eq!(move_list.get_move_by_uci("e8d8").unwrap().get_move_kind(), MoveKind::NORMAL);
 
         // println!("{:?}", move_list);
     }
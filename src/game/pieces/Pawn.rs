@@ -1,5 +1,6 @@
 use crate::constants::*;
 use crate::game::analysis::kingattackrayanalyzer::KingAttackRayAnalyzer;
+use crate::game::moves::compactmove::*;
 use crate::game::moves::gamemovelist::*;
 use crate::game::pieces::piece::*;
 use crate::game::position::*;
@@ -43,26 +44,36 @@ impl Pawn {
     }
 
     #[inline(always)]
-    pub fn add_pawn_movement(move_list: &mut GameMoveList, position: &Position, mut squares: u64, source_square_offset: i8, is_capture: bool) -> u64 {
+    pub fn add_pawn_movement(move_list: &mut GameMoveList, position: &Position, mut squares: u64, source_square_offset: i8, is_capture: bool, move_kind: MoveKind) -> u64 {
         let mut valid_movements = 0u64;
         while squares > 0 {
             // trailing_zeros() gives square index from 0..63
             let target_square = squares.trailing_zeros() as u8;
             let source_square = (target_square as i8 + source_square_offset) as u8;
-            if position.pin_ray_masks[source_square as usize] & SINGLE_BITBOARDS[target_square as usize] <= 0 {
+            let target_pos = SINGLE_BITBOARDS[target_square as usize];
+            if position.pin_ray_masks[source_square as usize] & target_pos == 0 {
                 squares &= squares - 1;
                 continue;
             }
-            valid_movements |= SINGLE_BITBOARDS[target_square as usize];
+            valid_movements |= target_pos;
+
+            // A capture onto the en passant square takes the pawn beside it, which isn't on the target square itself
+            let flags = if !is_capture {
+                if move_kind == MoveKind::DOUBLE_PAWN_PUSH { DOUBLE_PAWN_PUSH_FLAG } else { QUIET_MOVE_FLAG }
+            } else if target_pos & position.en_passant_sq > 0 {
+                EN_PASSANT_FLAG
+            } else {
+                CAPTURE_FLAG
+            };
 
             let target_rank = target_square >> 3;
             if target_rank == 0 || target_rank == 7 {
-                move_list.add_move(PieceType::PAWN, source_square, target_square, is_capture, PieceType::KNIGHT);
-                move_list.add_move(PieceType::PAWN, source_square, target_square, is_capture, PieceType::BISHOP);
-                move_list.add_move(PieceType::PAWN, source_square, target_square, is_capture, PieceType::ROOK);
-                move_list.add_move(PieceType::PAWN, source_square, target_square, is_capture, PieceType::QUEEN);
+                // The promotion piece is given by the bottom 2 bits of the flags: knight, bishop, rook then queen
+                for promotion_index in 0..4 {
+                    move_list.add_move(source_square, target_square, PROMOTION_FLAG | flags | promotion_index);
+                }
             } else {
-                move_list.add_move(PieceType::PAWN, source_square, target_square, is_capture, PieceType::NONE);
+                move_list.add_move(source_square, target_square, flags);
             }
 
             squares &= squares - 1;
@@ -85,7 +96,7 @@ impl Piece for Pawn {
     }
 
     #[inline(always)]
-    fn calc_movements(position: &Position, _piece_pos: u64, move_list: &mut GameMoveList, _enemy_attacked_squares: u64, _king_attack_analyzer: &mut KingAttackRayAnalyzer) -> (u64, u64) {
+    fn calc_movements_to(position: &Position, _piece_pos: u64, move_list: &mut GameMoveList, _enemy_attacked_squares: u64, _king_attack_analyzer: &mut KingAttackRayAnalyzer, target_squares: u64) -> (u64, u64) {
         let mut all_valid_movements = 0u64;
         let attacked_squares;
 
@@ -95,17 +106,17 @@ impl Piece for Pawn {
             attacked_squares = left_attacked | right_attacked;
 
             let possible_capture_squares = position.black_occupancy | position.en_passant_sq;
-            let capture_squares = attacked_squares & possible_capture_squares;
+            let capture_squares = attacked_squares & possible_capture_squares & target_squares;
 
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, left_attacked & capture_squares, -7, true);
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, right_attacked & capture_squares, -9, true);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, left_attacked & capture_squares, -7, true, MoveKind::NORMAL);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, right_attacked & capture_squares, -9, true, MoveKind::NORMAL);
 
             let forward_one_square_empty = (position.wp << 8) & position.non_occupancy;
-            let forward_one_square_moves = forward_one_square_empty & position.check_ray_mask;
-            let forward_two_square_moves = ((position.wp & RANK_2) << 16) & (forward_one_square_empty << 8) & position.non_occupancy & position.check_ray_mask;
+            let forward_one_square_moves = forward_one_square_empty & position.check_ray_mask & target_squares;
+            let forward_two_square_moves = ((position.wp & RANK_2) << 16) & (forward_one_square_empty << 8) & position.non_occupancy & position.check_ray_mask & target_squares;
 
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, forward_one_square_moves, -8, false);
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, forward_two_square_moves, -16, false);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, forward_one_square_moves, -8, false, MoveKind::NORMAL);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, forward_two_square_moves, -16, false, MoveKind::DOUBLE_PAWN_PUSH);
 
         } else {
             // Calculations for black
@@ -113,17 +124,17 @@ impl Piece for Pawn {
             attacked_squares = left_attacked | right_attacked;
 
             let possible_capture_squares = position.white_occupancy | position.en_passant_sq;
-            let capture_squares = attacked_squares & possible_capture_squares;
+            let capture_squares = attacked_squares & possible_capture_squares & target_squares;
 
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, left_attacked & capture_squares, 7, true);
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, right_attacked & capture_squares, 9, true);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, left_attacked & capture_squares, 7, true, MoveKind::NORMAL);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, right_attacked & capture_squares, 9, true, MoveKind::NORMAL);
 
             let forward_one_square_empty = (position.bp >> 8) & position.non_occupancy;
-            let forward_one_square_moves = forward_one_square_empty & position.check_ray_mask;
-            let forward_two_square_moves = ((position.bp & RANK_7) >> 16) & (forward_one_square_empty >> 8) & position.non_occupancy & position.check_ray_mask;
+            let forward_one_square_moves = forward_one_square_empty & position.check_ray_mask & target_squares;
+            let forward_two_square_moves = ((position.bp & RANK_7) >> 16) & (forward_one_square_empty >> 8) & position.non_occupancy & position.check_ray_mask & target_squares;
 
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, forward_one_square_moves, 8, false);
-            all_valid_movements |= Pawn::add_pawn_movement(move_list, &position, forward_two_square_moves, 16, false);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, forward_one_square_moves, 8, false, MoveKind::NORMAL);
+            all_valid_movements |= Pawn::add_pawn_movement(move_list, position, forward_two_square_moves, 16, false, MoveKind::DOUBLE_PAWN_PUSH);
         }
 
         (attacked_squares, all_valid_movements)
@@ -183,6 +194,20 @@ mod tests {
             vec!["b6", "c6", "d6", "e6", "f6", "a8", "c8", "e3", "f3", "g3", "h3", "g4"],
             "a5a6 a5b6 b7a8b b7a8n b7a8q b7a8r b7b8b b7b8n b7b8q b7b8r c5b6 d5c6 d5d6 e5e6 e5f6 g2g3 h3g4 h3h4"
         );
+
+        // The captured piece and move kind are recorded with each move
+        let move_of = |uci: &str| { let m = move_list.get_move_by_uci(uci).unwrap().to_game_move(&position); (m.captured_piece, m.move_kind) };
+        assert_eq!(move_of("a5b6"), (PieceType::PAWN, MoveKind::EN_PASSANT));
+        assert_eq!(move_of("c5b6"), (PieceType::PAWN, MoveKind::EN_PASSANT));
+        assert_eq!(move_of("b7a8q"), (PieceType::ROOK, MoveKind::NORMAL));
+        assert_eq!(move_of("e5f6"), (PieceType::KNIGHT, MoveKind::NORMAL));
+        assert_eq!(move_of("h3g4"), (PieceType::BISHOP, MoveKind::NORMAL));
+        assert_eq!(move_of("b7b8q"), (PieceType::NONE, MoveKind::NORMAL));
+
+        let (_, position, mut move_list, _, _) = LegalMovesTestHelper::init_test_position_from_fen_str(None);
+        Pawn::calc_movements(&position, position.wp, &mut move_list, 0, &mut king_attack_analyzer);
+        assert_eq!(move_list.get_move_by_uci("e2e4").unwrap().get_move_kind(), MoveKind::DOUBLE_PAWN_PUSH);
+        assert_eq!(move_list.get_move_by_uci("e2e3").unwrap().get_move_kind(), MoveKind::NORMAL);
     }
 
     #[test]
@@ -1,6 +1,7 @@
 use std::ops::Deref;
 use crate::constants::*;
 use crate::game::analysis::kingattackrayanalyzer::KingAttackRayAnalyzer;
+use crate::game::moves::compactmove::*;
 use crate::game::moves::gamemovelist::*;
 use crate::game::position::*;
 use crate::game::PIECE_ATTACK_SQUARES;
@@ -14,20 +15,34 @@ pub trait Piece {
 
     // Default implementation to add movements for the piece on the source_square to each of the target_squares
     // set to 1 in the target_squares bitboard
-    fn add_piece_movement(move_list: &mut GameMoveList, source_square: u8, mut target_squares: u64, is_capture: bool) {
+    fn add_piece_movement(move_list: &mut GameMoveList, source_square: u8, mut target_squares: u64, is_capture: bool, move_kind: MoveKind) {
+        let flags = if is_capture { CAPTURE_FLAG } else { QUIET_MOVE_FLAG };
         while target_squares > 0 {
             // trailing_zeros() gives square index from 0..63
-            let target_square_index = target_squares.trailing_zeros();
-            move_list.add_move(Self::get_piece_type(), source_square, target_square_index as u8, is_capture, PieceType::NONE);
+            let target_square_index = target_squares.trailing_zeros() as u8;
+            // The king always castles to the g-file on the king side, even in Chess960
+            let flags = match move_kind {
+                MoveKind::CASTLE if target_square_index & 7 == 6 => KING_CASTLE_FLAG,
+                MoveKind::CASTLE => QUEEN_CASTLE_FLAG,
+                _ => flags,
+            };
+            move_list.add_move(source_square, target_square_index, flags);
             target_squares &= target_squares - 1;
         }
     }
 
+    #[inline(always)]
+    fn calc_movements(position: &Position, piece_pos: u64, move_list: &mut GameMoveList, enemy_attacked_squares: u64, king_attack_analyzer: &mut KingAttackRayAnalyzer) -> (u64, u64) {
+        Self::calc_movements_to(position, piece_pos, move_list, enemy_attacked_squares, king_attack_analyzer, u64::MAX)
+    }
+
     // Default implementation that uses attacked squares to determine movement squares
     // Squares occupied by enemy pieces can be moved to (i.e. a capture) but squares with friendly pieces cannot
-    fn calc_movements(position: &Position, mut piece_pos: u64, move_list: &mut GameMoveList, _enemy_attacked_squares: u64, king_attack_analyzer: &mut KingAttackRayAnalyzer) -> (u64, u64) {
+    // Only movements onto the target_squares are added, e.g. the enemy_occupancy to generate the captures only
+    #[inline(always)]
+    fn calc_movements_to(position: &Position, mut piece_pos: u64, move_list: &mut GameMoveList, _enemy_attacked_squares: u64, king_attack_analyzer: &mut KingAttackRayAnalyzer, target_squares: u64) -> (u64, u64) {
         let attacked_squares = Self::calc_attacked_squares(position, piece_pos, if position.white_to_move {&PlayerColour::WHITE} else {&PlayerColour::BLACK}, 0, king_attack_analyzer);
-        let base_movement_squares = attacked_squares & !position.friendly_occupancy;
+        let base_movement_squares = attacked_squares & !position.friendly_occupancy & target_squares;
 
         let mut movement_squares = 0u64;
 
@@ -41,8 +56,8 @@ pub trait Piece {
 
             let capture_squares = cur_piece_movement_squares & position.enemy_occupancy;
             let non_capture_squares = cur_piece_movement_squares & position.non_occupancy;
-            Self::add_piece_movement(move_list, sq_ind as u8, capture_squares, true);
-            Self::add_piece_movement(move_list, sq_ind as u8, non_capture_squares, false);
+            Self::add_piece_movement(move_list, sq_ind as u8, capture_squares, true, MoveKind::NORMAL);
+            Self::add_piece_movement(move_list, sq_ind as u8, non_capture_squares, false, MoveKind::NORMAL);
 
             piece_pos &= piece_pos - 1;
         }
@@ -55,12 +70,12 @@ pub trait Piece {
     // but pieces to the left cannot.  For now I've implemented a simple shifting loop to handle this case
     // Since the math to do this didn't seem simple, and reusing the formula above means I'd have to swap the bits
     // within a byte (and this might tie me too tightly to the x86 architecture if I were to use an endian swap instruction, for eg.)
-    fn calc_rank_attacks(position: &Position, source_square: usize, rank_mask: u64, enemy_king_pos: u64) -> u64 {
+    fn calc_rank_attacks(all_occupancy: u64, source_square: usize, rank_mask: u64, enemy_king_pos: u64) -> u64 {
         let mut piece_pos = SINGLE_BITBOARDS[source_square];
         // The enemy king position is taken out to avoid an issue where the king is checked by a sliding piece
         // If the king is left into the calculation, then it might try to move to the square behind itself, but
         // still along the ray of the attacking (sliding) piece - the king is removed so it doesn't block this attack ray
-        let occupancy = position.all_occupancy & rank_mask & !enemy_king_pos;
+        let occupancy = all_occupancy & rank_mask & !enemy_king_pos;
         let blocker = occupancy & !piece_pos;   // o - r
 
         // If blocker is on a square with a higher bit index (i.e. to the right), can use the o ^ (o - 2s) formula
@@ -106,14 +121,14 @@ pub trait Piece {
     // These can all use the o ^ (o - 2s) formula, but for pieces south or west of the source piece,
     // the bytes need to be swapped first (this essentially reverses the bit ordering along the file
     // or diagonal ray so that the subtraction produces the expected result)
-    fn calc_file_or_diagonal_attacks(position: &Position, source_square: usize, ray_mask: u64, enemy_king_pos: u64) -> u64 {
+    fn calc_file_or_diagonal_attacks(all_occupancy: u64, source_square: usize, ray_mask: u64, enemy_king_pos: u64) -> u64 {
         // Below is the Hyperbola Quintessence approach (whatever that means)
         // described here: https://www.chessprogramming.org/Hyperbola_Quintessence
         let piece_pos = SINGLE_BITBOARDS[source_square];
         // The enemy king position is taken out to avoid an issue where the king is checked by a sliding piece
         // If the king is left into the calculation, then it might try to move to the square behind itself, but
         // still along the ray of the attacking (sliding) piece - the king is removed so it doesn't block this attack ray
-        let ray_occupancy = position.all_occupancy & ray_mask & !enemy_king_pos;
+        let ray_occupancy = all_occupancy & ray_mask & !enemy_king_pos;
         let mut forward = ray_occupancy & !piece_pos;   // o - r
         let mut reverse = forward.swap_bytes();     // o' - r'
 
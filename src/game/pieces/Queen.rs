@@ -20,10 +20,10 @@ impl Piece for Queen {
 
         while piece_pos > 0 {
             let sq_ind: usize = piece_pos.trailing_zeros() as usize;
-            let mut cur_piece_attacks = Rook::calc_rank_attacks(&position, sq_ind, RANKS[sq_ind], enemy_king_pos);
-            cur_piece_attacks |= Rook::calc_file_or_diagonal_attacks(&position, sq_ind, FILES[sq_ind], enemy_king_pos);
-            cur_piece_attacks |= Bishop::calc_file_or_diagonal_attacks(&position, sq_ind, DIAGONALS[sq_ind], enemy_king_pos);
-            cur_piece_attacks |= Bishop::calc_file_or_diagonal_attacks(&position, sq_ind, ANTI_DIAGONALS[sq_ind], enemy_king_pos);
+            let mut cur_piece_attacks = Rook::calc_rank_attacks(position.all_occupancy, sq_ind, RANKS[sq_ind], enemy_king_pos);
+            cur_piece_attacks |= Rook::calc_file_or_diagonal_attacks(position.all_occupancy, sq_ind, FILES[sq_ind], enemy_king_pos);
+            cur_piece_attacks |= Bishop::calc_file_or_diagonal_attacks(position.all_occupancy, sq_ind, DIAGONALS[sq_ind], enemy_king_pos);
+            cur_piece_attacks |= Bishop::calc_file_or_diagonal_attacks(position.all_occupancy, sq_ind, ANTI_DIAGONALS[sq_ind], enemy_king_pos);
 
             queen_attacks |= cur_piece_attacks;
 
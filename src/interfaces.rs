@@ -1,3 +1,6 @@
 pub mod uci;
 pub mod pgn;
-pub mod stockfish;
\ No newline at end of file
+pub mod stockfish;
+pub mod tfrecord;
+pub mod nnuedata;
+pub mod polyglot;
\ No newline at end of file
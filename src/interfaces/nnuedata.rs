@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use simple_error::{bail, SimpleError};
+use crate::engine::positionevaluator::PositionEvaluator;
+use crate::interfaces::pgn::PGNReader;
+
+/// Writes positions from PGN files as NNUE training data, one position per line in the text format
+/// used by common NNUE trainers:
+///   <FEN> | <score in centipawns from white's point of view> | <game result for white: 1.0, 0.5 or 0.0>
+/// The score comes from the PGN's %eval annotation when there is one, otherwise from the classical evaluator
+pub struct NNUEDataExporter {}
+
+impl NNUEDataExporter {
+    /// Formats a single training data line, or returns None if the position should not be used for training
+    /// Positions with the side to move in check are skipped since their static evaluation is unreliable
+    pub fn format_position(reader: &PGNReader, position_eval: Option<f32>, game_result: f32) -> Option<String> {
+        let position = reader.get_current_position();
+        if position.king_in_check { return None; }
+
+        let score = match position_eval {
+            Some(eval) => eval.round() as i32,
+            None => PositionEvaluator::evaluate_with_trace(position).total(),
+        };
+        Some(format!("{} | {} | {:.1}", position.to_fen(), score, (game_result + 1.0) / 2.0))
+    }
+
+    /// Converts the positions from the given PGN files and returns the number of positions written
+    pub fn export_pgn_files(pgn_files: &[PathBuf], output_path: &Path, max_positions: Option<usize>) -> Result<usize, SimpleError> {
+        let file = match File::create(output_path) {
+            Err(why) => bail!("Couldn't create NNUE data file {}: {}", output_path.display(), why),
+            Ok(file) => file,
+        };
+        let mut writer = BufWriter::with_capacity(1 << 20, file);
+        let mut total_positions = 0usize;
+        let before = Instant::now();
+
+        println!("Exporting NNUE training positions from {} PGN file(s)", pgn_files.len());
+        'files: for pgn_file in pgn_files {
+            let mut reader = PGNReader::init_pgn_file(&pgn_file.to_string_lossy());
+            while let Some(nn_data) = reader.load_next_position() {
+                let line = match NNUEDataExporter::format_position(&reader, nn_data.position_eval, nn_data.game_result) {
+                    Some(line) => line,
+                    None => continue,
+                };
+                if let Err(why) = writeln!(writer, "{}", line) { bail!("Error writing NNUE data: {}", why); }
+                total_positions += 1;
+
+                if total_positions.is_multiple_of(100000) {
+                    println!("Total positions: {}\tElapsed: {:.2?}", total_positions, before.elapsed());
+                }
+                if max_positions.is_some_and(|max| total_positions >= max) { break 'files; }
+            }
+        }
+
+        if let Err(why) = writer.flush() { bail!("Error flushing NNUE data file: {}", why); }
+        println!("Wrote {} positions to {}", total_positions, output_path.display());
+        Ok(total_positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_pgn_file() {
+        let mut output_path = std::env::temp_dir();
+        output_path.push("my_chess_ql_test_nnue_data.txt");
+
+        let pgn_files = vec![PathBuf::from("src/test/resources/TestMoveHistoryPGN.pgn")];
+        let total_positions = NNUEDataExporter::export_pgn_files(&pgn_files, &output_path, None).unwrap();
+        assert!(total_positions > 0 && total_positions <= 20);
+
+        let data = std::fs::read_to_string(&output_path).unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+        assert_eq!(lines.len(), total_positions);
+        assert!(lines[0].starts_with("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 0 | "));
+        for line in lines {
+            let fields: Vec<&str> = line.split(" | ").collect();
+            assert_eq!(fields.len(), 3);
+            assert!(fields[1].parse::<i32>().is_ok());
+            assert!(["0.0", "0.5", "1.0"].contains(&fields[2]));
+        }
+
+        assert_eq!(NNUEDataExporter::export_pgn_files(&pgn_files, &output_path, Some(3)).unwrap(), 3);
+    }
+}
@@ -17,6 +17,215 @@ use crate::neural::positionconverter::*;
 
 const MIN_ELO_RATING: i16 = 2200;
 
+// Forced mates found in %eval annotations are scored as (PGN_MATE_EVAL_CP - moves to mate)
+pub const PGN_MATE_EVAL_CP: f32 = 10000.0;
+
+/// Neural network training data for a single position, as returned by load_next_position()
+#[derive(Clone, Debug, PartialEq)]
+pub struct NNTrainingPosition {
+    // A single input to the NN (X matrix) containing the last 8 positions seen when that position arose + the auxiliary plane values at that time
+    pub input_data: Vec<f32>,
+    // A mask for the output (y matrix), where the 1 values are the legal moves in the position
+    pub output_mask: Vec<f32>,
+    // The target output (y matrix), with a single one-hot encoded value representing the move that was played in that position
+    pub output_target: Vec<f32>,
+    // The game result, used to train the win probability output for the NN
+    pub game_result: f32,
+    pub white_to_move: bool,
+    // Whether or not this position comes from a new game
+    pub is_new_game: bool,
+    // The engine evaluation of the position in centipawns from white's point of view, if the PGN includes %eval annotations
+    pub position_eval: Option<f32>,
+    // The remaining clock time in seconds for the side to move and its opponent, if the PGN includes %clk annotations
+    pub mover_clock: Option<f32>,
+    pub opponent_clock: Option<f32>,
+    // The sample weight for the position, based on the SampleWeightConfig
+    pub sample_weight: f32,
+}
+
+// Game phase is measured by the remaining non-pawn material, using these values per piece
+const PHASE_KNIGHT: u32 = 1;
+const PHASE_BISHOP: u32 = 1;
+const PHASE_ROOK: u32 = 2;
+const PHASE_QUEEN: u32 = 4;
+const PHASE_TOTAL: u32 = 4 * PHASE_KNIGHT + 4 * PHASE_BISHOP + 4 * PHASE_ROOK + 2 * PHASE_QUEEN;
+
+/// Controls the sample weight calculated for each training position, which is the product of:
+///   - below_min_elo_weight if the player to move is rated below MIN_ELO_RATING (or unrated)
+///   - 1 + elo_weight_per_100 * (mover's Elo - elo_reference) / 100, if the mover is rated (never less than 0)
+///   - opening_weight for the first opening_moves moves of the game, otherwise a blend of middlegame_weight
+///     and endgame_weight based on how much non-pawn material remains on the board
+///   - win_weight / draw_weight / loss_weight depending on the game result for the player to move
+///
+/// The defaults give every position a weight of 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleWeightConfig {
+    pub below_min_elo_weight: f32,
+    pub elo_reference: f32,
+    pub elo_weight_per_100: f32,
+    pub opening_moves: f32,
+    pub opening_weight: f32,
+    pub middlegame_weight: f32,
+    pub endgame_weight: f32,
+    pub win_weight: f32,
+    pub draw_weight: f32,
+    pub loss_weight: f32,
+}
+
+impl Default for SampleWeightConfig {
+    fn default() -> Self {
+        SampleWeightConfig {
+            below_min_elo_weight: 1.0,
+            elo_reference: MIN_ELO_RATING as f32,
+            elo_weight_per_100: 0.0,
+            opening_moves: 0.0,
+            opening_weight: 1.0,
+            middlegame_weight: 1.0,
+            endgame_weight: 1.0,
+            win_weight: 1.0,
+            draw_weight: 1.0,
+            loss_weight: 1.0,
+        }
+    }
+}
+
+impl SampleWeightConfig {
+    /// Sets a single parameter by name (e.g. from command line / Python keyword arguments)
+    pub fn set_value(&mut self, name: &str, value: f32) -> Result<(), SimpleError> {
+        match name {
+            "below_min_elo_weight" => self.below_min_elo_weight = value,
+            "elo_reference" => self.elo_reference = value,
+            "elo_weight_per_100" => self.elo_weight_per_100 = value,
+            "opening_moves" => self.opening_moves = value,
+            "opening_weight" => self.opening_weight = value,
+            "middlegame_weight" => self.middlegame_weight = value,
+            "endgame_weight" => self.endgame_weight = value,
+            "win_weight" => self.win_weight = value,
+            "draw_weight" => self.draw_weight = value,
+            "loss_weight" => self.loss_weight = value,
+            _ => bail!("Unknown sample weight parameter {}", name)
+        }
+        Ok(())
+    }
+
+    /// Parses a comma-separated list of name=value pairs, e.g. "win_weight=1.5,loss_weight=0.5"
+    pub fn from_str(config: &str) -> Result<Self, SimpleError> {
+        let mut result = SampleWeightConfig::default();
+        for name_value in config.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let (name, value) = match name_value.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => bail!("Invalid sample weight parameter {} (expected name=value)", name_value)
+            };
+            match value.parse::<f32>() {
+                Ok(value) => result.set_value(name, value)?,
+                Err(_) => bail!("Invalid value for sample weight parameter {}: {}", name, value)
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the fraction of non-pawn material remaining, from 1.0 (all pieces) to 0.0 (kings and pawns only)
+    pub fn calc_game_phase(position: &Position) -> f32 {
+        let phase = (position.wn | position.bn).count_ones() * PHASE_KNIGHT
+            + (position.wb | position.bb).count_ones() * PHASE_BISHOP
+            + (position.wr | position.br).count_ones() * PHASE_ROOK
+            + (position.wq | position.bq).count_ones() * PHASE_QUEEN;
+        phase.min(PHASE_TOTAL) as f32 / PHASE_TOTAL as f32
+    }
+
+    /// Calculates the sample weight for a position given the Elo of the player to move (or -1 if unknown)
+    /// and the game result from white's point of view
+    pub fn calc_sample_weight(&self, position: &Position, mover_elo: i16, game_result: f32) -> f32 {
+        let mut weight = 1.0;
+
+        if mover_elo < MIN_ELO_RATING { weight *= self.below_min_elo_weight; }
+        if mover_elo >= 0 {
+            weight *= (1.0 + self.elo_weight_per_100 * (mover_elo as f32 - self.elo_reference) / 100.0).max(0.0);
+        }
+
+        weight *= if (position.move_number as f32) <= self.opening_moves {
+            self.opening_weight
+        } else {
+            let phase = SampleWeightConfig::calc_game_phase(position);
+            self.endgame_weight + (self.middlegame_weight - self.endgame_weight) * phase
+        };
+
+        let mover_result = if position.white_to_move { game_result } else { -game_result };
+        weight *= if mover_result > 0.0 { self.win_weight } else if mover_result < 0.0 { self.loss_weight } else { self.draw_weight };
+
+        weight
+    }
+}
+
+lazy_static! {
+    // Annotations found in Lichess database dumps, e.g. { [%eval 0.17] [%clk 0:03:00] } or { [%eval #-3] }
+    static ref EVAL_ANNOTATION: Regex = Regex::new(r"\[%eval\s+(#?)(-?\d+(?:\.\d+)?)").unwrap();
+    static ref CLOCK_ANNOTATION: Regex = Regex::new(r"\[%clk\s+(\d+):(\d+):(\d+(?:\.\d+)?)\]").unwrap();
+}
+
+/// Engine evaluation + clock time annotations from the comment following a single move
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PGNMoveAnnotation {
+    pub eval_cp: Option<f32>,       // centipawns from white's point of view, after the move was played
+    pub clock_secs: Option<f32>,    // mover's remaining time after the move was played
+}
+
+/// Keeps track of commentary blocks while parsing game moves, since they may span multiple lines
+#[derive(Default)]
+struct PGNMoveParserState {
+    commentary_brackets_found: u8,
+    move_comment: Option<String>,
+}
+
+// A game as read from the PGN file: the starting position, moves, move annotations, starting clock, game result
+// and the white / black Elo ratings
+type PGNGame = (Position, VecDeque<String>, VecDeque<PGNMoveAnnotation>, Option<f32>, f32, i16, i16);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataSplit {
+    TRAIN,
+    VALIDATION,
+    TEST,
+}
+
+impl DataSplit {
+    pub fn from_str(split: &str) -> Result<DataSplit, SimpleError> {
+        match split.to_lowercase().as_str() {
+            "train" => Ok(DataSplit::TRAIN),
+            "validation" | "val" => Ok(DataSplit::VALIDATION),
+            "test" => Ok(DataSplit::TEST),
+            _ => bail!("Invalid data split {} (expected train, validation or test)", split)
+        }
+    }
+}
+
+/// Restricts a PGNReader to only the games that fall into one bucket of a train / validation / test split
+/// Each game is assigned to a bucket using a stable hash of its headers and moves, so the same game always
+/// lands in the same bucket regardless of which file it is in or where in the file it appears
+#[derive(Clone, Copy, Debug)]
+pub struct DataSplitConfig {
+    pub split: DataSplit,
+    pub validation_ratio: f32,
+    pub test_ratio: f32,
+}
+
+impl DataSplitConfig {
+    pub fn new(split: DataSplit, validation_ratio: f32, test_ratio: f32) -> Result<Self, SimpleError> {
+        if validation_ratio < 0.0 || test_ratio < 0.0 || validation_ratio + test_ratio > 1.0 {
+            bail!("Invalid validation / test ratios {} / {}", validation_ratio, test_ratio)
+        }
+        Ok(DataSplitConfig { split, validation_ratio, test_ratio })
+    }
+
+    /// Returns the bucket for a game based on its hash (test games first, then validation, then training)
+    pub fn get_game_split(&self, game_hash: u64) -> DataSplit {
+        let bucket = (game_hash % 1_000_000) as f32 / 1_000_000.0;
+        if bucket < self.test_ratio { DataSplit::TEST }
+        else if bucket < self.test_ratio + self.validation_ratio { DataSplit::VALIDATION }
+        else { DataSplit::TRAIN }
+    }
+}
+
 pub struct PGNReader {
     file: BufReader<File>,
     buf: Vec<u8>,
@@ -24,11 +233,18 @@ pub struct PGNReader {
     position_moves: GameMoveList,
     pgn_next_move_played: GameMove,
     pgn_game_moves: VecDeque<String>,
+    pgn_game_annotations: VecDeque<PGNMoveAnnotation>,
+    pgn_next_move_annotation: PGNMoveAnnotation,
     pgn_game_result: f32,
+    position_eval: Option<f32>,
+    white_clock: Option<f32>,
+    black_clock: Option<f32>,
     move_maker: MoveMaker,
-    white_min_elo: bool,
-    black_min_elo: bool,
-    nn_converter: NNPositionConverter
+    white_elo: i16,
+    black_elo: i16,
+    sample_weights: SampleWeightConfig,
+    nn_converter: NNPositionConverter,
+    data_split: Option<DataSplitConfig>,
 }
 
 impl PGNReader {
@@ -50,12 +266,37 @@ impl PGNReader {
             position_moves: GameMoveList::default(),
             pgn_next_move_played: GameMove::default(),
             pgn_game_moves: VecDeque::with_capacity(256),
+            pgn_game_annotations: VecDeque::with_capacity(256),
+            pgn_next_move_annotation: PGNMoveAnnotation::default(),
             pgn_game_result: 0.0,
+            position_eval: None,
+            white_clock: None,
+            black_clock: None,
             move_maker: MoveMaker::default(),
-            white_min_elo: false,
-            black_min_elo: false,
-            nn_converter: NNPositionConverter::new()
+            white_elo: -1,
+            black_elo: -1,
+            sample_weights: SampleWeightConfig::default(),
+            nn_converter: NNPositionConverter::new(),
+            data_split: None,
+        }
+    }
+
+    pub fn set_sample_weight_config(&mut self, sample_weights: SampleWeightConfig) {
+        self.sample_weights = sample_weights;
+    }
+
+    /// Only return positions from games in the given split (or all games if None)
+    pub fn set_data_split(&mut self, data_split: Option<DataSplitConfig>) {
+        self.data_split = data_split;
+    }
+
+    // FNV-1a, so the game hashes (and with them the data splits) stay the same between runs
+    fn update_game_hash(game_hash: u64, data: &str) -> u64 {
+        let mut game_hash = game_hash;
+        for byte in data.as_bytes().iter().chain(b"\n") {
+            game_hash = (game_hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
         }
+        game_hash
     }
 
     fn get_next_pgn_line(&mut self) -> Option<String> {
@@ -90,12 +331,12 @@ impl PGNReader {
         bail!("Invalid header record {}", header_line)    // record was invalid
     }
 
-    fn parse_pgn_game_moves(game_moves_line: &str, game_result: &str, game_moves: &mut VecDeque<String>) {
+    fn parse_pgn_game_moves(game_moves_line: &str, game_result: &str, game_moves: &mut VecDeque<String>,
+                            move_annotations: &mut VecDeque<PGNMoveAnnotation>, parser_state: &mut PGNMoveParserState) {
         let game_moves_line = String::from(game_moves_line);
 
-        // Split game moves into a vector, ignoring any comments / commentary
+        // Split game moves into a vector, ignoring any comments / commentary (other than the move annotations)
         let split_moves = game_moves_line.split(" ");
-        let mut commentary_brackets_found = 0u8;
         for candidate_move_token in split_moves {
             if candidate_move_token.len() <= 0 || candidate_move_token == game_result || candidate_move_token == "..." { continue; };
 
@@ -107,18 +348,33 @@ impl PGNReader {
 
             // Track start of a commentary block
             if candidate_move_token.starts_with("{") || candidate_move_token.starts_with("(") {
-                commentary_brackets_found += 1;
+                // Only a comment directly following a move in the main line can be annotating that move
+                if candidate_move_token.starts_with("{") && parser_state.commentary_brackets_found == 0 {
+                    parser_state.move_comment = Some(String::new());
+                }
+                parser_state.commentary_brackets_found += 1;
+            }
+
+            if let Some(move_comment) = parser_state.move_comment.as_mut() {
+                move_comment.push_str(candidate_move_token);
+                move_comment.push(' ');
             }
 
             // Ensure to catch the end of a commentary block before skipping this token (if we're
             // already within a block)
             if candidate_move_token.ends_with("}") || candidate_move_token.ends_with(")") {
-                commentary_brackets_found -= 1;
+                parser_state.commentary_brackets_found -= 1;
+
+                if parser_state.commentary_brackets_found == 0 {
+                    if let (Some(move_comment), Some(move_annotation)) = (parser_state.move_comment.take(), move_annotations.back_mut()) {
+                        PGNReader::parse_move_annotation(move_comment.as_str(), move_annotation);
+                    }
+                }
                 continue;
             }
 
             // Skip any commentary blocks
-            if commentary_brackets_found > 0 { continue; }
+            if parser_state.commentary_brackets_found > 0 { continue; }
 
             // Skip move numbers - this is much faster than using the GAME_MOVE_NUMBER regex
             let is_move_number = match candidate_move_token.chars().next().unwrap() {
@@ -130,9 +386,38 @@ impl PGNReader {
 
             // If we made it here, we should finally be looking at a valid move token
             game_moves.push_back(String::from(candidate_move_token.trim()));
+            move_annotations.push_back(PGNMoveAnnotation::default());
+        }
+    }
+
+    /// Extracts any %eval and %clk values from a move comment
+    fn parse_move_annotation(move_comment: &str, move_annotation: &mut PGNMoveAnnotation) {
+        if let Some(captures) = EVAL_ANNOTATION.captures(move_comment) {
+            let value = &captures[2];
+            move_annotation.eval_cp = if !captures[1].is_empty() {
+                // Mate scores are given as the number of moves to mate, with a negative number if black is mating
+                let moves_to_mate = value.trim_start_matches('-').parse::<f32>().ok();
+                let sign = if value.starts_with('-') { -1.0 } else { 1.0 };
+                moves_to_mate.map(|moves_to_mate| sign * (PGN_MATE_EVAL_CP - moves_to_mate))
+            } else {
+                // Evaluations are given in pawns
+                value.parse::<f32>().ok().map(|pawns| (pawns * 100.0).round())
+            };
+        }
+
+        if let Some(captures) = CLOCK_ANNOTATION.captures(move_comment) {
+            let hours = captures[1].parse::<f32>().unwrap_or(0.0);
+            let minutes = captures[2].parse::<f32>().unwrap_or(0.0);
+            let seconds = captures[3].parse::<f32>().unwrap_or(0.0);
+            move_annotation.clock_secs = Some(hours * 3600.0 + minutes * 60.0 + seconds);
         }
     }
 
+    /// Returns the starting clock time in seconds from a TimeControl header such as "180+2"
+    fn parse_pgn_time_control(time_control: &str) -> Option<f32> {
+        time_control.split('+').next()?.parse::<f32>().ok()
+    }
+
     fn parse_pgn_game_result(game_result: &str) -> f32 {
         // Not sure if I should really be assuming a draw if there was no real game result specified
         match game_result {
@@ -143,14 +428,18 @@ impl PGNReader {
         }
     }
 
-    fn get_next_pgn_game(&mut self) -> Option<(Position, VecDeque<String>, f32, bool, bool)> {
+    fn get_next_pgn_game(&mut self) -> Option<PGNGame> {
         // Loop over games
         loop {
             let (mut white_elo, mut black_elo) = (-1i16, -1i16);
             let mut position: Position = Position::from_fen(None, false).unwrap();
             let mut game_moves: VecDeque<String> = VecDeque::with_capacity(256);
+            let mut game_annotations: VecDeque<PGNMoveAnnotation> = VecDeque::with_capacity(256);
+            let mut parser_state = PGNMoveParserState::default();
+            let mut starting_clock: Option<f32> = None;
             let mut game_moves_found = false;
             let mut game_result: String = String::from("");
+            let mut game_hash = FNV_OFFSET_BASIS;
 
             // Loop over lines within the games
             loop {
@@ -168,15 +457,18 @@ impl PGNReader {
                     // Otherwise, just skip any blank lines
                     continue;
 
-                } else if line.starts_with("[") {
-                    // Check for header lines
+                } else if line.starts_with("[") && !game_moves_found {
+                    // Check for header lines (a line of game moves can also start with a bracket if it
+                    // continues a move comment from the previous line)
                     let (key, value) = PGNReader::parse_pgn_header(line.as_str()).unwrap();
+                    game_hash = PGNReader::update_game_hash(game_hash, line.as_str());
 
                     match key.as_str() {
                         "WhiteElo" => white_elo = value.parse::<i16>().unwrap(),
                         "BlackElo" => black_elo = value.parse::<i16>().unwrap(),
                         "FEN" => position = Position::from_fen(Some(value.as_str()), false).unwrap(),
                         "Result" => game_result = value,
+                        "TimeControl" => starting_clock = PGNReader::parse_pgn_time_control(value.as_str()),
                         _ => {} // don't care about any other headers for now
                     }
 
@@ -184,24 +476,34 @@ impl PGNReader {
                     // Once game moves are found, keep reading more lines until a blank is encountered
                     // Game moves
                     game_moves_found = true;
-                    PGNReader::parse_pgn_game_moves(line.as_str(), game_result.as_str(), &mut game_moves);
+                    PGNReader::parse_pgn_game_moves(line.as_str(), game_result.as_str(), &mut game_moves, &mut game_annotations, &mut parser_state);
 
                     // println!("{}", line);
                 }
             }
 
+            // Skip games that belong to a different train / validation / test split
+            if let Some(data_split) = self.data_split {
+                // Include the moves in the hash so that games without any distinguishing headers are still spread across the buckets
+                for game_move in game_moves.iter() {
+                    game_hash = PGNReader::update_game_hash(game_hash, game_move.as_str());
+                }
+                if data_split.get_game_split(game_hash) != data_split.split { continue; }
+            }
+
             let white_min_elo = white_elo >= MIN_ELO_RATING;
             let black_min_elo = black_elo >= MIN_ELO_RATING;
             if (white_min_elo || black_min_elo) && game_moves.len() > 0 {
-                return Some((position, game_moves, PGNReader::parse_pgn_game_result(game_result.as_str()), white_min_elo, black_min_elo));
+                return Some((position, game_moves, game_annotations, starting_clock, PGNReader::parse_pgn_game_result(game_result.as_str()), white_elo, black_elo));
             }
         }
     }
 
     fn set_next_pgn_move_played(&mut self) {
         let next_move_san = self.pgn_game_moves.pop_front().unwrap();
+        self.pgn_next_move_annotation = self.pgn_game_annotations.pop_front().unwrap_or_default();
 
-        let move_match = self.position_moves.get_move_by_partial_san(next_move_san.as_str());
+        let move_match = self.position_moves.get_move_by_partial_san(&self.pgn_game_position, next_move_san.as_str());
         if move_match.is_none() {
             println!("{:?}", self.pgn_game_moves);
             panic!("{}", format!("Can't find source move {}", next_move_san.as_str()).as_str());
@@ -209,27 +511,26 @@ impl PGNReader {
         //.expect(format!("Can't find source move {}", next_move_san.as_str()).as_str());
         self.pgn_next_move_played = move_match.unwrap();
 
-        // println!("Next move loaded: {}", self.pgn_next_move_played.extended_move_san);
+        // println!("Next move loaded: {}", self.pgn_next_move_played.get_extended_san_move_string());
     }
 
-    /// Returns the next position of the next available game in the PGN file, or None if no more games are available
-    /// First returned value contains a single input to the NN (X matrix) containing the last 8 positions seen when that position arose + the auxiliary plane values at that time
-    /// Second returned value contains a mask for the output (y matrix), where the 1 values are the legal moves in the position.  It can be used to mask out invalid outputs before re-normalizing
-    /// Third returned value contains the target output (y matrix), with a single one-hot encoded value representing the move that was played in that position
-    /// Fourth returned value contains the game result in order to train the win probability output for the NN
-    /// Fifth returned value contains a bool indicating whether it is white to move (true)
-    /// Sixth returned value contains a bool indicating whether or not this position comes from a new game
-    pub fn load_next_position (&mut self) -> Option<(Vec<f32>, Vec<f32>, Vec<f32>, f32, bool, bool)> {
+    /// Returns the neural network training data for the next position of the next available game in the PGN file,
+    /// or None if no more games are available
+    pub fn load_next_position (&mut self) -> Option<NNTrainingPosition> {
         // Need to load a new game if there are no moves
         let mut is_new_game = false;
         if self.pgn_game_moves.len() <= 0 {
             // Load the next game's data
-            let (pgn_game_position, pgn_game_moves, game_result, white_min_elo, black_min_elo) = self.get_next_pgn_game()?;
+            let (pgn_game_position, pgn_game_moves, pgn_game_annotations, starting_clock, game_result, white_elo, black_elo) = self.get_next_pgn_game()?;
             self.pgn_game_position = pgn_game_position;
             self.pgn_game_moves = pgn_game_moves;
+            self.pgn_game_annotations = pgn_game_annotations;
+            self.position_eval = None;
+            self.white_clock = starting_clock;
+            self.black_clock = starting_clock;
             self.pgn_game_result = game_result;
-            self.white_min_elo = white_min_elo;
-            self.black_min_elo = black_min_elo;
+            self.white_elo = white_elo;
+            self.black_elo = black_elo;
 
             // This resets the position history buffer so that positions from the previous games are not attached
             // to the position history of the new game
@@ -241,7 +542,14 @@ impl PGNReader {
         } else {
             // If we already have a position loaded and a valid next move, then make it!
             // (i.e. apply it to the current position)
+            let white_moved = self.pgn_game_position.white_to_move;
             self.move_maker.make_move(&mut self.pgn_game_position, &self.pgn_next_move_played, false);
+
+            // The annotation on the move just played describes the position that it led to
+            self.position_eval = self.pgn_next_move_annotation.eval_cp;
+            if let Some(clock_secs) = self.pgn_next_move_annotation.clock_secs {
+                if white_moved { self.white_clock = Some(clock_secs); } else { self.black_clock = Some(clock_secs); }
+            }
         }
 
         // Update list of available moves in the position
@@ -255,7 +563,39 @@ impl PGNReader {
         let (input_data, output_mask) = self.nn_converter.convert_position_for_nn(&self.pgn_game_position, &self.position_moves);
         let output_target = NNPositionConverter::convert_target_move_for_nn(&self.pgn_next_move_played, &self.pgn_game_position);
 
-        return Some((input_data, output_mask, output_target, self.pgn_game_result, self.pgn_game_position.white_to_move, is_new_game))
+        let (mover_clock, opponent_clock, mover_elo) = match self.pgn_game_position.white_to_move {
+            true => (self.white_clock, self.black_clock, self.white_elo),
+            false => (self.black_clock, self.white_clock, self.black_elo)
+        };
+        let sample_weight = self.sample_weights.calc_sample_weight(&self.pgn_game_position, mover_elo, self.pgn_game_result);
+
+        Some(NNTrainingPosition {
+            input_data,
+            output_mask,
+            output_target,
+            game_result: self.pgn_game_result,
+            white_to_move: self.pgn_game_position.white_to_move,
+            is_new_game,
+            position_eval: self.position_eval,
+            mover_clock,
+            opponent_clock,
+            sample_weight,
+        })
+    }
+
+    /// Returns the position most recently returned by load_next_position()
+    pub fn get_current_position(&self) -> &Position {
+        &self.pgn_game_position
+    }
+
+    /// Returns the move played in the game from the position most recently returned by load_next_position()
+    pub fn get_next_move_played(&self) -> &GameMove {
+        &self.pgn_next_move_played
+    }
+
+    /// Returns the number of positions left in the current game after the one most recently returned
+    pub fn get_remaining_game_positions(&self) -> usize {
+        self.pgn_game_moves.len()
     }
 }
 
@@ -264,6 +604,7 @@ impl PGNReader {
 mod tests {
     use std::io::Write;
     use std::time::Instant;
+    use float_cmp::assert_approx_eq;
     use super::*;
 
     // const GAME_1: &str = "\
@@ -298,7 +639,8 @@ mod tests {
 
     fn test_parse_game_moves_helper(game_move_str: &str, game_result: &str) -> String {
         let mut game_moves: VecDeque<String> = VecDeque::with_capacity(256);
-        PGNReader::parse_pgn_game_moves(game_move_str, game_result, &mut game_moves);
+        let mut move_annotations: VecDeque<PGNMoveAnnotation> = VecDeque::with_capacity(256);
+        PGNReader::parse_pgn_game_moves(game_move_str, game_result, &mut game_moves, &mut move_annotations, &mut PGNMoveParserState::default());
         format!("{:?}", game_moves)
     }
 
@@ -341,7 +683,7 @@ mod tests {
             let x = pgn.load_next_position();
             if x.is_none() { break; }
 
-            total_games += x.unwrap().5 as u64;
+            total_games += x.unwrap().is_new_game as u64;
             total_positions += 1;
 
             // if total_positions % (n_positions as u64 * 1000) == 0 as u64 {
@@ -358,5 +700,115 @@ mod tests {
         let elapsed = before.elapsed();
         println!("Elapsed time: {:.2?}  ({:.1?} pos/s)", elapsed, (total_positions as f64 / elapsed.as_millis() as f64) * 1000f64);
     }
+
+    #[test]
+    fn test_parse_move_annotations() {
+        let mut game_moves: VecDeque<String> = VecDeque::with_capacity(256);
+        let mut move_annotations: VecDeque<PGNMoveAnnotation> = VecDeque::with_capacity(256);
+        let mut parser_state = PGNMoveParserState::default();
+
+        // Comments within variations are ignored, and comments can continue onto the next line
+        PGNReader::parse_pgn_game_moves("1. e4 { [%eval 0.17] [%clk 0:03:00] } 1... e5 { [%eval -1.25] ( 1... c5 { [%eval 0.5] } ) [%clk 1:02:03.5] } 2. Nf3 { [%eval #-3]",
+                                        "*", &mut game_moves, &mut move_annotations, &mut parser_state);
+        PGNReader::parse_pgn_game_moves("[%clk 0:00:09] } 2... Nc6 { a regular comment } 3. Bb5 ( 3. Bc4 { [%eval 0.0] } ) { [%eval #2] } *",
+                                        "*", &mut game_moves, &mut move_annotations, &mut parser_state);
+
+        assert_eq!(format!("{:?}", game_moves), "[\"e4\", \"e5\", \"Nf3\", \"Nc6\", \"Bb5\"]");
+        assert_eq!(move_annotations, VecDeque::from(vec![
+            PGNMoveAnnotation { eval_cp: Some(17.0), clock_secs: Some(180.0) },
+            PGNMoveAnnotation { eval_cp: Some(-125.0), clock_secs: Some(3723.5) },
+            PGNMoveAnnotation { eval_cp: Some(-(PGN_MATE_EVAL_CP - 3.0)), clock_secs: Some(9.0) },
+            PGNMoveAnnotation { eval_cp: None, clock_secs: None },
+            PGNMoveAnnotation { eval_cp: Some(PGN_MATE_EVAL_CP - 2.0), clock_secs: None },
+        ]));
+
+        assert_eq!(PGNReader::parse_pgn_time_control("180+2"), Some(180.0));
+        assert_eq!(PGNReader::parse_pgn_time_control("-"), None);
+    }
+
+    #[test]
+    fn test_pgn_file_annotations() {
+        let mut pgn = PGNReader::init_pgn_file("src/test/resources/TestAnnotatedPGN.pgn");
+        let mut annotations = vec![];
+        while let Some(nn_data) = pgn.load_next_position() {
+            annotations.push((nn_data.white_to_move, nn_data.position_eval, nn_data.mover_clock, nn_data.opponent_clock));
+        }
+
+        // Each position gets the eval from the move that led to it, and the clocks of the side to move / its opponent
+        assert_eq!(annotations, vec![
+            (true, None, Some(180.0), Some(180.0)),
+            (false, Some(36.0), Some(180.0), Some(180.0)),
+            (true, Some(20.0), Some(180.0), Some(180.0)),
+            (false, Some(10.0), Some(180.0), Some(178.0)),
+            (true, Some(15.0), Some(178.0), Some(177.5)),
+            (false, Some(0.0), Some(177.5), Some(175.0)),
+            (true, Some(PGN_MATE_EVAL_CP - 1.0), Some(175.0), Some(160.0)),
+        ]);
+    }
+
+    #[test]
+    fn test_sample_weights() {
+        assert!(SampleWeightConfig::from_str("win_weight=2,unknown_weight=1").is_err());
+        assert!(SampleWeightConfig::from_str("win_weight").is_err());
+        let config = SampleWeightConfig::from_str("below_min_elo_weight=0.5, elo_weight_per_100=0.1, opening_moves=5, opening_weight=0.25, \
+            middlegame_weight=1.0, endgame_weight=2.0, win_weight=1.5, draw_weight=1.0, loss_weight=0.5").unwrap();
+
+        let start_position = Position::from_fen(None, false).unwrap();
+        assert_eq!(SampleWeightConfig::calc_game_phase(&start_position), 1.0);
+        assert_eq!(SampleWeightConfig::default().calc_sample_weight(&start_position, -1, 1.0), 1.0);
+
+        // Opening, black to move and lost the game, unrated
+        let position = Position::from_fen(Some("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"), false).unwrap();
+        assert_eq!(config.calc_sample_weight(&position, -1, 1.0), 0.5 * 0.25 * 0.5);
+
+        // Middlegame with all pieces, white to move and won the game, rated 2400
+        let position = Position::from_fen(Some("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 10"), false).unwrap();
+        assert_approx_eq!(f32, config.calc_sample_weight(&position, 2400, 1.0), 1.2 * 1.0 * 1.5);
+
+        // Half of the non-pawn material remaining (i.e. Q + R + B + N vs. Q), drawn, rated 2200
+        let position = Position::from_fen(Some("4k3/pp3ppp/8/3q4/8/8/PPP2PPP/RNBQK3 w - - 0 30"), false).unwrap();
+        assert_eq!(SampleWeightConfig::calc_game_phase(&position), 0.5);
+        assert_eq!(config.calc_sample_weight(&position, 2200, 0.0), 1.0 * 1.5 * 1.0);
+    }
+
+    fn load_split_game_results(file_path: &str, data_split: Option<DataSplitConfig>) -> Vec<String> {
+        // Returns the first move of each game returned along with its result, to identify the games
+        let mut pgn = PGNReader::init_pgn_file(file_path);
+        pgn.set_data_split(data_split);
+        let mut result = vec![];
+        while let Some(nn_data) = pgn.load_next_position() {
+            if nn_data.is_new_game { result.push(format!("{} {}", pgn.pgn_next_move_played.get_extended_san_move_string(), nn_data.game_result)); }
+        }
+        result
+    }
+
+    #[test]
+    fn test_data_split() {
+        let file_path = "src/test/resources/TestMoveHistoryPGN.pgn";
+        assert!(DataSplitConfig::new(DataSplit::TRAIN, 0.6, 0.5).is_err());
+        assert_eq!(DataSplit::from_str("Validation").unwrap(), DataSplit::VALIDATION);
+        assert!(DataSplit::from_str("dev").is_err());
+
+        // Every game lands in exactly one bucket
+        let all_games = load_split_game_results(file_path, None);
+        let mut split_games = vec![];
+        for split in [DataSplit::TRAIN, DataSplit::VALIDATION, DataSplit::TEST] {
+            split_games.append(&mut load_split_game_results(file_path, Some(DataSplitConfig::new(split, 0.4, 0.3).unwrap())));
+        }
+        split_games.sort();
+        let mut sorted_games = all_games.clone();
+        sorted_games.sort();
+        assert_eq!(split_games, sorted_games);
+
+        // The extreme ratios send every game to a single bucket
+        assert_eq!(load_split_game_results(file_path, Some(DataSplitConfig::new(DataSplit::TRAIN, 0.0, 0.0).unwrap())), all_games);
+        assert_eq!(load_split_game_results(file_path, Some(DataSplitConfig::new(DataSplit::TEST, 0.0, 1.0).unwrap())), all_games);
+        assert!(load_split_game_results(file_path, Some(DataSplitConfig::new(DataSplit::TRAIN, 0.0, 1.0).unwrap())).is_empty());
+
+        // Buckets are stable based on the game contents
+        let game_hash = PGNReader::update_game_hash(FNV_OFFSET_BASIS, "[Event \"Bundesliga 0001\"]");
+        assert_eq!(game_hash, PGNReader::update_game_hash(FNV_OFFSET_BASIS, "[Event \"Bundesliga 0001\"]"));
+        assert_ne!(game_hash, PGNReader::update_game_hash(FNV_OFFSET_BASIS, "[Event \"Bundesliga 0002\"]"));
+    }
 }
 
@@ -0,0 +1,351 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use simple_error::{bail, SimpleError};
+use crate::constants::*;
+use crate::interfaces::pgn::NNTrainingPosition;
+use crate::neural::trainingsampler::{TrainingSampler, TrainingSamplerConfig};
+
+// TFRecord files store each record as:
+//   uint64 length, uint32 masked_crc32c(length), byte data[length], uint32 masked_crc32c(data)
+// See: https://www.tensorflow.org/tutorials/load_data/tfrecord#tfrecords_format_details
+const CRC32C_POLYNOMIAL: u32 = 0x82f63b78;  // Castagnoli, reversed bit order
+const CRC32C_MASK_DELTA: u32 = 0xa282ead8;
+
+// Feature names used in the tf.train.Example records
+pub const TFRECORD_WHITE_TO_MOVE_FEATURE: &str = "white_to_move";
+pub const TFRECORD_NEW_GAME_FEATURE: &str = "is_new_game";
+pub const TFRECORD_POSITION_EVAL_FEATURE: &str = "position_eval";
+pub const TFRECORD_MOVER_CLOCK_FEATURE: &str = "mover_clock";
+pub const TFRECORD_OPPONENT_CLOCK_FEATURE: &str = "opponent_clock";
+pub const TFRECORD_SAMPLE_WEIGHT_FEATURE: &str = "sample_weight";
+
+lazy_static! {
+    static ref CRC32C_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 > 0 { (crc >> 1) ^ CRC32C_POLYNOMIAL } else { crc >> 1 };
+            }
+            *entry = crc;
+        }
+        table
+    };
+}
+
+/// Writes tf.train.Example records in the TFRecord format without needing any TensorFlow dependency
+/// The records can be read back in Python using tf.data.TFRecordDataset
+pub struct TFRecordWriter {
+    file: BufWriter<File>,
+    record_buf: Vec<u8>,
+    pub records_written: usize,
+}
+
+impl TFRecordWriter {
+    pub fn create(path: &Path) -> Result<Self, SimpleError> {
+        let file = match File::create(path) {
+            Err(why) => bail!("Couldn't create TFRecord file {}: {}", path.display(), why),
+            Ok(file) => file,
+        };
+
+        Ok(TFRecordWriter {
+            file: BufWriter::with_capacity(1 << 20, file),
+            record_buf: Vec::<u8>::with_capacity(NN_TOTAL_INPUT_SIZE_PER_POS << 3),
+            records_written: 0,
+        })
+    }
+
+    pub fn crc32c(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in data {
+            crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        !crc
+    }
+
+    /// TFRecord stores a rotated + offset version of the CRC, since computing the CRC of a string
+    /// that itself contains embedded CRCs is problematic
+    pub fn masked_crc32c(data: &[u8]) -> u32 {
+        let crc = TFRecordWriter::crc32c(data);
+        crc.rotate_right(15).wrapping_add(CRC32C_MASK_DELTA)
+    }
+
+    /// Writes a single, already-serialized record with the length + CRC framing around it
+    pub fn write_record(&mut self, data: &[u8]) -> Result<(), SimpleError> {
+        let length_bytes = (data.len() as u64).to_le_bytes();
+        let result = self.file.write_all(&length_bytes)
+            .and_then(|_| self.file.write_all(&TFRecordWriter::masked_crc32c(&length_bytes).to_le_bytes()))
+            .and_then(|_| self.file.write_all(data))
+            .and_then(|_| self.file.write_all(&TFRecordWriter::masked_crc32c(data).to_le_bytes()));
+
+        match result {
+            Err(why) => bail!("Error writing TFRecord: {}", why),
+            Ok(_) => { self.records_written += 1; Ok(()) }
+        }
+    }
+
+    /// Serializes the neural network training data for a single position into a tf.train.Example
+    /// and writes it out as the next record
+    /// Missing evaluations / clock times are written as NaN so that every record has the same features
+    pub fn write_position(&mut self, nn_data: &NNTrainingPosition) -> Result<(), SimpleError> {
+        let mut example = TFExampleBuilder::new();
+        example.add_float_feature(INPUT_MAIN_LAYER_NAME, &nn_data.input_data);
+        example.add_float_feature(OUTPUT_MASK_LAYER_NAME, &nn_data.output_mask);
+        example.add_float_feature(OUTPUT_MOVEMENTS_LAYER_NAME, &nn_data.output_target);
+        example.add_float_feature(OUTPUT_WIN_PROBABILITY_LAYER_NAME, &[nn_data.game_result]);
+        example.add_int64_feature(TFRECORD_WHITE_TO_MOVE_FEATURE, &[nn_data.white_to_move as i64]);
+        example.add_int64_feature(TFRECORD_NEW_GAME_FEATURE, &[nn_data.is_new_game as i64]);
+        example.add_float_feature(TFRECORD_POSITION_EVAL_FEATURE, &[nn_data.position_eval.unwrap_or(f32::NAN)]);
+        example.add_float_feature(TFRECORD_MOVER_CLOCK_FEATURE, &[nn_data.mover_clock.unwrap_or(f32::NAN)]);
+        example.add_float_feature(TFRECORD_OPPONENT_CLOCK_FEATURE, &[nn_data.opponent_clock.unwrap_or(f32::NAN)]);
+        example.add_float_feature(TFRECORD_SAMPLE_WEIGHT_FEATURE, &[nn_data.sample_weight]);
+
+        // Reuse the same buffer for each record to avoid re-allocating ~30KB per position
+        let mut record_buf = std::mem::take(&mut self.record_buf);
+        example.serialize_into(&mut record_buf);
+        let result = self.write_record(record_buf.as_slice());
+        self.record_buf = record_buf;
+        result
+    }
+
+    pub fn flush(&mut self) -> Result<(), SimpleError> {
+        match self.file.flush() {
+            Err(why) => bail!("Error flushing TFRecord file: {}", why),
+            Ok(_) => Ok(())
+        }
+    }
+}
+
+/// Minimal protobuf encoder for tf.train.Example messages, which have the structure:
+///   Example { Features features = 1; }
+///   Features { map<string, Feature> feature = 1; }
+///   Feature { oneof kind { BytesList bytes_list = 1; FloatList float_list = 2; Int64List int64_list = 3; } }
+///   FloatList { repeated float value = 1 [packed = true]; }
+///   Int64List { repeated int64 value = 1 [packed = true]; }
+pub struct TFExampleBuilder {
+    // Each entry is an already-encoded map<string, Feature> entry
+    feature_entries: Vec<Vec<u8>>,
+}
+
+impl TFExampleBuilder {
+    pub fn new() -> Self {
+        TFExampleBuilder {
+            feature_entries: Vec::with_capacity(8),
+        }
+    }
+
+    fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    // Writes a length-delimited field (wire type 2)
+    fn write_length_delimited(buf: &mut Vec<u8>, field_number: u8, data: &[u8]) {
+        buf.push((field_number << 3) | 2);
+        TFExampleBuilder::write_varint(buf, data.len() as u64);
+        buf.extend_from_slice(data);
+    }
+
+    fn add_feature(&mut self, name: &str, feature_kind_field: u8, packed_values: &[u8]) {
+        // FloatList / Int64List -> packed repeated field #1
+        let mut value_list = Vec::with_capacity(packed_values.len() + 8);
+        TFExampleBuilder::write_length_delimited(&mut value_list, 1, packed_values);
+
+        let mut feature = Vec::with_capacity(value_list.len() + 8);
+        TFExampleBuilder::write_length_delimited(&mut feature, feature_kind_field, value_list.as_slice());
+
+        // Map entries are encoded as a message with key = 1, value = 2
+        let mut entry = Vec::with_capacity(feature.len() + name.len() + 8);
+        TFExampleBuilder::write_length_delimited(&mut entry, 1, name.as_bytes());
+        TFExampleBuilder::write_length_delimited(&mut entry, 2, feature.as_slice());
+
+        self.feature_entries.push(entry);
+    }
+
+    pub fn add_float_feature(&mut self, name: &str, values: &[f32]) {
+        let mut packed_values = Vec::with_capacity(values.len() << 2);
+        for value in values {
+            packed_values.extend_from_slice(&value.to_le_bytes());
+        }
+        self.add_feature(name, 2, packed_values.as_slice());
+    }
+
+    pub fn add_int64_feature(&mut self, name: &str, values: &[i64]) {
+        let mut packed_values = Vec::with_capacity(values.len() * 10);
+        for value in values {
+            // int64 values are encoded as unsigned varints using two's complement
+            TFExampleBuilder::write_varint(&mut packed_values, *value as u64);
+        }
+        self.add_feature(name, 3, packed_values.as_slice());
+    }
+
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+        let mut features = Vec::with_capacity(self.feature_entries.iter().map(|e| e.len() + 8).sum());
+        for entry in &self.feature_entries {
+            TFExampleBuilder::write_length_delimited(&mut features, 1, entry.as_slice());
+        }
+
+        buf.clear();
+        TFExampleBuilder::write_length_delimited(buf, 1, features.as_slice());
+    }
+}
+
+/// Reads back raw records from a TFRecord file, verifying the CRCs of each one
+pub struct TFRecordReader {
+    file: BufReader<File>,
+}
+
+impl TFRecordReader {
+    pub fn open(path: &Path) -> Result<Self, SimpleError> {
+        match File::open(path) {
+            Err(why) => bail!("Couldn't open TFRecord file {}: {}", path.display(), why),
+            Ok(file) => Ok(TFRecordReader { file: BufReader::new(file) }),
+        }
+    }
+
+    fn read_u32(&mut self) -> Result<u32, SimpleError> {
+        let mut bytes = [0u8; 4];
+        match self.file.read_exact(&mut bytes) {
+            Err(why) => bail!("Truncated TFRecord: {}", why),
+            Ok(_) => Ok(u32::from_le_bytes(bytes))
+        }
+    }
+
+    /// Returns the next record's data, or None at the end of the file
+    pub fn read_record(&mut self) -> Result<Option<Vec<u8>>, SimpleError> {
+        let mut length_bytes = [0u8; 8];
+        match self.file.read(&mut length_bytes[0..1]) {
+            Ok(0) => return Ok(None),
+            Err(why) => bail!("Error reading TFRecord: {}", why),
+            _ => ()
+        }
+        if let Err(why) = self.file.read_exact(&mut length_bytes[1..]) { bail!("Truncated TFRecord: {}", why) }
+
+        if self.read_u32()? != TFRecordWriter::masked_crc32c(&length_bytes) { bail!("TFRecord length CRC mismatch") }
+
+        let mut data = vec![0u8; u64::from_le_bytes(length_bytes) as usize];
+        if let Err(why) = self.file.read_exact(data.as_mut_slice()) { bail!("Truncated TFRecord: {}", why) }
+
+        if self.read_u32()? != TFRecordWriter::masked_crc32c(data.as_slice()) { bail!("TFRecord data CRC mismatch") }
+        Ok(Some(data))
+    }
+}
+
+pub struct TFRecordExporter {}
+
+impl TFRecordExporter {
+    /// Returns all PGN files at the given path (either a single file or a directory searched recursively)
+    pub fn find_pgn_files(path: &Path) -> Vec<PathBuf> {
+        let mut result: Vec<PathBuf> = vec![];
+        if path.is_dir() {
+            if let Ok(entries) = std::fs::read_dir(path) {
+                for entry in entries.flatten() {
+                    result.append(&mut TFRecordExporter::find_pgn_files(&entry.path()));
+                }
+            }
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pgn")) {
+            result.push(path.to_path_buf());
+        }
+        result.sort();
+        result
+    }
+
+    fn get_shard_path(output_prefix: &str, shard_num: usize) -> PathBuf {
+        PathBuf::from(format!("{}-{:05}.tfrecord", output_prefix, shard_num))
+    }
+
+    /// Converts the positions sampled from the given PGN files into TFRecord files of at most
+    /// positions_per_shard records each (so that they can be interleaved when training), and
+    /// returns the total number of positions written
+    pub fn export_pgn_files(pgn_files: &[PathBuf], sampler_config: TrainingSamplerConfig, output_prefix: &str, positions_per_shard: usize, max_positions: Option<usize>) -> Result<usize, SimpleError> {
+        if positions_per_shard == 0 { bail!("Positions per shard must be greater than 0") }
+
+        let mut shard_num = 0usize;
+        let mut writer = TFRecordWriter::create(&TFRecordExporter::get_shard_path(output_prefix, shard_num))?;
+        let mut total_positions = 0usize;
+        let before = Instant::now();
+
+        println!("Exporting positions from {} PGN file(s)", pgn_files.len());
+        let mut sampler = TrainingSampler::new(pgn_files.to_vec(), sampler_config);
+
+        while let Some(nn_data) = sampler.load_next_position() {
+            if writer.records_written >= positions_per_shard {
+                writer.flush()?;
+                shard_num += 1;
+                writer = TFRecordWriter::create(&TFRecordExporter::get_shard_path(output_prefix, shard_num))?;
+            }
+
+            writer.write_position(&nn_data)?;
+            total_positions += 1;
+
+            if total_positions.is_multiple_of(10000) {
+                let elapsed = before.elapsed();
+                println!("Total positions: {}\tElapsed: {:.2?}  ({:.1?} pos/s)", total_positions, elapsed, (total_positions as f64 / elapsed.as_millis() as f64) * 1000f64);
+            }
+            if max_positions.is_some_and(|max| total_positions >= max) { break; }
+        }
+
+        writer.flush()?;
+        println!("Wrote {} positions to {} shard(s)", total_positions, shard_num + 1);
+        Ok(total_positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        // Standard CRC-32C check value
+        assert_eq!(TFRecordWriter::crc32c(b"123456789"), 0xe3069283);
+        assert_eq!(TFRecordWriter::crc32c(&[0u8; 32]), 0x8a9136aa);
+
+        // The masked CRC of the length prefix of an empty record
+        assert_eq!(TFRecordWriter::crc32c(&0u64.to_le_bytes()), 0x8c28b28a);
+        assert_eq!(TFRecordWriter::masked_crc32c(&0u64.to_le_bytes()), 0x07980329);
+    }
+
+    #[test]
+    fn test_example_serialization() {
+        let mut example = TFExampleBuilder::new();
+        example.add_float_feature("a", &[1.0]);
+        example.add_int64_feature("b", &[-1]);
+
+        let mut buf = vec![];
+        example.serialize_into(&mut buf);
+        assert_eq!(buf, vec![
+            0x0a, 0x24,
+                0x0a, 0x0d, 0x0a, 0x01, b'a', 0x12, 0x08, 0x12, 0x06, 0x0a, 0x04, 0x00, 0x00, 0x80, 0x3f,
+                0x0a, 0x13, 0x0a, 0x01, b'b', 0x12, 0x0e, 0x1a, 0x0c, 0x0a, 0x0a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+        ]);
+    }
+
+    #[test]
+    fn test_export_pgn_file() {
+        let mut output_prefix = std::env::temp_dir();
+        output_prefix.push("my_chess_ql_test_export");
+        let output_prefix = output_prefix.to_str().unwrap();
+
+        let pgn_files = vec![PathBuf::from("src/test/resources/TestMoveHistoryPGN.pgn")];
+        let total_positions = TFRecordExporter::export_pgn_files(&pgn_files, TrainingSamplerConfig::default(), output_prefix, 15, None).unwrap();
+        assert_eq!(total_positions, 20);
+
+        // 15 records in the first shard and 5 in the second, each with valid CRCs
+        for (shard_num, expected_records) in [(0usize, 15usize), (1, 5)] {
+            let mut reader = TFRecordReader::open(&TFRecordExporter::get_shard_path(output_prefix, shard_num)).unwrap();
+            let mut record_count = 0usize;
+            while let Some(record) = reader.read_record().unwrap() {
+                assert!(record.len() > (NN_TOTAL_INPUT_SIZE_PER_POS + 2 * NN_TOTAL_OUTPUT_SIZE_PER_POS) << 2);
+                record_count += 1;
+            }
+            assert_eq!(record_count, expected_records);
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use crate::constants::TOP_K_OUTPUTS;
 use crate::engine::enginecontroller::*;
+use crate::game::moves::gamemove::GameMove;
 use std::io::{Write};
 use std::path::PathBuf;
 
@@ -6,13 +8,15 @@ const HELLO_STRING: &str = "id name MyChessQL";
 const AUTHOR_STRING: &str = "id author John Pazzelli";
 
 pub struct UCIInterface { //<'a> {
-    engine: EngineController
+    engine: EngineController,
+    show_wdl: bool,
 }
 
 impl UCIInterface {
     pub fn init_interface(nn_model_path: PathBuf) -> UCIInterface {
         UCIInterface {
-            engine: EngineController::init(nn_model_path)
+            engine: EngineController::init(nn_model_path),
+            show_wdl: false,
         }
     }
 
@@ -24,6 +28,14 @@ impl UCIInterface {
                 UCIInterface::send_to_gui(HELLO_STRING);
                 UCIInterface::send_to_gui(AUTHOR_STRING);
                 // println!("option name Hash type spin default 1 min 1 max 128");
+                UCIInterface::send_to_gui(format!("option name Evaluator type combo default {} var Neural var Classical var NNUE",
+                    if self.engine.evaluator_type == EvaluatorType::NEURAL { "Neural" } else { "Classical" }).as_str());
+                UCIInterface::send_to_gui("option name EvalFile type string default <empty>");
+                UCIInterface::send_to_gui("option name UCI_ShowWDL type check default false");
+                UCIInterface::send_to_gui("option name UCI_Chess960 type check default false");
+                UCIInterface::send_to_gui("option name OwnBook type check default false");
+                UCIInterface::send_to_gui("option name BookFile type string default <empty>");
+                UCIInterface::send_to_gui("option name SyzygyPath type string default <empty>");
                 UCIInterface::send_to_gui("uciok");
             },
 
@@ -31,41 +43,86 @@ impl UCIInterface {
 
             "stop" => self.engine.stop_search(),
 
+            // setoption name <id> [value <x>]
+            "setoption" => self.set_option(&cmd_tokens),
+
+            // Non-standard command (as in Stockfish) to show the classical evaluation breakdown of the current position
+            "eval" => match self.engine.get_evaluation_trace() {
+                Some(trace) => UCIInterface::send_to_gui(format!("{}", trace).as_str()),
+                None => UCIInterface::send_to_gui("info string No position set"),
+            },
+
             "ucinewgame" => self.engine.init_new_game(),
 
             // TODO: need to support the 'moves' command within 'position' to play the list of moves on the board
             // from the position given in the FEN string:
             //   * position [fen <fenstring> | startpos ]  moves <move1> .... <movei>
             "position" => {
-                match cmd_tokens[1] {
+                let result = match cmd_tokens[1] {
                     // "fen" => self.engine.init_position(Some(&cmd_tokens[2..].join(""))),
                     "fen" => self.engine.init_position(Some(&cmd_tokens[2..].join(" "))),
 
                     "startpos" | _ => self.engine.init_position(None)   // start position is the default
                 };
+                if let Err(e) = result {
+                    UCIInterface::send_to_gui(format!("info string Invalid FEN string: {}", e).as_str());
+                }
             }
 
             "go" => {
+                self.engine.start_search();
+
+                // A 'position' command that failed leaves no position set, but the GUI still expects a reply
+                if self.engine.position.is_none() {
+                    UCIInterface::send_to_gui("info string No position set");
+                    UCIInterface::send_to_gui("bestmove 0000");
+                    return true;
+                }
+
                 // movetime 3000 --> might be included in the 'go' command, will need to consider this later
 
                 // Example of how to send the current move being considered back to the UI program
                 // UCIInterface::send_to_gui("info currmove d2d4 d7d5 currmovenumber 1");
 
-                // Get the best moves based on the NN prediction
+                // Play straight from the opening book if the position is in it
+                if let Some(book_move) = self.engine.get_book_move() {
+                    UCIInterface::send_to_gui(format!("info string book move {}", self.engine.get_uci_move_string(&book_move)).as_str());
+                    UCIInterface::send_to_gui(format!("bestmove {}", self.engine.get_uci_move_string(&book_move)).as_str());
+                    return true;
+                }
+
+                // With few enough pieces left, play the best move from the tablebases (the moves returned all have
+                // the same result, with the quickest win / slowest loss first)
+                if let Some(root_moves) = self.engine.get_tablebase_moves() {
+                    for (i, root_move) in root_moves.iter().take(TOP_K_OUTPUTS).enumerate() {
+                        let (score, (win, draw, loss)) = self.engine.calc_tablebase_move_score(root_move);
+                        let wdl = if self.show_wdl { format!(" wdl {} {} {}", win, draw, loss) } else { String::new() };
+                        UCIInterface::send_to_gui(format!("info multipv {} score {}{} depth 1 nodes 1 tbhits {} time 1 pv {}", (i+1), score.to_uci_string(), wdl, self.engine.get_tb_hits(), self.engine.get_uci_move_string(&root_move.game_move)).as_str());
+                    }
+                    UCIInterface::send_to_gui(format!("bestmove {}", self.engine.get_uci_move_string(&root_moves[0].game_move)).as_str());
+                    return true;
+                }
+
+                // Get the probability of every legal move based on the NN prediction
                 let (best_moves, _win_prob) = self.engine.get_best_moves();
-                // UCIInterface::send_to_gui(format!("info score cp {:.7?}", win_prob).as_str());
 
-                // Need to flip the evaluation in the UI so it doesn't always show negative numbers for black
-                // For now, the evaluation shows the probability of making the move, not the actual position strength
-                let multiplier = (((self.engine.position.as_ref().unwrap().white_to_move as i8) << 1) - 1) as f32;
-                for i in 0..best_moves.len() {
-                    // In case there are fewer than K available moves
-                    if best_moves[i].0.is_none() { break; }
+                // No legal moves, so the game is already over
+                if best_moves.is_empty() {
+                    let position = self.engine.position.as_ref().unwrap();
+                    let score = if position.is_checkmate { EngineScore::MATE(0) } else { EngineScore::CENTIPAWNS(0) };
+                    UCIInterface::send_to_gui(format!("info depth 0 score {}", score.to_uci_string()).as_str());
+                    UCIInterface::send_to_gui("bestmove 0000");
+                    return true;
+                }
 
-                    // Send info about all K top moves to the UI using the 'info multipv' command
+                // Each of the top K moves is scored from the evaluation of the position after it
+                let top_moves: Vec<GameMove> = best_moves.iter().take(TOP_K_OUTPUTS).map(|(game_move, _)| *game_move).collect();
+                let move_scores = self.engine.calc_move_scores(&top_moves);
+                for (i, (game_move, (score, (win, draw, loss)))) in top_moves.iter().zip(move_scores).enumerate() {
+                    // Send info about the top K moves to the UI using the 'info multipv' command
                     // the 'time 1' is necessary here so ChessX doesn't ignore the line entirely
-                    let game_move = best_moves[i].0.unwrap();
-                    UCIInterface::send_to_gui(format!("info multipv {} score cp {:.0?} depth 1 nodes 1 time 1 pv {}", (i+1), (best_moves[i].1) * multiplier * 100f32, game_move.get_uci_move_string()).as_str());
+                    let wdl = if self.show_wdl { format!(" wdl {} {} {}", win, draw, loss) } else { String::new() };
+                    UCIInterface::send_to_gui(format!("info multipv {} score {}{} depth 1 nodes 1 tbhits {} time 1 pv {}", (i+1), score.to_uci_string(), wdl, self.engine.get_tb_hits(), self.engine.get_uci_move_string(game_move)).as_str());
 
                     // Sample command:
                     // UCIInterface::send_to_gui("info score cp 153  depth 1 nodes 13 time 15 pv d2d4 d7d5");
@@ -74,7 +131,7 @@ impl UCIInterface {
                 std::thread::sleep(std::time::Duration::from_millis(1000));
 
                 // For now, just send the top move but this can be adjusted later
-                UCIInterface::send_to_gui(format!("bestmove {:?}", best_moves[0].0.unwrap()).as_str());
+                UCIInterface::send_to_gui(format!("bestmove {}", self.engine.get_uci_move_string(&best_moves[0].0)).as_str());
             },
 
             "quit" => return false,
@@ -86,6 +143,40 @@ impl UCIInterface {
         true
     }
 
+    fn set_option(&mut self, cmd_tokens: &Vec<&str>) {
+        let value_index = cmd_tokens.iter().position(|t| *t == "value").unwrap_or(cmd_tokens.len());
+        let name = cmd_tokens[2.min(value_index)..value_index].join(" ");
+        let value = cmd_tokens[(value_index + 1).min(cmd_tokens.len())..].join(" ");
+
+        if name.eq_ignore_ascii_case("UCI_ShowWDL") {
+            self.show_wdl = value.eq_ignore_ascii_case("true");
+        } else if name.eq_ignore_ascii_case("UCI_Chess960") {
+            self.engine.chess960 = value.eq_ignore_ascii_case("true");
+        } else if name.eq_ignore_ascii_case("OwnBook") {
+            self.engine.use_book = value.eq_ignore_ascii_case("true");
+        } else if name.eq_ignore_ascii_case("BookFile") {
+            if let Err(e) = self.engine.set_book_file(&value) {
+                UCIInterface::send_to_gui(format!("info string {}", e).as_str());
+            }
+        } else if name.eq_ignore_ascii_case("SyzygyPath") {
+            match self.engine.set_syzygy_path(&value) {
+                Ok(_) => if let Some(tablebase) = self.engine.tablebase.as_ref() {
+                    UCIInterface::send_to_gui(format!("info string Found {} tablebases with up to {} pieces", tablebase.table_count(), tablebase.max_pieces).as_str());
+                },
+                Err(e) => UCIInterface::send_to_gui(format!("info string {}", e).as_str()),
+            }
+        } else if name.eq_ignore_ascii_case("EvalFile") {
+            if let Err(e) = self.engine.set_eval_file(&value) {
+                UCIInterface::send_to_gui(format!("info string {}", e).as_str());
+            }
+        } else if name.eq_ignore_ascii_case("Evaluator") {
+            let result = EvaluatorType::from_str(&value).and_then(|evaluator_type| self.engine.set_evaluator_type(evaluator_type));
+            if let Err(e) = result {
+                UCIInterface::send_to_gui(format!("info string {}", e).as_str());
+            }
+        }
+    }
+
     pub fn send_to_gui(msg: &str) {
         let mut msg = String::from(msg);
         if !msg.ends_with("\n") {msg.push_str("\n")};
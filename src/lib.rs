@@ -9,18 +9,23 @@ mod interfaces;
 mod neural;
 mod benchmarks;
 mod test;
+mod python;
 
 use pyo3::prelude::*;
 use pyo3::pyclass;
 use pyo3::pyproto;
 use pyo3::class::iter::{IterNextOutput};
 use pyo3::PyIterProtocol;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyDict;
 use crate::constants::*;
 use crate::interfaces::pgn::*;
+use crate::neural::trainingsampler::*;
+use crate::python::pyposition::PyPosition;
 
 #[pyclass]
 pub struct NeuralTrainer {
-    pgn: PGNReader,
+    sampler: TrainingSampler,
 }
 
 #[pyproto]
@@ -42,8 +47,8 @@ impl PyIterProtocol for NeuralTrainer {
     //     // Ok(slf.into())
     // }
 
-    fn __next__(mut slf: PyRefMut<Self>) -> IterNextOutput<(Vec<f32>, Vec<f32>, Vec<f32>, f32, bool, bool), &'static str> {
-        match slf.pgn.load_next_position() {
+    fn __next__(mut slf: PyRefMut<Self>) -> IterNextOutput<NNTrainingPosition, &'static str> {
+        match slf.sampler.load_next_position() {
             Some(nn_data) => {
                 IterNextOutput::Yield(nn_data)
             },
@@ -52,13 +57,47 @@ impl PyIterProtocol for NeuralTrainer {
     }
 }
 
+// Training positions are handed to Python as plain tuples, in field order, so that the training scripts
+// can unpack them directly
+impl IntoPy<PyObject> for NNTrainingPosition {
+    fn into_py(self, py: Python) -> PyObject {
+        (self.input_data, self.output_mask, self.output_target, self.game_result, self.white_to_move, self.is_new_game,
+         self.position_eval, self.mover_clock, self.opponent_clock, self.sample_weight).into_py(py)
+    }
+}
+
 #[pymethods]
 impl NeuralTrainer {
+    /// split can be one of "train", "validation" or "test" to only iterate over the games in that
+    /// bucket, based on the validation_ratio and test_ratio (or None for all games in the file)
+    /// sample_weights is a dict of SampleWeightConfig parameters, e.g. {"win_weight": 1.2}
     #[new]
-    pub fn new(file_path: &str) -> Self {
-        NeuralTrainer {
-            pgn: PGNReader::init_pgn_file(file_path)
+    #[args(shuffle_buffer_size = "0", positions_per_game = "0", deduplicate = "false", seed = "0",
+        split = "None", validation_ratio = "0.05", test_ratio = "0.05", sample_weights = "None")]
+    // Each argument is a Python keyword argument, so they can't be grouped into a struct
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(file_path: &str, shuffle_buffer_size: usize, positions_per_game: usize, deduplicate: bool, seed: u64,
+               split: Option<&str>, validation_ratio: f32, test_ratio: f32, sample_weights: Option<&PyDict>) -> PyResult<Self> {
+        let data_split = match split {
+            Some(split) => Some(DataSplit::from_str(split)
+                .and_then(|split| DataSplitConfig::new(split, validation_ratio, test_ratio))
+                .map_err(|e| PyValueError::new_err(e.to_string()))?),
+            None => None
+        };
+
+        let mut sample_weight_config = SampleWeightConfig::default();
+        if let Some(sample_weights) = sample_weights {
+            for (name, value) in sample_weights.iter() {
+                sample_weight_config.set_value(name.extract()?, value.extract()?)
+                    .map_err(|e| PyValueError::new_err(e.to_string()))?;
+            }
         }
+
+        let config = TrainingSamplerConfig { shuffle_buffer_size, positions_per_game, deduplicate, seed, data_split,
+            sample_weights: sample_weight_config, ..Default::default() };
+        Ok(NeuralTrainer {
+            sampler: TrainingSampler::new(vec![std::path::PathBuf::from(file_path)], config)
+        })
     }
 }
 
@@ -69,10 +108,13 @@ fn my_chess_ql(_py: Python, m: &PyModule) -> PyResult<()> {
     // fn double(x: usize) -> usize {
     //     x * 2
     // }
-    m.add_class::<NeuralTrainer>()
+    m.add_class::<NeuralTrainer>()?;
+    m.add_class::<PyPosition>()?;
+    m.add_class::<python::pyenvironment::PyEnvironment>()?;
+    python::pyencoding::add_functions(m)?;
     //     // m.add_function(wrap_pyfunction!(get_positions_from_pgn_file, m)?)?;
 
-    // Ok(())
+    Ok(())
 }
 
 
@@ -29,7 +29,14 @@ use game::analysis::positionanalyzer::*;
 use game::position::*;
 use game::positionhelper::*;
 use game::moves::gamemovelist::*;
-use crate::interfaces::pgn::PGNReader;
+use crate::interfaces::pgn::{DataSplit, DataSplitConfig, PGNReader, SampleWeightConfig};
+use crate::interfaces::tfrecord::TFRecordExporter;
+use crate::interfaces::nnuedata::NNUEDataExporter;
+use crate::interfaces::polyglot::PolyglotBookBuilder;
+use crate::engine::endgametablebase::EndgameTablebase;
+use crate::neural::trainingsampler::TrainingSamplerConfig;
+use crate::neural::valuecalibration::ValueCalibration;
+use crate::benchmarks::moveformatbenchmark::MoveFormatBenchmark;
 use crate::benchmarks::perftbenchmark::PerftBenchmark;
 use crate::game::moves::gamemove::GameMove;
 use crate::game::moves::movemaker::MoveMaker;
@@ -88,6 +95,150 @@ fn main() {
             .arg(Arg::with_name("debug")
              .long("debug")
              .help("perform 'intense verification' (uses stockfish, if installed - reduces performance but finds bugs)"))
+            .arg(Arg::with_name("compare-move-formats")
+             .long("compare-move-formats")
+             .help("compare perft with the packed 16-bit moves against perft with every move expanded to a GameMove"))
+            )
+        .subcommand(SubCommand::with_name("export-tfrecord")
+            .about("converts positions from PGN files into TFRecord files for training the neural network")
+            .arg(Arg::with_name("pgn")
+             .long("pgn")
+             .value_name("PGN_PATH")
+             .help("a PGN file or a folder containing PGN files")
+             .required(true))
+            .arg(Arg::with_name("output")
+             .long("output")
+             .value_name("OUTPUT_PREFIX")
+             .help("output file prefix - shards are written as <OUTPUT_PREFIX>-00000.tfrecord, etc.")
+             .required(true))
+            .arg(Arg::with_name("shard-size")
+             .long("shard-size")
+             .value_name("POSITIONS")
+             .default_value("100000"))
+            .arg(Arg::with_name("max-positions")
+             .long("max-positions")
+             .value_name("POSITIONS"))
+            .arg(Arg::with_name("shuffle-buffer")
+             .long("shuffle-buffer")
+             .value_name("POSITIONS")
+             .default_value("0")
+             .help("shuffle positions using a buffer of this size (0 = no shuffling)"))
+            .arg(Arg::with_name("positions-per-game")
+             .long("positions-per-game")
+             .value_name("POSITIONS")
+             .default_value("0")
+             .help("keep at most this many random positions from each game (0 = all)"))
+            .arg(Arg::with_name("dedup")
+             .long("dedup")
+             .help("skip positions that have already been exported from a previous game"))
+            .arg(Arg::with_name("dedup-table-size")
+             .long("dedup-table-size")
+             .value_name("POSITIONS")
+             .default_value("4194304")
+             .help("number of position hashes remembered by --dedup (8 bytes each)"))
+            .arg(Arg::with_name("seed")
+             .long("seed")
+             .value_name("SEED")
+             .default_value("0"))
+            .arg(Arg::with_name("split")
+             .long("split")
+             .value_name("SPLIT")
+             .possible_values(&["train", "validation", "test"])
+             .help("only export games assigned to this bucket (all games if not specified)"))
+            .arg(Arg::with_name("validation-ratio")
+             .long("validation-ratio")
+             .value_name("RATIO")
+             .default_value("0.05"))
+            .arg(Arg::with_name("test-ratio")
+             .long("test-ratio")
+             .value_name("RATIO")
+             .default_value("0.05"))
+            .arg(Arg::with_name("sample-weights")
+             .long("sample-weights")
+             .value_name("NAME=VALUE,...")
+             .default_value("")
+             .help("sample weight parameters, e.g. 'below_min_elo_weight=0.5,win_weight=1.2' (see SampleWeightConfig)"))
+            )
+        .subcommand(SubCommand::with_name("export-nnue-data")
+            .about("converts positions from PGN files into text training data for the NNUE evaluation network")
+            .arg(Arg::with_name("pgn")
+             .long("pgn")
+             .value_name("PGN_PATH")
+             .help("a PGN file or a folder containing PGN files")
+             .required(true))
+            .arg(Arg::with_name("output")
+             .long("output")
+             .value_name("OUTPUT_FILE")
+             .required(true))
+            .arg(Arg::with_name("max-positions")
+             .long("max-positions")
+             .value_name("POSITIONS"))
+            )
+        .subcommand(SubCommand::with_name("fit-value-calibration")
+            .about("fits the mapping from the value head's win probability to centipawns / WDL using the %eval annotations in PGN files")
+            .arg(Arg::with_name("pgn")
+             .long("pgn")
+             .value_name("PGN_PATH")
+             .help("a PGN file or a folder containing PGN files")
+             .required(true))
+            .arg(Arg::with_name("output")
+             .long("output")
+             .value_name("OUTPUT_FILE")
+             .help("usually value_calibration.json in the model folder, where the engine loads it from")
+             .required(true))
+            .arg(Arg::with_name("max-positions")
+             .long("max-positions")
+             .value_name("POSITIONS"))
+            )
+        .subcommand(SubCommand::with_name("build-book")
+            .about("builds a Polyglot opening book (.bin) from the games in PGN files")
+            .arg(Arg::with_name("pgn")
+             .long("pgn")
+             .value_name("PGN_PATH")
+             .help("a PGN file or a folder containing PGN files")
+             .required(true))
+            .arg(Arg::with_name("output")
+             .long("output")
+             .value_name("OUTPUT_FILE")
+             .required(true))
+            .arg(Arg::with_name("max-ply")
+             .long("max-ply")
+             .value_name("PLIES")
+             .default_value("20")
+             .help("only include moves from the first PLIES half-moves of each game"))
+            .arg(Arg::with_name("min-games")
+             .long("min-games")
+             .value_name("GAMES")
+             .default_value("3")
+             .help("leave out moves played in fewer than GAMES games"))
+            .arg(Arg::with_name("win-weight")
+             .long("win-weight")
+             .value_name("WEIGHT")
+             .default_value("2"))
+            .arg(Arg::with_name("draw-weight")
+             .long("draw-weight")
+             .value_name("WEIGHT")
+             .default_value("1"))
+            .arg(Arg::with_name("loss-weight")
+             .long("loss-weight")
+             .value_name("WEIGHT")
+             .default_value("0"))
+            )
+        .subcommand(SubCommand::with_name("endgame-tables")
+            .about("generates distance-to-mate tables for endgames with up to 4 pieces by retrograde analysis, and / or probes a position")
+            .arg(Arg::with_name("dir")
+             .long("dir")
+             .value_name("TABLES_DIR")
+             .help("the folder the tables are saved to and loaded from")
+             .required(true))
+            .arg(Arg::with_name("build")
+             .long("build")
+             .value_name("MATERIAL")
+             .help("comma-separated material codes of the tables to generate (e.g. KQvK,KBNvK), along with any tables they depend on"))
+            .arg(Arg::with_name("fen")
+             .long("fen")
+             .value_name("FEN_STR")
+             .help("a position to look up, printing the distance to mate and the best move"))
             )
         .get_matches();
 
@@ -101,9 +252,114 @@ fn main() {
         if matches.is_present("fen") {
             fen = matches.value_of("fen");
         }
+        if matches.is_present("compare-move-formats") {
+            return MoveFormatBenchmark::run_move_format_benchmark(fen, depth, 5);
+        }
         return PerftBenchmark::run_perft(fen, depth, matches.is_present("debug"));
     }
 
+    // Export PGN positions to TFRecord files, if specified
+    if let Some(matches) = matches.subcommand_matches("export-tfrecord") {
+        // PGNReader resolves relative paths against the crate folder, so make the input path absolute first
+        let pgn_path = std::fs::canonicalize(matches.value_of("pgn").unwrap()).expect("PGN path not found");
+        let shard_size: usize = matches.value_of("shard-size").unwrap().parse().expect("Invalid shard size");
+        let max_positions: Option<usize> = matches.value_of("max-positions").map(|v| v.parse().expect("Invalid max positions"));
+        let data_split = matches.value_of("split").map(|split| DataSplitConfig::new(
+            DataSplit::from_str(split).unwrap(),
+            matches.value_of("validation-ratio").unwrap().parse().expect("Invalid validation ratio"),
+            matches.value_of("test-ratio").unwrap().parse().expect("Invalid test ratio"),
+        ).unwrap());
+        let sampler_config = TrainingSamplerConfig {
+            shuffle_buffer_size: matches.value_of("shuffle-buffer").unwrap().parse().expect("Invalid shuffle buffer size"),
+            positions_per_game: matches.value_of("positions-per-game").unwrap().parse().expect("Invalid positions per game"),
+            deduplicate: matches.is_present("dedup"),
+            deduplicate_table_size: matches.value_of("dedup-table-size").unwrap().parse().expect("Invalid dedup table size"),
+            seed: matches.value_of("seed").unwrap().parse().expect("Invalid seed"),
+            data_split,
+            sample_weights: SampleWeightConfig::from_str(matches.value_of("sample-weights").unwrap()).unwrap(),
+        };
+
+        let pgn_files = TFRecordExporter::find_pgn_files(&pgn_path);
+        if let Err(e) = TFRecordExporter::export_pgn_files(&pgn_files, sampler_config, matches.value_of("output").unwrap(), shard_size, max_positions) {
+            println!("Error exporting TFRecord files: {}", e);
+        }
+        return;
+    }
+
+    // Export PGN positions as NNUE training data, if specified
+    if let Some(matches) = matches.subcommand_matches("export-nnue-data") {
+        let pgn_path = std::fs::canonicalize(matches.value_of("pgn").unwrap()).expect("PGN path not found");
+        let max_positions: Option<usize> = matches.value_of("max-positions").map(|v| v.parse().expect("Invalid max positions"));
+
+        let pgn_files = TFRecordExporter::find_pgn_files(&pgn_path);
+        if let Err(e) = NNUEDataExporter::export_pgn_files(&pgn_files, &PathBuf::from(matches.value_of("output").unwrap()), max_positions) {
+            println!("Error exporting NNUE data: {}", e);
+        }
+        return;
+    }
+
+    // Fit the value calibration, if specified
+    if let Some(matches) = matches.subcommand_matches("fit-value-calibration") {
+        let pgn_path = std::fs::canonicalize(matches.value_of("pgn").unwrap()).expect("PGN path not found");
+        let max_positions: Option<usize> = matches.value_of("max-positions").map(|v| v.parse().expect("Invalid max positions"));
+
+        let pgn_files = TFRecordExporter::find_pgn_files(&pgn_path);
+        let result = ValueCalibration::fit_from_pgn_files(&pgn_files, max_positions)
+            .and_then(|calibration| {
+                println!("Fitted value calibration: {:?}", calibration);
+                calibration.save(&PathBuf::from(matches.value_of("output").unwrap()))
+            });
+        if let Err(e) = result {
+            println!("Error fitting value calibration: {}", e);
+        }
+        return;
+    }
+
+    // Build a Polyglot opening book, if specified
+    if let Some(matches) = matches.subcommand_matches("build-book") {
+        let pgn_path = std::fs::canonicalize(matches.value_of("pgn").unwrap()).expect("PGN path not found");
+        let mut builder = PolyglotBookBuilder::new(
+            matches.value_of("max-ply").unwrap().parse().expect("Invalid max ply"),
+            matches.value_of("min-games").unwrap().parse().expect("Invalid min games"),
+            matches.value_of("win-weight").unwrap().parse().expect("Invalid win weight"),
+            matches.value_of("draw-weight").unwrap().parse().expect("Invalid draw weight"),
+            matches.value_of("loss-weight").unwrap().parse().expect("Invalid loss weight"),
+        );
+
+        let pgn_files = TFRecordExporter::find_pgn_files(&pgn_path);
+        if let Err(e) = builder.build_from_pgn_files(&pgn_files, &PathBuf::from(matches.value_of("output").unwrap())) {
+            println!("Error building opening book: {}", e);
+        }
+        return;
+    }
+
+    // Generate and / or probe endgame tables, if specified
+    if let Some(matches) = matches.subcommand_matches("endgame-tables") {
+        let dir = PathBuf::from(matches.value_of("dir").unwrap());
+        let mut tablebase = EndgameTablebase::init(&dir);
+        if let Some(codes) = matches.value_of("build") {
+            let result = codes.split(',')
+                .try_for_each(|code| tablebase.generate(code.trim()))
+                .and_then(|_| tablebase.save_tables(&dir));
+            match result {
+                Ok(saved_count) => println!("Saved {} endgame tables to {}", saved_count, dir.display()),
+                Err(e) => println!("Error generating endgame tables: {}", e),
+            }
+        }
+
+        if let Some(fen) = matches.value_of("fen") {
+            let result = Position::from_fen(Some(fen), false)
+                .and_then(|position| tablebase.load_tables_for(&position).map(|_| position))
+                .and_then(|position| tablebase.get_best_move(&position).map(|best_move| (tablebase.probe_dtm(&position), best_move)));
+            match result {
+                Ok((Ok(dtm), Some((best_move, _)))) => println!("{:?}, best move {}", dtm, best_move.get_uci_move_string()),
+                Ok((Ok(dtm), None)) => println!("{:?}, no legal moves", dtm),
+                Ok((Err(e), _)) | Err(e) => println!("Error probing endgame tables: {}", e),
+            }
+        }
+        return;
+    }
+
     // Default invocation - wait for input command line args from a chess UI program
     let mut uci = uci::UCIInterface::init_interface(get_nn_model_dir("models"));
     process_ui_commands(&mut uci);
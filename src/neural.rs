@@ -1,2 +1,10 @@
 pub mod positionconverter;
-pub mod nnprediction;
\ No newline at end of file
+pub mod nnprediction;
+pub mod trainingsampler;
+pub mod chessenvironment;
+pub mod evaluator;
+pub mod cpuevaluator;
+pub mod nnue;
+pub mod valuecalibration;
+#[cfg(not(compile_training))]
+pub mod tfevaluator;
\ No newline at end of file
@@ -0,0 +1,582 @@
+use std::fs;
+use std::path::Path;
+use simple_error::{bail, SimpleError};
+
+use crate::constants::*;
+use crate::neural::evaluator::{calc_top_k_outputs, Evaluator};
+
+/// Files written by TrainingModel.export_cpu_weights() in training_model.py
+pub const CPU_MODEL_MANIFEST_FILE: &str = "cpu_model.json";
+pub const CPU_MODEL_WEIGHTS_FILE: &str = "cpu_model.bin";
+
+// Keras defaults for the layers used in training_model.py
+const LEAKY_RELU_ALPHA: f32 = 0.3;
+const BATCH_NORM_EPSILON: f32 = 0.001;
+
+/// Feature map stored in channels last order, as used by the Keras model after the TransposeChannelsLastLayer
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureMap {
+    pub height: usize,
+    pub width: usize,
+    pub channels: usize,
+    pub data: Vec<f32>,
+}
+
+impl FeatureMap {
+    pub fn new(height: usize, width: usize, channels: usize) -> Self {
+        FeatureMap { height, width, channels, data: vec![0f32; height * width * channels] }
+    }
+
+    /// Converts the encoded input planes (planes x 8 x 8) for one position into an 8 x 8 x planes feature map
+    pub fn from_input_planes(input_data: &[f32]) -> Self {
+        let mut result = FeatureMap::new(8, 8, NN_TOTAL_PLANES_PER_POS);
+        for plane in 0..NN_TOTAL_PLANES_PER_POS {
+            for sq in 0..64 {
+                result.data[sq * NN_TOTAL_PLANES_PER_POS + plane] = input_data[(plane << 6) + sq];
+            }
+        }
+        result
+    }
+
+    #[inline(always)]
+    fn index(&self, y: usize, x: usize, c: usize) -> usize {
+        (y * self.width + x) * self.channels + c
+    }
+
+    fn leaky_relu(&mut self) {
+        for v in self.data.iter_mut() {
+            if *v < 0f32 { *v *= LEAKY_RELU_ALPHA; }
+        }
+    }
+
+    fn add(&mut self, other: &FeatureMap) {
+        for (v, o) in self.data.iter_mut().zip(other.data.iter()) { *v += o; }
+    }
+
+    /// Concatenates the feature maps along the channel axis
+    pub fn concat(feature_maps: &[FeatureMap]) -> FeatureMap {
+        let channels = feature_maps.iter().map(|f| f.channels).sum();
+        let mut result = FeatureMap::new(feature_maps[0].height, feature_maps[0].width, channels);
+        for pixel in 0..(result.height * result.width) {
+            let mut offset = pixel * channels;
+            for f in feature_maps {
+                result.data[offset..(offset + f.channels)].copy_from_slice(&f.data[(pixel * f.channels)..((pixel + 1) * f.channels)]);
+                offset += f.channels;
+            }
+        }
+        result
+    }
+
+    /// 2x2 max pooling with a stride of 1 and 'same' padding (the padding is added after the input, as TF does)
+    pub fn max_pool_same(&self) -> FeatureMap {
+        let mut result = FeatureMap::new(self.height, self.width, self.channels);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for c in 0..self.channels {
+                    let mut max_value = f32::NEG_INFINITY;
+                    for iy in y..usize::min(y + 2, self.height) {
+                        for ix in x..usize::min(x + 2, self.width) {
+                            max_value = f32::max(max_value, self.data[self.index(iy, ix, c)]);
+                        }
+                    }
+                    let i = result.index(y, x, c);
+                    result.data[i] = max_value;
+                }
+            }
+        }
+        result
+    }
+
+    /// 2x2 pooling with a stride of 2 and 'valid' padding, taking either the max or the average of each window
+    pub fn pool_valid(&self, use_max: bool) -> FeatureMap {
+        let mut result = FeatureMap::new(self.height / 2, self.width / 2, self.channels);
+        for y in 0..result.height {
+            for x in 0..result.width {
+                for c in 0..self.channels {
+                    let window = [
+                        self.data[self.index(2 * y, 2 * x, c)], self.data[self.index(2 * y, 2 * x + 1, c)],
+                        self.data[self.index(2 * y + 1, 2 * x, c)], self.data[self.index(2 * y + 1, 2 * x + 1, c)],
+                    ];
+                    let i = result.index(y, x, c);
+                    result.data[i] = if use_max { window.iter().cloned().fold(f32::NEG_INFINITY, f32::max) } else { window.iter().sum::<f32>() / 4f32 };
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Reads the named tensors out of the exported weights, checking their shapes against the manifest
+struct WeightReader {
+    manifest: json::JsonValue,
+    weights: Vec<f32>,
+}
+
+impl WeightReader {
+    fn read(&self, name: &str, expected_shape: &[usize]) -> Result<Vec<f32>, SimpleError> {
+        let tensor_info = &self.manifest["tensors"][name];
+        if tensor_info.is_null() { bail!("Tensor {} not found in {}", name, CPU_MODEL_MANIFEST_FILE); }
+
+        let shape: Vec<usize> = tensor_info["shape"].members().filter_map(|d| d.as_usize()).collect();
+        if shape != expected_shape {
+            bail!("Tensor {} has shape {:?} but {:?} was expected", name, shape, expected_shape);
+        }
+
+        let offset = tensor_info["offset"].as_usize().unwrap_or(usize::MAX);
+        let size: usize = shape.iter().product();
+        if offset.saturating_add(size) > self.weights.len() {
+            bail!("Tensor {} is outside the bounds of {}", name, CPU_MODEL_WEIGHTS_FILE);
+        }
+        Ok(self.weights[offset..(offset + size)].to_vec())
+    }
+}
+
+/// Keras Conv2D layer with a stride of 1 and 'same' padding
+pub struct Conv2D {
+    kernel: Vec<f32>,   // [kernel_height][kernel_width][in_channels][out_channels], as stored by Keras
+    bias: Vec<f32>,
+    kernel_size: usize,
+    in_channels: usize,
+    out_channels: usize,
+}
+
+impl Conv2D {
+    pub fn new(kernel: Vec<f32>, bias: Vec<f32>, kernel_size: usize, in_channels: usize, out_channels: usize) -> Self {
+        Conv2D { kernel, bias, kernel_size, in_channels, out_channels }
+    }
+
+    fn load(weights: &WeightReader, name: &str, kernel_size: usize, in_channels: usize, out_channels: usize) -> Result<Self, SimpleError> {
+        Ok(Conv2D::new(
+            weights.read(&format!("{}/kernel", name), &[kernel_size, kernel_size, in_channels, out_channels])?,
+            weights.read(&format!("{}/bias", name), &[out_channels])?,
+            kernel_size, in_channels, out_channels
+        ))
+    }
+
+    pub fn forward(&self, input: &FeatureMap) -> FeatureMap {
+        let mut result = FeatureMap::new(input.height, input.width, self.out_channels);
+        // TF puts any odd padding after the input, so even sized kernels are only padded at the end
+        let pad_before = (self.kernel_size - 1) / 2;
+
+        for y in 0..input.height {
+            for x in 0..input.width {
+                let out_offset = result.index(y, x, 0);
+                let output = &mut result.data[out_offset..(out_offset + self.out_channels)];
+                output.copy_from_slice(&self.bias);
+
+                for ky in 0..self.kernel_size {
+                    let iy = (y + ky) as isize - pad_before as isize;
+                    if iy < 0 || iy >= input.height as isize { continue; }
+                    for kx in 0..self.kernel_size {
+                        let ix = (x + kx) as isize - pad_before as isize;
+                        if ix < 0 || ix >= input.width as isize { continue; }
+
+                        let in_offset = input.index(iy as usize, ix as usize, 0);
+                        for ic in 0..self.in_channels {
+                            let v = input.data[in_offset + ic];
+                            if v == 0f32 { continue; }     // the input planes are mostly empty
+                            let kernel_offset = ((ky * self.kernel_size + kx) * self.in_channels + ic) * self.out_channels;
+                            for (o, k) in output.iter_mut().zip(&self.kernel[kernel_offset..(kernel_offset + self.out_channels)]) {
+                                *o += v * k;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Keras BatchNormalization layer (inference only), folded into a single scale + shift per channel
+pub struct BatchNorm {
+    scale: Vec<f32>,
+    shift: Vec<f32>,
+}
+
+impl BatchNorm {
+    fn load(weights: &WeightReader, name: &str, channels: usize) -> Result<Self, SimpleError> {
+        let gamma = weights.read(&format!("{}/gamma", name), &[channels])?;
+        let beta = weights.read(&format!("{}/beta", name), &[channels])?;
+        let moving_mean = weights.read(&format!("{}/moving_mean", name), &[channels])?;
+        let moving_variance = weights.read(&format!("{}/moving_variance", name), &[channels])?;
+
+        let scale: Vec<f32> = (0..channels).map(|c| gamma[c] / (moving_variance[c] + BATCH_NORM_EPSILON).sqrt()).collect();
+        let shift = (0..channels).map(|c| beta[c] - moving_mean[c] * scale[c]).collect();
+        Ok(BatchNorm { scale, shift })
+    }
+
+    fn forward(&self, input: &mut FeatureMap) {
+        for pixel in input.data.chunks_mut(self.scale.len()) {
+            for ((value, scale), shift) in pixel.iter_mut().zip(self.scale.iter()).zip(self.shift.iter()) {
+                *value = *value * scale + shift;
+            }
+        }
+    }
+}
+
+/// Matches TrainingModel.create_resnet_layer_block()
+struct ResnetBlock {
+    conv1: Conv2D,
+    bn1: BatchNorm,
+    conv2: Conv2D,
+    bn2: BatchNorm,
+}
+
+impl ResnetBlock {
+    fn load(weights: &WeightReader, name: &str, filter_count: usize, filter_size: usize) -> Result<Self, SimpleError> {
+        Ok(ResnetBlock {
+            conv1: Conv2D::load(weights, &format!("{}_conv1", name), filter_size, filter_count, filter_count)?,
+            bn1: BatchNorm::load(weights, &format!("{}_bn1", name), filter_count)?,
+            conv2: Conv2D::load(weights, &format!("{}_conv2", name), filter_size, filter_count, filter_count)?,
+            bn2: BatchNorm::load(weights, &format!("{}_bn2", name), filter_count)?,
+        })
+    }
+
+    fn forward(&self, input: &FeatureMap) -> FeatureMap {
+        let mut y = self.conv1.forward(input);
+        self.bn1.forward(&mut y);
+        y.leaky_relu();
+
+        let mut y = self.conv2.forward(&y);
+        y.add(input);   // the input 'skips' over the intermediate layers
+        self.bn2.forward(&mut y);
+        y.leaky_relu();
+        y
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PoolingType {
+    NONE,
+    MAX,
+    AVG,
+}
+
+/// Matches TrainingModel.add_inception_layer()
+struct InceptionLayer {
+    conv1x1: Conv2D,
+    conv2x2_1x1: Conv2D,
+    conv2x2_blocks: Vec<ResnetBlock>,
+    conv3x3_1x1: Conv2D,
+    conv3x3_blocks: Vec<ResnetBlock>,
+    maxpool_conv1x1: Conv2D,
+    pooling_type: PoolingType,
+}
+
+impl InceptionLayer {
+    fn load(weights: &WeightReader, layer_num: usize, in_channels: usize, filter_count: usize, block_count: usize, pooling_type: PoolingType) -> Result<Self, SimpleError> {
+        let load_blocks = |name: &str, filter_size: usize| -> Result<Vec<ResnetBlock>, SimpleError> {
+            (1..=block_count).map(|i| ResnetBlock::load(weights, &format!("i{}_{}_{}", layer_num, name, i), filter_count, filter_size)).collect()
+        };
+
+        Ok(InceptionLayer {
+            conv1x1: Conv2D::load(weights, &format!("i{}_conv1x1", layer_num), 1, in_channels, filter_count)?,
+            conv2x2_1x1: Conv2D::load(weights, &format!("i{}_conv2x2_1x1", layer_num), 1, in_channels, filter_count)?,
+            conv2x2_blocks: load_blocks("conv2x2", 2)?,
+            conv3x3_1x1: Conv2D::load(weights, &format!("i{}_conv3x3_1x1", layer_num), 1, in_channels, filter_count)?,
+            conv3x3_blocks: load_blocks("conv3x3", 3)?,
+            maxpool_conv1x1: Conv2D::load(weights, &format!("i{}_maxpool2x2_conv1x1", layer_num), 1, in_channels, filter_count)?,
+            pooling_type,
+        })
+    }
+
+    fn forward(&self, input: &FeatureMap) -> FeatureMap {
+        let conv1x1 = self.conv1x1.forward(input);
+        let conv2x2 = self.conv2x2_blocks.iter().fold(self.conv2x2_1x1.forward(input), |y, block| block.forward(&y));
+        let conv3x3 = self.conv3x3_blocks.iter().fold(self.conv3x3_1x1.forward(input), |y, block| block.forward(&y));
+        let max_pool = self.maxpool_conv1x1.forward(&input.max_pool_same());
+
+        let concat = FeatureMap::concat(&[conv1x1, conv2x2, conv3x3, max_pool]);
+        match self.pooling_type {
+            PoolingType::NONE => concat,
+            PoolingType::MAX => concat.pool_valid(true),
+            PoolingType::AVG => concat.pool_valid(false),
+        }
+    }
+}
+
+/// Keras Dense layer without an activation
+struct Dense {
+    kernel: Vec<f32>,   // [inputs][outputs]
+    bias: Vec<f32>,
+}
+
+impl Dense {
+    fn load(weights: &WeightReader, name: &str, inputs: usize, outputs: usize) -> Result<Self, SimpleError> {
+        Ok(Dense {
+            kernel: weights.read(&format!("{}/kernel", name), &[inputs, outputs])?,
+            bias: weights.read(&format!("{}/bias", name), &[outputs])?,
+        })
+    }
+
+    fn forward(&self, input: &[f32]) -> Vec<f32> {
+        let mut result = self.bias.clone();
+        let outputs = result.len();
+        for (i, v) in input.iter().enumerate() {
+            if *v == 0f32 { continue; }
+            for (o, k) in result.iter_mut().zip(&self.kernel[(i * outputs)..((i + 1) * outputs)]) {
+                *o += v * k;
+            }
+        }
+        result
+    }
+}
+
+/// Dependency-free evaluator backend that runs the inception / resnet architecture from training_model.py
+/// directly in Rust, using the weights exported by TrainingModel.export_cpu_weights()
+/// This allows the engine to run on machines without libtensorflow
+pub struct CPUEvaluator {
+    inception_layers: Vec<InceptionLayer>,
+    output_raw: Dense,
+    win_probability: Dense,
+}
+
+impl CPUEvaluator {
+    /// Loads cpu_model.json (the architecture + the shape / offset of every tensor) and cpu_model.bin
+    /// (all tensors as little endian f32 values) from the model directory
+    pub fn init_from_exported_weights(model_dir: &Path) -> Result<CPUEvaluator, SimpleError> {
+        let manifest_str = fs::read_to_string(model_dir.join(CPU_MODEL_MANIFEST_FILE)).map_err(SimpleError::from)?;
+        let manifest = json::parse(&manifest_str).map_err(SimpleError::from)?;
+        let weight_bytes = fs::read(model_dir.join(CPU_MODEL_WEIGHTS_FILE)).map_err(SimpleError::from)?;
+        let weights = weight_bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+
+        CPUEvaluator::from_weights(&WeightReader { manifest, weights })
+    }
+
+    fn from_weights(weights: &WeightReader) -> Result<CPUEvaluator, SimpleError> {
+        let mut inception_layers = vec![];
+        let (mut channels, mut board_size) = (NN_TOTAL_PLANES_PER_POS, 8usize);
+        for (i, layer_config) in weights.manifest["inception_layers"].members().enumerate() {
+            let filter_count = layer_config["filters"].as_usize().ok_or(SimpleError::new("Missing inception layer filters"))?;
+            let block_count = layer_config["block_count"].as_usize().ok_or(SimpleError::new("Missing inception layer block_count"))?;
+            let pooling_type = match layer_config["pooling"].as_str() {
+                Some("") => PoolingType::NONE,
+                Some("max") => PoolingType::MAX,
+                Some("avg") => PoolingType::AVG,
+                p => bail!("Invalid inception layer pooling {:?}", p),
+            };
+
+            inception_layers.push(InceptionLayer::load(weights, i + 1, channels, filter_count, block_count, pooling_type)?);
+            channels = filter_count * 4;
+            if pooling_type != PoolingType::NONE { board_size /= 2; }
+        }
+        if inception_layers.is_empty() { bail!("No inception layers found in {}", CPU_MODEL_MANIFEST_FILE); }
+
+        let flatten_size = board_size * board_size * channels;
+        Ok(CPUEvaluator {
+            inception_layers,
+            output_raw: Dense::load(weights, OUTPUT_RAW_LAYER_NAME, flatten_size, NN_TOTAL_OUTPUT_SIZE_PER_POS)?,
+            win_probability: Dense::load(weights, OUTPUT_WIN_PROBABILITY_LAYER_NAME, flatten_size, 1)?,
+        })
+    }
+
+    /// Runs the network for a single position, returning the softmax movement output and the win probability
+    pub fn evaluate_position(&self, input_data: &[f32]) -> (Vec<f32>, f32) {
+        let input = FeatureMap::from_input_planes(input_data);
+        let flatten = self.inception_layers.iter().fold(input, |y, layer| layer.forward(&y)).data;
+
+        // Softmax over the raw movement outputs
+        let mut movement_output = self.output_raw.forward(&flatten);
+        let max_output = movement_output.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        for v in movement_output.iter_mut() { *v = (*v - max_output).exp(); }
+        let total: f32 = movement_output.iter().sum();
+        for v in movement_output.iter_mut() { *v /= total; }
+
+        let win_probability = 1f32 / (1f32 + (-self.win_probability.forward(&flatten)[0]).exp());
+        (movement_output, win_probability)
+    }
+}
+
+impl Evaluator for CPUEvaluator {
+    fn evaluate_batch(&mut self, input_data: &[f32], output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError> {
+        if input_data.len() != batch_size * NN_TOTAL_INPUT_SIZE_PER_POS || output_masks.len() != batch_size * NN_TOTAL_OUTPUT_SIZE_PER_POS {
+            bail!("Input sizes don't match the batch size of {}", batch_size);
+        }
+
+        let mut top_k_outputs = Vec::with_capacity(batch_size * TOP_K_OUTPUTS * 2);
+        let mut win_probabilities = Vec::with_capacity(batch_size);
+        for (position_input, position_mask) in input_data.chunks(NN_TOTAL_INPUT_SIZE_PER_POS).zip(output_masks.chunks(NN_TOTAL_OUTPUT_SIZE_PER_POS)) {
+            let (movement_output, win_probability) = self.evaluate_position(position_input);
+            top_k_outputs.extend(calc_top_k_outputs(&movement_output, position_mask));
+            win_probabilities.push(win_probability);
+        }
+        Ok((top_k_outputs, win_probabilities))
+    }
+
+    fn evaluate_policy_batch(&mut self, input_data: &[f32], output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError> {
+        if input_data.len() != batch_size * NN_TOTAL_INPUT_SIZE_PER_POS || output_masks.len() != batch_size * NN_TOTAL_OUTPUT_SIZE_PER_POS {
+            bail!("Input sizes don't match the batch size of {}", batch_size);
+        }
+
+        let mut movement_outputs = Vec::with_capacity(batch_size * NN_TOTAL_OUTPUT_SIZE_PER_POS);
+        let mut win_probabilities = Vec::with_capacity(batch_size);
+        for position_input in input_data.chunks(NN_TOTAL_INPUT_SIZE_PER_POS) {
+            let (movement_output, win_probability) = self.evaluate_position(position_input);
+            movement_outputs.extend(movement_output);
+            win_probabilities.push(win_probability);
+        }
+        Ok((movement_outputs, win_probabilities))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use float_cmp::assert_approx_eq;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+    use crate::game::moves::gamemovelist::GameMoveList;
+    use crate::game::position::Position;
+    use crate::neural::positionconverter::NNPositionConverter;
+    use super::*;
+
+    fn feature_map(height: usize, width: usize, data: Vec<f32>) -> FeatureMap {
+        FeatureMap { height, width, channels: data.len() / (height * width), data }
+    }
+
+    #[test]
+    fn test_conv2d_same_padding() {
+        let input = feature_map(3, 3, vec![1., 2., 3., 4., 5., 6., 7., 8., 9.]);
+
+        // 2x2 kernels are only padded after the input
+        let conv = Conv2D::new(vec![1., 10., 100., 1000.], vec![0.5], 2, 1, 1);
+        assert_eq!(conv.forward(&input).data, vec![
+            5421.5, 6532.5, 603.5,
+            8754.5, 9865.5, 906.5,
+            87.5, 98.5, 9.5,
+        ]);
+
+        // 3x3 kernels are padded on both sides
+        let conv = Conv2D::new(vec![0., 0., 0., 0., 1., 1., 0., 0., 0.], vec![0.], 3, 1, 1);
+        assert_eq!(conv.forward(&input).data, vec![3., 5., 3., 9., 11., 6., 15., 17., 9.]);
+
+        // Multiple input / output channels
+        let input = feature_map(1, 1, vec![1., 2.]);
+        let conv = Conv2D::new(vec![1., 2., 3., 4.], vec![0., 1.], 1, 2, 2);
+        assert_eq!(conv.forward(&input).data, vec![7., 11.]);
+    }
+
+    #[test]
+    fn test_pooling() {
+        let input = feature_map(2, 2, vec![1., -1., 2., -2., 3., -3., 4., -4.]);
+        assert_eq!(input.max_pool_same().data, vec![4., -1., 4., -2., 4., -3., 4., -4.]);
+        assert_eq!(input.pool_valid(true).data, vec![4., -1.]);
+        assert_eq!(input.pool_valid(false).data, vec![2.5, -2.5]);
+
+        let concat = FeatureMap::concat(&[input.clone(), feature_map(2, 2, vec![5., 6., 7., 8.])]);
+        assert_eq!(concat.data, vec![1., -1., 5., 2., -2., 6., 3., -3., 7., 4., -4., 8.]);
+    }
+
+    // Builds a small model with the same architecture, using deterministic pseudo-random weights
+    fn create_test_weights(filter_count: usize) -> WeightReader {
+        let mut tensors: Vec<(String, Vec<usize>)> = vec![];
+        let add_conv = |tensors: &mut Vec<(String, Vec<usize>)>, name: String, k: usize, i: usize, o: usize| {
+            tensors.push((format!("{}/kernel", name), vec![k, k, i, o]));
+            tensors.push((format!("{}/bias", name), vec![o]));
+        };
+
+        let mut channels = NN_TOTAL_PLANES_PER_POS;
+        for layer_num in 1..=2 {
+            for name in ["conv1x1", "conv2x2_1x1", "conv3x3_1x1", "maxpool2x2_conv1x1"] {
+                add_conv(&mut tensors, format!("i{}_{}", layer_num, name), 1, channels, filter_count);
+            }
+            for (name, k) in [("conv2x2", 2), ("conv3x3", 3)] {
+                for conv in ["conv1", "conv2"] {
+                    add_conv(&mut tensors, format!("i{}_{}_1_{}", layer_num, name, conv), k, filter_count, filter_count);
+                }
+                for bn in ["bn1", "bn2"] {
+                    for w in ["gamma", "beta", "moving_mean", "moving_variance"] {
+                        tensors.push((format!("i{}_{}_1_{}/{}", layer_num, name, bn, w), vec![filter_count]));
+                    }
+                }
+            }
+            channels = filter_count * 4;
+        }
+        let flatten_size = 4 * 4 * channels;
+        tensors.push((format!("{}/kernel", OUTPUT_RAW_LAYER_NAME), vec![flatten_size, NN_TOTAL_OUTPUT_SIZE_PER_POS]));
+        tensors.push((format!("{}/bias", OUTPUT_RAW_LAYER_NAME), vec![NN_TOTAL_OUTPUT_SIZE_PER_POS]));
+        tensors.push((format!("{}/kernel", OUTPUT_WIN_PROBABILITY_LAYER_NAME), vec![flatten_size, 1]));
+        tensors.push((format!("{}/bias", OUTPUT_WIN_PROBABILITY_LAYER_NAME), vec![1]));
+
+        let mut manifest = json::object!{
+            "inception_layers" => json::array![
+                json::object!{"filters" => filter_count, "block_count" => 1, "pooling" => ""},
+                json::object!{"filters" => filter_count, "block_count" => 1, "pooling" => "max"},
+            ],
+            "tensors" => json::object!{},
+        };
+        let mut weights = vec![];
+        let mut seed = 12345u32;
+        for (name, shape) in tensors {
+            manifest["tensors"][name.as_str()] = json::object!{"shape" => shape.clone(), "offset" => weights.len()};
+            let is_variance = name.ends_with("moving_variance");
+            for _ in 0..shape.iter().product::<usize>() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let v = ((seed >> 16) & 0x7fff) as f32 / 32768.0 - 0.5;
+                weights.push(if is_variance { v + 1.0 } else { v * 0.2 });
+            }
+        }
+
+        WeightReader { manifest, weights }
+    }
+
+    #[test]
+    fn test_evaluate_batch() {
+        let mut evaluator = CPUEvaluator::from_weights(&create_test_weights(4)).unwrap();
+
+        let mut nn_converter = NNPositionConverter::new();
+        let mut input_data = vec![];
+        let mut output_masks = vec![];
+        let mut move_lists = vec![];
+        for fen in [START_POSITION, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"] {
+            let mut position = Position::from_fen(Some(fen), false).unwrap();
+            let mut move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+            let (position_input, position_mask) = nn_converter.convert_position_for_nn(&position, &move_list);
+            input_data.extend(position_input);
+            output_masks.extend(position_mask);
+            move_lists.push((move_list, !position.white_to_move));
+        }
+
+        let (top_k_outputs, win_probabilities) = evaluator.evaluate_batch(&input_data, &output_masks, 2).unwrap();
+        assert_eq!(top_k_outputs.len(), 2 * TOP_K_OUTPUTS * 2);
+        assert_eq!(win_probabilities.len(), 2);
+
+        for (i, (move_list, flip_for_black)) in move_lists.iter().enumerate() {
+            let row = &top_k_outputs[(i * TOP_K_OUTPUTS * 2)..((i + 1) * TOP_K_OUTPUTS * 2)];
+            assert!(win_probabilities[i] > 0f32 && win_probabilities[i] < 1f32);
+            // (the epsilon added by the TopKLayer to prevent NaN is significant given the tiny probabilities of an untrained model)
+            assert_approx_eq!(f32, row[TOP_K_OUTPUTS..].iter().sum::<f32>(), 1.0, epsilon = 0.001);
+
+            // Only legal moves can be chosen
+            let legal_indices: HashMap<u16, ()> = move_list.moves().iter()
+                .map(|m| (NNPositionConverter::get_movement_index(*m, *flip_for_black), ())).collect();
+            assert!(row[0..TOP_K_OUTPUTS].iter().all(|i| legal_indices.contains_key(&(*i as u16))));
+
+            // Batched results match evaluating each position on its own
+            let (movement_output, win_probability) = evaluator.evaluate_position(&input_data[(i * NN_TOTAL_INPUT_SIZE_PER_POS)..((i + 1) * NN_TOTAL_INPUT_SIZE_PER_POS)]);
+            assert_eq!(win_probability, win_probabilities[i]);
+            assert_approx_eq!(f32, movement_output.iter().sum::<f32>(), 1.0, epsilon = 0.0001);
+        }
+
+        assert!(evaluator.evaluate_batch(&input_data, &output_masks, 3).is_err());
+
+        // The full policy output holds the complete softmax for each position
+        let (movement_outputs, policy_win_probabilities) = evaluator.evaluate_policy_batch(&input_data, &output_masks, 2).unwrap();
+        assert_eq!(movement_outputs.len(), 2 * NN_TOTAL_OUTPUT_SIZE_PER_POS);
+        assert_eq!(policy_win_probabilities, win_probabilities);
+        assert_eq!(movement_outputs[0..NN_TOTAL_OUTPUT_SIZE_PER_POS].to_vec(), evaluator.evaluate_position(&input_data[0..NN_TOTAL_INPUT_SIZE_PER_POS]).0);
+        assert!(evaluator.evaluate_policy_batch(&input_data, &output_masks, 3).is_err());
+    }
+
+    #[test]
+    fn test_missing_weights() {
+        let mut weights = create_test_weights(2);
+        weights.manifest["tensors"].remove("i2_conv3x3_1_bn2/gamma");
+        assert!(CPUEvaluator::from_weights(&weights).is_err());
+
+        let mut weights = create_test_weights(2);
+        weights.manifest["tensors"]["i1_conv1x1/bias"]["shape"] = json::array![3];
+        assert!(CPUEvaluator::from_weights(&weights).is_err());
+    }
+}
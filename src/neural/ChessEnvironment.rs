@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use simple_error::{bail, SimpleError};
+
+use crate::constants::*;
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::moves::compactmove::CompactMove;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::position::Position;
+use crate::neural::positionconverter::NNPositionConverter;
+
+// The fifty move counter is stored in plies
+const FIFTY_MOVE_RULE_PLIES: u8 = 100;
+const REPETITION_DRAW_COUNT: u8 = 3;
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameStatus {
+    IN_PROGRESS,
+    CHECKMATE,
+    STALEMATE,
+    REPETITION,
+    FIFTY_MOVE_RULE,
+}
+
+impl GameStatus {
+    pub fn is_terminal(self) -> bool {
+        self != GameStatus::IN_PROGRESS
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            GameStatus::IN_PROGRESS => "in_progress",
+            GameStatus::CHECKMATE => "checkmate",
+            GameStatus::STALEMATE => "stalemate",
+            GameStatus::REPETITION => "repetition",
+            GameStatus::FIFTY_MOVE_RULE => "fifty_move_rule",
+        }
+    }
+}
+
+/// Result of a single step() call in the environment
+#[derive(Clone, Debug)]
+pub struct EnvironmentStep {
+    pub observation: Vec<f32>,
+    // From the point of view of the side that just moved: 1.0 for delivering checkmate, 0.0 otherwise
+    pub reward: f32,
+    // The game has ended according to the rules (mate, stalemate, repetition or fifty-move rule)
+    pub terminated: bool,
+    // The game was cut off after max_plies, without a result
+    pub truncated: bool,
+}
+
+/// Gym-style reinforcement learning environment where the actions are the neural net output
+/// indices (0 to NN_TOTAL_OUTPUT_SIZE_PER_POS - 1) and the observations are the neural net input
+/// planes, exactly as encoded by NNPositionConverter
+pub struct ChessEnvironment {
+    position: Position,
+    legal_moves: GameMoveList,
+    nn_converter: NNPositionConverter,
+    move_maker: MoveMaker,
+    observation: Vec<f32>,
+    legal_action_mask: Vec<f32>,
+    // Number of times each position has occurred in the game so far, for detecting repetitions
+    position_counts: HashMap<u64, u8>,
+    status: GameStatus,
+    ply_count: usize,
+    // The episode is truncated after this many plies (0 for no limit)
+    max_plies: usize,
+}
+
+impl ChessEnvironment {
+    pub fn new(max_plies: usize) -> Self {
+        let mut env = ChessEnvironment {
+            position: Position::default(),
+            legal_moves: GameMoveList::default(),
+            nn_converter: NNPositionConverter::new(),
+            move_maker: MoveMaker::default(),
+            observation: vec![],
+            legal_action_mask: vec![],
+            position_counts: HashMap::new(),
+            status: GameStatus::IN_PROGRESS,
+            ply_count: 0,
+            max_plies,
+        };
+        env.reset(None).unwrap();
+        env
+    }
+
+    /// Starts a new episode from the given FEN (or the standard starting position) and returns the first observation
+    pub fn reset(&mut self, fen: Option<&str>) -> Result<Vec<f32>, SimpleError> {
+        self.position = Position::from_fen(fen.or(Some(START_POSITION)), false)?;
+        self.nn_converter.init_new_game();
+        self.position_counts.clear();
+        self.ply_count = 0;
+        self.update_position_state();
+        Ok(self.observation.clone())
+    }
+
+    /// Plays the move for the given action index, which must be set in legal_action_mask()
+    pub fn step(&mut self, action_index: u16) -> Result<EnvironmentStep, SimpleError> {
+        if self.status.is_terminal() {
+            bail!("The game has already ended ({}), reset() must be called first", self.status.to_str());
+        }
+
+        let game_move = match self.get_move_for_action(action_index) {
+            Some(game_move) => game_move,
+            None => bail!("Action {} is not a legal move in position {}", action_index, self.position.to_fen())
+        };
+
+        self.move_maker.make_move(&mut self.position, &game_move, false);
+        self.ply_count += 1;
+        self.update_position_state();
+
+        Ok(EnvironmentStep {
+            observation: self.observation.clone(),
+            reward: (self.status == GameStatus::CHECKMATE) as u8 as f32,
+            terminated: self.status.is_terminal(),
+            truncated: !self.status.is_terminal() && self.max_plies > 0 && self.ply_count >= self.max_plies,
+        })
+    }
+
+    pub fn get_move_for_action(&self, action_index: u16) -> Option<GameMove> {
+        if action_index as usize >= NN_TOTAL_OUTPUT_SIZE_PER_POS { return None; }
+        NNPositionConverter::find_move_for_movement_index(action_index, &self.legal_moves, !self.position.white_to_move)
+            .map(|compact_move| compact_move.to_game_move(&self.position))
+    }
+
+    pub fn get_action_for_move(&self, move_uci: &str) -> Option<u16> {
+        self.position.get_move_by_uci(&self.legal_moves, move_uci)
+            .map(|game_move| NNPositionConverter::get_movement_index(CompactMove::from_game_move(&game_move), !self.position.white_to_move))
+    }
+
+    /// 1.0 for every action index that is a legal move in the current position, 0.0 otherwise
+    pub fn legal_action_mask(&self) -> &Vec<f32> {
+        &self.legal_action_mask
+    }
+
+    pub fn legal_actions(&self) -> Vec<u16> {
+        self.legal_moves.moves().iter()
+            .map(|compact_move| NNPositionConverter::get_movement_index(*compact_move, !self.position.white_to_move))
+            .collect()
+    }
+
+    pub fn get_observation(&self) -> &Vec<f32> {
+        &self.observation
+    }
+
+    pub fn get_position(&self) -> &Position {
+        &self.position
+    }
+
+    pub fn get_status(&self) -> GameStatus {
+        self.status
+    }
+
+    pub fn get_ply_count(&self) -> usize {
+        self.ply_count
+    }
+
+    // Regenerates the legal moves, observation and game status after the position has changed
+    fn update_position_state(&mut self) {
+        self.legal_moves.clear();
+        PositionAnalyzer::calc_legal_moves(&mut self.position, &mut self.legal_moves);
+
+        let (observation, legal_action_mask) = self.nn_converter.convert_position_for_nn(&self.position, &self.legal_moves);
+        self.observation = observation;
+        self.legal_action_mask = legal_action_mask;
+
+        let position_count = self.position_counts.entry(self.position.calc_position_hash()).or_insert(0);
+        *position_count += 1;
+
+        self.status = if self.position.is_checkmate {
+            GameStatus::CHECKMATE
+        } else if self.position.is_stalemate {
+            GameStatus::STALEMATE
+        } else if *position_count >= REPETITION_DRAW_COUNT {
+            GameStatus::REPETITION
+        } else if self.position.fifty_move_count >= FIFTY_MOVE_RULE_PLIES {
+            GameStatus::FIFTY_MOVE_RULE
+        } else {
+            GameStatus::IN_PROGRESS
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play_moves(env: &mut ChessEnvironment, moves: Vec<&str>) -> EnvironmentStep {
+        let mut result = None;
+        for move_uci in moves {
+            let action = env.get_action_for_move(move_uci).unwrap();
+            assert_eq!(env.legal_action_mask()[action as usize], 1f32);
+            result = Some(env.step(action).unwrap());
+        }
+        result.unwrap()
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut env = ChessEnvironment::new(0);
+        assert_eq!(env.get_observation().len(), NN_TOTAL_INPUT_SIZE_PER_POS);
+        assert_eq!(env.legal_action_mask().len(), NN_TOTAL_OUTPUT_SIZE_PER_POS);
+        assert_eq!(env.legal_action_mask().iter().sum::<f32>(), 20f32);
+        assert_eq!(env.legal_actions().len(), 20);
+        assert_eq!(env.get_status(), GameStatus::IN_PROGRESS);
+
+        env.reset(Some("4k3/8/8/8/8/8/8/4K2R w K - 0 1")).unwrap();
+        assert_eq!(env.legal_actions().len(), 15);
+        assert!(env.reset(Some("not a fen")).is_err());
+    }
+
+    #[test]
+    fn test_checkmate() {
+        let mut env = ChessEnvironment::new(0);
+        let step = play_moves(&mut env, vec!["f2f3", "e7e5", "g2g4", "d8h4"]);
+        assert_eq!(step.reward, 1f32);
+        assert!(step.terminated);
+        assert!(!step.truncated);
+        assert_eq!(env.get_status(), GameStatus::CHECKMATE);
+        assert_eq!(env.legal_action_mask().iter().sum::<f32>(), 0f32);
+
+        // No more moves are allowed once the game has ended
+        assert!(env.step(0).is_err());
+    }
+
+    #[test]
+    fn test_draws() {
+        let mut env = ChessEnvironment::new(0);
+        env.reset(Some("k7/8/8/1Q6/8/8/8/7K w - - 0 1")).unwrap();
+        let step = play_moves(&mut env, vec!["b5b6"]);
+        assert!(step.terminated);
+        assert_eq!(step.reward, 0f32);
+        assert_eq!(env.get_status(), GameStatus::STALEMATE);
+
+        // The starting position occurs for the 3rd time after both knights go out and back twice
+        env.reset(None).unwrap();
+        let step = play_moves(&mut env, vec!["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"]);
+        assert!(!step.terminated);
+        let step = play_moves(&mut env, vec!["f6g8"]);
+        assert!(step.terminated);
+        assert_eq!(env.get_status(), GameStatus::REPETITION);
+
+        env.reset(Some("4k3/8/8/8/8/8/8/4K2R w K - 99 80")).unwrap();
+        let step = play_moves(&mut env, vec!["h1h2"]);
+        assert!(step.terminated);
+        assert_eq!(env.get_status(), GameStatus::FIFTY_MOVE_RULE);
+    }
+
+    #[test]
+    fn test_truncation_and_illegal_actions() {
+        let mut env = ChessEnvironment::new(2);
+        let illegal_action = (0..NN_TOTAL_OUTPUT_SIZE_PER_POS as u16).find(|i| env.legal_action_mask()[*i as usize] == 0f32).unwrap();
+        assert!(env.step(illegal_action).is_err());
+        assert!(env.step(NN_TOTAL_OUTPUT_SIZE_PER_POS as u16).is_err());
+
+        assert!(!play_moves(&mut env, vec!["e2e4"]).truncated);
+        let step = play_moves(&mut env, vec!["e7e5"]);
+        assert!(step.truncated);
+        assert!(!step.terminated);
+    }
+}
@@ -0,0 +1,82 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use simple_error::{bail, SimpleError};
+
+use crate::constants::*;
+use crate::neural::cpuevaluator::{CPUEvaluator, CPU_MODEL_MANIFEST_FILE};
+#[cfg(not(compile_training))]
+use crate::neural::tfevaluator::TFEvaluator;
+
+/// A neural network backend that can evaluate batches of positions already encoded by NNPositionConverter
+pub trait Evaluator {
+    /// Evaluates batch_size positions, where input_data and output_masks hold the input planes and legal move
+    /// masks of every position back to back
+    /// Returns the top K outputs (K movement indices followed by their K probabilities) for every position,
+    /// and the win probability for every position
+    fn evaluate_batch(&mut self, input_data: &[f32], output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError>;
+
+    /// Same as evaluate_batch() but returns the full softmax movement output (NN_TOTAL_OUTPUT_SIZE_PER_POS values)
+    /// for every position instead of only the top K, so that every legal move gets a probability
+    fn evaluate_policy_batch(&mut self, input_data: &[f32], output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError>;
+}
+
+/// Loads the model from the given directory, using the TensorFlow SavedModel when it exists and the
+/// TensorFlow backend is compiled in, otherwise falling back to the weights exported for the CPU backend
+pub fn load_evaluator(model_dir: &Path) -> Result<Box<dyn Evaluator>, SimpleError> {
+    #[cfg(not(compile_training))]
+    if model_dir.join("saved_model.pb").exists() {
+        return Ok(Box::new(TFEvaluator::init_from_saved_model(model_dir)?));
+    }
+
+    if model_dir.join(CPU_MODEL_MANIFEST_FILE).exists() {
+        return Ok(Box::new(CPUEvaluator::init_from_exported_weights(model_dir)?));
+    }
+
+    bail!("No usable model found in {}", model_dir.display())
+}
+
+/// Calculates the same top K outputs as the TopKLayer in training_model.py from the softmax output
+/// of a single position: the K highest legal movement indices, followed by their probabilities
+/// normalised to add up to 1
+pub fn calc_top_k_outputs(movement_output: &[f32], output_mask: &[f32]) -> Vec<f32> {
+    let masked_output: Vec<f32> = movement_output.iter().zip(output_mask).map(|(p, m)| p * m).collect();
+
+    // Stable sort so that ties are broken by the lowest index first, as tf.math.top_k does
+    let mut movement_indices: Vec<usize> = (0..masked_output.len()).collect();
+    movement_indices.sort_by(|a, b| masked_output[*b].partial_cmp(&masked_output[*a]).unwrap_or(Ordering::Equal));
+    movement_indices.truncate(TOP_K_OUTPUTS);
+
+    let total: f32 = movement_indices.iter().map(|i| masked_output[*i]).sum();
+    let mut result: Vec<f32> = movement_indices.iter().map(|i| *i as f32).collect();
+    result.extend(movement_indices.iter().map(|i| masked_output[*i] / (total + 0.000001)));    // to prevent NaN in case all inputs are 0.0
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+    use super::*;
+
+    #[test]
+    fn test_calc_top_k_outputs() {
+        let mut movement_output = vec![0f32; NN_TOTAL_OUTPUT_SIZE_PER_POS];
+        let mut output_mask = vec![0f32; NN_TOTAL_OUTPUT_SIZE_PER_POS];
+        for (i, p) in [(5, 0.4), (10, 0.2), (20, 0.1), (30, 0.1), (40, 0.05), (50, 0.05), (60, 0.02), (70, 0.01), (80, 0.01)] {
+            movement_output[i] = p;
+            output_mask[i] = 1.0;
+        }
+        // Masked out movements are never chosen
+        movement_output[0] = 0.06;
+
+        let top_k = calc_top_k_outputs(&movement_output, &output_mask);
+        assert_eq!(top_k.len(), TOP_K_OUTPUTS * 2);
+        assert_eq!(top_k[0..TOP_K_OUTPUTS].to_vec(), vec![5.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0]);
+        assert_approx_eq!(f32, top_k[TOP_K_OUTPUTS], 0.4 / 0.93, epsilon = 0.00001);
+        assert_approx_eq!(f32, top_k[TOP_K_OUTPUTS..].iter().sum::<f32>(), 1.0, epsilon = 0.00001);
+    }
+
+    #[test]
+    fn test_load_evaluator_missing_model() {
+        assert!(load_evaluator(Path::new("src/test/resources")).is_err());
+    }
+}
@@ -4,62 +4,44 @@ use std::io::prelude::*;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
-#[cfg(not(compile_training))]
-use tensorflow::{Graph, SavedModelBundle, SessionOptions, SessionRunArgs, Tensor, Status};
+use simple_error::{bail, SimpleError};
 
 use crate::constants::*;
 use crate::game::analysis::positionanalyzer::PositionAnalyzer;
 use crate::game::moves::gamemove::GameMove;
 use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
 use crate::game::position::Position;
+use crate::neural::evaluator::{load_evaluator, Evaluator};
 use crate::neural::positionconverter::NNPositionConverter;
 
-// Create a dummy implementation of the NNPrediction class when compiling for training
-// This essentially eliminates the rust-tensorflow dependency at the code level
-#[cfg(compile_training)]
-pub struct NNPrediction {
-    // Dummy
-}
-
-#[cfg(compile_training)]
-impl NNPrediction {
-    pub fn init_from_saved_model(_model_dir: PathBuf) -> Result<NNPrediction, Status> {
-        Ok(NNPrediction{})
-    }
-
-    pub fn init_new_game(&mut self) { }
+/// The top K legal moves (with their probabilities) and the win probability predicted for a position
+pub type NNPositionPrediction = ([(Option<GameMove>, f32); TOP_K_OUTPUTS], f32);
 
-    pub fn make_prediction(&mut self, _position: &mut Position) -> ([(Option<GameMove>, f32); TOP_K_OUTPUTS], f32) {
-        ([(None, 0f32); TOP_K_OUTPUTS], 0f32)
-    }
-}
+/// Every legal move with its probability (best first, adding up to 1) and the win probability predicted for a position
+pub type NNPolicyPrediction = (Vec<(GameMove, f32)>, f32);
 
-#[cfg(not(compile_training))]
 pub struct NNPrediction {
-    graph: Graph,
-    saved_model: SavedModelBundle,
+    evaluator: Box<dyn Evaluator>,
     nn_converter: NNPositionConverter,
     // predictor: Py<PyAny>
 }
 
-#[cfg(not(compile_training))]
 impl NNPrediction {
-    /// Opens the specified saved model, import the graph and create the TF Session
-    pub fn init_from_saved_model(model_dir: PathBuf) -> Result<NNPrediction, Status> {
+    /// Opens the model in the specified directory, using whichever Evaluator backend is available for it
+    pub fn init_from_saved_model(model_dir: PathBuf) -> Result<NNPrediction, SimpleError> {
         if !model_dir.exists() {
-            panic!("TensorFlow model not found: {}", model_dir.display());
+            bail!("Neural network model not found: {}", model_dir.display());
         }
 
-        // Load the saved model exported by regression_savedmodel.py.
-        let mut graph = Graph::new();
-        let bundle =
-            SavedModelBundle::load(&SessionOptions::new(), &["serve"], &mut graph, model_dir)?;
+        Ok(NNPrediction::from_evaluator(load_evaluator(&model_dir)?))
+    }
 
-        Ok(NNPrediction {
-            graph,
-            saved_model: bundle,
+    pub fn from_evaluator(evaluator: Box<dyn Evaluator>) -> NNPrediction {
+        NNPrediction {
+            evaluator,
             nn_converter: NNPositionConverter::new(),
-        })
+        }
     }
 
     pub fn init_new_game(&mut self) {
@@ -67,125 +49,136 @@ impl NNPrediction {
     }
 
     /// Makes a prediction through the neural network and returns the top K moves
-    pub fn make_prediction(&mut self, position: &mut Position) -> ([(Option<GameMove>, f32); TOP_K_OUTPUTS], f32) {
+    pub fn make_prediction(&mut self, position: &mut Position) -> Result<NNPositionPrediction, SimpleError> {
         // Calc all legal moves in the position - required to mask out the output vector to only
         // consider legal moves (done prior to applying the final softmax activation to the outputs)
         let mut move_list = GameMoveList::default();
         PositionAnalyzer::calc_legal_moves(position, &mut move_list);
 
         // Make the prediction
-        let pred = self.make_prediction_from_nn(&position, &move_list).unwrap();
+        let (input_data, output_mask) = self.nn_converter.convert_position_for_nn(position, &move_list);
+        let (top_k_outputs, win_probabilities) = self.evaluator.evaluate_batch(&input_data, &output_mask, 1)?;
 
         // Locate and return the top K gave moves and discard the rest
-        let mut top_k_moves: [(Option<GameMove>, f32); TOP_K_OUTPUTS] = [(None, 0f32); TOP_K_OUTPUTS];
-        // pred.1 is the top move count returned by the NN
-        let max_ind = usize::min(usize::min(TOP_K_OUTPUTS, move_list.list_len), pred.1);
-        for i in 0..max_ind {
-            // println!("{}, {}", pred.0[i].0, pred.0[i].1);
-            top_k_moves[i] = (Some(move_list.move_list[pred.0[i].0 as usize]), pred.0[i].1)
+        Ok((NNPrediction::resolve_top_k_movements(&top_k_outputs, &move_list, position), win_probabilities[0]))
+    }
+
+    /// Makes predictions for many positions in a single call to the Evaluator, which is much faster
+    /// than calling make_prediction() for each one
+    /// Each position has its own NNPositionConverter holding the move history leading up to it, which
+    /// is updated with the position exactly as make_prediction() does for its own history
+    pub fn make_batch_prediction(&mut self, positions: &mut [Position], nn_converters: &mut [NNPositionConverter]) -> Result<Vec<NNPositionPrediction>, SimpleError> {
+        assert_eq!(positions.len(), nn_converters.len(), "Each position needs its own NNPositionConverter");
+        if positions.is_empty() { return Ok(vec![]); }
+
+        let batch_size = positions.len();
+        let mut move_lists = Vec::with_capacity(batch_size);
+        let mut input_data = Vec::with_capacity(batch_size * NN_TOTAL_INPUT_SIZE_PER_POS);
+        let mut output_masks = Vec::with_capacity(batch_size * NN_TOTAL_OUTPUT_SIZE_PER_POS);
+        for (position, nn_converter) in positions.iter_mut().zip(nn_converters.iter_mut()) {
+            let mut move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+
+            let (position_input_data, position_output_mask) = nn_converter.convert_position_for_nn(position, &move_list);
+            input_data.extend_from_slice(&position_input_data);
+            output_masks.extend_from_slice(&position_output_mask);
+            move_lists.push(move_list);
         }
 
-        (top_k_moves, pred.2)
+        let (top_k_outputs, win_probabilities) = self.evaluator.evaluate_batch(&input_data, &output_masks, batch_size)?;
+
+        // Each row of the top K outputs holds K movement indices followed by their K probabilities
+        Ok((0..batch_size).map(|i| (
+            NNPrediction::resolve_top_k_movements(&top_k_outputs[(i * TOP_K_OUTPUTS * 2)..((i + 1) * TOP_K_OUTPUTS * 2)], &move_lists[i], &positions[i]),
+            win_probabilities[i]
+        )).collect())
+    }
+
+    /// Makes a prediction through the neural network and returns a probability for every legal move in the
+    /// position, rather than only the top K
+    pub fn make_policy_prediction(&mut self, position: &mut Position) -> Result<NNPolicyPrediction, SimpleError> {
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(position, &mut move_list);
+
+        let (input_data, output_mask) = self.nn_converter.convert_position_for_nn(position, &move_list);
+        let (movement_output, win_probabilities) = self.evaluator.evaluate_policy_batch(&input_data, &output_mask, 1)?;
+
+        Ok((NNPrediction::calc_legal_move_distribution(&movement_output, &move_list, position), win_probabilities[0]))
+    }
+
+    /// Predicts the win probability of the position after each of the given moves in a single batch, so that every move
+    /// gets its own evaluation rather than sharing the one for the current position
+    /// The positions follow on from the move history of the current game, so this should be called after a prediction
+    /// for the current position
+    pub fn make_move_value_predictions(&mut self, position: &Position, game_moves: &[GameMove]) -> Result<Vec<f32>, SimpleError> {
+        let mut move_maker = MoveMaker::default();
+        let mut next_positions: Vec<Position> = game_moves.iter().map(|game_move| {
+            let mut next_position = position.clone();
+            move_maker.make_move(&mut next_position, game_move, false);
+            next_position
+        }).collect();
+        let mut nn_converters = vec![self.nn_converter.clone(); next_positions.len()];
+
+        Ok(self.make_batch_prediction(&mut next_positions, &mut nn_converters)?.iter().map(|(_, win_probability)| *win_probability).collect())
     }
 
-    /// Converts the position and game moves into the necessary inputs for the NN, then invokes
-    /// the TensorFlow graph to produce the actual outputs
-    fn make_prediction_from_nn(&mut self, position: &Position, game_move_list: &GameMoveList) -> Result<([(i16, f32); TOP_K_OUTPUTS], usize, f32), Status> {
-        // Sample usage / example, taken from:
-        // https://github.com/tensorflow/rust/blob/master/examples/regression_savedmodel.rs
-
-        // inputs:  [main_input, main_output_mask]
-        let (input_data, output_mask) = self.nn_converter.convert_position_for_nn(&position, &game_move_list);
-        let main_input = Tensor::new(&[1, NN_TOTAL_INPUT_SIZE_PER_POS as u64]).with_values(&input_data.as_slice())?;
-        let main_output_mask = Tensor::new(&[1, NN_TOTAL_OUTPUT_SIZE_PER_POS as u64]).with_values(&output_mask.as_slice())?;
-
-        // Load the 'serving_default' model call graph signature, which is added by default
-        // by Keras when saving the model
-        let call_signature = self.saved_model.meta_graph_def().get_signature("serving_default")?;
-        // // How to debug the call signature (see inputs/outputs):
-        // println!("sig name: {}", &call_signature.name().to_string());
-        // println!("{:?}", call_signature.inputs());
-        // println!("{:?}", call_signature.outputs());
-
-        // Retrieve the inputs & outputs
-        let main_input_info = call_signature.get_input(INPUT_MAIN_LAYER_NAME).unwrap();
-        let output_mask_info = call_signature.get_input(OUTPUT_MASK_LAYER_NAME).unwrap();
-        // let movement_output_info = call_signature.get_output(OUTPUT_MOVEMENTS_LAYER_NAME).unwrap();
-        let top_k_output_info = call_signature.get_output(OUTPUT_TOP_K_MOVEMENTS_LAYER_NAME).unwrap();
-        let win_probability_info = call_signature.get_output(OUTPUT_WIN_PROBABILITY_LAYER_NAME).unwrap();
-
-        // Retrieve the graph operations required to bind the inputs / outputs
-        let op_main_inp = self.graph.operation_by_name_required(&main_input_info.name().name)?;
-        let op_output_mask = self.graph.operation_by_name_required(&output_mask_info.name().name)?;
-        // let op_movement_output = self.graph.operation_by_name_required(&movement_output_info.name().name)?;
-        let op_top_k_outputs = self.graph.operation_by_name_required(&top_k_output_info.name().name)?;
-        let op_win_probability = self.graph.operation_by_name_required(&win_probability_info.name().name)?;
-
-        // Create a session, required to run operations in the graph, and bind the inputs/outputs
-        let mut call_step = SessionRunArgs::new();
-        call_step.add_feed(&op_main_inp, 0, &main_input);
-        call_step.add_feed(&op_output_mask, 0, &main_output_mask);
-        // call_step.add_target(&op_movement_output);
-        call_step.add_target(&op_top_k_outputs);
-        call_step.add_target(&op_win_probability);
-
-        // Grab the data out of the session using a fetch token
-        // let movement_output_fetch = call_step.request_fetch(&op_movement_output, 0);
-        let top_k_outputs_fetch = call_step.request_fetch(&op_top_k_outputs, 1);
-        let win_probability_fetch = call_step.request_fetch(&op_win_probability, 2);
-
-        // Run the session / graph operations
-        self.saved_model.session.run(&mut call_step)?;
-
-        // Retrieve outputs as tensors
-        // let movement_output: Tensor<f32> = call_step.fetch(movement_output_fetch)?;
-        let top_k_outputs: Tensor<f32> = call_step.fetch(top_k_outputs_fetch)?;
-        let win_probability: f32 = call_step.fetch(win_probability_fetch)?[0];
-
-        // println!("\nTop k outputs: {:?}", top_k_outputs.iter());
-        // println!("\nwin probability: {:?}", win_probability);
-
-        let (top_k_game_move_indices, top_moves_found) = NNPrediction::convert_nn_movements_to_game_move_indices(top_k_outputs, &game_move_list, !position.white_to_move);
-        Ok((top_k_game_move_indices, top_moves_found, win_probability))
+    /// Maps every legal move (including each promotion piece separately) to its probability in the full
+    /// movement output of the neural net, re-normalised over the legal moves so that they add up to 1
+    /// The moves are returned best first, keeping move generation order for equal probabilities
+    /// If the network gives no probability to any legal move, they are all given the same probability
+    /// The position must be the one the moves were generated from
+    pub fn calc_legal_move_distribution(movement_output: &[f32], game_move_list: &GameMoveList, position: &Position) -> Vec<(GameMove, f32)> {
+        let flip_for_black = !position.white_to_move;
+        let mut distribution: Vec<(GameMove, f32)> = game_move_list.moves().iter()
+            .map(|compact_move| (compact_move.to_game_move(position), movement_output[NNPositionConverter::get_movement_index(*compact_move, flip_for_black) as usize]))
+            .collect();
+
+        let probability_total: f32 = distribution.iter().map(|(_, p)| p).sum();
+        for (_, probability) in distribution.iter_mut() {
+            *probability = if probability_total > 0f32 { *probability / probability_total } else { 1f32 / game_move_list.list_len as f32 };
+        }
+
+        distribution.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        distribution
     }
 
-    /// Converts the index of the top K neural net output neurons (in the top_k_movements tensor)
-    /// into an array of indices into the GameMoveList (to locate the appropriate GameMove) for each one
-    /// The &GameMove objects themselves cannot be returned since they would then have different lifetimes
-    /// than the GameMoveList that is passed in here
-    fn convert_nn_movements_to_game_move_indices(top_k_movements: Tensor<f32>, game_move_list: &GameMoveList, flip_for_black: bool) -> ([(i16, f32); TOP_K_OUTPUTS], usize) {
-        let mut top_k_out = [(-1i16, 0f32); TOP_K_OUTPUTS];
+    /// Converts one row of the top K neural net outputs (K movement indices followed by their K probabilities)
+    /// into the matching legal moves, best first
+    /// Any movements that aren't legal moves are dropped and the remaining probabilities are re-normalised
+    /// to add up to 1 for the position, which must be the one the moves were generated from
+    pub fn resolve_top_k_movements(top_k_movements: &[f32], game_move_list: &GameMoveList, position: &Position) -> [(Option<GameMove>, f32); TOP_K_OUTPUTS] {
+        let flip_for_black = !position.white_to_move;
+        let mut top_k_out = [(None, 0f32); TOP_K_OUTPUTS];
 
-        // Build a temporary map of source / target squares -> index in the top_k array
+        // Build a temporary map of movement index -> index in the top_k array
         // This is for efficiency so we can just loop over all the game moves one time and resolve
         // all top K movements in a single pass (below)
-        let mut top_k_src_tgt_squares = HashMap::<(u8, u8), usize>::with_capacity(TOP_K_OUTPUTS);
-        for i in 0..TOP_K_OUTPUTS {
-            let (source_square, target_square, _promotion_piece) = NNPositionConverter::decode_movement(top_k_movements[i] as u16, flip_for_black);
-            top_k_src_tgt_squares.insert((source_square, target_square), i);
-            // println!("#{}: src square {}, tgt square {}", (i+1), source_square, target_square);
+        let mut top_k_movement_indices = HashMap::<u16, usize>::with_capacity(TOP_K_OUTPUTS);
+        for (i, movement_index) in top_k_movements[..TOP_K_OUTPUTS].iter().enumerate() {
+            top_k_movement_indices.insert(*movement_index as u16, i);
         }
 
         // Loop over game moves in a single pass
-        let mut top_moves_found = 0usize;
-        for i in 0..game_move_list.move_list.len() {
-            // If this game move's source square is in the map created above, it is in the top K moves
-            let move_source_sq = game_move_list.move_list[i].source_square;
-            let move_target_sq = game_move_list.move_list[i].target_square;
-            if top_k_src_tgt_squares.contains_key(&(move_source_sq, move_target_sq)) {
-                // Use the temporary map to resolve the index in the top K array
-                let top_k_array_ind = top_k_src_tgt_squares[&(move_source_sq, move_target_sq)];
-
-                // The top_k_movements output by the NN has the softmax percentage / probability offset exactly K positions
-                // away from the movement index, since this tensor is a concatenation of the movement index + probabilities
-                top_k_out[top_k_array_ind] = (i as i16, top_k_movements[top_k_array_ind + TOP_K_OUTPUTS]);
-                top_moves_found += 1;
-
-                if top_moves_found >= TOP_K_OUTPUTS { break; }
+        // The probabilities are offset exactly K positions away from the movement indices, since the
+        // top K output is a concatenation of the movement indices + probabilities
+        let mut top_k_found = [(None, 0f32); TOP_K_OUTPUTS];
+        for compact_move in game_move_list.moves().iter() {
+            if let Some(&top_k_array_ind) = top_k_movement_indices.get(&NNPositionConverter::get_movement_index(*compact_move, flip_for_black)) {
+                top_k_found[top_k_array_ind] = (Some(compact_move.to_game_move(position)), top_k_movements[top_k_array_ind + TOP_K_OUTPUTS]);
             }
         }
 
-        (top_k_out, top_moves_found)
+        // Remove the gaps left by any movements that weren't found, keeping the original order
+        for (out, found) in top_k_out.iter_mut().zip(top_k_found.iter().filter(|found| found.0.is_some())) {
+            *out = *found;
+        }
+
+        let probability_total: f32 = top_k_out.iter().map(|found| found.1).sum();
+        if probability_total > 0f32 {
+            for found in top_k_out.iter_mut() { found.1 /= probability_total; }
+        }
+
+        top_k_out
     }
 
     // ANOTHER METHOD CALLING PYTHON DIRECTLY FROM RUST, BUT THIS WILL BE DIFFICULT TO DEPLOY
@@ -244,9 +237,9 @@ impl NNPrediction {
     // }
 }
 
+
 #[cfg(test)]
 mod tests {
-    use arrayvec::ArrayString;
     use float_cmp::{approx_eq, assert_approx_eq};
     use std::collections::HashMap;
     use itertools::min;
@@ -256,6 +249,90 @@ mod tests {
     use crate::PGNReader;
     use super::*;
 
+    #[test]
+    fn test_resolve_top_k_movements() {
+        // Black to move, so the movement indices are flipped vertically
+        let mut position = Position::from_fen(Some("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+
+        let movement_index = |uci: &str| NNPositionConverter::get_movement_index(move_list.get_move_by_uci(uci).unwrap(), true) as f32;
+        let illegal_index = NNPositionConverter::get_movement_index(move_list.get_move_by_uci("e7e5").unwrap(), false) as f32;
+
+        // Top K outputs are the movement indices followed by their probabilities (the illegal one gets dropped)
+        let mut top_k_movements = vec![
+            movement_index("e7e5"), illegal_index, movement_index("c7c5"), movement_index("g8f6"),
+            movement_index("d7d5"), movement_index("e7e6"), movement_index("c7c6"), movement_index("b8c6"),
+        ];
+        top_k_movements.extend([0.3, 0.2, 0.1, 0.1, 0.1, 0.1, 0.1, 0.0]);
+
+        let top_k_moves = NNPrediction::resolve_top_k_movements(&top_k_movements, &move_list, &position);
+        let top_k_uci: Vec<String> = top_k_moves.iter().filter_map(|m| m.0).map(|m| m.get_uci_move_string()).collect();
+        assert_eq!(top_k_uci, vec!["e7e5", "c7c5", "g8f6", "d7d5", "e7e6", "c7c6", "b8c6"]);
+
+        // The remaining probabilities are re-normalised to add up to 1
+        assert_approx_eq!(f32, top_k_moves[0].1, 0.375);
+        assert_approx_eq!(f32, top_k_moves[1].1, 0.125);
+        assert_approx_eq!(f32, top_k_moves.iter().map(|m| m.1).sum::<f32>(), 1.0);
+        assert!(top_k_moves[7].0.is_none());
+    }
+
+    #[test]
+    fn test_calc_legal_move_distribution() {
+        // Black to move with all four promotions available on both b1 & c1
+        let mut position = Position::from_fen(Some("4k3/8/8/8/8/8/1p6/2R1K3 b - - 0 1"), false).unwrap();
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+
+        // Give each promotion a distinct probability and leave every other move at 0
+        let mut movement_output = vec![0f32; NN_TOTAL_OUTPUT_SIZE_PER_POS];
+        let promotions = ["b2c1q", "b2c1r", "b2c1b", "b2c1n", "b2b1q", "b2b1r", "b2b1b", "b2b1n"];
+        for (i, uci) in promotions.iter().enumerate() {
+            let movement_index = NNPositionConverter::get_movement_index(move_list.get_move_by_uci(uci).unwrap(), true);
+            movement_output[movement_index as usize] = (8 - i) as f32;
+        }
+        // Illegal movements are ignored
+        movement_output[0] = 100.0;
+
+        let distribution = NNPrediction::calc_legal_move_distribution(&movement_output, &move_list, &position);
+        assert_eq!(distribution.len(), move_list.list_len);
+        let distribution_uci: Vec<String> = distribution.iter().map(|(m, _)| m.get_uci_move_string()).collect();
+        assert_eq!(distribution_uci[0..8].to_vec(), promotions.to_vec());
+        assert_approx_eq!(f32, distribution[0].1, 8.0 / 36.0);
+        assert_approx_eq!(f32, distribution[7].1, 1.0 / 36.0);
+        assert_approx_eq!(f32, distribution.iter().map(|(_, p)| p).sum::<f32>(), 1.0);
+        assert!(distribution[8..].iter().all(|(_, p)| *p == 0.0));
+
+        // Moves the network gives no probability to are still returned, with an even distribution if necessary
+        let distribution = NNPrediction::calc_legal_move_distribution(&vec![0f32; NN_TOTAL_OUTPUT_SIZE_PER_POS], &move_list, &position);
+        assert_eq!(distribution.len(), move_list.list_len);
+        assert_approx_eq!(f32, distribution[0].1, 1.0 / move_list.list_len as f32);
+    }
+
+    // Gives each position in a batch a different win probability, in batch order
+    struct BatchOrderEvaluator {}
+
+    impl Evaluator for BatchOrderEvaluator {
+        fn evaluate_batch(&mut self, _input_data: &[f32], _output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError> {
+            Ok((vec![0f32; batch_size * TOP_K_OUTPUTS * 2], (0..batch_size).map(|i| i as f32 / batch_size as f32).collect()))
+        }
+
+        fn evaluate_policy_batch(&mut self, _input_data: &[f32], _output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError> {
+            Ok((vec![0f32; batch_size * NN_TOTAL_OUTPUT_SIZE_PER_POS], vec![0.5; batch_size]))
+        }
+    }
+
+    #[test]
+    fn test_make_move_value_predictions() {
+        let mut predictor = NNPrediction::from_evaluator(Box::new(BatchOrderEvaluator {}));
+        let mut position = Position::from_fen(None, false).unwrap();
+        let (best_moves, _) = predictor.make_policy_prediction(&mut position).unwrap();
+
+        let game_moves: Vec<GameMove> = best_moves.iter().take(4).map(|(game_move, _)| *game_move).collect();
+        assert_eq!(predictor.make_move_value_predictions(&position, &game_moves).unwrap(), vec![0.0, 0.25, 0.5, 0.75]);
+        assert!(predictor.make_move_value_predictions(&position, &[]).unwrap().is_empty());
+    }
+
     #[test]
     fn test_make_prediction_start_position() {
         let mut position = Position::from_fen(None, false).unwrap();
@@ -263,13 +340,12 @@ mod tests {
 
         let mut predictor = NNPrediction::init_from_saved_model(PathBuf::from("/Users/John/IdeaProjects/MyChessQL/models")).unwrap();
 
-        let (best_moves, win_prob) = predictor.make_prediction(&mut position);
+        let (best_moves, win_prob) = predictor.make_prediction(&mut position).unwrap();
         for i in 0..best_moves.len() {
             // Ensure that the game moves were properly found
-            let mut game_move = best_moves[i].0.expect(format!("Top game move #{} not found", (i+1)).as_str());
+            let game_move = best_moves[i].0.expect(format!("Top game move #{} not found", (i+1)).as_str());
 
-            game_move.set_extended_san_move_string();
-            println!("#{}: {}, {:.3?}", (i+1), game_move.extended_move_san, best_moves[i].1);
+            println!("#{}: {}, {:.3?}", (i+1), game_move.get_extended_san_move_string(), best_moves[i].1);
         }
         println!("\nWin probability: {:.7?}\n", win_prob);
 
@@ -282,8 +358,7 @@ mod tests {
         //             // assert_ne!(top_k_move_indices[i].0, -1i16);
         //             //
         //             // let mut game_move = game_move_list.move_list[top_k_move_indices[i].0 as usize];
-        //             // game_move.set_extended_san_move_string();
-        //             // println!("#{}: {}, {:.3?}", (i+1), game_move.extended_move_san, top_k_move_indices[i].1);
+        //             // println!("#{}: {}, {:.3?}", (i+1), game_move.get_extended_san_move_string(), top_k_move_indices[i].1);
         //         }
         //         println!("\nWin probability: {:.7?}\n", win_prob);
         //     }
@@ -0,0 +1,434 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use simple_error::{bail, SimpleError};
+
+use crate::game::position::Position;
+
+// HalfKP features: (own king square, piece square, non-king piece type & colour) for each side's perspective
+// The 10 piece indices are pawn..queen for the perspective's own pieces, then pawn..queen for the enemy's
+pub const NNUE_PIECE_INDICES: usize = 10;
+pub const NNUE_FEATURES: usize = 64 * NNUE_PIECE_INDICES * 64;
+
+// Layer sizes: 2 x NNUE_L1 accumulator outputs -> NNUE_L2 -> NNUE_L3 -> 1
+pub const NNUE_L1: usize = 256;
+pub const NNUE_L2: usize = 32;
+pub const NNUE_L3: usize = 32;
+
+/// Weight file format (all values little endian):
+///   8 byte magic "MCQNNUE1", then u32 NNUE_FEATURES, NNUE_L1, NNUE_L2, NNUE_L3
+///   i16 feature transformer biases [L1], i16 feature transformer weights [FEATURES][L1]
+///   i32 hidden layer 1 biases [L2], i8 hidden layer 1 weights [L2][2 * L1]
+///   i32 hidden layer 2 biases [L3], i8 hidden layer 2 weights [L3][L2]
+///   i32 output bias, i8 output weights [L3]
+pub const NNUE_MAGIC: &[u8; 8] = b"MCQNNUE1";
+
+// Quantisation: activations are clipped to 0..127 (representing 0.0..1.0) and hidden layer weights are
+// scaled by 2^WEIGHT_SCALE_BITS, so each hidden layer's output is shifted back down by that much
+const ACTIVATION_MAX: i32 = 127;
+const WEIGHT_SCALE_BITS: u32 = 6;
+// Divides the raw network output to get centipawns
+const OUTPUT_SCALE: i32 = 16;
+
+/// Quantised weights of an efficiently updatable neural network
+pub struct NNUENetwork {
+    pub ft_biases: Vec<i16>,
+    pub ft_weights: Vec<i16>,
+    pub l1_biases: Vec<i32>,
+    pub l1_weights: Vec<i8>,
+    pub l2_biases: Vec<i32>,
+    pub l2_weights: Vec<i8>,
+    pub output_bias: i32,
+    pub output_weights: Vec<i8>,
+}
+
+/// Reads consecutive little endian values out of a weight file
+struct NNUEWeightReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> NNUEWeightReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SimpleError> {
+        if self.offset + len > self.data.len() { bail!("NNUE weight file is truncated"); }
+        let bytes = &self.data[self.offset..(self.offset + len)];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, SimpleError> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_i8s(&mut self, count: usize) -> Result<Vec<i8>, SimpleError> {
+        Ok(self.read_bytes(count)?.iter().map(|b| *b as i8).collect())
+    }
+
+    fn read_i16s(&mut self, count: usize) -> Result<Vec<i16>, SimpleError> {
+        Ok(self.read_bytes(count * 2)?.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect())
+    }
+
+    fn read_i32s(&mut self, count: usize) -> Result<Vec<i32>, SimpleError> {
+        Ok(self.read_bytes(count * 4)?.chunks_exact(4).map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+    }
+}
+
+impl NNUENetwork {
+    /// Loads a weight file in the format described by NNUE_MAGIC
+    pub fn load(path: &Path) -> Result<NNUENetwork, SimpleError> {
+        match fs::read(path) {
+            Err(why) => bail!("Couldn't read NNUE weight file {}: {}", path.display(), why),
+            Ok(data) => NNUENetwork::from_bytes(&data),
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<NNUENetwork, SimpleError> {
+        let mut reader = NNUEWeightReader { data, offset: 0 };
+        if reader.read_bytes(NNUE_MAGIC.len())? != NNUE_MAGIC { bail!("Not an NNUE weight file"); }
+
+        let dims = [reader.read_u32()?, reader.read_u32()?, reader.read_u32()?, reader.read_u32()?];
+        let expected_dims = [NNUE_FEATURES as u32, NNUE_L1 as u32, NNUE_L2 as u32, NNUE_L3 as u32];
+        if dims != expected_dims {
+            bail!("NNUE weight file has layer sizes {:?} but {:?} was expected", dims, expected_dims);
+        }
+
+        let network = NNUENetwork {
+            ft_biases: reader.read_i16s(NNUE_L1)?,
+            ft_weights: reader.read_i16s(NNUE_FEATURES * NNUE_L1)?,
+            l1_biases: reader.read_i32s(NNUE_L2)?,
+            l1_weights: reader.read_i8s(NNUE_L2 * 2 * NNUE_L1)?,
+            l2_biases: reader.read_i32s(NNUE_L3)?,
+            l2_weights: reader.read_i8s(NNUE_L3 * NNUE_L2)?,
+            output_bias: reader.read_i32s(1)?[0],
+            output_weights: reader.read_i8s(NNUE_L3)?,
+        };
+        if reader.offset != data.len() { bail!("NNUE weight file has {} unexpected trailing bytes", data.len() - reader.offset); }
+        Ok(network)
+    }
+
+    /// Serialises the network in the same format read by from_bytes()
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(NNUE_FEATURES * NNUE_L1 * 2 + (1 << 16));
+        data.extend_from_slice(NNUE_MAGIC);
+        for dim in [NNUE_FEATURES, NNUE_L1, NNUE_L2, NNUE_L3] {
+            data.extend_from_slice(&(dim as u32).to_le_bytes());
+        }
+        self.ft_biases.iter().chain(&self.ft_weights).for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        self.l1_biases.iter().for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        data.extend(self.l1_weights.iter().map(|v| *v as u8));
+        self.l2_biases.iter().for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        data.extend(self.l2_weights.iter().map(|v| *v as u8));
+        data.extend_from_slice(&self.output_bias.to_le_bytes());
+        data.extend(self.output_weights.iter().map(|v| *v as u8));
+        data
+    }
+
+    /// Returns the HalfKP feature index for a piece, from the given perspective (0 = white, 1 = black)
+    /// Black's perspective flips the board vertically so that both sides see their own pieces from rank 1
+    #[inline(always)]
+    pub fn get_feature_index(perspective: usize, king_sq: usize, piece_index: usize, piece_sq: usize) -> usize {
+        let flip = perspective * 56;
+        (((king_sq ^ flip) * NNUE_PIECE_INDICES) + piece_index) * 64 + (piece_sq ^ flip)
+    }
+
+    /// Runs the layers after the feature transformer on the accumulated values, returning centipawns
+    /// from the point of view of the side to move
+    pub fn propagate(&self, accumulator: &[[i16; NNUE_L1]; 2], white_to_move: bool) -> i32 {
+        // The side to move's perspective always comes first
+        let (us, them) = if white_to_move { (0, 1) } else { (1, 0) };
+        let mut input = [0u8; 2 * NNUE_L1];
+        for i in 0..NNUE_L1 {
+            input[i] = (accumulator[us][i] as i32).clamp(0, ACTIVATION_MAX) as u8;
+            input[NNUE_L1 + i] = (accumulator[them][i] as i32).clamp(0, ACTIVATION_MAX) as u8;
+        }
+
+        let mut hidden1 = [0u8; NNUE_L2];
+        NNUENetwork::calc_hidden_layer(&input, &self.l1_weights, &self.l1_biases, &mut hidden1);
+        let mut hidden2 = [0u8; NNUE_L3];
+        NNUENetwork::calc_hidden_layer(&hidden1, &self.l2_weights, &self.l2_biases, &mut hidden2);
+
+        (self.output_bias + dot_product(&hidden2, &self.output_weights)) / OUTPUT_SCALE
+    }
+
+    fn calc_hidden_layer(input: &[u8], weights: &[i8], biases: &[i32], output: &mut [u8]) {
+        for (o, out) in output.iter_mut().enumerate() {
+            let sum = biases[o] + dot_product(input, &weights[(o * input.len())..((o + 1) * input.len())]);
+            *out = (sum >> WEIGHT_SCALE_BITS).clamp(0, ACTIVATION_MAX) as u8;
+        }
+    }
+}
+
+/// Dot product of unsigned 8-bit activations and signed 8-bit weights, using AVX2 when the CPU supports it
+pub fn dot_product(input: &[u8], weights: &[i8]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        return unsafe { dot_product_avx2(input, weights) };
+    }
+    dot_product_scalar(input, weights)
+}
+
+pub fn dot_product_scalar(input: &[u8], weights: &[i8]) -> i32 {
+    input.iter().zip(weights).map(|(a, w)| *a as i32 * *w as i32).sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_product_avx2(input: &[u8], weights: &[i8]) -> i32 {
+    use std::arch::x86_64::*;
+
+    let len = input.len().min(weights.len());
+    let chunks = len / 32;
+    let ones = _mm256_set1_epi16(1);
+    let mut sum = _mm256_setzero_si256();
+    for i in 0..chunks {
+        let a = _mm256_loadu_si256(input.as_ptr().add(i * 32) as *const __m256i);
+        let w = _mm256_loadu_si256(weights.as_ptr().add(i * 32) as *const __m256i);
+        // u8 x i8 pairs summed into i16 (activations <= 127 so this cannot saturate), then pairs of i16 into i32
+        sum = _mm256_add_epi32(sum, _mm256_madd_epi16(_mm256_maddubs_epi16(a, w), ones));
+    }
+
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+    lanes.iter().sum::<i32>() + dot_product_scalar(&input[(chunks * 32)..len], &weights[(chunks * 32)..len])
+}
+
+/// Returns the 12 piece bitboards in the order wp, wn, wb, wr, wq, wk, bp, bn, bb, br, bq, bk
+#[inline(always)]
+pub fn get_piece_bitboards(position: &Position) -> [u64; 12] {
+    [position.wp, position.wn, position.wb, position.wr, position.wq, position.wk,
+     position.bp, position.bn, position.bb, position.br, position.bq, position.bk]
+}
+
+/// The feature transformer outputs for both perspectives, updated incrementally as moves are made
+#[derive(Clone)]
+pub struct NNUEAccumulator {
+    network: Arc<NNUENetwork>,
+    pub values: [[i16; NNUE_L1]; 2],
+    history: Vec<[[i16; NNUE_L1]; 2]>,
+}
+
+impl NNUEAccumulator {
+    pub fn new(network: Arc<NNUENetwork>, position: &Position) -> Self {
+        let mut accumulator = NNUEAccumulator {
+            network,
+            values: [[0i16; NNUE_L1]; 2],
+            history: Vec::with_capacity(64),
+        };
+        accumulator.refresh(position);
+        accumulator
+    }
+
+    /// Recalculates both perspectives from scratch
+    pub fn refresh(&mut self, position: &Position) {
+        let pieces = get_piece_bitboards(position);
+        self.refresh_perspective(&pieces, 0);
+        self.refresh_perspective(&pieces, 1);
+    }
+
+    fn refresh_perspective(&mut self, pieces: &[u64; 12], perspective: usize) {
+        self.values[perspective].copy_from_slice(&self.network.ft_biases);
+
+        let king_sq = pieces[perspective * 6 + 5].trailing_zeros() as usize & 63;
+        for piece_bitboard_index in (0..12).filter(|i| i % 6 != 5) {
+            let piece_index = NNUEAccumulator::get_piece_index(perspective, piece_bitboard_index);
+            let mut piece_pos = pieces[piece_bitboard_index];
+            while piece_pos > 0 {
+                let sq_ind = piece_pos.trailing_zeros() as usize;
+                self.add_feature(perspective, NNUENetwork::get_feature_index(perspective, king_sq, piece_index, sq_ind));
+                piece_pos &= piece_pos - 1;
+            }
+        }
+    }
+
+    // Maps an index into get_piece_bitboards() to the HalfKP piece index for the perspective
+    #[inline(always)]
+    fn get_piece_index(perspective: usize, piece_bitboard_index: usize) -> usize {
+        let is_enemy = (piece_bitboard_index / 6) != perspective;
+        (piece_bitboard_index % 6) + (is_enemy as usize) * 5
+    }
+
+    // These loops are simple enough for the compiler to vectorise
+    #[inline(always)]
+    fn add_feature(&mut self, perspective: usize, feature_index: usize) {
+        let weights = &self.network.ft_weights[(feature_index * NNUE_L1)..((feature_index + 1) * NNUE_L1)];
+        for (value, weight) in self.values[perspective].iter_mut().zip(weights) {
+            *value = value.wrapping_add(*weight);
+        }
+    }
+
+    #[inline(always)]
+    fn remove_feature(&mut self, perspective: usize, feature_index: usize) {
+        let weights = &self.network.ft_weights[(feature_index * NNUE_L1)..((feature_index + 1) * NNUE_L1)];
+        for (value, weight) in self.values[perspective].iter_mut().zip(weights) {
+            *value = value.wrapping_sub(*weight);
+        }
+    }
+
+    /// Saves the current values so that they can be restored by pop() when the move is unmade
+    pub fn push(&mut self) {
+        self.history.push(self.values);
+    }
+
+    pub fn pop(&mut self) {
+        if let Some(values) = self.history.pop() {
+            self.values = values;
+        }
+    }
+
+    /// Updates the accumulator for a move, given the piece bitboards before the move and the position after it
+    /// Only the pieces that changed squares are added / removed, which covers captures, castling, en passant
+    /// and promotions without any special cases - unless a king moved, in which case that perspective's
+    /// features all change and it is recalculated
+    pub fn update(&mut self, old_pieces: &[u64; 12], position: &Position) {
+        let new_pieces = get_piece_bitboards(position);
+
+        for perspective in 0..2 {
+            let king_index = perspective * 6 + 5;
+            if old_pieces[king_index] != new_pieces[king_index] {
+                self.refresh_perspective(&new_pieces, perspective);
+                continue;
+            }
+
+            let king_sq = new_pieces[king_index].trailing_zeros() as usize & 63;
+            for piece_bitboard_index in (0..12).filter(|i| i % 6 != 5) {
+                let changed = old_pieces[piece_bitboard_index] ^ new_pieces[piece_bitboard_index];
+                if changed == 0 { continue; }
+
+                let piece_index = NNUEAccumulator::get_piece_index(perspective, piece_bitboard_index);
+                let mut removed = changed & old_pieces[piece_bitboard_index];
+                let mut added = changed & new_pieces[piece_bitboard_index];
+                while removed > 0 {
+                    self.remove_feature(perspective, NNUENetwork::get_feature_index(perspective, king_sq, piece_index, removed.trailing_zeros() as usize));
+                    removed &= removed - 1;
+                }
+                while added > 0 {
+                    self.add_feature(perspective, NNUENetwork::get_feature_index(perspective, king_sq, piece_index, added.trailing_zeros() as usize));
+                    added &= added - 1;
+                }
+            }
+        }
+    }
+
+    /// Evaluates the position in centipawns from the point of view of the side to move
+    pub fn evaluate(&self, white_to_move: bool) -> i32 {
+        self.network.propagate(&self.values, white_to_move)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+    use crate::constants::START_POSITION;
+    use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+    use crate::game::moves::gamemovelist::GameMoveList;
+    use crate::game::moves::movemaker::MoveMaker;
+    use super::*;
+
+    fn init_random_network(seed: u64) -> NNUENetwork {
+        // Small weights so that the accumulator cannot overflow
+        let mut rng = StdRng::seed_from_u64(seed);
+        NNUENetwork {
+            ft_biases: (0..NNUE_L1).map(|_| rng.gen_range(0..64)).collect(),
+            // A cheap hash rather than the RNG, since there are over 10 million of these
+            ft_weights: (0..(NNUE_FEATURES * NNUE_L1) as u64).map(|i| ((i.wrapping_add(seed).wrapping_mul(0x9e3779b97f4a7c15) >> 59) as i16) - 16).collect(),
+            l1_biases: (0..NNUE_L2).map(|_| rng.gen_range(-1000..1000)).collect(),
+            l1_weights: (0..(NNUE_L2 * 2 * NNUE_L1)).map(|_| rng.gen_range(-8..8)).collect(),
+            l2_biases: (0..NNUE_L3).map(|_| rng.gen_range(-1000..1000)).collect(),
+            l2_weights: (0..(NNUE_L3 * NNUE_L2)).map(|_| rng.gen_range(-32..32)).collect(),
+            output_bias: rng.gen_range(-1000..1000),
+            output_weights: (0..NNUE_L3).map(|_| rng.gen_range(-64..64)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_dot_product() {
+        let input: Vec<u8> = (0..100).map(|i| (i * 7 % 128) as u8).collect();
+        let weights: Vec<i8> = (0..100).map(|i| (i * 13 % 256) as u8 as i8).collect();
+        assert_eq!(dot_product(&input, &weights), dot_product_scalar(&input, &weights));
+        assert_eq!(dot_product_scalar(&[127, 1, 0], &[-128, 5, 100]), -16256 + 5);
+    }
+
+    #[test]
+    fn test_feature_index_perspective() {
+        // White's view of white's e1 king & a2 pawn matches black's view of black's e8 king & a7 pawn
+        assert_eq!(NNUENetwork::get_feature_index(0, 4, 0, 8), NNUENetwork::get_feature_index(1, 60, 0, 48));
+        assert_eq!(NNUENetwork::get_feature_index(0, 63, 9, 63), NNUE_FEATURES - 1);
+
+        // Evaluating a position and its colour-flipped equivalent gives the same result for the side to move
+        let network = Arc::new(init_random_network(1));
+        let position = Position::from_fen(Some("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"), false).unwrap();
+        let mirrored = Position::from_fen(Some("rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR b KQkq - 2 3"), false).unwrap();
+        let accumulator = NNUEAccumulator::new(network.clone(), &position);
+        let mirrored_accumulator = NNUEAccumulator::new(network, &mirrored);
+        assert_eq!(accumulator.values[0], mirrored_accumulator.values[1]);
+        assert_eq!(accumulator.evaluate(true), mirrored_accumulator.evaluate(false));
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let network = Arc::new(init_random_network(2));
+        // Castling, en passant and promotion are all available in this line
+        let mut position = Position::from_fen(Some("r3k2r/1P6/8/8/4p3/8/3P4/R3K2R w KQkq - 0 1"), false).unwrap();
+        let mut move_maker = MoveMaker::default();
+        move_maker.nnue_accumulator = Some(NNUEAccumulator::new(network.clone(), &position));
+
+        for move_uci in ["d2d4", "e4d3", "b7a8n", "e8g8", "e1c1", "g8g7"] {
+            let mut move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+            let game_move = move_list.get_move_by_uci(move_uci).unwrap();
+            let before_values = move_maker.nnue_accumulator.as_ref().unwrap().values;
+
+            // Unmaking the move restores the previous values
+            move_maker.make_compact_move(&mut position, game_move, true);
+            move_maker.unmake_move(&mut position);
+            assert_eq!(move_maker.nnue_accumulator.as_ref().unwrap().values, before_values);
+
+            move_maker.make_compact_move(&mut position, game_move, false);
+            let refreshed = NNUEAccumulator::new(network.clone(), &position);
+            let accumulator = move_maker.nnue_accumulator.as_ref().unwrap();
+            assert_eq!(accumulator.values, refreshed.values, "after {}", move_uci);
+            assert_eq!(accumulator.evaluate(position.white_to_move), refreshed.evaluate(position.white_to_move));
+        }
+    }
+
+    #[test]
+    fn test_unmake_without_saved_state() {
+        let network = Arc::new(init_random_network(4));
+        let mut position = Position::from_fen(None, false).unwrap();
+        let mut move_maker = MoveMaker::default();
+        move_maker.nnue_accumulator = Some(NNUEAccumulator::new(network.clone(), &position));
+
+        let mut move_list = GameMoveList::default();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+        let e2e4 = move_list.get_move_by_uci("e2e4").unwrap();
+        let d2d4 = move_list.get_move_by_uci("d2d4").unwrap();
+        move_maker.make_compact_move(&mut position, e2e4, true);
+        move_maker.unmake_move(&mut position);
+
+        // Nothing was pushed for this move, so unmaking it must not pop anything
+        move_maker.make_compact_move(&mut position, d2d4, false);
+        move_maker.unmake_move(&mut position);
+        assert_eq!(position.to_fen(), START_POSITION);
+        assert_eq!(move_maker.nnue_accumulator.as_ref().unwrap().values, NNUEAccumulator::new(network, &position).values);
+    }
+
+    #[test]
+    fn test_load_weights() {
+        let network = init_random_network(3);
+        let data = network.to_bytes();
+        let loaded = NNUENetwork::from_bytes(&data).unwrap();
+        assert_eq!(loaded.ft_weights, network.ft_weights);
+        assert_eq!(loaded.output_weights, network.output_weights);
+        assert_eq!(loaded.output_bias, network.output_bias);
+
+        let position = Position::from_fen(None, false).unwrap();
+        let accumulator = NNUEAccumulator::new(Arc::new(network), &position);
+        let loaded_accumulator = NNUEAccumulator::new(Arc::new(loaded), &position);
+        assert_eq!(accumulator.evaluate(true), loaded_accumulator.evaluate(true));
+
+        assert!(NNUENetwork::from_bytes(&data[0..(data.len() - 1)]).is_err());
+        assert!(NNUENetwork::from_bytes(b"NOTNNUE!").is_err());
+        assert!(NNUENetwork::load(Path::new("src/test/resources/missing.nnue")).is_err());
+    }
+}
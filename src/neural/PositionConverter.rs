@@ -3,11 +3,13 @@ use itertools::Itertools;
 
 use crate::game::position::*;
 use crate::constants::*;
+use crate::game::moves::compactmove::*;
 use crate::game::moves::gamemove::GameMove;
 use crate::game::moves::gamemovelist::GameMoveList;
 use crate::game::pieces::piece::Piece;
 use crate::game::positionhelper::PositionHelper;
 
+#[derive(Clone)]
 pub struct NNPositionConverter {
     pub move_history_buffer_white: Vec<f32>,
     pub move_history_buffer_black: Vec<f32>,
@@ -123,14 +125,8 @@ impl NNPositionConverter {
     // Output vector contains one square for every valid / unique / possible move, given the piece movement
     // rules of chess.  Pawn underpromotion moves encoded separately into their own slot since promotion
     // moves share the same source/target square combo but can promote to 4 possible piece types
-    pub fn encode_movement(movement_planes: *mut f32, game_move: &GameMove, flip_for_black: bool) {
-        let flip_for_black = flip_for_black as u8;
-        let source_square = ((flip_for_black * VERTICAL_FLIP_INDICES[game_move.source_square as usize]) + ((1 - flip_for_black) * game_move.source_square)) as u16;
-        let target_square = ((flip_for_black * VERTICAL_FLIP_INDICES[game_move.target_square as usize]) + ((1 - flip_for_black) * game_move.target_square)) as u16;
-        let promotion_piece = ((game_move.promotion_piece as u8) % 4) as u16;    // makes queen promotions wrap around to 0
-
-        // println!("{}", ((promotion_piece << 12) + (source_square << 6) + target_square));
-        let offset = MOVEMENTS_TO_NN_OUTPUT_INDICES[&((promotion_piece << 12) + (source_square << 6) + target_square)];
+    pub fn encode_movement(movement_planes: *mut f32, compact_move: CompactMove, flip_for_black: bool) {
+        let offset = NNPositionConverter::get_movement_index(compact_move, flip_for_black);
         unsafe {
             movement_planes.offset(offset as isize).write(1.0);
         }
@@ -159,6 +155,37 @@ impl NNPositionConverter {
         // movement_planes[((knight_movement_stride + movement_direction_stride + squares_moved) << 6) + game_move.source_square as usize] = 1;
     }
 
+    /// Returns the index in the neural net output vector (0 to NN_TOTAL_OUTPUT_SIZE_PER_POS - 1) for a single game move
+    /// Castling is encoded as the king moving to its target square, except in Chess960 when the king moves less than 2
+    /// squares (which would be the same as a king move, or no move at all).  The corner square on the castling side is
+    /// used instead, which is either at least 2 squares away or holds the castling rook, so no king move can go there
+    pub fn get_movement_index(compact_move: CompactMove, flip_for_black: bool) -> u16 {
+        let source_square = compact_move.get_source_square();
+        let mut target_square = compact_move.get_target_square();
+        if compact_move.is_castle() && (target_square as i8 - source_square as i8).abs() < 2 {
+            target_square = (target_square & !7) | if target_square & 7 == 6 { 7 } else { 0 };
+        }
+        NNPositionConverter::calc_movement_index(source_square, target_square, compact_move.get_promotion_piece(), flip_for_black)
+    }
+
+    #[inline(always)]
+    fn calc_movement_index(source_square: u8, target_square: u8, promotion_piece: PieceType, flip_for_black: bool) -> u16 {
+        let flip_for_black = flip_for_black as u8;
+        let source_square = ((flip_for_black * VERTICAL_FLIP_INDICES[source_square as usize]) + ((1 - flip_for_black) * source_square)) as u16;
+        let target_square = ((flip_for_black * VERTICAL_FLIP_INDICES[target_square as usize]) + ((1 - flip_for_black) * target_square)) as u16;
+        let promotion_piece = ((promotion_piece as u8) % 4) as u16;    // makes queen promotions wrap around to 0
+
+        MOVEMENTS_TO_NN_OUTPUT_INDICES[&((promotion_piece << 12) + (source_square << 6) + target_square)]
+    }
+
+    /// Resolves a neural net output vector index to the matching move in the list of possible moves, if any
+    /// Unlike decode_movement(), this is able to tell queen promotions apart from non-promotion moves
+    pub fn find_move_for_movement_index(nn_output_index: u16, possible_moves: &GameMoveList, flip_for_black: bool) -> Option<CompactMove> {
+        possible_moves.moves().iter()
+            .find(|compact_move| NNPositionConverter::get_movement_index(**compact_move, flip_for_black) == nn_output_index)
+            .copied()
+    }
+
     /// Decodes the neural net output vector index back to a source /target square on the board
     /// The promotion piece / target square aren't needed since the source / target squares alone
     /// are uniquely able to identify the GameMove object for the current position
@@ -189,9 +216,8 @@ impl NNPositionConverter {
     // Encodes all possible game moves for a given position into a set of output planes
     // This will be used to mask out invalid output values before re-normalizing to get the final movement probabilities
     fn encode_movement_output_planes_for_nn (output_move_mask_planes: *mut f32, possible_moves: &GameMoveList, flip_for_black: bool) {
-        for i in 0..possible_moves.list_len {
-            let game_move = &possible_moves.move_list[i];
-            NNPositionConverter::encode_movement(output_move_mask_planes, &game_move, flip_for_black);
+        for compact_move in possible_moves.moves().iter() {
+            NNPositionConverter::encode_movement(output_move_mask_planes, *compact_move, flip_for_black);
         }
     }
 
@@ -245,20 +271,20 @@ impl NNPositionConverter {
     // Converts a target move (for supervised learning) into the set of output planes for the neural network
     pub fn convert_target_move_for_nn (target_move: &GameMove, position: &Position) -> Vec<f32> {
         let mut target_output = vec![0f32; NN_TOTAL_OUTPUT_SIZE_PER_POS];
-        NNPositionConverter::encode_movement(target_output.as_mut_ptr(), &target_move, !position.white_to_move);
+        NNPositionConverter::encode_movement(target_output.as_mut_ptr(), CompactMove::from_game_move(target_move), !position.white_to_move);
         target_output
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use arrayvec::ArrayString;
     use float_cmp::{approx_eq, assert_approx_eq};
     use std::collections::HashMap;
     use simple_error::bail;
     use crate::game::analysis::positionanalyzer::PositionAnalyzer;
     use crate::game::moves::movemaker::MoveMaker;
     use crate::PGNReader;
+    use crate::interfaces::pgn::NNTrainingPosition;
     use super::*;
 
     fn compare_f32_vectors(vec1: &Vec<f32>, vec2: &Vec<f32>) {
@@ -340,17 +366,16 @@ mod tests {
             if output_planes[i] <= 0f32 { continue; }
 
             let (source_square, target_square, promotion_piece) = NNPositionConverter::decode_movement(i as u16, !white_to_move);
-            let mut game_move = GameMove {
+            let game_move = GameMove {
                 piece: PieceType::NONE,
                 source_square,
                 target_square,
                 promotion_piece,
                 is_capture: false,
-                extended_move_san: Default::default()
+                captured_piece: PieceType::NONE,
+                move_kind: MoveKind::NORMAL,
             };
-            game_move.set_extended_san_move_string();
-
-            println!("{}", game_move.extended_move_san);
+            println!("{}", game_move.get_extended_san_move_string());
         }
     }
 
@@ -395,24 +420,21 @@ mod tests {
                 let mut cardinal_moves = QUEEN_ATTACKS[sq_ind];
                 let mut knight_moves = KNIGHT_ATTACKS[sq_ind];
 
-                let mut game_move = GameMove::default();
-                game_move.source_square = sq_ind as u8;
+                let source_square = sq_ind as u8;
 
                 while cardinal_moves > 0 {
-                    game_move.piece = PieceType::QUEEN;
-                    game_move.target_square = cardinal_moves.trailing_zeros() as u8;
+                    let target_square = cardinal_moves.trailing_zeros() as u8;
 
-                    NNPositionConverter::encode_movement(movement_planes.as_mut_ptr(), &game_move, flip_for_black);
+                    NNPositionConverter::encode_movement(movement_planes.as_mut_ptr(), CompactMove::new(source_square, target_square, QUIET_MOVE_FLAG), flip_for_black);
 
                     // Test underpromotions - these should be encoded into separate indices
-                    if (!flip_for_black && game_move.target_square >= 56 && game_move.source_square >= 48 && game_move.source_square < 56)
-                        || (flip_for_black && game_move.target_square <= 7 && game_move.source_square >= 8 && game_move.source_square < 16) {
+                    if (!flip_for_black && target_square >= 56 && source_square >= 48 && source_square < 56)
+                        || (flip_for_black && target_square <= 7 && source_square >= 8 && source_square < 16) {
 
-                        game_move.piece = PieceType::PAWN;
-                        for promotion_piece in [PieceType::KNIGHT, PieceType::BISHOP, PieceType::ROOK] {
-                            game_move.promotion_piece = promotion_piece;
-                            NNPositionConverter::encode_movement(movement_planes.as_mut_ptr(), &game_move, flip_for_black);
-                            game_move.promotion_piece = PieceType::NONE;
+                        // Knight, bishop and rook promotions
+                        for promotion_index in 0..3 {
+                            let promotion_move = CompactMove::new(source_square, target_square, PROMOTION_FLAG | promotion_index);
+                            NNPositionConverter::encode_movement(movement_planes.as_mut_ptr(), promotion_move, flip_for_black);
 
                             target_count += 1;
                         }
@@ -422,10 +444,9 @@ mod tests {
                 }
 
                 while knight_moves > 0 {
-                    game_move.piece = PieceType::KNIGHT;
-                    game_move.target_square = knight_moves.trailing_zeros() as u8;
+                    let target_square = knight_moves.trailing_zeros() as u8;
 
-                    NNPositionConverter::encode_movement(movement_planes.as_mut_ptr(), &game_move, flip_for_black);
+                    NNPositionConverter::encode_movement(movement_planes.as_mut_ptr(), CompactMove::new(source_square, target_square, QUIET_MOVE_FLAG), flip_for_black);
                     knight_moves &= knight_moves - 1;
                 }
 
@@ -507,14 +528,15 @@ mod tests {
 
         NNPositionConverter::encode_movement(
             movement_planes.as_mut_ptr(),
-            &GameMove {
+            CompactMove::from_game_move(&GameMove {
                 piece,
                 source_square,
                 target_square,
                 is_capture: false,
-                promotion_piece,
-                extended_move_san: ArrayString::default(),
-            },
+                captured_piece: PieceType::NONE,
+                move_kind: MoveKind::NORMAL,
+                promotion_piece
+            }),
             flip_for_black
         );
         assert_eq!(movement_planes[MOVEMENTS_TO_NN_OUTPUT_INDICES[&expected_index] as usize], 1f32);
@@ -559,7 +581,57 @@ mod tests {
         // rook -> 3 * 4096
         test_encode_movement_helper(PieceType::PAWN, 10, 3, PieceType::ROOK, true, 15547);
     }
-    
+
+    #[test]
+    fn test_find_move_for_movement_index() {
+        // Includes promotions (with captures) for both sides, and the 2 queen promotions share their
+        // output index with the corresponding non-promotion movement
+        for fen in ["r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1", "r3k2r/8/8/8/8/8/1p6/R3K2R b KQkq - 0 1"] {
+            let mut position = Position::from_fen(Some(fen), false).unwrap();
+            let mut move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+            let flip_for_black = !position.white_to_move;
+
+            let mut indices_found = vec![];
+            for compact_move in move_list.moves().iter() {
+                let index = NNPositionConverter::get_movement_index(*compact_move, flip_for_black);
+                assert!((index as usize) < NN_TOTAL_OUTPUT_SIZE_PER_POS);
+                assert!(!indices_found.contains(&index));
+                indices_found.push(index);
+
+                let found_move = NNPositionConverter::find_move_for_movement_index(index, &move_list, flip_for_black).unwrap();
+                assert_eq!(found_move, *compact_move);
+            }
+
+            // Indices not matching any legal move can't be resolved
+            let unused_index = (0..NN_TOTAL_OUTPUT_SIZE_PER_POS as u16).find(|i| !indices_found.contains(i)).unwrap();
+            assert!(NNPositionConverter::find_move_for_movement_index(unused_index, &move_list, flip_for_black).is_none());
+        }
+    }
+
+    #[test]
+    fn test_chess960_castling_movement_index() {
+        // The king castles on the king side without moving, and on the queen side past its normal moves
+        for fen in ["1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1", "1r4kr/8/8/8/8/8/8/1R4KR b HBhb - 0 1"] {
+            let mut position = Position::from_fen(Some(fen), false).unwrap();
+            let mut move_list = GameMoveList::default();
+            PositionAnalyzer::calc_legal_moves(&mut position, &mut move_list);
+            let flip_for_black = !position.white_to_move;
+            assert_eq!(move_list.moves().iter().filter(|m| m.is_castle()).count(), 2);
+
+            // Every legal move has its own index in the output mask
+            let mut nn_converter = NNPositionConverter::new();
+            let (_, output_mask) = nn_converter.convert_position_for_nn(&position, &move_list);
+            assert_eq!(output_mask.iter().filter(|v| **v > 0.0).count(), move_list.list_len);
+
+            for compact_move in move_list.moves().iter() {
+                let index = NNPositionConverter::get_movement_index(*compact_move, flip_for_black);
+                let found_move = NNPositionConverter::find_move_for_movement_index(index, &move_list, flip_for_black).unwrap();
+                assert_eq!(found_move, *compact_move);
+            }
+        }
+    }
+
     #[test]
     fn test_movement_history() {
         let file_path = "src/test/resources/TestMoveHistoryPGN.pgn";
@@ -599,7 +671,7 @@ mod tests {
             let pos_result = pgn.load_next_position();
             if pos_result.is_none() { break; }
 
-            let (input_data, output_mask, output_target, _game_result, _white_to_move, _is_new_game) = pos_result.unwrap();
+            let NNTrainingPosition { input_data, output_mask, output_target, .. } = pos_result.unwrap();
 
             // println!("{}", game_result);
 
@@ -0,0 +1,115 @@
+use std::os::raw::c_int;
+use std::path::Path;
+use simple_error::SimpleError;
+use tensorflow::{Graph, Operation, SavedModelBundle, SessionOptions, SessionRunArgs, Tensor, Status};
+
+use crate::constants::*;
+use crate::neural::evaluator::Evaluator;
+
+/// Evaluator backend running the Keras SavedModel through libtensorflow
+pub struct TFEvaluator {
+    graph: Graph,
+    saved_model: SavedModelBundle,
+    // The graph operations (and their output indices) are resolved from the model signature once
+    // at load time, rather than on every prediction
+    op_main_input: Operation,
+    main_input_index: c_int,
+    op_output_mask: Operation,
+    output_mask_index: c_int,
+    op_top_k_outputs: Operation,
+    top_k_outputs_index: c_int,
+    op_win_probability: Operation,
+    win_probability_index: c_int,
+    op_movement_output: Operation,
+    movement_output_index: c_int,
+}
+
+impl TFEvaluator {
+    /// Opens the specified saved model, import the graph and create the TF Session
+    pub fn init_from_saved_model(model_dir: &Path) -> Result<TFEvaluator, SimpleError> {
+        TFEvaluator::load_saved_model(model_dir).map_err(SimpleError::from)
+    }
+
+    fn load_saved_model(model_dir: &Path) -> Result<TFEvaluator, Status> {
+        // Load the saved model exported by regression_savedmodel.py.
+        let mut graph = Graph::new();
+        let bundle =
+            SavedModelBundle::load(&SessionOptions::new(), &["serve"], &mut graph, model_dir)?;
+
+        // Load the 'serving_default' model call graph signature, which is added by default
+        // by Keras when saving the model
+        // Sample usage / example, taken from:
+        // https://github.com/tensorflow/rust/blob/master/examples/regression_savedmodel.rs
+        let call_signature = bundle.meta_graph_def().get_signature("serving_default")?;
+        // // How to debug the call signature (see inputs/outputs):
+        // println!("sig name: {}", &call_signature.name().to_string());
+        // println!("{:?}", call_signature.inputs());
+        // println!("{:?}", call_signature.outputs());
+
+        // Retrieve the inputs & outputs
+        let main_input_info = call_signature.get_input(INPUT_MAIN_LAYER_NAME)?;
+        let output_mask_info = call_signature.get_input(OUTPUT_MASK_LAYER_NAME)?;
+        let top_k_output_info = call_signature.get_output(OUTPUT_TOP_K_MOVEMENTS_LAYER_NAME)?;
+        let win_probability_info = call_signature.get_output(OUTPUT_WIN_PROBABILITY_LAYER_NAME)?;
+        let movement_output_info = call_signature.get_output(OUTPUT_MOVEMENTS_LAYER_NAME)?;
+
+        // Retrieve the graph operations required to bind the inputs / outputs
+        let op_main_input = graph.operation_by_name_required(&main_input_info.name().name)?;
+        let op_output_mask = graph.operation_by_name_required(&output_mask_info.name().name)?;
+        let op_top_k_outputs = graph.operation_by_name_required(&top_k_output_info.name().name)?;
+        let op_win_probability = graph.operation_by_name_required(&win_probability_info.name().name)?;
+        let op_movement_output = graph.operation_by_name_required(&movement_output_info.name().name)?;
+
+        Ok(TFEvaluator {
+            main_input_index: main_input_info.name().index,
+            output_mask_index: output_mask_info.name().index,
+            top_k_outputs_index: top_k_output_info.name().index,
+            win_probability_index: win_probability_info.name().index,
+            movement_output_index: movement_output_info.name().index,
+            op_main_input,
+            op_output_mask,
+            op_top_k_outputs,
+            op_win_probability,
+            op_movement_output,
+            graph,
+            saved_model: bundle,
+        })
+    }
+
+    /// Runs the model, fetching the given movement output (either the top K outputs or the full movement softmax)
+    /// along with the win probability
+    fn run_session(&self, input_data: &[f32], output_masks: &[f32], batch_size: usize, op_movements: &Operation, movements_index: c_int) -> Result<(Vec<f32>, Vec<f32>), Status> {
+        // inputs:  [main_input, main_output_mask]
+        let main_input = Tensor::new(&[batch_size as u64, NN_TOTAL_INPUT_SIZE_PER_POS as u64]).with_values(input_data)?;
+        let main_output_mask = Tensor::new(&[batch_size as u64, NN_TOTAL_OUTPUT_SIZE_PER_POS as u64]).with_values(output_masks)?;
+
+        // Bind the inputs/outputs
+        let mut call_step = SessionRunArgs::new();
+        call_step.add_feed(&self.op_main_input, self.main_input_index, &main_input);
+        call_step.add_feed(&self.op_output_mask, self.output_mask_index, &main_output_mask);
+        call_step.add_target(op_movements);
+        call_step.add_target(&self.op_win_probability);
+
+        // Grab the data out of the session using a fetch token
+        let movements_fetch = call_step.request_fetch(op_movements, movements_index);
+        let win_probability_fetch = call_step.request_fetch(&self.op_win_probability, self.win_probability_index);
+
+        // Run the session / graph operations
+        self.saved_model.session.run(&mut call_step)?;
+
+        // Retrieve outputs as tensors
+        let movements: Tensor<f32> = call_step.fetch(movements_fetch)?;
+        let win_probabilities: Tensor<f32> = call_step.fetch(win_probability_fetch)?;
+        Ok((movements.to_vec(), win_probabilities.to_vec()))
+    }
+}
+
+impl Evaluator for TFEvaluator {
+    fn evaluate_batch(&mut self, input_data: &[f32], output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError> {
+        self.run_session(input_data, output_masks, batch_size, &self.op_top_k_outputs, self.top_k_outputs_index).map_err(SimpleError::from)
+    }
+
+    fn evaluate_policy_batch(&mut self, input_data: &[f32], output_masks: &[f32], batch_size: usize) -> Result<(Vec<f32>, Vec<f32>), SimpleError> {
+        self.run_session(input_data, output_masks, batch_size, &self.op_movement_output, self.movement_output_index).map_err(SimpleError::from)
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use rand::seq::index;
+use crate::interfaces::pgn::{DataSplitConfig, NNTrainingPosition, PGNReader, SampleWeightConfig};
+
+// 4M hashes (32MB)
+pub const DEFAULT_DEDUPLICATE_TABLE_SIZE: usize = 1 << 22;
+
+/// Controls how positions are sampled from the PGN files when generating training data
+/// The defaults return every position in file order, exactly as PGNReader::load_next_position() does
+#[derive(Clone, Debug)]
+pub struct TrainingSamplerConfig {
+    // Positions are held in a buffer of this size and returned in random order (0 or 1 disables shuffling)
+    pub shuffle_buffer_size: usize,
+    // Keep at most this many randomly chosen positions from each game (0 keeps all positions)
+    pub positions_per_game: usize,
+    // Skip any position that has already been returned previously (e.g. common opening positions)
+    pub deduplicate: bool,
+    // Number of position hashes remembered for deduplication (8 bytes each), so that memory use stays bounded
+    // over a whole PGN corpus. Each hash maps to a single slot, which only remembers the most recent position,
+    // so some older duplicates are let through once the table starts filling up
+    pub deduplicate_table_size: usize,
+    // Seed for all random choices so that runs are reproducible
+    pub seed: u64,
+    // Only use games from one bucket of the train / validation / test split (None uses all games)
+    pub data_split: Option<DataSplitConfig>,
+    // How the sample weight returned with each position is calculated
+    pub sample_weights: SampleWeightConfig,
+}
+
+impl Default for TrainingSamplerConfig {
+    fn default() -> Self {
+        TrainingSamplerConfig {
+            shuffle_buffer_size: 0,
+            positions_per_game: 0,
+            deduplicate: false,
+            deduplicate_table_size: DEFAULT_DEDUPLICATE_TABLE_SIZE,
+            seed: 0,
+            data_split: None,
+            sample_weights: SampleWeightConfig::default(),
+        }
+    }
+}
+
+/// Wraps PGNReader to decorrelate the stream of training positions, since consecutive positions
+/// from the same game are very similar to each other
+/// Note: once shuffling is enabled, the is_new_game flag no longer implies anything about the
+/// positions that come after it
+pub struct TrainingSampler {
+    config: TrainingSamplerConfig,
+    pgn_files: VecDeque<PathBuf>,
+    pgn: Option<PGNReader>,
+    rng: StdRng,
+    shuffle_buffer: Vec<NNTrainingPosition>,
+    // Hashes of the positions returned so far, indexed by hash % table size (allocated on first use)
+    seen_positions: Vec<Option<u64>>,
+    game_positions_selected: Vec<bool>,
+    game_position_index: usize,
+    game_start_pending: bool,
+}
+
+impl TrainingSampler {
+    pub fn new(pgn_files: Vec<PathBuf>, config: TrainingSamplerConfig) -> Self {
+        TrainingSampler {
+            rng: StdRng::seed_from_u64(config.seed),
+            shuffle_buffer: Vec::with_capacity(config.shuffle_buffer_size),
+            config,
+            pgn_files: VecDeque::from(pgn_files),
+            pgn: None,
+            seen_positions: vec![],
+            game_positions_selected: Vec::with_capacity(256),
+            game_position_index: 0,
+            game_start_pending: false,
+        }
+    }
+
+    // Reads positions across all of the PGN files in order, moving on to the next file once the
+    // current one is exhausted
+    fn load_next_pgn_position(&mut self) -> Option<NNTrainingPosition> {
+        loop {
+            if let Some(pgn) = self.pgn.as_mut() {
+                if let Some(nn_data) = pgn.load_next_position() { return Some(nn_data) }
+            }
+
+            let pgn_file = self.pgn_files.pop_front()?;
+            let mut pgn = PGNReader::init_pgn_file(pgn_file.to_str().unwrap());
+            pgn.set_data_split(self.config.data_split);
+            pgn.set_sample_weight_config(self.config.sample_weights);
+            self.pgn = Some(pgn);
+        }
+    }
+
+    // Randomly chooses which positions of a new game will be kept
+    fn select_game_positions(&mut self, total_game_positions: usize) {
+        self.game_positions_selected.clear();
+        self.game_position_index = 0;
+
+        if self.config.positions_per_game == 0 || total_game_positions <= self.config.positions_per_game {
+            self.game_positions_selected.resize(total_game_positions, true);
+            return;
+        }
+
+        self.game_positions_selected.resize(total_game_positions, false);
+        for i in index::sample(&mut self.rng, total_game_positions, self.config.positions_per_game) {
+            self.game_positions_selected[i] = true;
+        }
+    }
+
+    // Returns whether the position hash is already in the deduplication table, and adds it if not
+    fn check_seen_position(&mut self, position_hash: u64) -> bool {
+        if self.seen_positions.is_empty() {
+            self.seen_positions = vec![None; self.config.deduplicate_table_size.max(1)];
+        }
+
+        let slot = (position_hash % self.seen_positions.len() as u64) as usize;
+        if self.seen_positions[slot] == Some(position_hash) { return true; }
+        self.seen_positions[slot] = Some(position_hash);
+        false
+    }
+
+    // Returns the next position after subsampling + deduplication, but before shuffling
+    fn load_next_sampled_position(&mut self) -> Option<NNTrainingPosition> {
+        loop {
+            let mut nn_data = self.load_next_pgn_position()?;
+            if nn_data.is_new_game {
+                let total_game_positions = self.pgn.as_ref().unwrap().get_remaining_game_positions() + 1;
+                self.select_game_positions(total_game_positions);
+                self.game_start_pending = true;
+            }
+
+            let is_selected = self.game_positions_selected[self.game_position_index];
+            self.game_position_index += 1;
+            if !is_selected { continue; }
+
+            if self.config.deduplicate && self.check_seen_position(self.pgn.as_ref().unwrap().get_current_position().calc_position_hash()) {
+                continue;
+            }
+
+            // Make sure the first position returned from each game is still flagged as such, even if
+            // the game's actual first position was skipped
+            nn_data.is_new_game = self.game_start_pending;
+            self.game_start_pending = false;
+            return Some(nn_data);
+        }
+    }
+
+    /// Returns the next training position, or None once all of the PGN files have been exhausted
+    pub fn load_next_position(&mut self) -> Option<NNTrainingPosition> {
+        if self.config.shuffle_buffer_size <= 1 {
+            return self.load_next_sampled_position();
+        }
+
+        // Top up the buffer before choosing a random position from it
+        while self.shuffle_buffer.len() < self.config.shuffle_buffer_size {
+            match self.load_next_sampled_position() {
+                Some(nn_data) => self.shuffle_buffer.push(nn_data),
+                None => break
+            }
+        }
+
+        if self.shuffle_buffer.is_empty() { return None }
+        let i = self.rng.gen_range(0..self.shuffle_buffer.len());
+        Some(self.shuffle_buffer.swap_remove(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PGN_FILE: &str = "src/test/resources/TestMoveHistoryPGN.pgn";
+
+    fn load_all_positions(pgn_files: Vec<&str>, config: TrainingSamplerConfig) -> Vec<NNTrainingPosition> {
+        let mut sampler = TrainingSampler::new(pgn_files.iter().map(PathBuf::from).collect(), config);
+        let mut result = vec![];
+        while let Some(nn_data) = sampler.load_next_position() {
+            result.push(nn_data);
+        }
+        result
+    }
+
+    #[test]
+    fn test_default_config_matches_pgn_reader() {
+        let mut pgn = PGNReader::init_pgn_file(TEST_PGN_FILE);
+        let positions = load_all_positions(vec![TEST_PGN_FILE], TrainingSamplerConfig::default());
+
+        assert_eq!(positions.len(), 20);
+        for nn_data in positions {
+            assert_eq!(nn_data, pgn.load_next_position().unwrap());
+        }
+        assert!(pgn.load_next_position().is_none());
+    }
+
+    #[test]
+    fn test_positions_per_game() {
+        let config = TrainingSamplerConfig { positions_per_game: 3, ..Default::default() };
+        let positions = load_all_positions(vec![TEST_PGN_FILE], config);
+
+        // Each game is 10 positions long, so 3 of each should be kept, with the first flagged as a new game
+        assert_eq!(positions.len(), 6);
+        assert_eq!(positions.iter().map(|p| p.is_new_game).collect::<Vec<bool>>(), vec![true, false, false, true, false, false]);
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let config = TrainingSamplerConfig { deduplicate: true, ..Default::default() };
+
+        // Both games start from the initial position, so only the first one should be kept
+        assert_eq!(load_all_positions(vec![TEST_PGN_FILE], config.clone()).len(), 19);
+
+        // All positions in the second copy of the file have already been seen
+        assert_eq!(load_all_positions(vec![TEST_PGN_FILE, TEST_PGN_FILE], config.clone()).len(), 19);
+
+        // A single slot table only remembers the previous position, so nothing is skipped
+        let config = TrainingSamplerConfig { deduplicate_table_size: 1, ..config };
+        assert_eq!(load_all_positions(vec![TEST_PGN_FILE, TEST_PGN_FILE], config.clone()).len(), 40);
+
+        // Empty slots are tracked separately, so a hash of 0 isn't taken as already seen
+        let mut sampler = TrainingSampler::new(vec![], config);
+        assert!(!sampler.check_seen_position(0));
+        assert!(sampler.check_seen_position(0));
+    }
+
+    #[test]
+    fn test_shuffle_is_reproducible() {
+        let unshuffled = load_all_positions(vec![TEST_PGN_FILE], TrainingSamplerConfig::default());
+        let config = TrainingSamplerConfig { shuffle_buffer_size: 8, seed: 42, ..Default::default() };
+        let shuffled = load_all_positions(vec![TEST_PGN_FILE], config.clone());
+
+        // Same positions, but in a different order
+        assert_eq!(shuffled.len(), unshuffled.len());
+        assert!(shuffled.iter().all(|p| unshuffled.contains(p)));
+        assert_ne!(shuffled, unshuffled);
+
+        // The same seed always produces the same order, while a different seed does not
+        assert_eq!(shuffled, load_all_positions(vec![TEST_PGN_FILE], config.clone()));
+        assert_ne!(shuffled, load_all_positions(vec![TEST_PGN_FILE], TrainingSamplerConfig { seed: 43, ..config }));
+    }
+}
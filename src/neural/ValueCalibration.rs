@@ -0,0 +1,227 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use simple_error::{bail, SimpleError};
+
+use crate::interfaces::pgn::{PGNReader, PGN_MATE_EVAL_CP};
+
+/// Saved alongside the model, so that each model can have its own calibration
+pub const VALUE_CALIBRATION_FILE: &str = "value_calibration.json";
+
+// Scores are reported within +/- this many centipawns (any further and it should be a mate score instead)
+const MAX_CALIBRATED_CP: i32 = 5000;
+// Evaluations beyond this are ignored when fitting, since the game result is a foregone conclusion
+const MAX_FIT_EVAL_CP: f32 = 2000.0;
+const FIT_BUCKET_CP: f32 = 10.0;
+const FIT_ITERATIONS: usize = 20000;
+const FIT_LEARNING_RATE: f32 = 0.5;
+
+/// Ordered logistic model relating a centipawn score to win / draw / loss probabilities:
+///   P(win) = sigmoid(scale * cp - draw_margin), P(loss) = sigmoid(-scale * cp - draw_margin), P(draw) = the rest
+/// The value head predicts white's expected score (P(win) + P(draw) / 2), which is converted back to
+/// centipawns by inverting the model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueCalibration {
+    pub scale: f32,
+    pub draw_margin: f32,
+}
+
+impl Default for ValueCalibration {
+    /// The usual 1 / (1 + 10 ^ (-cp / 400)) curve with roughly 1/3 draws in a level position
+    fn default() -> Self {
+        ValueCalibration { scale: std::f32::consts::LN_10 / 400.0, draw_margin: 0.7 }
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl ValueCalibration {
+    /// Returns the (win, draw, loss) probabilities for the given score in centipawns
+    pub fn calc_wdl(&self, cp: f32) -> (f32, f32, f32) {
+        let win = sigmoid(self.scale * cp - self.draw_margin);
+        let loss = sigmoid(-self.scale * cp - self.draw_margin);
+        (win, (1.0 - win - loss).max(0.0), loss)
+    }
+
+    /// Returns the (win, draw, loss) probabilities in permille, adding up to exactly 1000 as UCI_ShowWDL expects
+    pub fn calc_wdl_permille(&self, cp: f32) -> (u32, u32, u32) {
+        let (win, _, loss) = self.calc_wdl(cp);
+        let win = (win * 1000.0).round() as u32;
+        let loss = ((loss * 1000.0).round() as u32).min(1000 - win);
+        (win, 1000 - win - loss, loss)
+    }
+
+    pub fn calc_expected_score(&self, cp: f32) -> f32 {
+        let (win, draw, _) = self.calc_wdl(cp);
+        win + draw / 2.0
+    }
+
+    /// Converts an expected score (0 to 1) back to centipawns, within +/- MAX_CALIBRATED_CP
+    pub fn calc_centipawns(&self, expected_score: f32) -> i32 {
+        // (the expected score rounds to exactly 0 or 1 well before MAX_CALIBRATED_CP)
+        if expected_score >= 1.0 { return MAX_CALIBRATED_CP; }
+        if expected_score <= 0.0 { return -MAX_CALIBRATED_CP; }
+
+        // The expected score always increases with the centipawn score, so a bisection search is enough
+        let (mut low, mut high) = (-MAX_CALIBRATED_CP as f32, MAX_CALIBRATED_CP as f32);
+        for _ in 0..32 {
+            let mid = (low + high) / 2.0;
+            if self.calc_expected_score(mid) < expected_score { low = mid; } else { high = mid; }
+        }
+        ((low + high) / 2.0).round() as i32
+    }
+
+    /// Loads the calibration saved in the model directory, or the default calibration if there isn't one
+    pub fn load_or_default(model_dir: &Path) -> ValueCalibration {
+        let path = model_dir.join(VALUE_CALIBRATION_FILE);
+        if !path.exists() { return ValueCalibration::default(); }
+
+        ValueCalibration::load(&path).unwrap_or_else(|e| {
+            println!("info string {} - using the default value calibration", e);
+            ValueCalibration::default()
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<ValueCalibration, SimpleError> {
+        let json_str = match fs::read_to_string(path) {
+            Err(why) => bail!("Couldn't read value calibration {}: {}", path.display(), why),
+            Ok(json_str) => json_str,
+        };
+        let calibration = json::parse(&json_str).map_err(SimpleError::from)?;
+        match (calibration["scale"].as_f32(), calibration["draw_margin"].as_f32()) {
+            (Some(scale), Some(draw_margin)) if scale > 0.0 => Ok(ValueCalibration { scale, draw_margin }),
+            _ => bail!("Invalid value calibration in {}", path.display()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SimpleError> {
+        let calibration = json::object!{"scale" => self.scale, "draw_margin" => self.draw_margin};
+        fs::write(path, calibration.pretty(4)).map_err(SimpleError::from)
+    }
+
+    /// Fits the model by maximum likelihood to (centipawn score, game result) samples, both from the same
+    /// side's point of view, with results of 1, 0 or -1 for a win, draw or loss
+    pub fn fit(samples: &[(f32, f32)]) -> Result<ValueCalibration, SimpleError> {
+        // Bucket the samples by score so each iteration only needs to visit a few hundred buckets
+        let bucket_count = (2.0 * MAX_FIT_EVAL_CP / FIT_BUCKET_CP) as usize + 1;
+        let mut outcome_counts = vec![[0f32; 3]; bucket_count];
+        let mut total_samples = 0f32;
+        for (cp, result) in samples {
+            if cp.abs() > MAX_FIT_EVAL_CP { continue; }
+            let bucket = ((cp + MAX_FIT_EVAL_CP) / FIT_BUCKET_CP).round() as usize;
+            let outcome = if *result > 0.0 { 0 } else if *result < 0.0 { 2 } else { 1 };
+            outcome_counts[bucket][outcome] += 1.0;
+            total_samples += 1.0;
+        }
+        if outcome_counts.iter().all(|counts| counts[0] == 0.0) || outcome_counts.iter().all(|counts| counts[2] == 0.0) {
+            bail!("At least one win and one loss are needed to fit the value calibration");
+        }
+
+        // Gradient ascent on the mean log likelihood, with the scale measured per 100 centipawns so that
+        // both parameters are of a similar size
+        let default = ValueCalibration::default();
+        let (mut scale, mut draw_margin) = (default.scale * 100.0, default.draw_margin);
+        for _ in 0..FIT_ITERATIONS {
+            let (mut scale_gradient, mut draw_margin_gradient) = (0f32, 0f32);
+            for (bucket, counts) in outcome_counts.iter().enumerate() {
+                if counts.iter().sum::<f32>() == 0.0 { continue; }
+                let x = (bucket as f32 * FIT_BUCKET_CP - MAX_FIT_EVAL_CP) / 100.0;
+                let win = sigmoid(scale * x - draw_margin);
+                let loss = sigmoid(-scale * x - draw_margin);
+                let draw = (1.0 - win - loss).max(1e-6);
+
+                scale_gradient += counts[0] * (1.0 - win) * x - counts[2] * (1.0 - loss) * x
+                    - counts[1] * (win * (1.0 - win) * x - loss * (1.0 - loss) * x) / draw;
+                draw_margin_gradient += -counts[0] * (1.0 - win) - counts[2] * (1.0 - loss)
+                    + counts[1] * (win * (1.0 - win) + loss * (1.0 - loss)) / draw;
+            }
+            scale = (scale + FIT_LEARNING_RATE * scale_gradient / total_samples).max(0.001);
+            // A negative draw margin would give a negative draw probability
+            draw_margin = (draw_margin + FIT_LEARNING_RATE * draw_margin_gradient / total_samples).max(0.0);
+        }
+
+        Ok(ValueCalibration { scale: scale / 100.0, draw_margin })
+    }
+
+    /// Fits the calibration to the %eval annotations and game results in the given PGN files
+    pub fn fit_from_pgn_files(pgn_files: &[PathBuf], max_positions: Option<usize>) -> Result<ValueCalibration, SimpleError> {
+        let mut samples = vec![];
+        'files: for pgn_file in pgn_files {
+            let mut reader = PGNReader::init_pgn_file(&pgn_file.to_string_lossy());
+            while let Some(nn_data) = reader.load_next_position() {
+                // Both the evaluation and the result are from white's point of view (forced mates are skipped)
+                match nn_data.position_eval {
+                    Some(eval) if eval.abs() < PGN_MATE_EVAL_CP / 2.0 => samples.push((eval, nn_data.game_result)),
+                    _ => continue,
+                }
+                if max_positions.is_some_and(|max| samples.len() >= max) { break 'files; }
+            }
+        }
+
+        println!("Fitting value calibration to {} evaluated positions", samples.len());
+        ValueCalibration::fit(&samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+    use super::*;
+
+    #[test]
+    fn test_wdl_and_centipawns() {
+        let calibration = ValueCalibration::default();
+        let (win, draw, loss) = calibration.calc_wdl(0.0);
+        assert_approx_eq!(f32, win, loss);
+        assert_approx_eq!(f32, win + draw + loss, 1.0);
+        assert_approx_eq!(f32, calibration.calc_expected_score(0.0), 0.5);
+        assert!(calibration.calc_expected_score(100.0) > 0.6);
+
+        // Converting to an expected score and back gives the original score
+        for cp in [-1500, -300, -25, 0, 40, 250, 1200] {
+            assert!((calibration.calc_centipawns(calibration.calc_expected_score(cp as f32)) - cp).abs() <= 1);
+        }
+        assert_eq!(calibration.calc_centipawns(1.0), MAX_CALIBRATED_CP);
+        assert_eq!(calibration.calc_centipawns(0.0), -MAX_CALIBRATED_CP);
+
+        let (win, draw, loss) = calibration.calc_wdl_permille(150.0);
+        assert_eq!(win + draw + loss, 1000);
+        assert!(win > loss);
+    }
+
+    #[test]
+    fn test_fit() {
+        // Generate results from a known calibration and check that fitting recovers it
+        let expected = ValueCalibration { scale: 0.004, draw_margin: 1.2 };
+        let mut samples = vec![];
+        for cp in (-600..=600).step_by(20) {
+            let (win, draw, _) = expected.calc_wdl(cp as f32);
+            let win_count = (win * 1000.0).round() as usize;
+            let draw_count = (draw * 1000.0).round() as usize;
+            for i in 0..1000 {
+                samples.push((cp as f32, if i < win_count { 1.0 } else if i < win_count + draw_count { 0.0 } else { -1.0 }));
+            }
+        }
+
+        let fitted = ValueCalibration::fit(&samples).unwrap();
+        assert_approx_eq!(f32, fitted.scale, expected.scale, epsilon = 0.0002);
+        assert_approx_eq!(f32, fitted.draw_margin, expected.draw_margin, epsilon = 0.05);
+
+        assert!(ValueCalibration::fit(&[(100.0, 1.0), (0.0, 0.0)]).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut path = std::env::temp_dir();
+        path.push("my_chess_ql_test_value_calibration.json");
+
+        let calibration = ValueCalibration { scale: 0.005, draw_margin: 0.9 };
+        calibration.save(&path).unwrap();
+        let loaded = ValueCalibration::load(&path).unwrap();
+        assert_approx_eq!(f32, loaded.scale, calibration.scale);
+        assert_approx_eq!(f32, loaded.draw_margin, calibration.draw_margin);
+
+        assert_eq!(ValueCalibration::load_or_default(Path::new("src/test/resources")), ValueCalibration::default());
+    }
+}
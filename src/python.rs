@@ -0,0 +1,3 @@
+pub mod pyposition;
+pub mod pyencoding;
+pub mod pyenvironment;
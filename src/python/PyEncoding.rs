@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use crate::constants::*;
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::moves::compactmove::CompactMove;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::position::Position;
+use crate::neural::positionconverter::NNPositionConverter;
+
+// Python functions exposing the exact input plane / movement encoding used by NNPositionConverter
+// so predictions can be inspected and debugged from notebooks
+
+fn position_from_fen(fen: &str) -> PyResult<(Position, GameMoveList)> {
+    let mut position = Position::from_fen(Some(fen), false).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let mut legal_moves = GameMoveList::default();
+    PositionAnalyzer::calc_legal_moves(&mut position, &mut legal_moves);
+    Ok((position, legal_moves))
+}
+
+/// Encodes the final position reached by playing the UCI moves from the starting position, with the
+/// earlier positions filling the move history planes exactly as they would when reading a PGN file
+pub fn encode_game_positions(mut position: Position, moves: &Vec<String>) -> PyResult<(Vec<f32>, Vec<f32>)> {
+    let mut nn_converter = NNPositionConverter::new();
+    let mut move_maker = MoveMaker::default();
+    let mut legal_moves = GameMoveList::default();
+    PositionAnalyzer::calc_legal_moves(&mut position, &mut legal_moves);
+    let mut nn_data = nn_converter.convert_position_for_nn(&position, &legal_moves);
+
+    for move_uci in moves {
+        let game_move = match position.get_move_by_uci(&legal_moves, move_uci) {
+            Some(game_move) => game_move,
+            None => return Err(PyValueError::new_err(format!("Illegal move {} in position {}", move_uci, position.to_fen())))
+        };
+        move_maker.make_move(&mut position, &game_move, false);
+
+        legal_moves.clear();
+        PositionAnalyzer::calc_legal_moves(&mut position, &mut legal_moves);
+        nn_data = nn_converter.convert_position_for_nn(&position, &legal_moves);
+    }
+
+    Ok(nn_data)
+}
+
+/// Returns the (input_planes, legal_move_mask) pair for the given FEN, after playing the optional list of UCI moves
+#[pyfunction(moves = "None")]
+pub fn encode_position(fen: &str, moves: Option<Vec<String>>) -> PyResult<(Vec<f32>, Vec<f32>)> {
+    let position = Position::from_fen(Some(fen), false).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    encode_game_positions(position, &moves.unwrap_or_default())
+}
+
+/// Returns the policy output index for a legal UCI move in the given position
+#[pyfunction]
+pub fn move_to_policy_index(fen: &str, move_uci: &str) -> PyResult<u16> {
+    let (position, legal_moves) = position_from_fen(fen)?;
+    match position.get_move_by_uci(&legal_moves, move_uci) {
+        Some(game_move) => Ok(NNPositionConverter::get_movement_index(CompactMove::from_game_move(&game_move), !position.white_to_move)),
+        None => Err(PyValueError::new_err(format!("Illegal move {} in position {}", move_uci, fen)))
+    }
+}
+
+/// Returns the legal UCI move in the given position that the policy output index refers to
+#[pyfunction]
+pub fn policy_index_to_move(fen: &str, index: u16) -> PyResult<String> {
+    if index as usize >= NN_TOTAL_OUTPUT_SIZE_PER_POS {
+        return Err(PyValueError::new_err(format!("Policy index {} must be less than {}", index, NN_TOTAL_OUTPUT_SIZE_PER_POS)));
+    }
+
+    let (position, legal_moves) = position_from_fen(fen)?;
+    match NNPositionConverter::find_move_for_movement_index(index, &legal_moves, !position.white_to_move) {
+        Some(compact_move) => Ok(position.get_uci_move_string(&compact_move.to_game_move(&position))),
+        None => Err(PyValueError::new_err(format!("Policy index {} is not a legal move in position {}", index, fen)))
+    }
+}
+
+/// Returns a dict of every legal UCI move in the given position -> its policy output index
+#[pyfunction]
+pub fn legal_move_policy_indices(fen: &str) -> PyResult<HashMap<String, u16>> {
+    let (position, legal_moves) = position_from_fen(fen)?;
+    Ok(legal_moves.moves().iter()
+        .map(|compact_move| (position.get_uci_move_string(&compact_move.to_game_move(&position)), NNPositionConverter::get_movement_index(*compact_move, !position.white_to_move)))
+        .collect())
+}
+
+pub fn add_functions(m: &PyModule) -> PyResult<()> {
+    m.add("POLICY_SIZE", NN_TOTAL_OUTPUT_SIZE_PER_POS)?;
+    m.add("INPUT_PLANES", NN_TOTAL_PLANES_PER_POS)?;
+    m.add_function(wrap_pyfunction!(encode_position, m)?)?;
+    m.add_function(wrap_pyfunction!(move_to_policy_index, m)?)?;
+    m.add_function(wrap_pyfunction!(policy_index_to_move, m)?)?;
+    m.add_function(wrap_pyfunction!(legal_move_policy_indices, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python::pyposition::PyPosition;
+
+    #[test]
+    fn test_encode_position() {
+        let (planes, mask) = encode_position(START_POSITION, None).unwrap();
+        assert_eq!(planes.len(), NN_TOTAL_INPUT_SIZE_PER_POS);
+        assert_eq!(mask.len(), NN_TOTAL_OUTPUT_SIZE_PER_POS);
+        assert_eq!(mask.iter().sum::<f32>(), 20f32);
+        for index in legal_move_policy_indices(START_POSITION).unwrap().values() {
+            assert_eq!(mask[*index as usize], 1f32);
+        }
+
+        // Playing moves from the FEN gives the same encoding (history planes included) as pushing them onto a Position
+        let moves = vec!["e2e4".to_string(), "c7c5".to_string(), "g1f3".to_string()];
+        let mut position = PyPosition::new(None).unwrap();
+        for move_uci in moves.iter() { position.push_uci(move_uci).unwrap(); }
+        assert_eq!(encode_position(START_POSITION, Some(moves)).unwrap(), position.encode().unwrap());
+
+        assert!(encode_position(START_POSITION, Some(vec!["e2e5".to_string()])).is_err());
+        assert!(encode_position("not a fen", None).is_err());
+    }
+
+    #[test]
+    fn test_policy_index_round_trip() {
+        // Both sides to move, with promotions (including under-promotions) and castling
+        let fens = ["r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1", "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R b KQkq - 0 1"];
+        for fen in fens {
+            let indices = legal_move_policy_indices(fen).unwrap();
+            for promotion in ["q", "r", "b", "n"] {
+                let move_uci = if fen.contains(" w ") { format!("b7a8{}", promotion) } else { format!("b2a1{}", promotion) };
+                assert!(indices.contains_key(&move_uci), "{}", move_uci);
+            }
+
+            for (move_uci, index) in indices.iter() {
+                assert_eq!(move_to_policy_index(fen, move_uci).unwrap(), *index);
+                assert_eq!(policy_index_to_move(fen, *index).unwrap(), *move_uci);
+            }
+
+            // Every legal move gets its own index
+            let mut unique_indices: Vec<u16> = indices.values().cloned().collect();
+            unique_indices.sort_unstable();
+            unique_indices.dedup();
+            assert_eq!(unique_indices.len(), indices.len());
+        }
+
+        assert!(move_to_policy_index(START_POSITION, "e2e5").is_err());
+        assert!(policy_index_to_move(START_POSITION, NN_TOTAL_OUTPUT_SIZE_PER_POS as u16).is_err());
+        let illegal_index = (0..NN_TOTAL_OUTPUT_SIZE_PER_POS as u16)
+            .find(|i| !legal_move_policy_indices(START_POSITION).unwrap().values().any(|index| index == i)).unwrap();
+        assert!(policy_index_to_move(START_POSITION, illegal_index).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use crate::neural::chessenvironment::ChessEnvironment;
+
+// (observation, reward, terminated, truncated, info) as returned to Python by step()
+type PyEnvironmentStep = (Vec<f32>, f32, bool, bool, HashMap<&'static str, String>);
+
+/// Gym-style environment for reinforcement learning, using the same 1858 action indices and input
+/// planes as the neural net
+#[pyclass(name = "ChessEnvironment")]
+pub struct PyEnvironment {
+    env: ChessEnvironment,
+}
+
+#[pymethods]
+impl PyEnvironment {
+    /// max_plies truncates each episode after that many plies (0 for no limit)
+    #[new]
+    #[args(max_plies = "0")]
+    pub fn new(max_plies: usize) -> Self {
+        PyEnvironment { env: ChessEnvironment::new(max_plies) }
+    }
+
+    /// Starts a new episode from the given FEN (or the standard starting position) and returns the first observation
+    #[args(fen = "None")]
+    pub fn reset(&mut self, fen: Option<&str>) -> PyResult<Vec<f32>> {
+        self.env.reset(fen).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Returns (observation, reward, terminated, truncated, info), where the reward is from the point of view
+    /// of the side that just moved and info holds the game status and FEN
+    pub fn step(&mut self, action_index: u16) -> PyResult<PyEnvironmentStep> {
+        let step = self.env.step(action_index).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok((step.observation, step.reward, step.terminated, step.truncated, HashMap::from([
+            ("status", self.env.get_status().to_str().to_string()),
+            ("fen", self.env.get_position().to_fen()),
+        ])))
+    }
+
+    pub fn legal_action_mask(&self) -> Vec<f32> {
+        self.env.legal_action_mask().clone()
+    }
+
+    pub fn legal_actions(&self) -> Vec<u16> {
+        self.env.legal_actions()
+    }
+
+    pub fn observation(&self) -> Vec<f32> {
+        self.env.get_observation().clone()
+    }
+
+    /// Converts an action index to a UCI move (or None if it is not legal in the current position)
+    pub fn action_to_uci(&self, action_index: u16) -> Option<String> {
+        self.env.get_move_for_action(action_index).map(|game_move| self.env.get_position().get_uci_move_string(&game_move))
+    }
+
+    /// Converts a UCI move to an action index (or None if it is not legal in the current position)
+    pub fn uci_to_action(&self, move_uci: &str) -> Option<u16> {
+        self.env.get_action_for_move(move_uci)
+    }
+
+    pub fn fen(&self) -> String {
+        self.env.get_position().to_fen()
+    }
+
+    /// One of "in_progress", "checkmate", "stalemate", "repetition" or "fifty_move_rule"
+    #[getter]
+    pub fn status(&self) -> &'static str {
+        self.env.get_status().to_str()
+    }
+
+    #[getter]
+    pub fn white_to_move(&self) -> bool {
+        self.env.get_position().white_to_move
+    }
+
+    #[getter]
+    pub fn ply_count(&self) -> usize {
+        self.env.get_ply_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_and_step() {
+        let mut env = PyEnvironment::new(0);
+        let observation = env.reset(None).unwrap();
+        assert_eq!(observation, env.observation());
+        assert_eq!(env.legal_actions().len(), 20);
+        assert_eq!(env.status(), "in_progress");
+        assert!(env.reset(Some("not a fen")).is_err());
+
+        let mut env = PyEnvironment::new(0);
+        let mut result = None;
+        for move_uci in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            result = Some(env.step(env.uci_to_action(move_uci).unwrap()).unwrap());
+        }
+        let (observation, reward, terminated, truncated, info) = result.unwrap();
+        assert_eq!(observation, env.observation());
+        assert_eq!(reward, 1f32);
+        assert!(terminated);
+        assert!(!truncated);
+        assert_eq!(info["status"], "checkmate");
+        assert_eq!(info["fen"], "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        assert_eq!(env.ply_count(), 4);
+        assert!(env.white_to_move());
+        assert!(env.step(0).is_err());
+    }
+
+    #[test]
+    fn test_action_to_uci() {
+        let mut env = PyEnvironment::new(0);
+        env.reset(Some("r3k3/1P6/8/8/8/8/8/4K2R w K - 0 1")).unwrap();
+        for move_uci in ["e1g1", "b7a8q", "b7a8n", "b7b8r", "h1h8"] {
+            let action = env.uci_to_action(move_uci).unwrap();
+            assert_eq!(env.legal_action_mask()[action as usize], 1f32);
+            assert_eq!(env.action_to_uci(action).unwrap(), move_uci);
+        }
+        for action in env.legal_actions() {
+            assert_eq!(env.uci_to_action(&env.action_to_uci(action).unwrap()), Some(action));
+        }
+
+        assert!(env.uci_to_action("e1c1").is_none());
+        assert!(env.action_to_uci(1858).is_none());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use pyo3::pyproto;
+use pyo3::PyObjectProtocol;
+use crate::game::analysis::positionanalyzer::PositionAnalyzer;
+use crate::game::moves::gamemove::GameMove;
+use crate::game::moves::gamemovelist::GameMoveList;
+use crate::game::moves::movemaker::MoveMaker;
+use crate::game::position::Position;
+use crate::game::positionhelper::PositionHelper;
+use crate::python::pyencoding::encode_game_positions;
+
+/// Exposes the Rust move generator to Python so that the same legal moves / FENs are used when
+/// preparing data as when training and playing
+#[pyclass(name = "Position")]
+pub struct PyPosition {
+    pub position: Position,
+    pub legal_moves: GameMoveList,
+    // The position before each move was made, so that pop() can restore it exactly
+    pub move_stack: Vec<(Position, GameMove)>,
+}
+
+impl PyPosition {
+    pub fn from_position(position: Position) -> Self {
+        let mut result = PyPosition {
+            position,
+            legal_moves: GameMoveList::default(),
+            move_stack: vec![],
+        };
+        result.update_legal_moves();
+        result
+    }
+
+    // Legal move generation also updates the check / checkmate / stalemate flags on the position
+    fn update_legal_moves(&mut self) {
+        self.legal_moves.clear();
+        PositionAnalyzer::calc_legal_moves(&mut self.position, &mut self.legal_moves);
+    }
+
+    fn strip_san_suffix(san: &str) -> &str {
+        san.trim_end_matches(['+', '#', '!', '?'])
+    }
+
+    pub fn get_move_by_san(&mut self, san: &str) -> Option<GameMove> {
+        // Try an exact match first, falling back to a partial match to also allow over-specified moves (e.g. "Ng1f3")
+        let san_stripped = PyPosition::strip_san_suffix(san);
+        let exact_match = self.legal_moves.moves().iter()
+            .map(|m| m.to_game_move(&self.position))
+            .find(|m| PyPosition::strip_san_suffix(m.get_san_move_string(&self.position, &self.legal_moves).as_str()) == san_stripped);
+        exact_match.or_else(|| self.legal_moves.get_move_by_partial_san(&self.position, san))
+    }
+
+    pub fn push_move(&mut self, game_move: GameMove) {
+        self.move_stack.push((self.position.clone(), game_move));
+        MoveMaker::default().make_move(&mut self.position, &game_move, false);
+        self.update_legal_moves();
+    }
+}
+
+#[pymethods]
+impl PyPosition {
+    #[new]
+    #[args(fen = "None")]
+    pub fn new(fen: Option<&str>) -> PyResult<Self> {
+        match Position::from_fen(fen, false) {
+            Ok(position) => Ok(PyPosition::from_position(position)),
+            Err(e) => Err(PyValueError::new_err(e.to_string()))
+        }
+    }
+
+    pub fn fen(&self) -> String {
+        self.position.to_fen()
+    }
+
+    pub fn copy(&self) -> Self {
+        let mut result = PyPosition::from_position(self.position.clone());
+        result.move_stack = self.move_stack.clone();
+        result
+    }
+
+    /// Returns all legal moves in UCI format (e.g. "e2e4", "e7e8q")
+    pub fn legal_moves(&self) -> Vec<String> {
+        self.legal_moves.moves().iter().map(|m| self.position.get_uci_move_string(&m.to_game_move(&self.position))).collect()
+    }
+
+    /// Returns all legal moves in Standard Algebraic Notation (e.g. "e4", "exd8=Q+"), in the same order as legal_moves()
+    pub fn legal_moves_san(&self) -> Vec<String> {
+        self.legal_moves.moves().iter().map(|m| m.to_game_move(&self.position).get_san_move_string(&self.position, &self.legal_moves)).collect()
+    }
+
+    /// Converts a legal move from UCI format to SAN
+    pub fn san(&self, uci: &str) -> PyResult<String> {
+        match self.position.get_move_by_uci(&self.legal_moves, uci) {
+            Some(game_move) => Ok(game_move.get_san_move_string(&self.position, &self.legal_moves)),
+            None => Err(PyValueError::new_err(format!("Illegal move {} in position {}", uci, self.position.to_fen())))
+        }
+    }
+
+    /// Converts a legal move from SAN format to UCI
+    pub fn uci(&mut self, san: &str) -> PyResult<String> {
+        match self.get_move_by_san(san) {
+            Some(game_move) => Ok(self.position.get_uci_move_string(&game_move)),
+            None => Err(PyValueError::new_err(format!("Illegal move {} in position {}", san, self.position.to_fen())))
+        }
+    }
+
+    pub fn push_uci(&mut self, uci: &str) -> PyResult<()> {
+        match self.position.get_move_by_uci(&self.legal_moves, uci) {
+            Some(game_move) => { self.push_move(game_move); Ok(()) },
+            None => Err(PyValueError::new_err(format!("Illegal move {} in position {}", uci, self.position.to_fen())))
+        }
+    }
+
+    pub fn push_san(&mut self, san: &str) -> PyResult<()> {
+        match self.get_move_by_san(san) {
+            Some(game_move) => { self.push_move(game_move); Ok(()) },
+            None => Err(PyValueError::new_err(format!("Illegal move {} in position {}", san, self.position.to_fen())))
+        }
+    }
+
+    /// Takes back the last move pushed, returning it in UCI format
+    pub fn pop(&mut self) -> PyResult<String> {
+        match self.move_stack.pop() {
+            Some((position, game_move)) => {
+                self.position = position;
+                self.update_legal_moves();
+                Ok(self.position.get_uci_move_string(&game_move))
+            },
+            None => Err(PyValueError::new_err("No moves to pop"))
+        }
+    }
+
+    /// Returns the moves pushed so far in UCI format
+    pub fn move_stack(&self) -> Vec<String> {
+        self.move_stack.iter().map(|(position, m)| position.get_uci_move_string(m)).collect()
+    }
+
+    /// Returns the (input_planes, legal_move_mask) pair for the neural net, using the moves pushed so far as the move history
+    pub fn encode(&self) -> PyResult<(Vec<f32>, Vec<f32>)> {
+        match self.move_stack.first() {
+            Some((start_position, _)) => encode_game_positions(start_position.clone(), &self.move_stack()),
+            None => encode_game_positions(self.position.clone(), &vec![])
+        }
+    }
+
+    #[getter]
+    pub fn white_to_move(&self) -> bool { self.position.white_to_move }
+
+    #[getter]
+    pub fn is_check(&self) -> bool { self.position.king_in_check }
+
+    #[getter]
+    pub fn is_double_check(&self) -> bool { self.position.king_in_double_check }
+
+    #[getter]
+    pub fn is_checkmate(&self) -> bool { self.position.is_checkmate }
+
+    #[getter]
+    pub fn is_stalemate(&self) -> bool { self.position.is_stalemate }
+
+    #[getter]
+    pub fn fifty_move_count(&self) -> u8 { self.position.fifty_move_count }
+
+    #[getter]
+    pub fn move_number(&self) -> u16 { self.position.move_number }
+
+    /// Castling rights in FEN format, e.g. "KQkq" or "-"
+    #[getter]
+    pub fn castling_rights(&self) -> String {
+        self.position.to_fen().split(' ').nth(2).unwrap().to_string()
+    }
+
+    /// En passant target square in algebraic notation (e.g. "e3"), if any
+    #[getter]
+    pub fn en_passant_square(&self) -> Option<String> {
+        PositionHelper::algebraic_from_bitboard(self.position.en_passant_sq).pop()
+    }
+
+    /// Returns each piece bitboard (e.g. "wp" for white pawns) plus the occupancy bitboards, with a1 = bit 0 and h8 = bit 63
+    pub fn bitboards(&self) -> HashMap<&'static str, u64> {
+        let p = &self.position;
+        HashMap::from([
+            ("wp", p.wp), ("wn", p.wn), ("wb", p.wb), ("wr", p.wr), ("wq", p.wq), ("wk", p.wk),
+            ("bp", p.bp), ("bn", p.bn), ("bb", p.bb), ("br", p.br), ("bq", p.bq), ("bk", p.bk),
+            ("white", p.white_occupancy), ("black", p.black_occupancy), ("all", p.all_occupancy),
+            ("en_passant", p.en_passant_sq), ("castling_rights", p.castling_rights),
+        ])
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for PyPosition {
+    fn __repr__(&self) -> String {
+        format!("Position('{}')", self.position.to_fen())
+    }
+
+    fn __str__(&self) -> String {
+        self.position.to_fen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::START_POSITION;
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut position = PyPosition::new(None).unwrap();
+        position.push_uci("e2e4").unwrap();
+        position.push_san("e5").unwrap();
+        position.push_san("Ng1f3").unwrap();
+        assert_eq!(position.fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        assert_eq!(position.move_stack(), vec!["e2e4", "e7e5", "g1f3"]);
+
+        assert_eq!(position.pop().unwrap(), "g1f3");
+        assert_eq!(position.fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+        assert_eq!(position.pop().unwrap(), "e7e5");
+        assert_eq!(position.pop().unwrap(), "e2e4");
+        assert_eq!(position.fen(), START_POSITION);
+        assert_eq!(position.legal_moves().len(), 20);
+        assert!(position.pop().is_err());
+
+        // Illegal moves leave the position as it was
+        assert!(position.push_uci("e2e5").is_err());
+        assert!(position.push_san("Nf6").is_err());
+        assert_eq!(position.fen(), START_POSITION);
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        let position = PyPosition::new(Some("r3k3/1P6/8/8/8/8/8/4K2R w K - 0 1")).unwrap();
+        let uci_moves = position.legal_moves();
+        let san_moves = position.legal_moves_san();
+        assert_eq!(uci_moves.len(), san_moves.len());
+
+        let expected = [("e1g1", "O-O"), ("b7a8q", "bxa8=Q+"), ("b7b8n", "b8=N"), ("h1h8", "Rh8+")];
+        for (uci, san) in expected {
+            let index = uci_moves.iter().position(|m| m == uci).unwrap();
+            assert_eq!(san_moves[index], san);
+            assert_eq!(position.san(uci).unwrap(), san);
+        }
+
+        let mut position = position;
+        assert_eq!(position.uci("O-O").unwrap(), "e1g1");
+        assert_eq!(position.uci("bxa8=Q").unwrap(), "b7a8q");
+        assert!(position.uci("O-O-O").is_err());
+        assert!(position.san("e1c1").is_err());
+    }
+
+    #[test]
+    fn test_check_flags() {
+        let mut position = PyPosition::new(None).unwrap();
+        for san in ["e4", "f5", "Qh5+"] { position.push_san(san).unwrap(); }
+        assert!(position.is_check());
+        assert!(!position.is_checkmate());
+        assert_eq!(position.legal_moves(), vec!["g7g6"]);
+
+        let mut position = PyPosition::new(None).unwrap();
+        for uci in ["f2f3", "e7e5", "g2g4", "d8h4"] { position.push_uci(uci).unwrap(); }
+        assert!(position.is_check());
+        assert!(position.is_checkmate());
+        assert!(position.legal_moves().is_empty());
+
+        // Popping the mating move clears the flags again
+        position.pop().unwrap();
+        assert!(!position.is_check());
+        assert!(!position.is_checkmate());
+
+        let position = PyPosition::new(Some("k7/8/1Q6/8/8/8/8/7K b - - 0 1")).unwrap();
+        assert!(position.is_stalemate());
+        assert!(!position.is_check());
+        assert!(position.legal_moves().is_empty());
+    }
+}
@@ -21,7 +21,7 @@ impl MoveMakerTestHelper {
         let orig = position.clone();
 
         move_maker.make_move(position, game_move, true);
-        move_maker.unmake_move(position, game_move);
+        move_maker.unmake_move(position);
 
         MoveMakerTestHelper::compare_positions(&orig, &position)
     }